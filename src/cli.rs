@@ -2,3 +2,1004 @@
 // Copyright (c) 2025 Lunatic Fringers
 // This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
 // See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+pub mod commands;
+pub mod template;
+
+use crate::core::config::{ConfigError, ConfigFormat};
+use crate::core::logger::LogLevel;
+use crate::core::monitor::MonitorError;
+use crate::core::otp::OtpError;
+use crate::core::session::SessionError;
+use crate::core::wg::{self, WgError};
+use chrono::{DateTime, Local, NaiveDateTime};
+use clap::{Parser, Subcommand};
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use template::TemplateError;
+use thiserror::Error;
+
+/// Define the errors raised while running a command.
+#[derive(Debug, Error)]
+pub enum CliError {
+  #[error(transparent)]
+  Config(#[from] ConfigError),
+  #[error(transparent)]
+  Wg(#[from] WgError),
+  #[error(transparent)]
+  Session(#[from] SessionError),
+  #[error(transparent)]
+  Monitor(#[from] MonitorError),
+  #[error(transparent)]
+  Otp(#[from] OtpError),
+  #[error("Failed to write output: {0}")]
+  Io(#[from] io::Error),
+  #[error("Failed to serialize output: {0}")]
+  Json(#[from] serde_json::Error),
+  #[error("WireGuard configuration file {0} does not exist")]
+  MissingConfigFile(String),
+  #[error("Directory {0} does not exist")]
+  MissingDirectory(String),
+  #[error("No path {path}, configured paths: {}", list_or_none(.available))]
+  UnknownPath { path: String, available: Vec<String> },
+  #[error("--otp requires --otp-uri")]
+  MissingOtpUri,
+  #[error("--encrypt requires --otp-uri")]
+  NothingToEncrypt,
+  #[error("--assign-address requires an address_pool in the configuration file")]
+  MissingAddressPool,
+  #[error("The passphrase must not be empty")]
+  EmptyPassphrase,
+  #[error("The passphrases do not match")]
+  PassphraseMismatch,
+  #[error("Invalid OTP URI: {0}")]
+  InvalidOtpUri(String),
+  #[error("No user with config path {path}, configured paths: {}", list_or_none(.available))]
+  UnknownUser { path: String, available: Vec<String> },
+  #[error("No configured tunnel has interface {interface}, configured interfaces: {}", list_or_none(.available))]
+  UnknownInterface { interface: String, available: Vec<String> },
+  #[error("Endpoint {endpoint} is unreachable: {reason}")]
+  UnreachableEndpoint { endpoint: String, reason: String },
+  #[error("{0} tunnel(s) failed to connect")]
+  ConnectFailed(usize),
+  #[error("{0} tunnel(s) failed to disconnect")]
+  DisconnectFailed(usize),
+  #[error("{0} problem(s) found")]
+  DoctorProblems(usize),
+  #[error(transparent)]
+  Template(#[from] TemplateError),
+  #[error("Invalid pattern: {0}")]
+  InvalidPattern(#[from] regex::Error),
+  #[error("No log line matches {0}")]
+  NoLogMatches(String),
+  #[error("{0} warning(s) found")]
+  LintWarnings(usize),
+  #[error("{up} tunnel(s) up, {required} required")]
+  TunnelsDown { up: usize, required: usize },
+  #[error("A config path is required, configured paths: {}", list_or_none(.0))]
+  MissingConfigPath(Vec<String>),
+  #[error("No tunnel sets reconnect_on_network_change, nothing to watch the network for")]
+  NoReconnectingTunnel,
+  #[error("Tunnel {0} is frozen, unfreeze it with `wgb unfreeze {0}` first")]
+  Frozen(String),
+  #[error("The home directory is unknown, nowhere to keep the PID of the monitor")]
+  NoMonitorDir,
+  #[error("{0} has no otp_uri to generate one-time passwords from")]
+  NoOtpUri(String),
+  #[error("The one-time password of {0} is invalid")]
+  InvalidOtp(String),
+  #[error("interface_template uses {{profile}}, which {0} does not set")]
+  MissingProfile(String),
+  #[error(
+    "interface_template names the interface of {config_path} {name:?}, expected 1 to {} letters, digits or _=+.-",
+    wg::MAX_INTERFACE_NAME_LEN
+  )]
+  InvalidInterfaceName { config_path: String, name: String },
+  #[error("interface_template gives {first} and {second} the same interface name {name}")]
+  DuplicateInterfaceName { name: String, first: String, second: String },
+}
+
+/// Define the command line of WG-Bridge.
+#[derive(Debug, Parser)]
+#[command(name = "wgb", version, about = "A tool to manage WireGuard VPN connections.")]
+#[command(arg_required_else_help = true)]
+pub struct Cli {
+  /// Path of the configuration file, overriding WGB_CONFIG and the default
+  /// one in the home directory.
+  #[arg(short, long, global = true, value_name = "PATH")]
+  pub config: Option<PathBuf>,
+  /// Format of the configuration file, json or toml, overriding the one
+  /// detected from its extension.
+  #[arg(long, global = true, value_name = "FORMAT")]
+  pub config_format: Option<ConfigFormat>,
+  /// Abort the command when it runs longer than the given duration (e.g.
+  /// 30s, 5m), undoing a connection left halfway.
+  #[arg(long, global = true, value_name = "DURATION", value_parser = parse_duration)]
+  pub timeout: Option<Duration>,
+  /// Run wg and wg-quick on the given host over SSH, e.g. root@vpn; the
+  /// WireGuard configuration files must be at the same paths there.
+  #[arg(long, global = true, value_name = "DESTINATION")]
+  pub remote: Option<String>,
+  /// Print a notice to stderr when a newer version has been released.
+  #[arg(long, global = true)]
+  pub check_update: bool,
+  /// Disable colors in the output, as does setting NO_COLOR.
+  #[arg(long, global = true)]
+  pub no_color: bool,
+  /// Enable a verbose logging, also echoed to stderr.
+  #[arg(short, long, global = true)]
+  pub verbose: bool,
+  /// The command to be run.
+  #[command(subcommand)]
+  pub command: Command,
+}
+
+/// Define the commands of WG-Bridge.
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum Command {
+  /// Establish a VPN connection using a WireGuard configuration file.
+  Connect {
+    /// Full path to the WireGuard configuration file, optional when a single
+    /// one is configured.
+    config_path: Option<String>,
+    /// Establish the VPN connections of every configured file which is not
+    /// connected yet.
+    #[arg(long, conflicts_with = "config_path")]
+    all: bool,
+    /// Only act on the configured files with the given tag.
+    #[arg(long, value_name = "NAME", requires = "all")]
+    tag: Option<String>,
+    /// Probe the UDP port of each peer endpoint first, and give up when one
+    /// is unreachable.
+    #[arg(long)]
+    probe: bool,
+    /// Probe the path MTU to the peer endpoints first, and set the MTU of
+    /// the interfaces whose file sets none from it.
+    #[arg(long)]
+    auto_mtu: bool,
+    /// Bring up to N tunnels up at once with --all, as many as the CPUs
+    /// when N is omitted.
+    #[arg(long, value_name = "N", requires = "all")]
+    parallel: Option<Option<NonZeroUsize>>,
+    /// Once connected, keep a monitor running in the background which
+    /// reconnects the tunnel when the network changes, until `wgb stop`.
+    #[arg(long, conflicts_with = "all")]
+    detach: bool,
+    /// The time between two polls of the routing tables by the monitor of
+    /// --detach (e.g. 5s).
+    #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = parse_duration, requires = "detach")]
+    interval: Duration,
+  },
+  /// Terminate the VPN connection of a WireGuard configuration file.
+  Disconnect {
+    /// Full path to the WireGuard configuration file, optional when a single
+    /// one is configured.
+    config_path: Option<String>,
+    /// Terminate the VPN connections of every configured file.
+    #[arg(long, conflicts_with = "config_path")]
+    all: bool,
+    /// Only act on the configured files with the given tag.
+    #[arg(long, value_name = "NAME", requires = "all")]
+    tag: Option<String>,
+    /// Bring up to N tunnels down at once with --all, as many as the CPUs
+    /// when N is omitted.
+    #[arg(long, value_name = "N", requires = "all")]
+    parallel: Option<Option<NonZeroUsize>>,
+  },
+  /// List the connected WireGuard tunnels, leaving out the ones which are
+  /// down.
+  #[command(visible_alias = "active")]
+  Up {
+    /// Print the tunnels as JSON, for scripting.
+    #[arg(long)]
+    json: bool,
+  },
+  /// Display the current status of the WireGuard connections.
+  Status {
+    /// Print the status as JSON, for monitoring scripts.
+    #[arg(long)]
+    json: bool,
+    /// Print a one-line summary instead of the table, and exit with a
+    /// non-zero code when a tunnel is down or stale.
+    #[arg(long)]
+    fail_if_down: bool,
+    /// Number of tunnels which must be up, all of them by default.
+    #[arg(long, value_name = "N", requires = "fail_if_down")]
+    threshold: Option<usize>,
+    /// Seconds since the latest handshake after which a tunnel which is up
+    /// is stale.
+    #[arg(long, value_name = "SECS", default_value_t = 180, requires = "fail_if_down")]
+    stale_after: u64,
+    /// Only display the configured files with the given tag.
+    #[arg(long, value_name = "NAME")]
+    tag: Option<String>,
+    /// Print each tunnel with a template, e.g. '{name}\t{up}\t{handshake}'.
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["json", "fail_if_down"])]
+    template: Option<String>,
+    /// Print what changed since the given time (seconds since the Unix
+    /// epoch, RFC 3339 or 'YYYY-MM-DD HH:MM:SS'), the traffic being counted
+    /// from the previous `status`.
+    #[arg(
+      long,
+      value_name = "TIMESTAMP",
+      value_parser = parse_timestamp,
+      conflicts_with_all = ["json", "fail_if_down", "template"]
+    )]
+    since: Option<SystemTime>,
+  },
+  /// List the configured WireGuard configurations.
+  List {
+    /// Print the list as JSON, for scripting.
+    #[arg(long)]
+    json: bool,
+    /// Only list the configured files with the given tag.
+    #[arg(long, value_name = "NAME")]
+    tag: Option<String>,
+    /// Print each configuration with a template, e.g. '{name}\t{tags}'.
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "json")]
+    template: Option<String>,
+  },
+  /// Check a WireGuard configuration file against best practices, and exit
+  /// with a non-zero code when a warning is found.
+  Lint {
+    /// Full path to the WireGuard configuration file, optional when a single
+    /// one is configured.
+    config_path: Option<String>,
+    /// Print the issues as JSON, for scripting.
+    #[arg(long)]
+    json: bool,
+  },
+  /// Check the configured WireGuard configurations for problems spanning
+  /// several of them, such as a private key used by more than one.
+  Doctor {
+    /// Probe the UDP port of each peer endpoint as well.
+    #[arg(long)]
+    probe: bool,
+  },
+  /// Print the settings in effect, merged from the command line, the
+  /// environment and the configuration files, along with the origin of each.
+  /// Secrets are redacted.
+  DumpEffectiveConfig {
+    /// Print the settings as JSON, for scripting.
+    #[arg(long)]
+    json: bool,
+  },
+  /// Replace the private key of a WireGuard configuration file with a new
+  /// one, backing up the file first, and print the new public key.
+  RotateKeys {
+    /// Full path to the WireGuard configuration file.
+    config_path: String,
+    /// Bring the tunnel down and up again with the new key, when it is up.
+    #[arg(long)]
+    reload: bool,
+  },
+  /// Measure the throughput through the tunnel of a WireGuard configuration
+  /// file, with iperf3 or an HTTP download. A tunnel which is not up is
+  /// brought up for the benchmark and down again afterwards.
+  Benchmark {
+    /// Full path to the WireGuard configuration file.
+    config_path: String,
+    /// The host of an iperf3 server reached through the tunnel.
+    #[arg(long, value_name = "HOST", required_unless_present = "url", conflicts_with = "url")]
+    server: Option<String>,
+    /// The URL of a file downloaded with curl through the tunnel instead.
+    #[arg(long)]
+    url: Option<String>,
+    /// The seconds the iperf3 test lasts.
+    #[arg(long, value_name = "SECS", default_value_t = 10, requires = "server")]
+    duration: u64,
+  },
+  /// Change a live interface of a configured tunnel with `wg set`, for the
+  /// settings WG-Bridge does not manage itself. The changes are lost when
+  /// the tunnel goes down.
+  WgSet {
+    /// The name of the interface, e.g. `wg0`.
+    interface: String,
+    /// The arguments given to `wg set` after the interface, e.g.
+    /// `peer <key> persistent-keepalive 25`.
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<String>,
+  },
+  /// Bring down the interfaces WG-Bridge brought up whose configuration is
+  /// gone, e.g. removed while the tunnel was up, asking for each one.
+  Cleanup {
+    /// Bring them down without asking for confirmation.
+    #[arg(short, long)]
+    yes: bool,
+  },
+  /// Watch the network the tunnels run over, and reconnect the active ones
+  /// setting `reconnect_on_network_change` once it changes (e.g. another
+  /// Wi-Fi) or the system resumes, until interrupted.
+  WatchNetwork {
+    /// The time between two polls of the routing tables (e.g. 5s).
+    #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = parse_duration)]
+    interval: Duration,
+  },
+  /// Run the monitor of a tunnel started by `connect --detach`, in the
+  /// background.
+  #[command(hide = true)]
+  Monitor {
+    /// Full path to the WireGuard configuration file.
+    config_path: String,
+    /// The time between two polls of the routing tables.
+    #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = parse_duration)]
+    interval: Duration,
+  },
+  /// Print the current one-time password of a configuration, generated from
+  /// its otp_uri.
+  GenerateTotp {
+    /// Full path to the WireGuard configuration file, optional when a single
+    /// one is configured.
+    config_path: Option<String>,
+    /// Generate the password at the given time instead of now, in seconds
+    /// since the Unix epoch, as RFC 3339 or as a local YYYY-MM-DD HH:MM:SS.
+    #[arg(long, value_name = "TIME", value_parser = parse_timestamp, hide = true)]
+    at: Option<SystemTime>,
+  },
+  /// Check a one-time password against the otp_uri of a configuration,
+  /// failing when it is invalid.
+  VerifyOtp {
+    /// The one-time password.
+    code: String,
+    /// Full path to the WireGuard configuration file, optional when a single
+    /// one is configured.
+    config_path: Option<String>,
+    /// Check the password at the given time instead of now, in seconds since
+    /// the Unix epoch, as RFC 3339 or as a local YYYY-MM-DD HH:MM:SS.
+    #[arg(long, value_name = "TIME", value_parser = parse_timestamp, hide = true)]
+    at: Option<SystemTime>,
+  },
+  /// Stop the background monitor of a tunnel started by `connect --detach`.
+  Stop {
+    /// Full path to the WireGuard configuration file, optional when a single
+    /// one is configured.
+    config_path: Option<String>,
+  },
+  /// Add a WireGuard configuration.
+  AddUser {
+    /// Full path to the WireGuard configuration file.
+    #[arg(long)]
+    config_path: String,
+    /// Require a one-time password to connect.
+    #[arg(long)]
+    otp: bool,
+    /// The otpauth:// URI used to generate the one-time passwords, or the
+    /// bare base32 secret given by some providers.
+    #[arg(long, value_name = "URI")]
+    otp_uri: Option<String>,
+    /// Store the OTP URI encrypted with a passphrase, asked for on the
+    /// terminal.
+    #[arg(long)]
+    encrypt: bool,
+    /// Set the Address of the file to the next free address of the
+    /// configured address_pool.
+    #[arg(long)]
+    assign_address: bool,
+  },
+  /// Find the WireGuard configuration files of /etc/wireguard and of the
+  /// configured paths which are not configured yet, and add them, asking
+  /// first.
+  Discover {
+    /// The directories to scan instead.
+    #[arg(value_name = "DIR")]
+    dirs: Vec<String>,
+    /// Add them without asking for confirmation.
+    #[arg(short, long)]
+    yes: bool,
+  },
+  /// Remove a WireGuard configuration.
+  RemoveUser {
+    /// Full path to the WireGuard configuration file.
+    config_path: String,
+    /// Remove without asking for confirmation.
+    #[arg(short, long)]
+    yes: bool,
+  },
+  /// Freeze the tunnel of a WireGuard configuration, so that it is neither
+  /// brought up nor changed until unfrozen, e.g. while its file is fixed.
+  Freeze {
+    /// Full path to the WireGuard configuration file.
+    config_path: String,
+  },
+  /// Unfreeze the tunnel of a WireGuard configuration.
+  Unfreeze {
+    /// Full path to the WireGuard configuration file.
+    config_path: String,
+  },
+  /// Manage the tags grouping the WireGuard configurations.
+  Tag {
+    /// The tag command to be run.
+    #[command(subcommand)]
+    command: TagCommand,
+  },
+  /// Search the log files.
+  Logs {
+    /// The logs command to be run.
+    #[command(subcommand)]
+    command: LogsCommand,
+  },
+  /// Manage the directories where WireGuard configuration files are saved.
+  Path {
+    /// The path command to be run.
+    #[command(subcommand)]
+    command: PathCommand,
+  },
+  /// Manage the configuration file of WG-Bridge.
+  Config {
+    /// The config command to be run.
+    #[command(subcommand)]
+    command: ConfigCommand,
+  },
+  /// Show or edit the WireGuard configuration file of a user.
+  WgConfig {
+    /// The wg-config command to be run.
+    #[command(subcommand)]
+    command: WgConfigCommand,
+  },
+}
+
+/// Define the subcommands of `tag`.
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum TagCommand {
+  /// Add a tag to a WireGuard configuration.
+  Add {
+    /// Full path to the WireGuard configuration file.
+    config_path: String,
+    /// The tag, e.g. work.
+    tag: String,
+  },
+  /// Remove a tag from a WireGuard configuration.
+  Remove {
+    /// Full path to the WireGuard configuration file.
+    config_path: String,
+    /// The tag.
+    tag: String,
+  },
+}
+
+/// Define the subcommands of `logs`.
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum LogsCommand {
+  /// Print the lines of the dated log files matching a regular expression,
+  /// from the oldest file to the newest.
+  Grep {
+    /// The regular expression.
+    pattern: String,
+    /// Match the pattern regardless of case.
+    #[arg(short, long)]
+    ignore_case: bool,
+    /// Only match lines of the given level or a more severe one.
+    #[arg(long, value_name = "LEVEL")]
+    level: Option<LogLevel>,
+    /// Print N lines before and after each match.
+    #[arg(short = 'C', long, value_name = "N", default_value_t = 0)]
+    context: usize,
+    /// Directory holding the log files, the current one by default.
+    #[arg(long, value_name = "PATH", default_value = ".")]
+    dir: PathBuf,
+  },
+}
+
+/// Define the subcommands of `path`.
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum PathCommand {
+  /// Add a directory where WireGuard configuration files are saved.
+  Add {
+    /// The directory; `~` and environment variables are expanded on use.
+    path: String,
+  },
+  /// Remove a directory where WireGuard configuration files are saved.
+  Delete {
+    /// The directory, as listed by `path list`.
+    path: String,
+  },
+  /// List the directories where WireGuard configuration files are saved.
+  List {
+    /// Print the list as JSON, for scripting.
+    #[arg(long)]
+    json: bool,
+  },
+}
+
+/// Define the subcommands of `config`.
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum ConfigCommand {
+  /// Rewrite the configuration file in its canonical form (sorted
+  /// configurations, tidied paths, fixed field order), so that its diffs
+  /// stay small, and tell whether anything changed.
+  Normalize,
+}
+
+/// Define the subcommands of `wg-config`.
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub enum WgConfigCommand {
+  /// Print a WireGuard configuration file, its private and preshared keys
+  /// redacted.
+  Show {
+    /// Full path to the WireGuard configuration file, optional when a single
+    /// one is configured.
+    config_path: Option<String>,
+    /// Print the private and preshared keys as well.
+    #[arg(long)]
+    show_secrets: bool,
+  },
+  /// Open a WireGuard configuration file in `$VISUAL` or `$EDITOR`, keeping
+  /// the changes only when the edited file is still valid.
+  Edit {
+    /// Full path to the WireGuard configuration file, optional when a single
+    /// one is configured.
+    config_path: Option<String>,
+  },
+}
+
+/// Function to parse the command line arguments.
+///
+/// On invalid arguments, or when help or the version is requested, the
+/// message is printed and the process exits.
+///
+/// # Returns
+/// * `Cli`: The parsed command line.
+pub fn parse() -> Cli {
+  Cli::parse()
+}
+
+/// Function to tell how many tunnels a `--parallel` option acts on at once.
+///
+/// # Arguments
+/// * `parallel`: The value of the option, `Some(None)` when given without a
+///   number.
+///
+/// # Returns
+/// * `usize`: One without the option, the number given, or the number of
+///   CPUs without a number.
+pub fn workers(parallel: Option<Option<NonZeroUsize>>) -> usize {
+  match parallel {
+    None => 1,
+    Some(Some(workers)) => workers.get(),
+    Some(None) => std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+  }
+}
+
+/// Function to format a list of values for an error message.
+///
+/// # Arguments
+/// * `values`: The values to be listed.
+///
+/// # Returns
+/// * `String`: The comma-separated values, or "none" when empty.
+fn list_or_none(values: &[String]) -> String {
+  if values.is_empty() {
+    "none".to_string()
+  } else {
+    values.join(", ")
+  }
+}
+
+/// Function to parse a duration given on the command line.
+///
+/// # Arguments
+/// * `value`: The duration, a number followed by `ms`, `s`, `m` or `h`, in
+///   seconds when bare.
+///
+/// # Returns
+/// * `Result<Duration, String>`: The duration, or the reason it is invalid.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+  let value = value.trim();
+  let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+  let (number, unit) = value.split_at(split);
+  let number: u64 = number
+      .parse()
+      .map_err(|_| format!("invalid duration {value}, expected e.g. 30s or 5m"))?;
+  let seconds = match unit {
+    "ms" => return Ok(Duration::from_millis(number)),
+    "" | "s" => 1,
+    "m" => 60,
+    "h" => 3600,
+    _ => return Err(format!("invalid duration unit {unit}, expected ms, s, m or h")),
+  };
+  Ok(Duration::from_secs(number.saturating_mul(seconds)))
+}
+
+/// Function to parse a point in time given on the command line.
+///
+/// # Arguments
+/// * `value`: The time, in seconds since the Unix epoch, as RFC 3339 or as
+///   a local `YYYY-MM-DD HH:MM:SS`.
+///
+/// # Returns
+/// * `Result<SystemTime, String>`: The time, or the reason why it is invalid.
+fn parse_timestamp(value: &str) -> Result<SystemTime, String> {
+  let value = value.trim();
+  if let Ok(seconds) = value.parse::<u64>() {
+    return Ok(UNIX_EPOCH + Duration::from_secs(seconds));
+  }
+  if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+    return Ok(time.into());
+  }
+  NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+      .ok()
+      .and_then(|time| time.and_local_timezone(Local).earliest())
+      .map(SystemTime::from)
+      .ok_or_else(|| format!("invalid timestamp {value}, expected e.g. 1735689600, 2025-01-01T00:00:00Z or 2025-01-01 00:00:00"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use clap::CommandFactory;
+
+  #[test]
+  fn parses_durations() {
+    assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+    assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+    assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+    assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+    assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+    for value in ["", "s", "-5s", "5d", "1.5s"] {
+      assert!(parse_duration(value).is_err(), "{value}");
+    }
+    assert_eq!(Cli::parse_from(["wgb", "--timeout", "1m", "status"]).timeout, Some(Duration::from_secs(60)));
+    assert_eq!(Cli::parse_from(["wgb", "status", "--remote", "root@vpn"]).remote.as_deref(), Some("root@vpn"));
+  }
+
+  #[test]
+  fn command_line_definition_is_consistent() {
+    Cli::command().debug_assert();
+  }
+
+  fn command(args: &[&str]) -> Command {
+    Cli::try_parse_from(["wgb"].iter().chain(args)).unwrap().command
+  }
+
+  #[test]
+  fn parses_connect_with_and_without_a_path() {
+    assert_eq!(
+      command(&["connect"]),
+      Command::Connect {
+        config_path: None,
+        all: false,
+        tag: None,
+        probe: false,
+        auto_mtu: false,
+        parallel: None,
+        detach: false,
+        interval: Duration::from_secs(5)
+      }
+    );
+    assert_eq!(
+      command(&["connect", "/etc/wireguard/wg0.conf", "--probe"]),
+      Command::Connect {
+        config_path: Some("/etc/wireguard/wg0.conf".to_string()),
+        all: false,
+        tag: None,
+        probe: true,
+        auto_mtu: false,
+        parallel: None,
+        detach: false,
+        interval: Duration::from_secs(5)
+      }
+    );
+    assert_eq!(
+      command(&["connect", "--all", "--tag", "work"]),
+      Command::Connect {
+        config_path: None,
+        all: true,
+        tag: Some("work".to_string()),
+        probe: false,
+        auto_mtu: false,
+        parallel: None,
+        detach: false,
+        interval: Duration::from_secs(5)
+      }
+    );
+    assert!(Cli::try_parse_from(["wgb", "connect", "--tag", "work"]).is_err());
+    assert!(matches!(command(&["connect", "--all", "--auto-mtu"]), Command::Connect { auto_mtu: true, .. }));
+    assert!(matches!(command(&["connect", "--all", "--parallel", "8"]), Command::Connect { parallel: Some(Some(n)), .. } if n.get() == 8));
+    assert!(matches!(command(&["connect", "--all", "--parallel"]), Command::Connect { parallel: Some(None), .. }));
+    assert!(Cli::try_parse_from(["wgb", "connect", "--all", "--parallel", "0"]).is_err());
+    assert!(Cli::try_parse_from(["wgb", "connect", "--parallel", "2"]).is_err());
+    assert!(matches!(
+      command(&["connect", "wg0.conf", "--detach", "--interval", "30s"]),
+      Command::Connect { detach: true, interval, .. } if interval == Duration::from_secs(30)
+    ));
+    assert!(Cli::try_parse_from(["wgb", "connect", "--all", "--detach"]).is_err());
+    assert!(Cli::try_parse_from(["wgb", "connect", "--interval", "30s"]).is_err());
+    assert_eq!(workers(None), 1);
+    assert_eq!(workers(NonZeroUsize::new(3).map(Some)), 3);
+    assert!(workers(Some(None)) >= 1);
+  }
+
+  #[test]
+  fn parses_disconnect_forms() {
+    assert_eq!(
+      command(&["disconnect", "wg0.conf"]),
+      Command::Disconnect {
+        config_path: Some("wg0.conf".to_string()),
+        all: false,
+        tag: None,
+        parallel: None
+      }
+    );
+    assert_eq!(
+      command(&["disconnect", "--all", "--tag", "work"]),
+      Command::Disconnect {
+        config_path: None,
+        all: true,
+        tag: Some("work".to_string()),
+        parallel: None
+      }
+    );
+    assert!(Cli::try_parse_from(["wgb", "disconnect", "--all", "wg0.conf"]).is_err());
+  }
+
+  #[test]
+  fn parses_status_and_list() {
+    let status = |json, fail_if_down, threshold, stale_after| Command::Status {
+      json,
+      fail_if_down,
+      threshold,
+      stale_after,
+      tag: None,
+      template: None,
+      since: None,
+    };
+    assert_eq!(command(&["status"]), status(false, false, None, 180));
+    assert_eq!(command(&["status", "--json"]), status(true, false, None, 180));
+    assert_eq!(command(&["status", "--fail-if-down"]), status(false, true, None, 180));
+    assert_eq!(
+      command(&["status", "--fail-if-down", "--threshold", "2", "--stale-after", "300"]),
+      status(false, true, Some(2), 300)
+    );
+    assert!(Cli::try_parse_from(["wgb", "status", "--threshold", "2"]).is_err());
+    assert!(matches!(command(&["status", "--tag", "work"]), Command::Status { tag: Some(tag), .. } if tag == "work"));
+    for (since, seconds) in [("1735689600", 1_735_689_600), ("2025-01-01T01:00:00+01:00", 1_735_689_600)] {
+      let expected = UNIX_EPOCH + Duration::from_secs(seconds);
+      assert!(matches!(command(&["status", "--since", since]), Command::Status { since: Some(time), .. } if time == expected));
+    }
+    assert!(parse_timestamp("2025-01-01 00:00:00").is_ok());
+    assert!(Cli::try_parse_from(["wgb", "status", "--since", "yesterday"]).is_err());
+    assert!(Cli::try_parse_from(["wgb", "status", "--since", "0", "--json"]).is_err());
+    assert_eq!(command(&["up"]), Command::Up { json: false });
+    assert_eq!(command(&["active", "--json"]), Command::Up { json: true });
+    assert_eq!(
+      command(&["list"]),
+      Command::List {
+        json: false,
+        tag: None,
+        template: None
+      }
+    );
+    assert_eq!(
+      command(&["list", "--json", "--tag", "work"]),
+      Command::List {
+        json: true,
+        tag: Some("work".to_string()),
+        template: None
+      }
+    );
+    assert!(matches!(command(&["list", "--template", "{name}"]), Command::List { template: Some(_), .. }));
+    assert!(Cli::try_parse_from(["wgb", "list", "--json", "--template", "{name}"]).is_err());
+    assert!(Cli::try_parse_from(["wgb", "status", "--fail-if-down", "--template", "{name}"]).is_err());
+    assert_eq!(command(&["doctor"]), Command::Doctor { probe: false });
+    assert_eq!(command(&["doctor", "--probe"]), Command::Doctor { probe: true });
+    assert_eq!(
+      command(&["benchmark", "wg0.conf", "--server", "10.0.0.1"]),
+      Command::Benchmark {
+        config_path: "wg0.conf".to_string(),
+        server: Some("10.0.0.1".to_string()),
+        url: None,
+        duration: 10
+      }
+    );
+    assert!(matches!(command(&["benchmark", "wg0.conf", "--url", "http://10.0.0.1/f"]), Command::Benchmark { url: Some(_), .. }));
+    assert!(Cli::try_parse_from(["wgb", "benchmark", "wg0.conf"]).is_err());
+    assert!(Cli::try_parse_from(["wgb", "benchmark", "wg0.conf", "--server", "h", "--url", "u"]).is_err());
+    assert_eq!(
+      command(&["wg-set", "wg0", "peer", "abc=", "remove"]),
+      Command::WgSet {
+        interface: "wg0".to_string(),
+        args: ["peer", "abc=", "remove"].map(String::from).to_vec()
+      }
+    );
+    assert!(Cli::try_parse_from(["wgb", "wg-set", "wg0"]).is_err());
+    assert_eq!(command(&["cleanup", "-y"]), Command::Cleanup { yes: true });
+    assert_eq!(
+      command(&["watch-network"]),
+      Command::WatchNetwork {
+        interval: Duration::from_secs(5)
+      }
+    );
+    assert_eq!(
+      command(&["watch-network", "--interval", "1m"]),
+      Command::WatchNetwork {
+        interval: Duration::from_secs(60)
+      }
+    );
+    assert_eq!(
+      command(&["monitor", "/etc/wireguard/wg0.conf", "--interval", "1500ms"]),
+      Command::Monitor {
+        config_path: "/etc/wireguard/wg0.conf".to_string(),
+        interval: Duration::from_millis(1500)
+      }
+    );
+    assert_eq!(command(&["stop"]), Command::Stop { config_path: None });
+    assert_eq!(
+      command(&["generate-totp", "wg0.conf", "--at", "59"]),
+      Command::GenerateTotp {
+        config_path: Some("wg0.conf".to_string()),
+        at: Some(UNIX_EPOCH + Duration::from_secs(59))
+      }
+    );
+    assert!(matches!(
+      command(&["verify-otp", "123456"]),
+      Command::VerifyOtp { code, config_path: None, at: None } if code == "123456"
+    ));
+    assert_eq!(
+      command(&["rotate-keys", "wg0.conf", "--reload"]),
+      Command::RotateKeys {
+        config_path: "wg0.conf".to_string(),
+        reload: true
+      }
+    );
+    assert_eq!(
+      command(&["lint", "wg0.conf", "--json"]),
+      Command::Lint {
+        config_path: Some("wg0.conf".to_string()),
+        json: true
+      }
+    );
+  }
+
+  #[test]
+  fn parses_logs_grep() {
+    assert_eq!(
+      command(&["logs", "grep", "wg0", "-i", "--level", "warn", "-C", "2"]),
+      Command::Logs {
+        command: LogsCommand::Grep {
+          pattern: "wg0".to_string(),
+          ignore_case: true,
+          level: Some(LogLevel::Warn),
+          context: 2,
+          dir: PathBuf::from("."),
+        }
+      }
+    );
+    assert!(Cli::try_parse_from(["wgb", "logs", "grep", "wg0", "--level", "loud"]).is_err());
+  }
+
+  #[test]
+  fn parses_add_user() {
+    assert_eq!(
+      command(&["add-user", "--config-path", "wg0.conf", "--otp", "--otp-uri", "otpauth://totp/x?secret=A"]),
+      Command::AddUser {
+        config_path: "wg0.conf".to_string(),
+        otp: true,
+        otp_uri: Some("otpauth://totp/x?secret=A".to_string()),
+        encrypt: false,
+        assign_address: false
+      }
+    );
+    assert_eq!(
+      command(&["add-user", "--config-path", "wg0.conf", "--otp-uri", "otpauth://totp/x?secret=A", "--encrypt"]),
+      Command::AddUser {
+        config_path: "wg0.conf".to_string(),
+        otp: false,
+        otp_uri: Some("otpauth://totp/x?secret=A".to_string()),
+        encrypt: true,
+        assign_address: false
+      }
+    );
+    assert_eq!(
+      command(&["add-user", "--config-path", "wg0.conf", "--assign-address"]),
+      Command::AddUser {
+        config_path: "wg0.conf".to_string(),
+        otp: false,
+        otp_uri: None,
+        encrypt: false,
+        assign_address: true
+      }
+    );
+    assert!(Cli::try_parse_from(["wgb", "add-user"]).is_err());
+  }
+
+  #[test]
+  fn parses_remove_user() {
+    assert_eq!(
+      command(&["remove-user", "wg0.conf", "-y"]),
+      Command::RemoveUser {
+        config_path: "wg0.conf".to_string(),
+        yes: true
+      }
+    );
+  }
+
+  #[test]
+  fn parses_tag_subcommands() {
+    assert_eq!(
+      command(&["tag", "add", "wg0.conf", "work"]),
+      Command::Tag {
+        command: TagCommand::Add {
+          config_path: "wg0.conf".to_string(),
+          tag: "work".to_string()
+        }
+      }
+    );
+    assert_eq!(
+      command(&["tag", "remove", "wg0.conf", "work"]),
+      Command::Tag {
+        command: TagCommand::Remove {
+          config_path: "wg0.conf".to_string(),
+          tag: "work".to_string()
+        }
+      }
+    );
+    assert!(Cli::try_parse_from(["wgb", "tag", "add", "wg0.conf"]).is_err());
+  }
+
+  #[test]
+  fn parses_path_subcommands() {
+    assert_eq!(
+      command(&["path", "add", "~/wg"]),
+      Command::Path {
+        command: PathCommand::Add { path: "~/wg".to_string() }
+      }
+    );
+    assert_eq!(
+      command(&["path", "delete", "~/wg"]),
+      Command::Path {
+        command: PathCommand::Delete { path: "~/wg".to_string() }
+      }
+    );
+    assert_eq!(
+      command(&["path", "list", "--json"]),
+      Command::Path {
+        command: PathCommand::List { json: true }
+      }
+    );
+  }
+
+  #[test]
+  fn parses_config_subcommands() {
+    assert_eq!(
+      command(&["config", "normalize"]),
+      Command::Config {
+        command: ConfigCommand::Normalize
+      }
+    );
+  }
+
+  #[test]
+  fn parses_wg_config_subcommands() {
+    assert_eq!(
+      command(&["wg-config", "show", "--show-secrets", "/etc/wireguard/wg0.conf"]),
+      Command::WgConfig {
+        command: WgConfigCommand::Show {
+          config_path: Some("/etc/wireguard/wg0.conf".to_string()),
+          show_secrets: true,
+        }
+      }
+    );
+    assert_eq!(
+      command(&["wg-config", "edit"]),
+      Command::WgConfig {
+        command: WgConfigCommand::Edit { config_path: None }
+      }
+    );
+  }
+
+  #[test]
+  fn parses_global_flags_anywhere() {
+    let cli = Cli::try_parse_from(["wgb", "list", "-v", "--config", "/tmp/wgb.json"]).unwrap();
+    assert!(cli.verbose);
+    assert_eq!(cli.config, Some(PathBuf::from("/tmp/wgb.json")));
+    assert!(!Cli::try_parse_from(["wgb", "status"]).unwrap().verbose);
+  }
+
+  #[test]
+  fn requires_a_command() {
+    assert!(Cli::try_parse_from(["wgb"]).is_err());
+    assert!(Cli::try_parse_from(["wgb", "frobnicate"]).is_err());
+  }
+}
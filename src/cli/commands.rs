@@ -0,0 +1,3802 @@
+// commands.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::CliError;
+use super::template::Template;
+use crate::core::bench::Benchmark;
+use crate::core::clock::{Clock, SystemClock};
+use crate::core::color::{self, Color};
+use crate::core::config::{self, Config, ConfigError, ConfigFormat, UserConfig};
+use crate::core::crypto;
+use crate::core::lint::{self, Lint, Severity};
+use crate::core::grep::{self, GrepOptions, LogGrep};
+use crate::core::logger::{self, LogLevel, Logger};
+use crate::core::monitor;
+use crate::core::mtu::{self, MtuProbe};
+use crate::core::netwatch::{CurrentNetwork, NetworkDetector, NetworkEvents, RoutePoller, TrustedNetwork};
+use crate::core::otp::{self, Totp};
+use crate::core::pool;
+use crate::core::session;
+use crate::core::snapshot::{self, Snapshot};
+use crate::core::wg::{
+  self, AddressPool, PeerStatus, PortChecker, Prober, PublicKey, Reachability, SystemPortChecker, SystemResolver, SystemRunner, Teardown,
+  TunnelStatus, WgConfig, WgError, WgParseError,
+};
+use regex::RegexBuilder;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+/// Define an entry of the `list` output. The OTP URI itself is never
+/// printed, only whether one is set.
+#[derive(Debug, Serialize)]
+struct ListEntry<'a> {
+  config_path: &'a str,
+  otp: bool,
+  otp_uri_set: bool,
+  otp_encrypted: bool,
+  tags: &'a [String],
+  frozen: bool,
+}
+
+/// The fields of a `list` entry available to `--template`.
+const LIST_FIELDS: [&str; 7] = ["name", "config_path", "otp", "otp_uri_set", "otp_encrypted", "tags", "frozen"];
+
+/// Implements the logic of a `list` entry
+impl ListEntry<'_> {
+  /// Function to retrieve the values of the fields of the entry.
+  ///
+  /// # Returns
+  /// * `[String; 7]`: The values, in the order of `LIST_FIELDS`; the tags are
+  ///   comma-separated.
+  fn fields(&self) -> [String; 7] {
+    let name = wg::interface_name(Path::new(self.config_path)).unwrap_or_default();
+    [
+      name.to_string(),
+      self.config_path.to_string(),
+      self.otp.to_string(),
+      self.otp_uri_set.to_string(),
+      self.otp_encrypted.to_string(),
+      self.tags.join(","),
+      self.frozen.to_string(),
+    ]
+  }
+}
+
+/// Implements the conversion of a configuration to a `list` entry
+impl<'a> From<&'a UserConfig> for ListEntry<'a> {
+  fn from(user: &'a UserConfig) -> Self {
+    ListEntry {
+      config_path: &user.config_path,
+      otp: user.otp,
+      otp_uri_set: !user.otp_uri.is_empty(),
+      otp_encrypted: user.otp_encrypted,
+      tags: &user.tags,
+      frozen: user.frozen,
+    }
+  }
+}
+
+/// Define an entry of the `status` output.
+#[derive(Debug, Serialize)]
+struct StatusEntry<'a> {
+  config_path: &'a str,
+  interface: &'a str,
+  up: bool,
+  peers: Vec<PeerEntry<'a>>,
+}
+
+/// Define a peer of a `status` entry. The latest handshake is given in
+/// seconds since the Unix epoch.
+#[derive(Debug, Serialize)]
+struct PeerEntry<'a> {
+  public_key: &'a str,
+  endpoint: Option<&'a str>,
+  roamed_from: Option<String>,
+  allowed_ips: &'a [String],
+  allowed_ips_drift: bool,
+  latest_handshake: Option<u64>,
+  rx_bytes: u64,
+  tx_bytes: u64,
+}
+
+/// Define a configured WireGuard tunnel, along with its live state.
+#[derive(Clone, Debug)]
+struct Tunnel {
+  /// The path to the WireGuard configuration file, as configured.
+  config_path: String,
+  /// The live state of the interface.
+  status: TunnelStatus,
+  /// The WireGuard configuration, read when the tunnel is up; `None` when
+  /// it is down or the file could not be parsed.
+  config: Option<WgConfig>,
+  /// The time without a handshake after which the tunnel is stale, the one
+  /// of the health check when `None`.
+  handshake_timeout: Option<Duration>,
+  /// The snapshot of the interface taken by the previous `status`, if any.
+  previous: Option<Snapshot>,
+}
+
+/// The fields of a `status` tunnel available to `--template`.
+const STATUS_FIELDS: [&str; 9] = [
+  "name",
+  "config_path",
+  "up",
+  "handshake",
+  "latest_handshake",
+  "endpoint",
+  "peers",
+  "rx_bytes",
+  "tx_bytes",
+];
+
+/// Implements the logic of a configured tunnel
+impl Tunnel {
+  /// Function to retrieve the values of the fields of the tunnel.
+  ///
+  /// # Arguments
+  /// * `now`: The current time, the handshake is printed relative to.
+  ///
+  /// # Returns
+  /// * `[String; 9]`: The values, in the order of `STATUS_FIELDS`. The
+  ///   handshake is the latest of the peers, `latest_handshake` giving it in
+  ///   seconds since the Unix epoch, empty when none took place; the endpoint
+  ///   is the one of the first peer and the bytes are the totals of the peers.
+  fn fields(&self, now: SystemTime) -> [String; 9] {
+    let peers = &self.status.peers;
+    let handshake = peers.iter().filter_map(|peer| peer.latest_handshake).max();
+    [
+      self.status.interface.clone(),
+      self.config_path.clone(),
+      self.status.up.to_string(),
+      format_handshake(now, handshake),
+      handshake
+          .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+          .map_or_else(String::new, |since| since.as_secs().to_string()),
+      peers.iter().find_map(|peer| peer.endpoint.clone()).unwrap_or_default(),
+      peers.len().to_string(),
+      peers.iter().map(|peer| peer.rx_bytes).sum::<u64>().to_string(),
+      peers.iter().map(|peer| peer.tx_bytes).sum::<u64>().to_string(),
+    ]
+  }
+
+  /// Function to tell whether a live peer roamed, i.e. its endpoint changed
+  /// since the previous `status` or differs from the address configured in
+  /// the WireGuard configuration file.
+  ///
+  /// A configured hostname is never compared, since it is resolved by `wg`.
+  ///
+  /// # Arguments
+  /// * `peer`: The live state of the peer.
+  ///
+  /// # Returns
+  /// * `Option<String>`: The endpoint the peer roamed from, `None` when it
+  ///   did not roam or has no endpoint.
+  fn roamed_from(&self, peer: &PeerStatus) -> Option<String> {
+    let live: SocketAddr = peer.endpoint.as_deref()?.parse().ok()?;
+    let differs = |endpoint: &&str| endpoint.parse::<SocketAddr>().is_ok_and(|endpoint| endpoint != live);
+    let previous = self.previous.as_ref().and_then(|snapshot| snapshot.endpoint(&peer.public_key));
+    let configured = self
+        .config
+        .as_ref()
+        .and_then(|config| config.peers.iter().find(|configured| configured.public_key == peer.public_key))
+        .and_then(|configured| configured.endpoint.as_deref());
+    previous.filter(differs).or(configured.filter(differs)).map(str::to_string)
+  }
+
+  /// Function to tell whether the allowed IPs of a live peer differ from the
+  /// ones in the WireGuard configuration file, e.g. after a `wg set`.
+  ///
+  /// # Arguments
+  /// * `peer`: The live state of the peer.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the allowed IPs differ, or the peer is not in the
+  ///   file; `false` when the file was not read.
+  fn drifted(&self, peer: &PeerStatus) -> bool {
+    let Some(config) = &self.config else {
+      return false;
+    };
+    config
+        .peers
+        .iter()
+        .find(|configured| configured.public_key == peer.public_key)
+        .is_none_or(|configured| !configured.allowed_ips_match(&peer.allowed_ips))
+  }
+}
+
+/// Implements the conversion of a tunnel to a `status` entry
+impl<'a> From<&'a Tunnel> for StatusEntry<'a> {
+  fn from(tunnel: &'a Tunnel) -> Self {
+    StatusEntry {
+      config_path: &tunnel.config_path,
+      interface: &tunnel.status.interface,
+      up: tunnel.status.up,
+      peers: tunnel
+          .status
+          .peers
+          .iter()
+          .map(|peer| PeerEntry::new(peer, tunnel.drifted(peer), tunnel.roamed_from(peer)))
+          .collect(),
+    }
+  }
+}
+
+/// Implements the conversion of a peer status to a `status` peer
+impl<'a> PeerEntry<'a> {
+  /// Function to create the `status` peer of a live peer.
+  ///
+  /// # Arguments
+  /// * `peer`: The live state of the peer.
+  /// * `allowed_ips_drift`: Whether its allowed IPs differ from the file.
+  /// * `roamed_from`: The endpoint the peer roamed from, if it did.
+  ///
+  /// # Returns
+  /// * `PeerEntry`: The entry.
+  fn new(peer: &'a PeerStatus, allowed_ips_drift: bool, roamed_from: Option<String>) -> Self {
+    PeerEntry {
+      public_key: &peer.public_key,
+      endpoint: peer.endpoint.as_deref(),
+      roamed_from,
+      allowed_ips: &peer.allowed_ips,
+      allowed_ips_drift,
+      latest_handshake: peer
+          .latest_handshake
+          .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+          .map(|since| since.as_secs()),
+      rx_bytes: peer.rx_bytes,
+      tx_bytes: peer.tx_bytes,
+    }
+  }
+}
+
+/// Function to print the configured WireGuard configurations.
+///
+/// # Arguments
+/// * `out`: The writer the list is printed to (e.g. stdout).
+/// * `json`: Whether the list is printed as JSON instead of a table.
+/// * `tag`: The tag the listed configurations must have, if any.
+/// * `template`: The template each configuration is printed with instead of
+///   a table, if any (see `LIST_FIELDS`).
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised for an invalid
+///   template or while printing.
+pub fn list(out: &mut impl Write, json: bool, tag: Option<&str>, template: Option<&str>) -> Result<(), CliError> {
+  let template = template.map(|template| Template::parse(template, &LIST_FIELDS)).transpose()?;
+  let config = Config::get();
+  let entries: Vec<ListEntry> = tagged(&config.user, tag).map(ListEntry::from).collect();
+  match template {
+    Some(template) => print_templated(out, &template, entries.iter().map(ListEntry::fields)),
+    None => print_list(out, &entries, json, tag),
+  }
+}
+
+/// Function to print items with a template, one per line.
+///
+/// # Arguments
+/// * `out`: The writer the items are printed to.
+/// * `template`: The template.
+/// * `items`: The values of the fields of each item.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised while printing.
+fn print_templated<const N: usize>(
+  out: &mut impl Write,
+  template: &Template,
+  items: impl Iterator<Item = [String; N]>,
+) -> Result<(), CliError> {
+  for values in items {
+    writeln!(out, "{}", template.render(&values))?;
+  }
+  Ok(())
+}
+
+/// Function to print the entries of the `list` output.
+///
+/// # Arguments
+/// * `out`: The writer the list is printed to.
+/// * `entries`: The entries.
+/// * `json`: Whether the list is printed as JSON instead of a table.
+/// * `tag`: The tag the entries were selected by, if any.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised while printing.
+fn print_list(out: &mut impl Write, entries: &[ListEntry], json: bool, tag: Option<&str>) -> Result<(), CliError> {
+  if json {
+    serde_json::to_writer_pretty(&mut *out, entries)?;
+    writeln!(out)?;
+    return Ok(());
+  }
+  if entries.is_empty() {
+    match tag {
+      Some(tag) => writeln!(out, "No users tagged {tag}, add one with `wgb tag add`")?,
+      None => writeln!(out, "No users configured, add one with `wgb add-user`")?,
+    }
+    return Ok(());
+  }
+
+  let width = entries
+      .iter()
+      .map(|entry| entry.config_path.len())
+      .chain(["CONFIG PATH".len()])
+      .max()
+      .unwrap_or_default();
+  let otp_uris: Vec<&str> = entries
+      .iter()
+      .map(|entry| match (entry.otp_uri_set, entry.otp_encrypted) {
+        (false, _) => "-",
+        (true, false) => "set",
+        (true, true) => "set (encrypted)",
+      })
+      .collect();
+  let otp_uri_width = otp_uris.iter().map(|otp_uri| otp_uri.len()).chain(["OTP URI".len()]).max().unwrap_or_default();
+  writeln!(out, "{:<width$}  {:<3}  {:<otp_uri_width$}  {:<6}  TAGS", "CONFIG PATH", "OTP", "OTP URI", "FROZEN")?;
+  for (entry, otp_uri) in entries.iter().zip(otp_uris) {
+    let otp = if entry.otp { "yes" } else { "no" };
+    let frozen = if entry.frozen { "yes" } else { "no" };
+    let tags = if entry.tags.is_empty() { "-".to_string() } else { entry.tags.join(",") };
+    writeln!(out, "{:<width$}  {otp:<3}  {otp_uri:<otp_uri_width$}  {frozen:<6}  {tags}", entry.config_path)?;
+  }
+  Ok(())
+}
+
+/// Function to select the WireGuard configurations with a tag.
+///
+/// # Arguments
+/// * `users`: The configured WireGuard configurations.
+/// * `tag`: The tag the configurations must have, every one being selected
+///   when `None`.
+///
+/// # Returns
+/// * `impl Iterator<Item = &UserConfig>`: The selected configurations, in the
+///   configured order.
+fn tagged<'a>(users: &'a [UserConfig], tag: Option<&'a str>) -> impl Iterator<Item = &'a UserConfig> {
+  users.iter().filter(move |user| tag.is_none_or(|tag| user.has_tag(tag)))
+}
+
+/// Function to check a WireGuard configuration file against best practices
+/// and print the issues found.
+///
+/// # Arguments
+/// * `out`: The writer the issues are printed to (e.g. stdout).
+/// * `config_path`: The path to the WireGuard configuration file, optional
+///   when a single one is configured.
+/// * `json`: Whether the issues are printed as JSON instead of one per line.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving the path,
+///   parsing the file or printing, or counting the warnings found, so that
+///   the process exits with a non-zero code.
+pub fn lint(out: &mut impl Write, config_path: Option<&str>, json: bool) -> Result<(), CliError> {
+  let path = tunnel_path(config_path)?;
+  if !path.exists() {
+    return Err(CliError::MissingConfigFile(path.display().to_string()));
+  }
+  let config = WgConfig::parse(&path).map_err(WgError::from)?;
+  print_lints(out, &path, &lint::lint(&config), json, color::stdout())
+}
+
+/// Function to print the issues found in a WireGuard configuration file.
+///
+/// # Arguments
+/// * `out`: The writer the issues are printed to (e.g. stdout).
+/// * `path`: The path of the checked file.
+/// * `lints`: The issues found.
+/// * `json`: Whether the issues are printed as JSON instead of one per line.
+/// * `color`: Whether the severities are colored.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised while printing, or
+///   counting the warnings found.
+fn print_lints(
+  out: &mut impl Write,
+  path: &Path,
+  lints: &[Lint],
+  json: bool,
+  color: bool,
+) -> Result<(), CliError> {
+  if json {
+    serde_json::to_writer_pretty(&mut *out, lints)?;
+    writeln!(out)?;
+  } else if lints.is_empty() {
+    writeln!(out, "No issues found in {}", path.display())?;
+  } else {
+    for lint in lints {
+      let severity = match lint.severity {
+        Severity::Warning => Color::Yellow,
+        Severity::Info => Color::Dim,
+      };
+      let severity = color::paint(&lint.severity.to_string(), severity, color);
+      writeln!(out, "{}: {severity}: {}: {} [{}]", path.display(), lint.section, lint.message, lint.code)?;
+    }
+  }
+  match lints.iter().filter(|lint| lint.severity == Severity::Warning).count() {
+    0 => Ok(()),
+    warnings => Err(CliError::LintWarnings(warnings)),
+  }
+}
+
+/// Define the global options given on the command line, overriding the
+/// environment and the configuration file.
+#[derive(Clone, Debug, Default)]
+pub struct GlobalFlags<'a> {
+  /// The path given with `--config`, if any.
+  pub config: Option<&'a Path>,
+  /// The format given with `--config-format`, if any.
+  pub config_format: Option<ConfigFormat>,
+  /// The duration given with `--timeout`, if any.
+  pub timeout: Option<Duration>,
+  /// The host given with `--remote`, if any.
+  pub remote: Option<&'a str>,
+  /// Whether `--no-color` was given.
+  pub no_color: bool,
+  /// Whether `--verbose` was given.
+  pub verbose: bool,
+}
+
+/// Define where the value of a setting comes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Origin {
+  /// A command line option.
+  Flag(&'static str),
+  /// An environment variable.
+  Env(&'static str),
+  /// A configuration file or fragment.
+  File(PathBuf),
+  /// The built-in default.
+  Default,
+}
+
+impl fmt::Display for Origin {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Origin::Flag(flag) => write!(f, "flag {flag}"),
+      Origin::Env(name) => write!(f, "env {name}"),
+      Origin::File(path) => write!(f, "file {}", path.display()),
+      Origin::Default => f.write_str("default"),
+    }
+  }
+}
+
+/// Define a setting in effect, as printed by `dump-effective-config`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct Setting {
+  /// The name of the setting, e.g. `config_file` or `user.1.tags`.
+  setting: String,
+  /// The value in effect, secrets being redacted.
+  value: String,
+  /// Where the value comes from.
+  origin: String,
+}
+
+/// Function to print the settings in effect, along with their origin.
+///
+/// # Arguments
+/// * `out`: The writer the settings are printed to (e.g. stdout).
+/// * `flags`: The global options given on the command line.
+/// * `json`: Whether the settings are printed as JSON instead of a table.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised retrieving the path
+///   of the configuration file or while printing.
+pub fn dump_effective_config(out: &mut impl Write, flags: &GlobalFlags, json: bool) -> Result<(), CliError> {
+  let path = Config::loaded_path()?;
+  let settings = effective_settings(flags, &|name| std::env::var(name).ok(), &Config::get(), &path);
+  if json {
+    serde_json::to_writer_pretty(&mut *out, &settings)?;
+    writeln!(out)?;
+    return Ok(());
+  }
+  let mut rows = vec![["SETTING", "VALUE", "ORIGIN"].map(String::from)];
+  rows.extend(settings.into_iter().map(|setting| [setting.setting, setting.value, setting.origin]));
+  write_table(out, &rows)?;
+  Ok(())
+}
+
+/// Function to resolve the settings in effect, with the precedence of the
+/// command: flags over environment variables over configuration files over
+/// defaults.
+///
+/// # Arguments
+/// * `flags`: The global options given on the command line.
+/// * `env`: The lookup of the environment variables.
+/// * `config`: The configuration, merged with its fragments.
+/// * `path`: The path of the configuration file.
+///
+/// # Returns
+/// * `Vec<Setting>`: The settings, global ones first, then the ones of each
+///   WireGuard configuration.
+fn effective_settings(
+  flags: &GlobalFlags,
+  env: &dyn Fn(&str) -> Option<String>,
+  config: &Config,
+  path: &Path,
+) -> Vec<Setting> {
+  let env = |name| env(name).filter(|value| !value.is_empty());
+  let mut settings = Vec::new();
+  let mut set = |setting: &str, value: String, origin: Origin| {
+    settings.push(Setting {
+      setting: setting.to_string(),
+      value,
+      origin: origin.to_string(),
+    })
+  };
+  let file = || Origin::File(path.to_path_buf());
+  let unset = || "unset".to_string();
+
+  let config_origin = match (flags.config, env(config::CONFIG_ENV)) {
+    (Some(_), _) => Origin::Flag("--config"),
+    (None, Some(_)) => Origin::Env(config::CONFIG_ENV),
+    (None, None) => Origin::Default,
+  };
+  set("config_file", path.display().to_string(), config_origin);
+  let format = match ConfigFormat::of(path) {
+    ConfigFormat::Json => "json",
+    ConfigFormat::Toml => "toml",
+  };
+  let format_origin = if flags.config_format.is_some() { Origin::Flag("--config-format") } else { Origin::Default };
+  set("config_format", format.to_string(), format_origin);
+
+  let (level, level_origin) = match (flags.verbose, env(logger::LOG_LEVEL_ENV).and_then(|level| level.parse().ok())) {
+    (true, _) => (LogLevel::Debug, Origin::Flag("--verbose")),
+    (false, Some(level)) => (level, Origin::Env(logger::LOG_LEVEL_ENV)),
+    (false, None) => (LogLevel::Info, Origin::Default),
+  };
+  set("log_level", level.as_str().to_lowercase(), level_origin);
+  match env(logger::LOG_TARGETS_ENV) {
+    Some(targets) => set("log_targets", targets, Origin::Env(logger::LOG_TARGETS_ENV)),
+    None => set("log_targets", unset(), Origin::Default),
+  }
+  let (color, color_origin) = match (flags.no_color, env(color::NO_COLOR_ENV), env(color::FORCE_COLOR_ENV)) {
+    (true, _, _) => ("never", Origin::Flag("--no-color")),
+    (false, Some(_), _) => ("never", Origin::Env(color::NO_COLOR_ENV)),
+    (false, None, Some(force)) if force != "0" => ("always", Origin::Env(color::FORCE_COLOR_ENV)),
+    _ => ("auto", Origin::Default),
+  };
+  set("color", color.to_string(), color_origin);
+  match flags.timeout {
+    Some(timeout) => set("timeout", format!("{}s", timeout.as_secs_f32()), Origin::Flag("--timeout")),
+    None => set("timeout", unset(), Origin::Default),
+  }
+  match flags.remote {
+    Some(remote) => set("remote", remote.to_string(), Origin::Flag("--remote")),
+    None => set("remote", "local".to_string(), Origin::Default),
+  }
+
+  let optional = |value: Option<String>| match value {
+    Some(value) => (value, file()),
+    None => (unset(), Origin::Default),
+  };
+  for (setting, value) in [
+    ("audit_log", config.audit_log.clone()),
+    ("endpoint_family", config.endpoint_family.map(|family| family.to_string())),
+    ("dns_backend", config.dns_backend.map(|backend| backend.to_string())),
+    ("address_pool", config.address_pool.clone()),
+    ("interface_template", config.interface_template.clone()),
+    ("log.mode", config.log.mode.clone()),
+    ("log.owner", config.log.owner.map(|owner| owner.to_string())),
+    ("log.group", config.log.group.map(|group| group.to_string())),
+    ("decrypt.age", config.decrypt.as_ref().and_then(|decrypt| decrypt.age.clone())),
+    ("decrypt.sops", config.decrypt.as_ref().and_then(|decrypt| decrypt.sops.clone())),
+  ] {
+    let (value, origin) = optional(value);
+    set(setting, value, origin);
+  }
+  let list = |values: &[String]| if values.is_empty() { "none".to_string() } else { values.join(", ") };
+  set("paths", list(&config.paths), file());
+  match config::fragment_dir() {
+    Some(dir) if env("XDG_CONFIG_HOME").is_some() => set("fragments", dir.display().to_string(), Origin::Env("XDG_CONFIG_HOME")),
+    Some(dir) => set("fragments", dir.display().to_string(), Origin::Default),
+    None => set("fragments", unset(), Origin::Default),
+  }
+
+  for (index, user) in (1..).zip(&config.user) {
+    let origin = || user.fragment.clone().map_or_else(file, Origin::File);
+    let otp_uri = if user.otp_uri.is_empty() { unset() } else { "<redacted>".to_string() };
+    set(&format!("user.{index}.config_path"), user.config_path.clone(), origin());
+    set(&format!("user.{index}.otp"), user.otp.to_string(), origin());
+    set(&format!("user.{index}.otp_uri"), otp_uri, origin());
+    set(&format!("user.{index}.otp_encrypted"), user.otp_encrypted.to_string(), origin());
+    match &user.otp_command {
+      Some(command) => set(&format!("user.{index}.otp_command"), command.clone(), origin()),
+      None => set(&format!("user.{index}.otp_command"), unset(), Origin::Default),
+    }
+    match user.otp_retries {
+      Some(retries) => set(&format!("user.{index}.otp_retries"), retries.to_string(), origin()),
+      None => set(&format!("user.{index}.otp_retries"), otp::DEFAULT_RETRIES.to_string(), Origin::Default),
+    }
+    set(&format!("user.{index}.tags"), list(&user.tags), origin());
+    match &user.profile {
+      Some(profile) => set(&format!("user.{index}.profile"), profile.clone(), origin()),
+      None => set(&format!("user.{index}.profile"), unset(), Origin::Default),
+    }
+    match user.handshake_timeout {
+      Some(timeout) => set(&format!("user.{index}.handshake_timeout"), format!("{timeout}s"), origin()),
+      None => set(&format!("user.{index}.handshake_timeout"), unset(), Origin::Default),
+    }
+    match user.connect_timeout {
+      Some(timeout) => set(&format!("user.{index}.connect_timeout"), format!("{timeout}s"), origin()),
+      None => set(&format!("user.{index}.connect_timeout"), unset(), Origin::Default),
+    }
+    set(
+      &format!("user.{index}.reconnect_on_network_change"),
+      user.reconnect_on_network_change.to_string(),
+      origin(),
+    );
+    set(&format!("user.{index}.frozen"), user.frozen.to_string(), origin());
+    let trusted: Vec<String> = user.trusted_networks.iter().map(TrustedNetwork::to_string).collect();
+    set(&format!("user.{index}.trusted_networks"), list(&trusted), origin());
+  }
+  settings
+}
+
+/// Function to check the configured WireGuard configurations for problems
+/// spanning several of them, i.e. a private key or a listen port used by
+/// more than one, or a listen port held by another program, and peer
+/// endpoints which are unreachable when probed.
+///
+/// # Arguments
+/// * `out`: The writer the problems are printed to (e.g. stdout).
+/// * `prober`: The prober of the peer endpoints, if they are probed.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving a path or
+///   printing, or counting the problems found, so that the process exits
+///   with a non-zero code.
+pub fn doctor(out: &mut impl Write, prober: Option<&dyn Prober>) -> Result<(), CliError> {
+  let users = Config::get().user.clone();
+  check_users(out, &users, &SystemPortChecker, prober)
+}
+
+/// Function to check WireGuard configurations for private keys and listen
+/// ports used by more than one of them, and listen ports already bound.
+///
+/// The keys are compared by their public key, so that two encodings of the
+/// same key (e.g. differing only in the bits clamped by X25519) are caught.
+/// `ListenPort = 0` picks a free port and is never a conflict, and the port
+/// of a tunnel which is up is held by the tunnel itself. A configuration
+/// which cannot be read is reported as a problem as well. With a prober, an
+/// unreachable peer endpoint is a problem too; one whose reachability is
+/// unknown is not, since UDP rarely tells.
+///
+/// # Arguments
+/// * `out`: The writer the problems are printed to (e.g. stdout).
+/// * `users`: The configurations to be checked.
+/// * `ports`: The view of the ports bound on the host.
+/// * `prober`: The prober of the peer endpoints, if they are probed.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving a path or
+///   printing, or counting the problems found.
+fn check_users(
+  out: &mut impl Write,
+  users: &[UserConfig],
+  ports: &dyn PortChecker,
+  prober: Option<&dyn Prober>,
+) -> Result<(), CliError> {
+  let mut problems = 0;
+  let mut keys: Vec<(&str, PublicKey)> = Vec::with_capacity(users.len());
+  let mut listen_ports: Vec<(&str, u16)> = Vec::new();
+  for user in users {
+    let path = user.resolved_path()?;
+    let checked = WgConfig::parse(&path).map_err(WgError::from).and_then(|config| {
+      let key = wg::public_from_private(&config.interface.private_key)?;
+      Ok((key, config))
+    });
+    match checked {
+      Ok((key, config)) => {
+        let listen_port = config.interface.listen_port;
+        keys.push((&user.config_path, key));
+        if let Some(port) = listen_port.filter(|port| *port != 0) {
+          listen_ports.push((&user.config_path, port));
+          let up = wg::interface_name(&path).is_some_and(|interface| ports.interface_exists(interface));
+          if !up && ports.in_use(port) {
+            writeln!(out, "{}: ListenPort {port} is already in use on this host", user.config_path)?;
+            problems += 1;
+          }
+        }
+        for (endpoint, reachability) in prober.map(|prober| wg::probe_endpoints(&config, prober)).unwrap_or_default() {
+          if let Reachability::Unreachable(reason) = reachability {
+            writeln!(out, "{}: Endpoint {endpoint} is unreachable: {reason}", user.config_path)?;
+            problems += 1;
+          }
+        }
+      }
+      Err(e) => {
+        writeln!(out, "{}: cannot be checked: {e}", user.config_path)?;
+        problems += 1;
+      }
+    }
+  }
+
+  problems += report_shared(out, &keys, |paths| format!("The same private key is used by {paths}"))?;
+  problems += report_shared(out, &listen_ports, |paths| format!("The same ListenPort is used by {paths}"))?;
+
+  if problems == 0 {
+    writeln!(out, "No problems found in {} configuration(s)", users.len())?;
+    return Ok(());
+  }
+  Err(CliError::DoctorProblems(problems))
+}
+
+/// Function to report the values shared by several configurations.
+///
+/// # Arguments
+/// * `out`: The writer the problems are printed to (e.g. stdout).
+/// * `values`: The value of each configuration, along with its path.
+/// * `message`: The builder of the message, given the sharing paths.
+///
+/// # Returns
+/// * `io::Result<usize>`: The number of shared values, or the error raised
+///   printing.
+fn report_shared<T: PartialEq>(
+  out: &mut impl Write,
+  values: &[(&str, T)],
+  message: impl Fn(&str) -> String,
+) -> io::Result<usize> {
+  let mut shared = 0;
+  let mut reported = vec![false; values.len()];
+  for (index, (path, value)) in values.iter().enumerate() {
+    if reported[index] {
+      continue;
+    }
+    let mut sharing = vec![*path];
+    for (other, (other_path, other_value)) in values.iter().enumerate().skip(index + 1) {
+      if other_value == value {
+        sharing.push(other_path);
+        reported[other] = true;
+      }
+    }
+    if sharing.len() > 1 {
+      writeln!(out, "{}", message(&sharing.join(", ")))?;
+      shared += 1;
+    }
+  }
+  Ok(shared)
+}
+
+/// Function to print the lines of the dated log files of a directory
+/// matching a regular expression, from the oldest file to the newest.
+///
+/// # Arguments
+/// * `out`: The writer the lines are printed to (e.g. stdout).
+/// * `dir`: The directory holding the log files.
+/// * `pattern`: The regular expression.
+/// * `ignore_case`: Whether the pattern is matched regardless of case.
+/// * `min_level`: The minimum level of a matching line, if any.
+/// * `context`: The number of lines printed before and after each match.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised because the pattern
+///   is invalid, reading the files or printing, or because no line matched,
+///   so that the process exits with a non-zero code like `grep`.
+pub fn logs_grep(
+  out: &mut impl Write,
+  dir: &Path,
+  pattern: &str,
+  ignore_case: bool,
+  min_level: Option<LogLevel>,
+  context: usize,
+) -> Result<(), CliError> {
+  let options = GrepOptions {
+    pattern: RegexBuilder::new(pattern).case_insensitive(ignore_case).build()?,
+    min_level,
+    context,
+  };
+  let mut search = LogGrep::new(&options);
+  let mut matches = 0;
+  for path in grep::log_files(dir)? {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    matches += search.search(out, &name, grep::open_log(&path)?)?;
+  }
+  match matches {
+    0 => Err(CliError::NoLogMatches(pattern.to_string())),
+    _ => Ok(()),
+  }
+}
+
+/// Define the requirements checked by `status --fail-if-down`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthCheck {
+  /// Number of tunnels which must be up and fresh, all of the configured
+  /// ones when `None`.
+  pub threshold: Option<usize>,
+  /// Time since the latest handshake after which a tunnel which is up is
+  /// stale, i.e. does not count as up.
+  pub stale_after: Duration,
+}
+
+/// Function to print the live state of the configured WireGuard tunnels.
+///
+/// With a health check, the table is replaced by a one-line summary (the
+/// JSON output is kept as is), and an error is returned when fewer tunnels
+/// than required are up, so that the process exits with a non-zero code.
+///
+/// # Arguments
+/// * `out`: The writer the status is printed to (e.g. stdout).
+/// * `json`: Whether the status is printed as JSON instead of a table.
+/// * `check`: The health check to be run, if any.
+/// * `tag`: The tag the displayed configurations must have, if any; the
+///   health check only counts them.
+/// * `template`: The template each tunnel is printed with instead of a
+///   table, if any (see `STATUS_FIELDS`).
+/// * `since`: The time the changes are printed since instead of the table,
+///   if any (see `print_changes`).
+///
+/// Every run records a snapshot of the tunnels which are up, the baseline
+/// of the next `since`.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised for an invalid
+///   template, resolving the paths, querying `wg`, while printing or because
+///   the check failed.
+pub fn status(
+  out: &mut impl Write,
+  json: bool,
+  check: Option<&HealthCheck>,
+  tag: Option<&str>,
+  template: Option<&str>,
+  since: Option<SystemTime>,
+) -> Result<(), CliError> {
+  let template = template.map(|template| Template::parse(template, &STATUS_FIELDS)).transpose()?;
+  let mut tunnels = configured_tunnels(tag)?;
+  let now = SystemTime::now();
+  let color = color::stdout();
+  let previous = record_snapshots(&tunnels, now);
+  for tunnel in &mut tunnels {
+    tunnel.previous = previous.get(&tunnel.status.interface).cloned();
+  }
+  if let Some(since) = since {
+    return print_changes(out, &tunnels, &previous, since, now);
+  }
+  if let Some(template) = template {
+    return print_templated(out, &template, tunnels.iter().map(|tunnel| tunnel.fields(now)));
+  }
+  match check {
+    Some(check) if !json => check_health(out, &tunnels, check, now, color),
+    Some(check) => {
+      print_status(out, &tunnels, json, now, color)?;
+      Health::assess(&tunnels, check, now).result()
+    }
+    None => print_status(out, &tunnels, json, now, color),
+  }
+}
+
+/// Function to print the configured WireGuard tunnels which are up, with the
+/// totals of their peers. Tunnels which are down are left out.
+///
+/// # Arguments
+/// * `out`: The writer the tunnels are printed to (e.g. stdout).
+/// * `json`: Whether the tunnels are printed as JSON instead of a table.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving the paths,
+///   querying `wg` or while printing.
+pub fn up(out: &mut impl Write, json: bool) -> Result<(), CliError> {
+  let tunnels = configured_tunnels(None)?;
+  print_up(out, &tunnels, json, SystemTime::now())
+}
+
+/// Function to retrieve the live state of the configured WireGuard tunnels.
+///
+/// `wg` is not queried when no tunnel is configured.
+///
+/// # Arguments
+/// * `tag`: The tag the tunnels must have, if any.
+///
+/// # Returns
+/// * `Result<Vec<Tunnel>, CliError>`: Every configured tunnel, in the
+///   configured order, or the error raised resolving the paths or querying
+///   `wg`.
+fn configured_tunnels(tag: Option<&str>) -> Result<Vec<Tunnel>, CliError> {
+  let users: Vec<UserConfig> = tagged(&Config::get().user, tag).cloned().collect();
+  let active = if users.is_empty() { Vec::new() } else { wg::status()? };
+  match_tunnels(users, &active)
+}
+
+/// Function to match the configured WireGuard tunnels with the live state of
+/// the interfaces, by interface name.
+///
+/// The WireGuard configuration files of the tunnels which are up are read,
+/// so that their allowed IPs can be compared with the live ones.
+///
+/// # Arguments
+/// * `users`: The configured WireGuard configurations.
+/// * `active`: The state of the interfaces which are up.
+///
+/// # Returns
+/// * `Result<Vec<Tunnel>, CliError>`: Every configured tunnel, down when
+///   its interface is not up, or the error raised resolving a path.
+fn match_tunnels(users: Vec<UserConfig>, active: &[TunnelStatus]) -> Result<Vec<Tunnel>, CliError> {
+  let mut tunnels: Vec<Tunnel> = Vec::with_capacity(users.len());
+  for user in users {
+    let path = user.resolved_path()?;
+    let interface = wg::interface_name(&path).unwrap_or_default();
+    let status = active
+        .iter()
+        .find(|tunnel| tunnel.interface == interface)
+        .cloned()
+        .unwrap_or_else(|| TunnelStatus::down(interface));
+    let config = status
+        .up
+        .then(|| WgConfig::parse(&path).and_then(|mut config| config.expand_allowed_ip_sets(&path).map(|_| config)))
+        .and_then(Result::ok);
+    tunnels.push(Tunnel {
+      config_path: user.config_path,
+      status,
+      config,
+      handshake_timeout: user.handshake_timeout.map(Duration::from_secs),
+      previous: None,
+    });
+  }
+  Ok(tunnels)
+}
+
+/// Function to print the state of the given tunnels, one row per peer.
+///
+/// The allowed IPs of a peer are summarized to the first two, followed by
+/// the count of the others, and marked when they differ from the WireGuard
+/// configuration file.
+///
+/// # Arguments
+/// * `out`: The writer the status is printed to.
+/// * `tunnels`: The tunnels.
+/// * `json`: Whether the status is printed as JSON instead of a table.
+/// * `now`: The current time, the handshakes are printed relative to.
+/// * `color`: Whether the state of the tunnels is colored.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised while printing.
+fn print_status(
+  out: &mut impl Write,
+  tunnels: &[Tunnel],
+  json: bool,
+  now: SystemTime,
+  color: bool,
+) -> Result<(), CliError> {
+  if json {
+    let entries: Vec<StatusEntry> = tunnels.iter().map(StatusEntry::from).collect();
+    serde_json::to_writer_pretty(&mut *out, &entries)?;
+    writeln!(out)?;
+    return Ok(());
+  }
+  if tunnels.is_empty() {
+    writeln!(out, "No users configured, add one with `wgb add-user`")?;
+    return Ok(());
+  }
+
+  let header = ["INTERFACE", "STATE", "HANDSHAKE", "RECEIVED", "SENT", "ENDPOINT", "ALLOWED IPS"];
+  let mut rows = vec![header.map(String::from)];
+  let mut drift = false;
+  let mut roamed = Vec::new();
+  for tunnel in tunnels {
+    let status = &tunnel.status;
+    let state = if status.up { "up" } else { "down" };
+    if status.peers.is_empty() {
+      rows.push([status.interface.as_str(), state, "-", "-", "-", "-", "-"].map(String::from));
+    }
+    for peer in &status.peers {
+      let drifted = tunnel.drifted(peer);
+      drift |= drifted;
+      if let (Some(from), Some(to)) = (tunnel.roamed_from(peer), &peer.endpoint) {
+        roamed.push(format!("{}: peer {} roamed to {to} from {from}", status.interface, peer.public_key));
+      }
+      rows.push([
+        status.interface.clone(),
+        state.to_string(),
+        format_handshake(now, peer.latest_handshake),
+        format_bytes(peer.rx_bytes),
+        format_bytes(peer.tx_bytes),
+        peer.endpoint.clone().unwrap_or("-".to_string()),
+        format_allowed_ips(&peer.allowed_ips, drifted),
+      ]);
+    }
+  }
+  write_table_painted(out, &rows, |column, cell| match (color, column, cell) {
+    (true, 1, "up") => Some(Color::Green),
+    (true, 1, "down") => Some(Color::Red),
+    _ => None,
+  })?;
+  if drift {
+    writeln!(out, "* allowed IPs differ from the WireGuard configuration file")?;
+  }
+  for note in roamed {
+    writeln!(out, "{note}")?;
+  }
+  Ok(())
+}
+
+/// Function to replace the snapshots of the tunnels which are up with their
+/// live state, keeping the ones of the other interfaces.
+///
+/// The snapshots only serve `status --since`, so a failure to read or write
+/// them is logged rather than failing the command.
+///
+/// # Arguments
+/// * `tunnels`: The tunnels.
+/// * `now`: The time the state of the tunnels was read.
+///
+/// # Returns
+/// * `BTreeMap<String, Snapshot>`: The snapshots as they were before, by
+///   interface name.
+fn record_snapshots(tunnels: &[Tunnel], now: SystemTime) -> BTreeMap<String, Snapshot> {
+  let log = Logger::get();
+  let Some(path) = snapshot::default_path() else {
+    return BTreeMap::new();
+  };
+  let previous = snapshot::load(&path).unwrap_or_else(|e| {
+    log.warn(&format!("{e}, counting the traffic from now on"));
+    BTreeMap::new()
+  });
+  let mut snapshots = previous.clone();
+  for tunnel in tunnels.iter().filter(|tunnel| tunnel.status.up) {
+    snapshots.insert(tunnel.status.interface.clone(), Snapshot::of(&tunnel.status, now));
+  }
+  if snapshots != previous
+    && let Err(e) = snapshot::save(&path, &snapshots)
+  {
+    log.warn(&e.to_string());
+  }
+  previous
+}
+
+/// Function to print what changed for the given tunnels: the peers with a
+/// handshake after a given time, and the traffic since the snapshot of
+/// their interface.
+///
+/// Traffic is unknown for the peers missing from the snapshot, and counted
+/// from zero for the ones whose counters were reset, e.g. by a reconnect.
+///
+/// # Arguments
+/// * `out`: The writer the changes are printed to.
+/// * `tunnels`: The tunnels.
+/// * `previous`: The snapshots of the interfaces, by name.
+/// * `since`: The time after which a handshake is new.
+/// * `now`: The current time, the snapshots are printed relative to.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised while printing.
+fn print_changes(
+  out: &mut impl Write,
+  tunnels: &[Tunnel],
+  previous: &BTreeMap<String, Snapshot>,
+  since: SystemTime,
+  now: SystemTime,
+) -> Result<(), CliError> {
+  if tunnels.is_empty() {
+    writeln!(out, "No users configured, add one with `wgb add-user`")?;
+    return Ok(());
+  }
+  let header = ["INTERFACE", "ENDPOINT", "HANDSHAKE", "RECEIVED", "SENT", "COUNTED FROM"];
+  let mut rows = vec![header.map(String::from)];
+  let mut reset = false;
+  for tunnel in tunnels {
+    let status = &tunnel.status;
+    let Some(snapshot) = previous.get(&status.interface).filter(|_| status.up) else {
+      let state = if status.up { "no snapshot" } else { "down" };
+      for peer in &status.peers {
+        let handshake = if peer.latest_handshake.is_some_and(|handshake| handshake > since) { "new" } else { "-" };
+        let endpoint = peer.endpoint.clone().unwrap_or("-".to_string());
+        rows.push([status.interface.clone(), endpoint, handshake.to_string(), "-".into(), "-".into(), state.into()]);
+      }
+      if status.peers.is_empty() {
+        rows.push([status.interface.as_str(), "-", "-", "-", "-", state].map(String::from));
+      }
+      continue;
+    };
+    for (peer, delta) in status.peers.iter().zip(snapshot.delta(status, since)) {
+      reset |= delta.reset;
+      let bytes = |bytes: Option<u64>| match bytes {
+        Some(bytes) if delta.reset => format!("+{}*", format_bytes(bytes)),
+        Some(bytes) => format!("+{}", format_bytes(bytes)),
+        None => "-".to_string(),
+      };
+      rows.push([
+        status.interface.clone(),
+        peer.endpoint.clone().unwrap_or("-".to_string()),
+        if delta.new_handshake { "new" } else { "-" }.to_string(),
+        bytes(delta.rx_bytes),
+        bytes(delta.tx_bytes),
+        format_ago(now, snapshot.taken_at()),
+      ]);
+    }
+  }
+  write_table(out, &rows)?;
+  if reset {
+    writeln!(out, "* counters were reset since the snapshot, counted from zero")?;
+  }
+  Ok(())
+}
+
+/// Function to print the given tunnels which are up, one row per tunnel with
+/// the latest handshake and the traffic totals of its peers.
+///
+/// # Arguments
+/// * `out`: The writer the tunnels are printed to.
+/// * `tunnels`: The tunnels.
+/// * `json`: Whether the tunnels are printed as JSON instead of a table.
+/// * `now`: The current time, the handshakes are printed relative to.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised while printing.
+fn print_up(
+  out: &mut impl Write,
+  tunnels: &[Tunnel],
+  json: bool,
+  now: SystemTime,
+) -> Result<(), CliError> {
+  let up: Vec<&Tunnel> = tunnels.iter().filter(|tunnel| tunnel.status.up).collect();
+  if json {
+    let entries: Vec<StatusEntry> = up.into_iter().map(StatusEntry::from).collect();
+    serde_json::to_writer_pretty(&mut *out, &entries)?;
+    writeln!(out)?;
+    return Ok(());
+  }
+  if up.is_empty() {
+    writeln!(out, "No tunnels connected")?;
+    return Ok(());
+  }
+
+  let mut rows = Vec::with_capacity(up.len());
+  for Tunnel { status: tunnel, .. } in up {
+    let handshake = tunnel.peers.iter().filter_map(|peer| peer.latest_handshake).max();
+    rows.push([
+      tunnel.interface.clone(),
+      format_handshake(now, handshake),
+      format!("{} received", format_bytes(tunnel.peers.iter().map(|peer| peer.rx_bytes).sum())),
+      format!("{} sent", format_bytes(tunnel.peers.iter().map(|peer| peer.tx_bytes).sum())),
+    ]);
+  }
+  write_table(out, &rows)?;
+  Ok(())
+}
+
+/// Define the outcome of a health check, listing the interfaces by state.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Health<'a> {
+  up: Vec<&'a str>,
+  stale: Vec<&'a str>,
+  down: Vec<&'a str>,
+  required: usize,
+}
+
+/// Implements the logic of the health check of the tunnels
+impl<'a> Health<'a> {
+  /// Function to sort the tunnels by state.
+  ///
+  /// A tunnel which is up is stale when none of its peers completed a
+  /// handshake within its `handshake_timeout`, defaulting to `stale_after`,
+  /// including when it has no peers.
+  ///
+  /// # Arguments
+  /// * `tunnels`: The tunnels.
+  /// * `check`: The requirements to be checked.
+  /// * `now`: The current time.
+  ///
+  /// # Returns
+  /// * `Health`: The interfaces up, stale and down, and how many must be up.
+  fn assess(tunnels: &'a [Tunnel], check: &HealthCheck, now: SystemTime) -> Self {
+    let mut health = Health {
+      required: check.threshold.unwrap_or(tunnels.len()),
+      ..Default::default()
+    };
+    for Tunnel {
+      status: tunnel,
+      handshake_timeout,
+      ..
+    } in tunnels
+    {
+      let stale_after = handshake_timeout.unwrap_or(check.stale_after);
+      let fresh = tunnel.peers.iter().filter_map(|peer| peer.latest_handshake).any(|time| {
+        now.duration_since(time).is_ok_and(|elapsed| elapsed <= stale_after) || time > now
+      });
+      let interface = tunnel.interface.as_str();
+      match (tunnel.up, fresh) {
+        (true, true) => health.up.push(interface),
+        (true, false) => health.stale.push(interface),
+        (false, _) => health.down.push(interface),
+      }
+    }
+    health
+  }
+
+  /// Function to describe the outcome in one line, for alert systems.
+  ///
+  /// # Returns
+  /// * `String`: The outcome (e.g. "CRITICAL: 1/3 tunnel(s) up, 3 required
+  ///   (stale: wg1; down: wg2)").
+  fn summary(&self) -> String {
+    let state = if self.up.len() >= self.required { "OK" } else { "CRITICAL" };
+    let total = self.up.len() + self.stale.len() + self.down.len();
+    let mut summary = format!("{state}: {}/{total} tunnel(s) up, {} required", self.up.len(), self.required);
+    let details: Vec<String> = [("stale", &self.stale), ("down", &self.down)]
+        .into_iter()
+        .filter(|(_, interfaces)| !interfaces.is_empty())
+        .map(|(state, interfaces)| format!("{state}: {}", interfaces.join(", ")))
+        .collect();
+    if !details.is_empty() {
+      summary.push_str(&format!(" ({})", details.join("; ")));
+    }
+    summary
+  }
+
+  /// Function to turn the outcome into the result of the command.
+  ///
+  /// # Returns
+  /// * `Result<(), CliError>`: `TunnelsDown` when fewer tunnels than
+  ///   required are up.
+  fn result(&self) -> Result<(), CliError> {
+    if self.up.len() >= self.required {
+      Ok(())
+    } else {
+      Err(CliError::TunnelsDown {
+        up: self.up.len(),
+        required: self.required,
+      })
+    }
+  }
+}
+
+/// Function to print the one-line summary of a health check.
+///
+/// # Arguments
+/// * `out`: The writer the summary is printed to.
+/// * `tunnels`: The tunnels.
+/// * `check`: The requirements to be checked.
+/// * `now`: The current time.
+/// * `color`: Whether the outcome is colored.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error raised while printing or because
+///   fewer tunnels than required are up.
+fn check_health(
+  out: &mut impl Write,
+  tunnels: &[Tunnel],
+  check: &HealthCheck,
+  now: SystemTime,
+  color: bool,
+) -> Result<(), CliError> {
+  let health = Health::assess(tunnels, check, now);
+  let summary = health.summary();
+  let (state, details) = summary.split_once(':').unwrap_or_default();
+  let state = color::paint(state, if health.result().is_ok() { Color::Green } else { Color::Red }, color);
+  writeln!(out, "{state}:{details}")?;
+  health.result()
+}
+
+/// Function to print rows as a table, padding every column to its widest
+/// cell.
+///
+/// # Arguments
+/// * `out`: The writer the table is printed to.
+/// * `rows`: The rows, the header first if any.
+///
+/// # Returns
+/// * `io::Result<()>`: The error, if any, raised while printing.
+fn write_table<const N: usize>(out: &mut impl Write, rows: &[[String; N]]) -> io::Result<()> {
+  write_table_painted(out, rows, |_, _| None)
+}
+
+/// Function to print rows as a table, coloring some of the cells.
+///
+/// # Arguments
+/// * `out`: The writer the table is printed to.
+/// * `rows`: The rows, the header first if any.
+/// * `paint`: The function giving the color of a cell from its column and
+///   its text, `None` leaving it plain.
+///
+/// # Returns
+/// * `io::Result<()>`: The error, if any, raised while printing.
+fn write_table_painted<const N: usize>(
+  out: &mut impl Write,
+  rows: &[[String; N]],
+  paint: impl Fn(usize, &str) -> Option<Color>,
+) -> io::Result<()> {
+  let mut widths = [0; N];
+  for row in rows {
+    for (width, cell) in widths.iter_mut().zip(row) {
+      *width = (*width).max(cell.len());
+    }
+  }
+  for row in rows {
+    let line: Vec<String> = row
+        .iter()
+        .zip(widths)
+        .enumerate()
+        .map(|(column, (cell, width))| match paint(column, cell) {
+          Some(color) => format!("{}{:<padding$}", color::paint(cell, color, true), "", padding = width - cell.len()),
+          None => format!("{cell:<width$}"),
+        })
+        .collect();
+    writeln!(out, "{}", line.join("  ").trim_end())?;
+  }
+  Ok(())
+}
+
+/// Function to add a WireGuard configuration and save the configuration file.
+///
+/// The WireGuard configuration file must exist and, when `otp` is set, the
+/// OTP URI must be a valid `otpauth://` URI or a bare base32 secret, stored
+/// as the URI built by `canonical_otp_uri`. The path is stored as given, so
+/// that `~` and environment variables are expanded on each use.
+///
+/// When the WireGuard configuration file sets `SaveConfig`, removing it is
+/// offered once the user is added.
+///
+/// When `encrypt` is set, the OTP URI is stored encrypted with a passphrase.
+/// If other URIs are already encrypted, the passphrase must be theirs, since
+/// the configuration is unlocked with it; otherwise it is asked twice.
+///
+/// When `assign_address` is set, the `Address` of the WireGuard configuration
+/// file is set to the next address of the `address_pool` which no other
+/// configured file uses.
+///
+/// # Arguments
+/// * `input`: The reader the passphrase and the answers are read from.
+/// * `out`: The writer the prompts and the outcome are printed to.
+/// * `config_path`: The path to the WireGuard configuration file.
+/// * `otp`: Whether connecting requires a one-time password.
+/// * `otp_uri`: The URI or bare secret used to generate the one-time
+///   passwords, if any.
+/// * `encrypt`: Whether to store the OTP URI encrypted.
+/// * `assign_address`: Whether to set the address from the pool.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised validating the user,
+///   because it is already configured, on a wrong passphrase, assigning the
+///   address or while saving.
+pub fn add_user(
+  input: &mut impl BufRead,
+  out: &mut impl Write,
+  config_path: String,
+  otp: bool,
+  otp_uri: Option<String>,
+  encrypt: bool,
+  assign_address: bool,
+) -> Result<(), CliError> {
+  let mut user = UserConfig {
+    config_path,
+    otp,
+    otp_uri: otp_uri.unwrap_or_default(),
+    otp_encrypted: encrypt,
+    ..Default::default()
+  };
+  let path = user.resolved_path()?;
+  if !path.is_file() {
+    return Err(CliError::MissingConfigFile(user.config_path));
+  }
+  if user.otp && user.otp_uri.is_empty() {
+    return Err(CliError::MissingOtpUri);
+  }
+  if user.otp_encrypted && user.otp_uri.is_empty() {
+    return Err(CliError::NothingToEncrypt);
+  }
+  if !user.otp_uri.is_empty() {
+    let label = wg::interface_name(&path).unwrap_or_default();
+    user.otp_uri = config::canonical_otp_uri(&user.otp_uri, label).map_err(CliError::InvalidOtpUri)?;
+  }
+
+  let mut config = Config::get();
+  if config.user.iter().any(|u| u.config_path == user.config_path) {
+    return Err(ConfigError::DuplicateUser(user.config_path).into());
+  }
+  if user.otp_encrypted {
+    let passphrase = read_passphrase(input, out, "Passphrase: ")?;
+    if !config.user.iter().any(|u| u.otp_encrypted) {
+      let confirmation = read_passphrase(input, out, "Confirm passphrase: ")?;
+      if confirmation != passphrase {
+        return Err(CliError::PassphraseMismatch);
+      }
+    }
+    config.unlock(&passphrase)?;
+  }
+  if assign_address {
+    let pool: AddressPool = config.address_pool.as_deref().ok_or(CliError::MissingAddressPool)?.parse()?;
+    let used: Vec<IpAddr> = config
+        .user
+        .iter()
+        .filter_map(|other| other.resolved_path().ok())
+        .filter_map(|other| wg::interface_addresses(&other).ok())
+        .flatten()
+        .collect();
+    let address = wg::assign_address(&path, &pool, &used)?;
+    writeln!(out, "Assigned {address} to {}", user.config_path)?;
+  }
+  let config_path = user.config_path.clone();
+  config.with_transaction(|draft| draft.add_user(user))?;
+  // Wipe the decrypted URIs from memory
+  config.lock()?;
+  drop(config);
+  writeln!(out, "Added {config_path}")?;
+  offer_strip_save_config(input, out, &path)?;
+  Ok(())
+}
+
+/// Function to remove a WireGuard configuration and save the configuration
+/// file, asking for confirmation first unless `yes` is set.
+///
+/// # Arguments
+/// * `input`: The reader the confirmation is read from (e.g. stdin).
+/// * `out`: The writer the prompt and the outcome are printed to.
+/// * `config_path`: The exact path of the configuration to be removed.
+/// * `yes`: Whether to remove without asking for confirmation.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised because no such user
+///   is configured, reading the confirmation or saving.
+pub fn remove_user(
+  input: &mut impl BufRead,
+  out: &mut impl Write,
+  config_path: &str,
+  yes: bool,
+) -> Result<(), CliError> {
+  let mut config = Config::get();
+  if config.get_user(config_path).is_none() {
+    return Err(unknown_user(&config, config_path));
+  }
+  config.check_editable(config_path)?;
+  if !yes && !confirm(input, out, &format!("Remove user {config_path}?"))? {
+    writeln!(out, "Nothing removed")?;
+    return Ok(());
+  }
+
+  config.remove_user(config_path);
+  config.save()?;
+  writeln!(out, "Removed {config_path}")?;
+  Ok(())
+}
+
+/// The directory `wg-quick` reads the WireGuard configuration files from,
+/// scanned by `discover` along with the configured paths.
+const WG_QUICK_DIR: &str = "/etc/wireguard";
+
+/// Define what `discover` found in a WireGuard configuration file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Discovery {
+  /// A valid file, not configured yet.
+  New,
+  /// A file configured already.
+  Registered,
+  /// A file which is not a valid WireGuard configuration, and why.
+  Invalid(String),
+  /// A file or directory which cannot be read, and why.
+  Unreadable(String),
+}
+
+/// Function to find the WireGuard configuration files of directories and
+/// register the ones which are not configured yet, asking first unless
+/// `yes` is set.
+///
+/// # Arguments
+/// * `input`: The reader the confirmation is read from (e.g. stdin).
+/// * `out`: The writer the files found and the outcome are printed to.
+/// * `dirs`: The directories, `/etc/wireguard` and the configured paths when
+///   none is given.
+/// * `yes`: Whether to register the files without asking for confirmation.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised expanding a path,
+///   reading the confirmation or saving.
+pub fn discover(input: &mut impl BufRead, out: &mut impl Write, dirs: &[String], yes: bool) -> Result<(), CliError> {
+  let (dirs, registered) = {
+    let config = Config::get();
+    let dirs = match dirs {
+      [] => [WG_QUICK_DIR.to_string()].into_iter().chain(config.paths.iter().cloned()).collect(),
+      dirs => dirs.to_vec(),
+    };
+    let registered = config
+        .user
+        .iter()
+        .map(UserConfig::resolved_path)
+        .collect::<Result<Vec<PathBuf>, ConfigError>>()?;
+    (dirs.iter().map(|dir| config::expand_path(dir)).collect::<Result<Vec<PathBuf>, ConfigError>>()?, registered)
+  };
+
+  let mut found = Vec::new();
+  for (path, discovery) in discover_files(&dirs, &registered) {
+    match discovery {
+      Discovery::New => {
+        writeln!(out, "Found {}", path.display())?;
+        found.push(path);
+      }
+      Discovery::Registered => writeln!(out, "{} is configured already", path.display())?,
+      Discovery::Invalid(reason) | Discovery::Unreadable(reason) => {
+        writeln!(out, "Skipped {}: {reason}", path.display())?;
+      }
+    }
+  }
+  if found.is_empty() {
+    writeln!(out, "No new WireGuard configuration files")?;
+    return Ok(());
+  }
+  if !yes && !confirm(input, out, &format!("Add {} configuration(s)?", found.len()))? {
+    return Ok(());
+  }
+  let users: Vec<UserConfig> = found
+      .iter()
+      .map(|path| UserConfig {
+        config_path: path.display().to_string(),
+        ..Default::default()
+      })
+      .collect();
+  Config::get().with_transaction(|draft| users.into_iter().try_for_each(|user| draft.add_user(user)))?;
+  for path in &found {
+    writeln!(out, "Added {}", path.display())?;
+  }
+  Ok(())
+}
+
+/// Function to find the WireGuard configuration files, i.e. the `.conf`
+/// files, of directories and tell whether each one can be registered.
+///
+/// A directory which does not exist is skipped, and a file found through
+/// several directories is listed once.
+///
+/// # Arguments
+/// * `dirs`: The directories.
+/// * `registered`: The resolved paths of the configured files.
+///
+/// # Returns
+/// * `Vec<(PathBuf, Discovery)>`: The files, or the directories which
+///   cannot be read, by directory and then by name.
+fn discover_files(dirs: &[PathBuf], registered: &[PathBuf]) -> Vec<(PathBuf, Discovery)> {
+  let unreadable = |e: &io::Error| match e.kind() {
+    io::ErrorKind::PermissionDenied => Discovery::Unreadable("permission denied, try again with sudo".to_string()),
+    _ => Discovery::Unreadable(e.to_string()),
+  };
+  let mut discovered: Vec<(PathBuf, Discovery)> = Vec::new();
+  for dir in dirs {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+      Ok(entries) => entries
+          .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+          .filter(|path| path.extension().is_some_and(|extension| extension == "conf") && path.is_file())
+          .collect(),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+      Err(e) => {
+        discovered.push((dir.clone(), unreadable(&e)));
+        continue;
+      }
+    };
+    paths.sort();
+    for path in paths {
+      if discovered.iter().any(|(known, _)| *known == path) {
+        continue;
+      }
+      let discovery = if registered.contains(&path) {
+        Discovery::Registered
+      } else {
+        match WgConfig::parse(&path) {
+          Ok(_) => Discovery::New,
+          Err(WgParseError::Io(e)) => unreadable(&e),
+          Err(e) => Discovery::Invalid(e.to_string()),
+        }
+      };
+      discovered.push((path, discovery));
+    }
+  }
+  discovered
+}
+
+/// Function to add a tag to a WireGuard configuration and save the
+/// configuration file.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `config_path`: The exact path of the configuration.
+/// * `tag`: The tag to be added.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised because no such user
+///   is configured, for an invalid tag or while saving.
+pub fn tag_add(out: &mut impl Write, config_path: &str, tag: &str) -> Result<(), CliError> {
+  let mut config = Config::get();
+  let Some(user) = config.get_user(config_path) else {
+    return Err(unknown_user(&config, config_path));
+  };
+  if user.has_tag(tag) {
+    writeln!(out, "{config_path} is already tagged with {tag}")?;
+    return Ok(());
+  }
+  config.check_editable(config_path)?;
+  config.with_transaction(|draft| match draft.get_user_mut(config_path) {
+    Some(user) => user.add_tag(tag),
+    None => Ok(false),
+  })?;
+  writeln!(out, "Tagged {config_path} with {tag}")?;
+  Ok(())
+}
+
+/// Function to remove a tag from a WireGuard configuration and save the
+/// configuration file.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `config_path`: The exact path of the configuration.
+/// * `tag`: The tag to be removed.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised because no such user
+///   is configured, or while saving.
+pub fn tag_remove(out: &mut impl Write, config_path: &str, tag: &str) -> Result<(), CliError> {
+  let mut config = Config::get();
+  let Some(user) = config.get_user(config_path) else {
+    return Err(unknown_user(&config, config_path));
+  };
+  if !user.has_tag(tag) {
+    writeln!(out, "{config_path} is not tagged with {tag}")?;
+    return Ok(());
+  }
+  config.check_editable(config_path)?;
+  config.with_transaction(|draft| {
+    Ok::<_, ConfigError>(draft.get_user_mut(config_path).is_some_and(|user| user.remove_tag(tag)))
+  })?;
+  writeln!(out, "Removed tag {tag} from {config_path}")?;
+  Ok(())
+}
+
+/// Function to freeze or unfreeze the tunnel of a WireGuard configuration
+/// and save the configuration file.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `config_path`: The exact path of the configuration.
+/// * `frozen`: Whether the tunnel is frozen or unfrozen.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised because no such user
+///   is configured, or while saving.
+pub fn freeze(out: &mut impl Write, config_path: &str, frozen: bool) -> Result<(), CliError> {
+  let mut config = Config::get();
+  let Some(user) = config.get_user(config_path) else {
+    return Err(unknown_user(&config, config_path));
+  };
+  let state = if frozen { "frozen" } else { "not frozen" };
+  if user.frozen == frozen {
+    writeln!(out, "{config_path} is {state} already")?;
+    return Ok(());
+  }
+  config.check_editable(config_path)?;
+  config.with_transaction(|draft| {
+    if let Some(user) = draft.get_user_mut(config_path) {
+      user.frozen = frozen;
+    }
+    Ok::<_, ConfigError>(())
+  })?;
+  match frozen {
+    true => writeln!(out, "Froze {config_path}, unfreeze it with `wgb unfreeze {config_path}`")?,
+    false => writeln!(out, "Unfroze {config_path}")?,
+  }
+  Ok(())
+}
+
+/// Function to refuse acting on the tunnel of a frozen configuration.
+///
+/// # Arguments
+/// * `users`: The configured WireGuard configurations.
+/// * `path`: The resolved path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<(), CliError>`: `CliError::Frozen` when the configuration of
+///   the file is frozen; a file which is not configured is never frozen.
+fn check_not_frozen(users: &[UserConfig], path: &Path) -> Result<(), CliError> {
+  match users.iter().find(|user| user.frozen && user.resolved_path().is_ok_and(|resolved| resolved == path)) {
+    Some(user) => Err(CliError::Frozen(user.config_path.clone())),
+    None => Ok(()),
+  }
+}
+
+/// Function to retrieve the `connect_timeout` of the configuration of a
+/// WireGuard configuration file.
+///
+/// # Arguments
+/// * `users`: The configured WireGuard configurations.
+/// * `path`: The resolved path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Option<Duration>`: The timeout, `None` when it is unset or the file is
+///   not configured.
+fn connect_timeout(users: &[UserConfig], path: &Path) -> Option<Duration> {
+  users
+      .iter()
+      .find(|user| user.resolved_path().is_ok_and(|resolved| resolved == path))
+      .and_then(|user| user.connect_timeout)
+      .map(Duration::from_secs)
+}
+
+/// Function to build the error raised for a configuration which is missing.
+///
+/// # Arguments
+/// * `config`: The configuration.
+/// * `config_path`: The path of the missing configuration.
+///
+/// # Returns
+/// * `CliError`: The error, listing the configured paths.
+fn unknown_user(config: &Config, config_path: &str) -> CliError {
+  CliError::UnknownUser {
+    path: config_path.to_string(),
+    available: config.user.iter().map(|user| user.config_path.clone()).collect(),
+  }
+}
+
+/// Function to add a directory where WireGuard configuration files are saved
+/// and save the configuration file.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `path`: The directory, stored as given so that `~` and environment
+///   variables are expanded on each use.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised because the directory
+///   does not exist or is already configured, or while saving.
+pub fn path_add(out: &mut impl Write, path: String) -> Result<(), CliError> {
+  if !config::expand_path(&path)?.is_dir() {
+    return Err(CliError::MissingDirectory(path));
+  }
+  let mut config = Config::get();
+  config.with_transaction(|draft| draft.add_path(path.clone()))?;
+  writeln!(out, "Added {path}")?;
+  Ok(())
+}
+
+/// Function to remove a directory where WireGuard configuration files are
+/// saved and save the configuration file.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `path`: The exact directory to be removed.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised because no such path
+///   is configured, or while saving.
+pub fn path_delete(out: &mut impl Write, path: &str) -> Result<(), CliError> {
+  let mut config = Config::get();
+  if !config.remove_path(path) {
+    return Err(CliError::UnknownPath {
+      path: path.to_string(),
+      available: config.paths.clone(),
+    });
+  }
+  config.save()?;
+  writeln!(out, "Removed {path}")?;
+  Ok(())
+}
+
+/// Function to print the directories where WireGuard configuration files are
+/// saved.
+///
+/// # Arguments
+/// * `out`: The writer the list is printed to (e.g. stdout).
+/// * `json`: Whether the list is printed as JSON instead of one per line.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised while printing.
+pub fn path_list(out: &mut impl Write, json: bool) -> Result<(), CliError> {
+  let config = Config::get();
+  if json {
+    serde_json::to_writer_pretty(&mut *out, &config.paths)?;
+    writeln!(out)?;
+  } else if config.paths.is_empty() {
+    writeln!(out, "No paths configured, add one with `wgb path add`")?;
+  } else {
+    for path in &config.paths {
+      writeln!(out, "{path}")?;
+    }
+  }
+  Ok(())
+}
+
+/// Function to rewrite the configuration file in its canonical form, see
+/// `Config::normalize`, leaving it untouched when it is in that form
+/// already.
+///
+/// Only the main file is rewritten, as read from the disk, so that the
+/// configurations of the fragments stay out of it.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised reading, normalizing
+///   or saving the configuration file.
+pub fn config_normalize(out: &mut impl Write) -> Result<(), CliError> {
+  let path = Config::loaded_path()?;
+  let content = std::fs::read_to_string(&path).map_err(ConfigError::from)?;
+  let mut config = Config::load_config(&path)?;
+  config.normalize()?;
+  if config.to_content(ConfigFormat::of(&path))? == content {
+    writeln!(out, "{} is normalized already", path.display())?;
+    return Ok(());
+  }
+  config.save_config(&path)?;
+  writeln!(out, "Normalized {}", path.display())?;
+  Ok(())
+}
+
+/// Function to print a WireGuard configuration file, its private and
+/// preshared keys redacted unless asked otherwise.
+///
+/// # Arguments
+/// * `out`: The writer the file is printed to (e.g. stdout).
+/// * `config_path`: The path to the WireGuard configuration file, optional
+///   when a single one is configured.
+/// * `show_secrets`: Whether the keys are printed as well.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving or reading
+///   the file.
+pub fn wg_config_show(out: &mut impl Write, config_path: Option<&str>, show_secrets: bool) -> Result<(), CliError> {
+  let path = tunnel_path(config_path)?;
+  let content = wg::read_config_text(&path, show_secrets)?;
+  out.write_all(content.as_bytes())?;
+  if !content.is_empty() && !content.ends_with('\n') {
+    writeln!(out)?;
+  }
+  Ok(())
+}
+
+/// Function to edit a WireGuard configuration file with `$VISUAL`, or else
+/// `$EDITOR` or `vi`, see `wg::edit_config`.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `config_path`: The path to the WireGuard configuration file, optional
+///   when a single one is configured.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving or editing
+///   the file, which is left unchanged when the edit is invalid.
+pub fn wg_config_edit(out: &mut impl Write, config_path: Option<&str>) -> Result<(), CliError> {
+  let path = tunnel_path(config_path)?;
+  let editor = ["VISUAL", "EDITOR"]
+      .iter()
+      .filter_map(|name| std::env::var(name).ok())
+      .find(|editor| !editor.trim().is_empty())
+      .unwrap_or_else(|| "vi".to_string());
+  if wg::edit_config(&path, &editor)? {
+    writeln!(out, "Saved {}", path.display())?;
+  } else {
+    writeln!(out, "{} is unchanged", path.display())?;
+  }
+  Ok(())
+}
+
+/// Define how `connect --all` and `disconnect --all` act on the configured
+/// tunnels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bulk<'a> {
+  /// The tag the tunnels must have, if any.
+  pub tag: Option<&'a str>,
+  /// The most tunnels acted on at once, given with `--parallel`.
+  pub workers: usize,
+}
+
+/// Define the probes `connect` runs on a tunnel before bringing it up.
+#[derive(Clone, Copy, Default)]
+pub struct Preflight<'a> {
+  /// The prober of the peer endpoints, given with `--probe`.
+  pub prober: Option<&'a dyn Prober>,
+  /// The probe of the path MTU to the peer endpoints, given with
+  /// `--auto-mtu`.
+  pub mtu: Option<&'a dyn MtuProbe>,
+}
+
+/// Implements the logic of the probes run before connecting
+impl Preflight<'_> {
+  /// Function to run the probes on a tunnel, printing their outcome.
+  ///
+  /// # Arguments
+  /// * `out`: The writer the outcomes are printed to.
+  /// * `path`: The path to the WireGuard configuration file.
+  ///
+  /// # Returns
+  /// * `Result<Option<u16>, CliError>`: The MTU to be set on the interface,
+  ///   if probed, or the error raised parsing the file or for an unreachable
+  ///   endpoint.
+  fn run(&self, out: &mut impl Write, path: &Path) -> Result<Option<u16>, CliError> {
+    if let Some(prober) = self.prober {
+      probe_tunnel(out, path, prober)?;
+    }
+    let Some(probe) = self.mtu else {
+      return Ok(None);
+    };
+    let config = WgConfig::parse(path).map_err(WgError::from)?;
+    let choice = mtu::choose(&config, &SystemResolver, probe);
+    writeln!(out, "MTU of {}: {choice}", path.display())?;
+    Ok(choice.mtu())
+  }
+}
+
+/// Function to print the one-time password of a configuration.
+///
+/// # Arguments
+/// * `input`: The reader the passphrase of an encrypted OTP URI is read
+///   from (e.g. stdin).
+/// * `out`: The writer the password is printed to (e.g. stdout).
+/// * `err`: The writer the passphrase prompt is printed to (e.g. stderr).
+/// * `config_path`: The exact path of the configuration, optional when a
+///   single one is configured.
+/// * `clock`: The clock the password is computed at, stopped at `--at` when
+///   given.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised for an unknown
+///   configuration, one without a usable OTP URI or a wrong passphrase.
+pub fn generate_totp(
+  input: &mut impl BufRead,
+  out: &mut impl Write,
+  err: &mut impl Write,
+  config_path: Option<&str>,
+  clock: &dyn Clock,
+) -> Result<(), CliError> {
+  let (_, totp) = configured_totp(input, err, config_path)?;
+  writeln!(out, "{}", totp.now(clock))?;
+  Ok(())
+}
+
+/// Function to check a one-time password against the OTP URI of a
+/// configuration.
+///
+/// # Arguments
+/// * `input`: The reader the passphrase of an encrypted OTP URI is read
+///   from (e.g. stdin).
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `err`: The writer the passphrase prompt is printed to (e.g. stderr).
+/// * `config_path`: The exact path of the configuration, optional when a
+///   single one is configured.
+/// * `code`: The one-time password.
+/// * `clock`: The clock the password is checked at, stopped at `--at` when
+///   given.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised for an unknown
+///   configuration, one without a usable OTP URI or a wrong passphrase, or
+///   `InvalidOtp`, so that the process exits with a non-zero code.
+pub fn verify_otp(
+  input: &mut impl BufRead,
+  out: &mut impl Write,
+  err: &mut impl Write,
+  config_path: Option<&str>,
+  code: &str,
+  clock: &dyn Clock,
+) -> Result<(), CliError> {
+  let (user, totp) = configured_totp(input, err, config_path)?;
+  if !totp.verify(code, clock) {
+    return Err(CliError::InvalidOtp(user.config_path));
+  }
+  writeln!(out, "The one-time password of {} is valid", user.config_path)?;
+  Ok(())
+}
+
+/// Function to read the one-time password generator of a configuration,
+/// decrypting its OTP URI with a passphrase read from the input when the
+/// configuration is locked.
+///
+/// # Arguments
+/// * `input`: The reader the passphrase is read from.
+/// * `out`: The writer the passphrase prompt is printed to.
+/// * `config_path`: The exact path of the configuration, optional when a
+///   single one is configured.
+///
+/// # Returns
+/// * `Result<(UserConfig, Totp), CliError>`: The configuration and its
+///   generator, or the error raised for an unknown configuration, one
+///   without a usable OTP URI or a wrong passphrase.
+fn configured_totp(
+  input: &mut impl BufRead,
+  out: &mut impl Write,
+  config_path: Option<&str>,
+) -> Result<(UserConfig, Totp), CliError> {
+  let (user, unlocked) = {
+    let config = Config::get();
+    let user = match config_path {
+      Some(path) => config.get_user(path).cloned().ok_or_else(|| unknown_user(&config, path))?,
+      None => match config.user.as_slice() {
+        [user] => user.clone(),
+        users => return Err(CliError::MissingConfigPath(users.iter().map(|user| user.config_path.clone()).collect())),
+      },
+    };
+    (user, config.is_unlocked())
+  };
+  let totp = user_totp(input, out, &user, unlocked, &mut None)?;
+  Ok((user, totp))
+}
+
+/// Function to read the one-time password generator of a configuration,
+/// decrypting its OTP URI when the configuration is locked with the
+/// passphrase read from the input, or the one read before.
+///
+/// # Arguments
+/// * `input`: The reader the passphrase is read from.
+/// * `out`: The writer the passphrase prompt is printed to.
+/// * `user`: The configuration.
+/// * `unlocked`: Whether the configuration is unlocked.
+/// * `passphrase`: The passphrase read before, if any, set once read.
+///
+/// # Returns
+/// * `Result<Totp, CliError>`: The generator, or the error raised for a
+///   configuration without a usable OTP URI or a wrong passphrase.
+fn user_totp(
+  input: &mut impl BufRead,
+  out: &mut impl Write,
+  user: &UserConfig,
+  unlocked: bool,
+  passphrase: &mut Option<Zeroizing<String>>,
+) -> Result<Totp, CliError> {
+  if user.otp_uri.is_empty() {
+    return Err(CliError::NoOtpUri(user.config_path.clone()));
+  }
+  if !user.otp_encrypted || unlocked {
+    return Ok(Totp::from_uri(&user.otp_uri)?);
+  }
+  let passphrase = match passphrase {
+    Some(passphrase) => passphrase,
+    None => passphrase.insert(read_passphrase(input, out, "Passphrase: ")?),
+  };
+  let otp_uri = crypto::decrypt(passphrase, &user.otp_uri).map_err(|e| ConfigError::Secret(user.config_path.clone(), e))?;
+  Ok(Totp::from_uri(&otp_uri)?)
+}
+
+/// Function to hand the current one-time password of a configuration to the
+/// gateway with its `otp_command`, retrying a rejected one with the code of
+/// the next period up to `otp_retries` times. A configuration which does
+/// not require a one-time password or sets no command is left as it is.
+///
+/// # Arguments
+/// * `input`: The reader the passphrase of an encrypted OTP URI is read
+///   from.
+/// * `out`: The writer the passphrase prompt is printed to.
+/// * `user`: The configuration.
+/// * `unlocked`: Whether the configuration is unlocked.
+/// * `passphrase`: The passphrase read before, if any, set once read.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised reading the OTP URI,
+///   because every code was rejected or because the command failed.
+fn authenticate(
+  input: &mut impl BufRead,
+  out: &mut impl Write,
+  user: &UserConfig,
+  unlocked: bool,
+  passphrase: &mut Option<Zeroizing<String>>,
+) -> Result<(), CliError> {
+  let Some(command) = user.otp_command.as_deref().filter(|_| user.otp) else {
+    return Ok(());
+  };
+  let totp = user_totp(input, out, user, unlocked, passphrase)?;
+  let retries = user.otp_retries.unwrap_or(otp::DEFAULT_RETRIES);
+  // The OTP stays local with --remote, like the configuration file
+  otp::authenticate_with(&SystemRunner, command, &totp, &SystemClock, retries, &thread::sleep)?;
+  Ok(())
+}
+
+/// Function to bring up the tunnel of a WireGuard configuration, or the
+/// tunnels of every configured one.
+///
+/// When the WireGuard configuration file sets `SaveConfig`, removing it is
+/// offered first. With a prober, the peer endpoints are probed and a tunnel
+/// with an unreachable one is not brought up; with an MTU probe, the MTU of
+/// an interface whose file sets none is set from the path MTU to its
+/// endpoints. A tunnel requiring a one-time password with an `otp_command`
+/// hands it to the gateway before it is brought up. With `all`, the tunnels which
+/// are up already are left as they are, and a tunnel failing to come up is
+/// reported while the others are still brought up; the frozen ones and the
+/// ones trusting the current network are skipped, while a frozen one is
+/// refused on its own. The offers and probes
+/// come first, one tunnel at a time, then up to `workers` tunnels are brought
+/// up at once; the outcomes are printed in the configured order.
+///
+/// # Arguments
+/// * `input`: The reader the answer to the offer is read from (e.g. stdin).
+/// * `out`: The writer the offer and the outcome are printed to.
+/// * `err`: The writer the failures of `all` are printed to (e.g. stderr).
+/// * `config_path`: The path to the WireGuard configuration file, optional
+///   when a single one is configured.
+/// * `all`: How to bring up every configured tunnel instead, if so.
+/// * `preflight`: The probes run on each tunnel first.
+/// * `detector`: The detector of the network the host is on, for the
+///   `trusted_networks` of `all`.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving the path,
+///   for a frozen tunnel, removing `SaveConfig`, for an unreachable endpoint,
+///   a rejected one-time password or bringing up the tunnel, or counting the tunnels which failed to come
+///   up with `all`.
+pub fn connect(
+  input: &mut impl BufRead,
+  out: &mut impl Write,
+  err: &mut impl Write,
+  config_path: Option<&str>,
+  all: Option<Bulk<'_>>,
+  preflight: Preflight<'_>,
+  detector: &dyn NetworkDetector,
+) -> Result<(), CliError> {
+  let Some(Bulk { tag, workers }) = all else {
+    let path = tunnel_path(config_path)?;
+    let (timeout, user, unlocked) = {
+      let config = Config::get();
+      check_not_frozen(&config.user, &path)?;
+      let user = config.user.iter().find(|user| user.resolved_path().is_ok_and(|resolved| resolved == path)).cloned();
+      (connect_timeout(&config.user, &path), user, config.is_unlocked())
+    };
+    offer_strip_save_config(input, out, &path)?;
+    let mtu = preflight.run(out, &path)?;
+    if let Some(user) = &user {
+      authenticate(input, out, user, unlocked, &mut None)?;
+    }
+    wg::bring_up(&path, timeout, mtu)?;
+    writeln!(out, "Connected {}", path.display())?;
+    return Ok(());
+  };
+
+  let log = Logger::get();
+  let (users, unlocked) = {
+    let config = Config::get();
+    (tagged(&config.user, tag).cloned().collect::<Vec<UserConfig>>(), config.is_unlocked())
+  };
+  let mut passphrase = None;
+  let active = if users.is_empty() { Vec::new() } else { wg::status()? };
+  // The network is only detected when a tunnel depends on it
+  let network = match users.iter().any(|user| !user.trusted_networks.is_empty()) {
+    true => detector.detect(),
+    false => CurrentNetwork::default(),
+  };
+  // The offers read the terminal, so only bringing up runs concurrently
+  let mut prepared = Vec::with_capacity(users.len());
+  let mut skipped = 0;
+  for user in &users {
+    prepared.push(user.resolved_path().map_err(CliError::from).and_then(|path| {
+      let interface = wg::interface_name(&path).unwrap_or_default();
+      match skip_reason(user, interface, &active, &network) {
+        Some(Skip::Active) => return Ok(None),
+        Some(reason) => {
+          writeln!(out, "Skipped {}, {reason}", path.display())?;
+          skipped += 1;
+          return Ok(None);
+        }
+        None => {}
+      }
+      offer_strip_save_config(input, out, &path)?;
+      let mtu = preflight.run(out, &path)?;
+      authenticate(input, out, user, unlocked, &mut passphrase)?;
+      Ok(Some((path, user.connect_timeout.map(Duration::from_secs), mtu)))
+    }));
+  }
+  let pending: Vec<(PathBuf, Option<Duration>, Option<u16>)> =
+      prepared.iter().filter_map(|prepared| prepared.as_ref().ok().cloned().flatten()).collect();
+  let mut brought_up = pool::map_bounded(&pending, workers, |(path, timeout, mtu)| wg::bring_up(path, *timeout, *mtu)).into_iter();
+
+  let mut connected = 0;
+  let mut failed = 0;
+  for (user, prepared) in users.iter().zip(prepared) {
+    let result = prepared.and_then(|prepared| match prepared {
+      Some((path, _, _)) => {
+        let outcome = brought_up.next().expect("one outcome per tunnel brought up");
+        outcome.map(|()| Some(path)).map_err(CliError::from)
+      }
+      None => Ok(None),
+    });
+    match result {
+      Ok(Some(path)) => {
+        writeln!(out, "Connected {}", path.display())?;
+        connected += 1;
+      }
+      Ok(None) => {}
+      Err(e) => {
+        log.error(&format!("Failed to connect {}: {e}", user.config_path));
+        writeln!(err, "Failed to connect {}: {e}", user.config_path)?;
+        failed += 1;
+      }
+    }
+  }
+  if failed > 0 {
+    return Err(CliError::ConnectFailed(failed));
+  }
+  if connected == 0 {
+    match (users.is_empty(), tag) {
+      (true, Some(tag)) => writeln!(out, "No tunnels tagged {tag}")?,
+      (true, None) => writeln!(out, "No tunnels configured")?,
+      (false, _) if skipped == 0 => writeln!(out, "Every tunnel is connected already")?,
+      (false, _) => {}
+    }
+  }
+  Ok(())
+}
+
+/// Define why `connect --all` leaves a tunnel down, or as it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Skip<'a> {
+  /// The tunnel is up already.
+  Active,
+  /// The tunnel is frozen.
+  Frozen,
+  /// The host is on a network the tunnel trusts.
+  Trusted(&'a TrustedNetwork),
+}
+
+impl fmt::Display for Skip<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Skip::Active => f.write_str("the tunnel is up already"),
+      Skip::Frozen => f.write_str("the tunnel is frozen"),
+      Skip::Trusted(network) => write!(f, "the network is trusted ({network})"),
+    }
+  }
+}
+
+/// Function to tell why `connect --all` leaves a tunnel as it is, if so.
+///
+/// # Arguments
+/// * `user`: The configuration of the tunnel.
+/// * `interface`: The name of its interface.
+/// * `active`: The state of the interfaces up.
+/// * `network`: The network the host is on.
+///
+/// # Returns
+/// * `Option<Skip>`: Why the tunnel is skipped, or `None` when it is to be
+///   brought up.
+fn skip_reason<'a>(
+  user: &'a UserConfig,
+  interface: &str,
+  active: &[TunnelStatus],
+  network: &CurrentNetwork,
+) -> Option<Skip<'a>> {
+  if active.iter().any(|tunnel| tunnel.interface == interface) {
+    return Some(Skip::Active);
+  }
+  if user.frozen {
+    return Some(Skip::Frozen);
+  }
+  user.trusted_networks.iter().find(|trusted| trusted.matches(network)).map(Skip::Trusted)
+}
+
+/// Function to probe the peer endpoints of a WireGuard configuration file
+/// and print the outcome of each one.
+///
+/// # Arguments
+/// * `out`: The writer the outcomes are printed to.
+/// * `path`: The path to the WireGuard configuration file.
+/// * `prober`: The prober of the endpoints.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised reading the file or
+///   printing, or for the first unreachable endpoint.
+fn probe_tunnel(out: &mut impl Write, path: &Path, prober: &dyn Prober) -> Result<(), CliError> {
+  let config = WgConfig::parse(path).map_err(WgError::from)?;
+  let mut unreachable = None;
+  for (endpoint, reachability) in wg::probe_endpoints(&config, prober) {
+    writeln!(out, "Endpoint {endpoint}: {reachability}")?;
+    if let Reachability::Unreachable(reason) = reachability {
+      unreachable.get_or_insert(CliError::UnreachableEndpoint { endpoint, reason });
+    }
+  }
+  unreachable.map_or(Ok(()), Err)
+}
+
+/// Function to replace the private key of a WireGuard configuration file
+/// with a newly generated one, and to print the new public key.
+///
+/// With `reload`, a tunnel which is up is brought down and up again, so that
+/// it uses the new key; one which is not up is left down.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `config_path`: The path to the WireGuard configuration file.
+/// * `reload`: Whether to reload the tunnel with the new key.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving the path,
+///   for a frozen tunnel, rotating the key or reloading the tunnel.
+pub fn rotate_keys(out: &mut impl Write, config_path: &str, reload: bool) -> Result<(), CliError> {
+  let path = tunnel_path(Some(config_path))?;
+  check_not_frozen(&Config::get().user, &path)?;
+  let (public, backup) = wg::rotate_private_key(&path)?;
+  writeln!(out, "Backed up {} to {}", path.display(), backup.display())?;
+  writeln!(out, "New public key: {public}")?;
+  writeln!(out, "Install it on the peers of {} in place of the old one", path.display())?;
+
+  if reload {
+    if wg::reconnect(&path)? {
+      writeln!(out, "Reloaded {}", path.display())?;
+    } else {
+      writeln!(out, "{} is not connected, nothing to reload", path.display())?;
+    }
+  }
+  Ok(())
+}
+
+/// Function to measure the throughput through the tunnel of a WireGuard
+/// configuration file, and to print it.
+///
+/// # Arguments
+/// * `out`: The writer the throughput is printed to (e.g. stdout).
+/// * `config_path`: The path to the WireGuard configuration file.
+/// * `benchmark`: How the throughput is measured.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving the path,
+///   bringing the tunnel up or down, or by the benchmark.
+pub fn benchmark(out: &mut impl Write, config_path: &str, benchmark: &Benchmark) -> Result<(), CliError> {
+  let path = tunnel_path(Some(config_path))?;
+  let (throughput, brought_up) = wg::benchmark(&path, benchmark)?;
+  writeln!(out, "Throughput through {}: {throughput}", path.display())?;
+  if brought_up {
+    writeln!(out, "{} was brought up for the benchmark and down again", path.display())?;
+  }
+  Ok(())
+}
+
+/// Function to change the live interface of a configured tunnel with `wg
+/// set`.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `interface`: The name of the interface.
+/// * `args`: The arguments given to `wg set` after the interface.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised because no configured
+///   tunnel has the interface or it is frozen, an argument is refused or
+///   `wg` failed.
+pub fn wg_set(out: &mut impl Write, interface: &str, args: &[String]) -> Result<(), CliError> {
+  let users = Config::get().user.clone();
+  let mut available = Vec::new();
+  let mut configured = None;
+  for user in &users {
+    let path = user.resolved_path()?;
+    if wg::interface_name(&path) == Some(interface) {
+      configured.get_or_insert(path.clone());
+    }
+    available.extend(wg::interface_name(&path).map(str::to_string));
+  }
+  let Some(path) = configured else {
+    return Err(CliError::UnknownInterface {
+      interface: interface.to_string(),
+      available,
+    });
+  };
+  check_not_frozen(&users, &path)?;
+  wg::set(interface, args)?;
+  writeln!(out, "Updated {interface}, until it goes down")?;
+  Ok(())
+}
+
+/// Function to bring down the interfaces brought up by WG-Bridge whose
+/// configuration is gone, asking for confirmation of each one unless `yes`
+/// is set.
+///
+/// The interfaces WG-Bridge did not bring up are left alone, and the
+/// sessions of the interfaces which are no longer active are forgotten.
+///
+/// # Arguments
+/// * `input`: The reader the confirmations are read from (e.g. stdin).
+/// * `out`: The writer the orphans and the outcome are printed to.
+/// * `yes`: Whether to bring them down without asking for confirmation.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised reading the sessions,
+///   listing the interfaces, reading a confirmation or bringing down an
+///   interface.
+pub fn cleanup(input: &mut impl BufRead, out: &mut impl Write, yes: bool) -> Result<(), CliError> {
+  let Some(dir) = session::default_dir() else {
+    writeln!(out, "No orphaned interfaces")?;
+    return Ok(());
+  };
+  let sessions = session::load(&dir)?;
+  let active: Vec<String> = if sessions.is_empty() {
+    Vec::new()
+  } else {
+    wg::status()?.into_iter().map(|tunnel| tunnel.interface).collect()
+  };
+  for interface in session::stale(&sessions, &active) {
+    session::forget(&dir, interface)?;
+  }
+  let configured = Config::get()
+      .user
+      .iter()
+      .map(UserConfig::resolved_path)
+      .collect::<Result<Vec<PathBuf>, ConfigError>>()?;
+
+  let orphans = session::orphans(&sessions, &active, &configured);
+  if orphans.is_empty() {
+    writeln!(out, "No orphaned interfaces")?;
+  }
+  for (interface, session, orphaned) in orphans {
+    let path = session.config_path.display();
+    writeln!(out, "{interface} was brought up from {path}, but {orphaned}")?;
+    if !yes && !confirm(input, out, &format!("Bring down {interface}?"))? {
+      writeln!(out, "Left {interface} up")?;
+      continue;
+    }
+    wg::tear_down_orphan(interface, &session.config_path)?;
+    writeln!(out, "Brought down {interface}")?;
+  }
+  Ok(())
+}
+
+/// Function to watch the network the tunnels run over, and to reconnect the
+/// active tunnels setting `reconnect_on_network_change` once it changes or
+/// the system resumes, until interrupted. The frozen tunnels are left alone.
+///
+/// # Arguments
+/// * `out`: The writer the changes and reconnections are printed to (e.g.
+///   stdout).
+/// * `err`: The writer the failed reconnections are printed to (e.g.
+///   stderr).
+/// * `interval`: The time between two polls of the routing tables.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised because no tunnel
+///   sets `reconnect_on_network_change` or resolving their paths.
+pub fn watch_network(out: &mut impl Write, err: &mut impl Write, interval: Duration) -> Result<(), CliError> {
+  let mut paths = Vec::new();
+  let mut interfaces = Vec::new();
+  for user in &Config::get().user {
+    let path = user.resolved_path()?;
+    interfaces.extend(wg::interface_name(&path).map(str::to_string));
+    if user.reconnect_on_network_change && !user.frozen {
+      paths.push(path);
+    }
+  }
+  if paths.is_empty() {
+    return Err(CliError::NoReconnectingTunnel);
+  }
+  let mut events = RoutePoller::new(interval, interfaces);
+  watch_network_with(out, err, &paths, &mut events, &wg::reconnect)
+}
+
+/// Function to reconnect tunnels on each change of the network given by an
+/// event source.
+///
+/// A tunnel failing to reconnect is reported, and reconnected again on the
+/// next change.
+///
+/// # Arguments
+/// * `out`: The writer the changes and reconnections are printed to.
+/// * `err`: The writer the failed reconnections are printed to.
+/// * `paths`: The paths to the WireGuard configuration files of the tunnels.
+/// * `events`: The source of the network changes.
+/// * `reconnect`: The function reconnecting a tunnel when it is active (see
+///   `wg::reconnect`).
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised writing the output,
+///   once the event source has no more changes.
+fn watch_network_with(
+  out: &mut impl Write,
+  err: &mut impl Write,
+  paths: &[PathBuf],
+  events: &mut dyn NetworkEvents,
+  reconnect: &dyn Fn(&Path) -> Result<bool, WgError>,
+) -> Result<(), CliError> {
+  writeln!(out, "Watching the network for {} tunnel(s)", paths.len())?;
+  while let Some(change) = events.next_change() {
+    writeln!(out, "Network change: {change}")?;
+    for path in paths {
+      match reconnect(path) {
+        Ok(true) => writeln!(out, "Reconnected {}", path.display())?,
+        Ok(false) => {}
+        Err(e) => writeln!(err, "Failed to reconnect {}: {e}", path.display())?,
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Function to start the background monitor of a tunnel brought up by
+/// `connect --detach`, which reconnects it on each change of the network
+/// until stopped with `stop`.
+///
+/// # Arguments
+/// * `out`: The writer the PID of the monitor is printed to (e.g. stdout).
+/// * `config_path`: The path to the WireGuard configuration file, optional
+///   when a single one is configured.
+/// * `interval`: The time between two polls of the routing tables.
+/// * `globals`: The global options given to the monitor first, e.g.
+///   `--config`.
+/// * `log_file`: The file the output of the monitor is appended to.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving the path,
+///   starting the monitor or recording its PID.
+pub fn detach(
+  out: &mut impl Write,
+  config_path: Option<&str>,
+  interval: Duration,
+  globals: &[OsString],
+  log_file: &Path,
+) -> Result<(), CliError> {
+  let dir = monitor::default_dir().ok_or(CliError::NoMonitorDir)?;
+  let path = tunnel_path(config_path)?;
+  let interface = wg::interface_name(&path).unwrap_or_default();
+  if let Some(pid) = monitor::running(&dir, interface)? {
+    writeln!(out, "The monitor of {} is running already (PID {pid})", path.display())?;
+    return Ok(());
+  }
+  let mut args = globals.to_vec();
+  args.extend([
+    "monitor".into(),
+    path.clone().into_os_string(),
+    "--interval".into(),
+    format!("{}ms", interval.as_millis()).into(),
+  ]);
+  let mut child = monitor::spawn(&args, log_file)?;
+  if let Err(e) = monitor::record(&dir, interface, child.id()) {
+    let _ = child.kill();
+    return Err(e.into());
+  }
+  writeln!(out, "Monitoring {} in the background (PID {}), stop it with `wgb stop`", path.display(), child.id())?;
+  Ok(())
+}
+
+/// Function to run the monitor of a tunnel started by `connect --detach`,
+/// reconnecting it on each change of the network, until terminated.
+///
+/// # Arguments
+/// * `out`: The writer the changes and reconnections are printed to (the
+///   log file once detached).
+/// * `err`: The writer the failed reconnections are printed to.
+/// * `config_path`: The path to the WireGuard configuration file.
+/// * `interval`: The time between two polls of the routing tables.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving the path or
+///   writing the output.
+pub fn monitor(out: &mut impl Write, err: &mut impl Write, config_path: &str, interval: Duration) -> Result<(), CliError> {
+  let path = tunnel_path(Some(config_path))?;
+  let interfaces = wg::interface_name(&path).map(str::to_string).into_iter().collect();
+  let mut events = RoutePoller::new(interval, interfaces);
+  watch_network_with(out, err, &[path], &mut events, &wg::reconnect)
+}
+
+/// Function to stop the background monitor of a tunnel started by
+/// `connect --detach`, leaving the tunnel up.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `config_path`: The path to the WireGuard configuration file, optional
+///   when a single one is configured.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving the path,
+///   because no monitor of the tunnel is running or stopping it.
+pub fn stop(out: &mut impl Write, config_path: Option<&str>) -> Result<(), CliError> {
+  let dir = monitor::default_dir().ok_or(CliError::NoMonitorDir)?;
+  let path = tunnel_path(config_path)?;
+  let pid = monitor::stop(&dir, wg::interface_name(&path).unwrap_or_default())?;
+  writeln!(out, "Stopped the monitor of {} (PID {pid})", path.display())?;
+  Ok(())
+}
+
+/// Function to bring down the tunnel of a WireGuard configuration, or the
+/// tunnels of every configured one.
+///
+/// A tunnel which is not active is reported, not treated as an error. With
+/// `all`, a tunnel failing to go down is reported and the others are still
+/// brought down, up to `workers` at once; the outcomes are printed in the
+/// configured order.
+///
+/// # Arguments
+/// * `out`: The writer the outcome is printed to (e.g. stdout).
+/// * `err`: The writer the failures of `all` are printed to (e.g. stderr).
+/// * `config_path`: The path to the WireGuard configuration file, optional
+///   when a single one is configured.
+/// * `all`: How to bring down every active configured tunnel instead, if
+///   so.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised resolving the path or
+///   bringing down the tunnel, or counting the tunnels which failed to go
+///   down with `all`.
+pub fn disconnect(
+  out: &mut impl Write,
+  err: &mut impl Write,
+  config_path: Option<&str>,
+  all: Option<Bulk<'_>>,
+) -> Result<(), CliError> {
+  let Some(Bulk { tag, workers }) = all else {
+    let path = tunnel_path(config_path)?;
+    match wg::bring_down(&path)? {
+      Teardown::Down => writeln!(out, "Disconnected {}", path.display())?,
+      Teardown::NotActive => writeln!(out, "{} is not active", path.display())?,
+    }
+    return Ok(());
+  };
+
+  let log = Logger::get();
+  let users: Vec<UserConfig> = tagged(&Config::get().user, tag).cloned().collect();
+  let mut paths = Vec::with_capacity(users.len());
+  let mut failed = 0;
+  for user in users {
+    match user.resolved_path() {
+      Ok(path) => paths.push(path),
+      Err(e) => {
+        log.error(&format!("Failed to disconnect {}: {e}", user.config_path));
+        writeln!(err, "Failed to disconnect {}: {e}", user.config_path)?;
+        failed += 1;
+      }
+    }
+  }
+
+  let mut disconnected = 0;
+  for (path, outcome) in wg::bring_down_all(&paths, workers)? {
+    match outcome {
+      Ok(Teardown::Down) => {
+        writeln!(out, "Disconnected {}", path.display())?;
+        disconnected += 1;
+      }
+      Ok(Teardown::NotActive) => {}
+      Err(e) => {
+        log.error(&format!("Failed to disconnect {}: {e}", path.display()));
+        writeln!(err, "Failed to disconnect {}: {e}", path.display())?;
+        failed += 1;
+      }
+    }
+  }
+  if failed > 0 {
+    return Err(CliError::DisconnectFailed(failed));
+  }
+  if disconnected == 0 {
+    writeln!(out, "No active tunnels")?;
+  }
+  Ok(())
+}
+
+/// Function to resolve the WireGuard configuration file a command acts on.
+///
+/// A path which is not configured is used as is, after expanding `~` and
+/// environment variables. Without a path, the only configured one is used.
+///
+/// # Arguments
+/// * `config_path`: The path given on the command line, if any.
+///
+/// # Returns
+/// * `Result<PathBuf, CliError>`: The resolved path, or the error raised
+///   expanding it or because no path is given and several are configured.
+fn tunnel_path(config_path: Option<&str>) -> Result<PathBuf, CliError> {
+  let config = Config::get();
+  let user = match config_path {
+    Some(path) => config.get_user(path).cloned().unwrap_or_else(|| UserConfig {
+      config_path: path.to_string(),
+      ..Default::default()
+    }),
+    None => match config.user.as_slice() {
+      [user] => user.clone(),
+      users => {
+        return Err(CliError::MissingConfigPath(
+          users.iter().map(|user| user.config_path.clone()).collect(),
+        ));
+      }
+    },
+  };
+  Ok(user.resolved_path()?)
+}
+
+/// The fields of the `interface_template`.
+const INTERFACE_FIELDS: [&str; 3] = ["profile", "user", "index"];
+
+/// Function to name the interfaces of the configured tunnels after the
+/// `interface_template`.
+///
+/// `{profile}` is the profile of the configuration, `{user}` its file name
+/// without the extension and `{index}` its position among the
+/// configurations of the same profile, from 1.
+///
+/// # Arguments
+/// * `users`: The configured WireGuard configurations, in order.
+/// * `template`: The template.
+///
+/// # Returns
+/// * `Result<HashMap<PathBuf, String>, CliError>`: The interface names by
+///   resolved path, or the error raised for an invalid template, for a
+///   configuration without the profile it uses, or for a name which is too
+///   long, invalid or given to two configurations.
+pub fn interface_names(users: &[UserConfig], template: &str) -> Result<HashMap<PathBuf, String>, CliError> {
+  let template = Template::parse(template, &INTERFACE_FIELDS)?;
+  let mut indexes: HashMap<Option<&str>, usize> = HashMap::new();
+  let mut owners: HashMap<String, &str> = HashMap::new();
+  let mut names = HashMap::new();
+  for user in users {
+    let path = user.resolved_path()?;
+    let profile = user.profile.as_deref();
+    if profile.is_none() && template.uses(0) {
+      return Err(CliError::MissingProfile(user.config_path.clone()));
+    }
+    let index = indexes.entry(profile).or_default();
+    *index += 1;
+    let file_name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = template.render(&[profile.unwrap_or_default().to_string(), file_name, index.to_string()]);
+    if !wg::is_valid_interface_name(&name) {
+      return Err(CliError::InvalidInterfaceName {
+        config_path: user.config_path.clone(),
+        name,
+      });
+    }
+    if let Some(first) = owners.insert(name.clone(), &user.config_path) {
+      return Err(CliError::DuplicateInterfaceName {
+        name,
+        first: first.to_string(),
+        second: user.config_path.clone(),
+      });
+    }
+    names.insert(path, name);
+  }
+  Ok(names)
+}
+
+/// Function to format how long ago a time was, at the coarsest two units.
+///
+/// # Arguments
+/// * `now`: The current time.
+/// * `time`: The past time.
+///
+/// # Returns
+/// * `String`: The elapsed time (e.g. "3m 12s ago"), "now" for a time in
+///   the future.
+fn format_ago(now: SystemTime, time: SystemTime) -> String {
+  let Ok(elapsed) = now.duration_since(time) else {
+    return "now".to_string();
+  };
+  let secs = elapsed.as_secs();
+  let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+  match (days, hours, minutes) {
+    (0, 0, 0) => format!("{secs}s ago"),
+    (0, 0, _) => format!("{minutes}m {}s ago", secs % 60),
+    (0, _, _) => format!("{hours}h {minutes}m ago"),
+    _ => format!("{days}d {hours}h ago"),
+  }
+}
+
+/// Function to format the time of the latest handshake with a peer.
+///
+/// # Arguments
+/// * `now`: The current time.
+/// * `handshake`: The time of the latest handshake, if any.
+///
+/// # Returns
+/// * `String`: How long ago the handshake took place, or "never".
+fn format_handshake(now: SystemTime, handshake: Option<SystemTime>) -> String {
+  handshake.map_or("never".to_string(), |time| format_ago(now, time))
+}
+
+/// Function to summarize the allowed IPs of a peer.
+///
+/// # Arguments
+/// * `allowed_ips`: The allowed IPs.
+/// * `drifted`: Whether they differ from the WireGuard configuration file.
+///
+/// # Returns
+/// * `String`: The first two allowed IPs, followed by the count of the
+///   others (e.g. "10.0.0.0/24, 10.1.0.0/24 (+3)"), "-" when there are none,
+///   and marked with a `*` when drifted.
+fn format_allowed_ips(allowed_ips: &[String], drifted: bool) -> String {
+  const SHOWN: usize = 2;
+  let mut summary = if allowed_ips.is_empty() {
+    "-".to_string()
+  } else {
+    allowed_ips[..allowed_ips.len().min(SHOWN)].join(", ")
+  };
+  if allowed_ips.len() > SHOWN {
+    summary.push_str(&format!(" (+{})", allowed_ips.len() - SHOWN));
+  }
+  if drifted {
+    summary.push_str(" *");
+  }
+  summary
+}
+
+/// Function to format a number of bytes with a binary unit.
+///
+/// # Arguments
+/// * `bytes`: The number of bytes.
+///
+/// # Returns
+/// * `String`: The formatted size (e.g. "512 B", "1.5 MiB").
+fn format_bytes(bytes: u64) -> String {
+  const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+  if bytes < 1024 {
+    return format!("{bytes} B");
+  }
+  let mut size = bytes as f64 / 1024.0;
+  let mut unit = 0;
+  while size >= 1024.0 && unit < UNITS.len() - 1 {
+    size /= 1024.0;
+    unit += 1;
+  }
+  format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Function to offer removing `SaveConfig` from a WireGuard configuration
+/// file which sets it.
+///
+/// With `SaveConfig`, `wg-quick down` writes the live state of the interface
+/// back to the file, dropping its comments and any change made to it while
+/// the tunnel was up. Files which cannot be parsed are left to the caller.
+///
+/// # Arguments
+/// * `input`: The reader the answer is read from.
+/// * `out`: The writer the offer and the outcome are printed to.
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<(), CliError>`: The error, if any, raised reading the answer or
+///   rewriting the file.
+fn offer_strip_save_config(input: &mut impl BufRead, out: &mut impl Write, path: &Path) -> Result<(), CliError> {
+  let Ok(wg_config) = WgConfig::parse(path) else {
+    return Ok(());
+  };
+  if !wg_config.interface.save_config {
+    return Ok(());
+  }
+  writeln!(
+    out,
+    "{} sets SaveConfig, so `wg-quick down` will overwrite it with the live state of the tunnel",
+    path.display()
+  )?;
+  if confirm(input, out, "Remove SaveConfig from it?")? {
+    wg::strip_save_config(path).map_err(WgError::from)?;
+    writeln!(out, "Removed SaveConfig from {}", path.display())?;
+  }
+  Ok(())
+}
+
+/// Function to ask a yes/no question, defaulting to no.
+///
+/// # Arguments
+/// * `input`: The reader the answer is read from.
+/// * `out`: The writer the question is printed to.
+/// * `question`: The question, to which ` [y/N] ` is appended.
+///
+/// # Returns
+/// * `io::Result<bool>`: Whether the answer is `y` or `yes`, in any case;
+///   an empty answer or the end of the input is a no.
+pub fn confirm(input: &mut impl BufRead, out: &mut impl Write, question: &str) -> io::Result<bool> {
+  write!(out, "{question} [y/N] ")?;
+  out.flush()?;
+  let mut answer = String::new();
+  input.read_line(&mut answer)?;
+  let answer = answer.trim();
+  Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
+
+/// Function to ask for a passphrase.
+///
+/// When stdin is a terminal, its echo is turned off while the passphrase is
+/// typed, so that it is not shown on screen.
+///
+/// # Arguments
+/// * `input`: The reader the passphrase is read from.
+/// * `out`: The writer the prompt is printed to.
+/// * `prompt`: The prompt.
+///
+/// # Returns
+/// * `Result<Zeroizing<String>, CliError>`: The passphrase, without the line
+///   break, wiped from memory when dropped, or `EmptyPassphrase`.
+pub fn read_passphrase(
+  input: &mut impl BufRead,
+  out: &mut impl Write,
+  prompt: &str,
+) -> Result<Zeroizing<String>, CliError> {
+  write!(out, "{prompt}")?;
+  out.flush()?;
+  let mut passphrase = Zeroizing::new(String::new());
+  {
+    let _echo = EchoOff::new();
+    input.read_line(&mut passphrase)?;
+  }
+  let len = passphrase.trim_end_matches(['\r', '\n']).len();
+  passphrase.truncate(len);
+  if passphrase.is_empty() {
+    return Err(CliError::EmptyPassphrase);
+  }
+  Ok(passphrase)
+}
+
+/// Define a guard turning off the echo of the terminal on stdin, restored
+/// when dropped. Line breaks are still echoed, so that the output carries on
+/// on the next line.
+struct EchoOff {
+  #[cfg(unix)]
+  original: Option<libc::termios>,
+}
+
+/// Implements the logic to turn off the echo of the terminal
+impl EchoOff {
+  /// Function to turn off the echo, when stdin is a terminal.
+  ///
+  /// # Returns
+  /// * `EchoOff`: The guard restoring the echo.
+  #[cfg(unix)]
+  fn new() -> Self {
+    // SAFETY: the termios structure is plain data, filled by tcgetattr
+    // before being used, and only stdin is acted on
+    unsafe {
+      let mut termios: libc::termios = std::mem::zeroed();
+      if libc::isatty(libc::STDIN_FILENO) != 1 || libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+        return EchoOff { original: None };
+      }
+      let original = termios;
+      termios.c_lflag &= !libc::ECHO;
+      termios.c_lflag |= libc::ECHONL;
+      if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+        return EchoOff { original: None };
+      }
+      EchoOff { original: Some(original) }
+    }
+  }
+
+  /// Function to turn off the echo; a no-op on this platform.
+  ///
+  /// # Returns
+  /// * `EchoOff`: The guard.
+  #[cfg(not(unix))]
+  fn new() -> Self {
+    EchoOff {}
+  }
+}
+
+impl Drop for EchoOff {
+  fn drop(&mut self) {
+    #[cfg(unix)]
+    if let Some(original) = &self.original {
+      // SAFETY: restores the attributes read by tcgetattr in `new`
+      unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use base64::Engine;
+  use base64::engine::general_purpose::STANDARD;
+  use crate::core::netwatch::NetworkChange;
+  use crate::core::testing::{KEY, temp_dir};
+  use std::fs;
+  use tempfile::TempDir;
+
+  /// Function to build the state of a tunnel with one peer per handshake,
+  /// given in seconds since the Unix epoch.
+  fn tunnel(interface: &str, handshakes: &[Option<u64>]) -> Tunnel {
+    let peers = handshakes
+        .iter()
+        .enumerate()
+        .map(|(index, handshake)| PeerStatus {
+          public_key: format!("peer-{index}"),
+          endpoint: Some(format!("198.51.100.{index}:51820")),
+          allowed_ips: vec![format!("10.0.{index}.0/24")],
+          latest_handshake: handshake.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+          rx_bytes: 1024,
+          tx_bytes: 512,
+        })
+        .collect();
+    let status = TunnelStatus {
+      interface: interface.to_string(),
+      up: true,
+      peers,
+    };
+    Tunnel {
+      config_path: format!("/etc/wireguard/{interface}.conf"),
+      status,
+      config: None,
+      handshake_timeout: None,
+      previous: None,
+    }
+  }
+
+  fn down(interface: &str) -> Tunnel {
+    Tunnel {
+      config_path: format!("/etc/wireguard/{interface}.conf"),
+      status: TunnelStatus::down(interface),
+      config: None,
+      handshake_timeout: None,
+      previous: None,
+    }
+  }
+
+  fn printed(print: impl FnOnce(&mut Vec<u8>) -> Result<(), CliError>) -> String {
+    let mut out = Vec::new();
+    print(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  /// A mix of tunnels: two up, one with a peer yet to handshake, and two down.
+  fn mixed_tunnels() -> Vec<Tunnel> {
+    vec![tunnel("wg0", &[Some(900), None]), down("wg1"), tunnel("wg2", &[None]), down("wg3")]
+  }
+
+  #[test]
+  fn print_lints_fails_on_warnings_only() {
+    let path = Path::new("/etc/wireguard/wg0.conf");
+    let lint = |severity| Lint {
+      code: "low-mtu",
+      severity,
+      section: "Interface".to_string(),
+      message: "MTU is low".to_string(),
+    };
+
+    assert_eq!(printed(|out| print_lints(out, path, &[], false, false)), "No issues found in /etc/wireguard/wg0.conf\n");
+    assert_eq!(
+      printed(|out| print_lints(out, path, &[lint(Severity::Info)], false, false)),
+      "/etc/wireguard/wg0.conf: info: Interface: MTU is low [low-mtu]\n"
+    );
+    let mut out = Vec::new();
+    let result = print_lints(&mut out, path, &[lint(Severity::Warning), lint(Severity::Info)], true, false);
+    assert!(matches!(result, Err(CliError::LintWarnings(1))));
+    let lints: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(lints[0]["severity"], "warning");
+    assert_eq!(lints[1]["code"], "low-mtu");
+  }
+
+  #[test]
+  fn colors_are_only_printed_when_enabled() {
+    let path = Path::new("/etc/wireguard/wg0.conf");
+    let lints = [Lint {
+      code: "low-mtu",
+      severity: Severity::Warning,
+      section: "Interface".to_string(),
+      message: "MTU is low".to_string(),
+    }];
+    let now = SystemTime::now();
+    let colored = |color| {
+      let mut out = Vec::new();
+      let _ = print_lints(&mut out, path, &lints, false, color);
+      print_status(&mut out, &mixed_tunnels(), false, now, color).unwrap();
+      let _ = check_health(&mut out, &mixed_tunnels(), &check(None), now, color);
+      String::from_utf8(out).unwrap()
+    };
+
+    let output = colored(true);
+    assert!(output.contains("\x1b[33mwarning\x1b[0m"));
+    assert!(output.contains("\x1b[32mup\x1b[0m   "));
+    assert!(output.contains("\x1b[31mdown\x1b[0m"));
+    assert!(output.contains("\x1b[31mCRITICAL\x1b[0m:"));
+    let plain = colored(false);
+    assert!(!plain.contains('\x1b'));
+    assert_eq!(plain, output.replace("\x1b[33m", "").replace("\x1b[32m", "").replace("\x1b[31m", "").replace("\x1b[0m", ""));
+  }
+
+  #[test]
+  fn changes_are_printed_since_the_snapshot() {
+    let mut before = tunnel("wg0", &[Some(100), None]);
+    before.status.peers[0].rx_bytes = 512;
+    before.status.peers[1].tx_bytes = 2048;
+    let previous = BTreeMap::from([("wg0".to_string(), Snapshot::of(&before.status, UNIX_EPOCH + Duration::from_secs(150)))]);
+    let tunnels = [tunnel("wg0", &[Some(200), Some(120)]), tunnel("wg1", &[None])];
+    let now = UNIX_EPOCH + Duration::from_secs(210);
+
+    let output = printed(|out| print_changes(out, &tunnels, &previous, UNIX_EPOCH + Duration::from_secs(150), now));
+    assert_eq!(
+      output,
+      "\
+INTERFACE  ENDPOINT            HANDSHAKE  RECEIVED   SENT     COUNTED FROM
+wg0        198.51.100.0:51820  new        +512 B     +0 B     1m 0s ago
+wg0        198.51.100.1:51820  -          +1.0 KiB*  +512 B*  1m 0s ago
+wg1        198.51.100.0:51820  -          -          -        no snapshot
+* counters were reset since the snapshot, counted from zero
+"
+    );
+  }
+
+  #[test]
+  fn effective_settings_tell_their_origin() {
+    let path = Path::new("/home/alice/.wgbconf.json");
+    let mut config = Config::default();
+    config.address_pool = Some("10.9.0.0/24".to_string());
+    config
+        .add_user(UserConfig {
+          config_path: "/etc/wireguard/wg0.conf".to_string(),
+          otp: true,
+          otp_uri: "otpauth://totp/wgb:alice?secret=JBSWY3DPEHPK3PXP".to_string(),
+          ..Default::default()
+        })
+        .unwrap();
+    let env = |name: &str| match name {
+      config::CONFIG_ENV => Some("/srv/wgbconf.json".to_string()),
+      logger::LOG_LEVEL_ENV => Some("warn".to_string()),
+      _ => None,
+    };
+    let origin = |settings: &[Setting], name: &str| {
+      let setting = settings.iter().find(|setting| setting.setting == name).unwrap();
+      (setting.value.clone(), setting.origin.clone())
+    };
+
+    let settings = effective_settings(&GlobalFlags::default(), &env, &config, path);
+    assert_eq!(origin(&settings, "config_file").1, "env WGB_CONFIG");
+    assert_eq!(origin(&settings, "log_level"), ("warn".to_string(), "env WGB_LOG_LEVEL".to_string()));
+    assert_eq!(origin(&settings, "timeout"), ("unset".to_string(), "default".to_string()));
+    assert_eq!(
+      origin(&settings, "address_pool"),
+      ("10.9.0.0/24".to_string(), "file /home/alice/.wgbconf.json".to_string())
+    );
+    assert_eq!(origin(&settings, "user.1.otp_uri").0, "<redacted>");
+    assert_eq!(origin(&settings, "user.1.otp_retries"), ("2".to_string(), "default".to_string()));
+    assert!(settings.iter().all(|setting| !setting.value.contains("JBSWY3DPEHPK3PXP")));
+
+    let flags = GlobalFlags {
+      config: Some(path),
+      timeout: Some(Duration::from_secs(30)),
+      verbose: true,
+      ..Default::default()
+    };
+    let settings = effective_settings(&flags, &env, &config, path);
+    assert_eq!(origin(&settings, "config_file"), (path.display().to_string(), "flag --config".to_string()));
+    assert_eq!(origin(&settings, "log_level"), ("debug".to_string(), "flag --verbose".to_string()));
+    assert_eq!(origin(&settings, "timeout"), ("30s".to_string(), "flag --timeout".to_string()));
+  }
+
+  #[test]
+  fn list_is_filtered_by_tag() {
+    let user = |config_path: &str, tags: &[&str]| UserConfig {
+      config_path: config_path.to_string(),
+      tags: tags.iter().map(|tag| tag.to_string()).collect(),
+      ..Default::default()
+    };
+    let users = [user("/etc/wireguard/wg0.conf", &["work", "eu"]), user("/etc/wireguard/wg1.conf", &["streaming"])];
+    let listed = |tag| {
+      let entries: Vec<ListEntry> = tagged(&users, tag).map(ListEntry::from).collect();
+      printed(|out| print_list(out, &entries, false, tag))
+    };
+
+    assert_eq!(
+      listed(Some("work")),
+      "CONFIG PATH              OTP  OTP URI  FROZEN  TAGS\n/etc/wireguard/wg0.conf  no   -        no      work,eu\n"
+    );
+    assert_eq!(listed(None).lines().count(), 3);
+    assert_eq!(listed(Some("home")), "No users tagged home, add one with `wgb tag add`\n");
+    let entries: Vec<ListEntry> = tagged(&users, Some("streaming")).map(ListEntry::from).collect();
+    let json: serde_json::Value = serde_json::from_str(&printed(|out| print_list(out, &entries, true, None))).unwrap();
+    assert_eq!(json[0]["tags"][0], "streaming");
+  }
+
+  #[test]
+  fn frozen_tunnels_are_refused_until_unfrozen() {
+    let mut users = [
+      UserConfig {
+        config_path: "/etc/wireguard/wg0.conf".to_string(),
+        frozen: true,
+        ..Default::default()
+      },
+      UserConfig {
+        config_path: "/etc/wireguard/wg1.conf".to_string(),
+        ..Default::default()
+      },
+    ];
+    let wg0 = Path::new("/etc/wireguard/wg0.conf");
+    let error = check_not_frozen(&users, wg0).unwrap_err();
+    assert_eq!(error.to_string(), "Tunnel /etc/wireguard/wg0.conf is frozen, unfreeze it with `wgb unfreeze /etc/wireguard/wg0.conf` first");
+    check_not_frozen(&users, Path::new("/etc/wireguard/wg1.conf")).unwrap();
+    check_not_frozen(&users, Path::new("/tmp/wg0.conf")).unwrap();
+    let entries: Vec<ListEntry> = users.iter().map(ListEntry::from).collect();
+    let listed = printed(|out| print_list(out, &entries, false, None));
+    assert_eq!(listed.lines().nth(1), Some("/etc/wireguard/wg0.conf  no   -        yes     -"));
+
+    users[0].frozen = false;
+    check_not_frozen(&users, wg0).unwrap();
+  }
+
+  #[test]
+  fn tunnels_are_skipped_on_trusted_networks() {
+    let mut user = UserConfig {
+      config_path: "/etc/wireguard/wg0.conf".to_string(),
+      trusted_networks: vec![TrustedNetwork::Ssid("Home".to_string()), TrustedNetwork::Subnet("10.1.0.0/16".to_string())],
+      ..Default::default()
+    };
+    let home = CurrentNetwork {
+      ssids: vec!["Home".to_string()],
+      ..Default::default()
+    };
+    let office = CurrentNetwork {
+      gateways: vec!["10.1.0.1".parse().unwrap()],
+      ..Default::default()
+    };
+    let cafe = CurrentNetwork {
+      gateways: vec!["192.168.1.1".parse().unwrap()],
+      ssids: vec!["Cafe".to_string()],
+      ..Default::default()
+    };
+
+    let reason = skip_reason(&user, "wg0", &[], &home).unwrap();
+    assert_eq!(reason.to_string(), "the network is trusted (SSID Home)");
+    assert_eq!(skip_reason(&user, "wg0", &[], &office), Some(Skip::Trusted(&user.trusted_networks[1])));
+    assert_eq!(skip_reason(&user, "wg0", &[], &cafe), None);
+    assert_eq!(skip_reason(&user, "wg0", &[TunnelStatus::down("wg0")], &cafe), Some(Skip::Active));
+    user.frozen = true;
+    assert_eq!(skip_reason(&user, "wg0", &[], &cafe), Some(Skip::Frozen));
+  }
+
+  #[test]
+  fn templates_render_each_item() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    let template = Template::parse(r"{name}\t{up}\t{handshake}\t{latest_handshake}", &STATUS_FIELDS).unwrap();
+    let output = printed(|out| print_templated(out, &template, mixed_tunnels().iter().map(|tunnel| tunnel.fields(now))));
+    assert_eq!(output, "wg0\ttrue\t1m 40s ago\t900\nwg1\tfalse\tnever\t\nwg2\ttrue\tnever\t\nwg3\tfalse\tnever\t\n");
+
+    let user = UserConfig {
+      config_path: "/etc/wireguard/wg0.conf".to_string(),
+      tags: vec!["work".to_string(), "eu".to_string()],
+      ..Default::default()
+    };
+    let template = Template::parse("{name}: {tags} (otp {otp})", &LIST_FIELDS).unwrap();
+    let output = printed(|out| print_templated(out, &template, [ListEntry::from(&user).fields()].into_iter()));
+    assert_eq!(output, "wg0: work,eu (otp false)\n");
+
+    let error = Template::parse("{name} {rx}", &STATUS_FIELDS).unwrap_err();
+    assert!(error.to_string().ends_with("valid fields: name, config_path, up, handshake, latest_handshake, endpoint, peers, rx_bytes, tx_bytes"));
+  }
+
+  #[test]
+  fn up_lists_only_the_tunnels_which_are_up() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    let output = printed(|out| print_up(out, &mixed_tunnels(), false, now));
+
+    assert_eq!(output, "wg0  1m 40s ago  2.0 KiB received  1.0 KiB sent\nwg2  never       1.0 KiB received  512 B sent\n");
+  }
+
+  #[test]
+  fn up_prints_only_the_tunnels_which_are_up_as_json() {
+    let output = printed(|out| print_up(out, &mixed_tunnels(), true, SystemTime::now()));
+    let entries: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+    let interfaces: Vec<&str> = entries.as_array().unwrap().iter().map(|entry| entry["interface"].as_str().unwrap()).collect();
+    assert_eq!(interfaces, ["wg0", "wg2"]);
+    assert_eq!(entries[0]["peers"][0]["latest_handshake"], 900);
+  }
+
+  #[test]
+  fn up_reports_when_no_tunnel_is_up() {
+    let tunnels = vec![down("wg1")];
+    assert_eq!(printed(|out| print_up(out, &tunnels, false, SystemTime::now())), "No tunnels connected\n");
+    assert_eq!(printed(|out| print_up(out, &tunnels, true, SystemTime::now())), "[]\n");
+  }
+
+  #[test]
+  fn status_lists_every_tunnel() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    let output = printed(|out| print_status(out, &mixed_tunnels(), false, now, false));
+
+    let interfaces: Vec<&str> = output.lines().skip(1).map(|line| line.split_whitespace().next().unwrap()).collect();
+    assert_eq!(interfaces, ["wg0", "wg0", "wg1", "wg2", "wg3"]);
+  }
+
+  #[test]
+  fn read_passphrase_strips_the_line_break_only() {
+    let mut out = Vec::new();
+    let passphrase = read_passphrase(&mut " correct horse \r\n".as_bytes(), &mut out, "Passphrase: ").unwrap();
+    assert_eq!(passphrase.as_str(), " correct horse ");
+    assert_eq!(out, b"Passphrase: ");
+  }
+
+  #[test]
+  fn read_passphrase_rejects_an_empty_passphrase() {
+    for input in ["", "\n"] {
+      let result = read_passphrase(&mut input.as_bytes(), &mut Vec::new(), "Passphrase: ");
+      assert!(matches!(result, Err(CliError::EmptyPassphrase)));
+    }
+  }
+
+  fn check(threshold: Option<usize>) -> HealthCheck {
+    HealthCheck {
+      threshold,
+      stale_after: Duration::from_secs(180),
+    }
+  }
+
+  #[test]
+  fn fail_if_down_passes_when_every_tunnel_is_up() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    let tunnels = vec![tunnel("wg0", &[Some(900)]), tunnel("wg1", &[None, Some(990)])];
+    let mut out = Vec::new();
+
+    assert!(check_health(&mut out, &tunnels, &check(None), now, false).is_ok());
+    assert_eq!(String::from_utf8(out).unwrap(), "OK: 2/2 tunnel(s) up, 2 required\n");
+  }
+
+  #[test]
+  fn fail_if_down_fails_when_a_tunnel_is_down_or_stale() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    let tunnels = mixed_tunnels();
+    let mut out = Vec::new();
+
+    let result = check_health(&mut out, &tunnels, &check(None), now, false);
+    assert!(matches!(result, Err(CliError::TunnelsDown { up: 1, required: 4 })));
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "CRITICAL: 1/4 tunnel(s) up, 4 required (stale: wg2; down: wg1, wg3)\n"
+    );
+
+    // The handshake of wg0 is 100s old
+    let strict = HealthCheck {
+      threshold: Some(1),
+      stale_after: Duration::from_secs(60),
+    };
+    let result = check_health(&mut Vec::new(), &tunnels, &strict, now, false);
+    assert!(matches!(result, Err(CliError::TunnelsDown { up: 0, required: 1 })));
+  }
+
+  #[test]
+  fn fail_if_down_honours_the_handshake_timeout_of_each_tunnel() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    // Both handshakes are 100s old
+    let mut tunnels = vec![tunnel("wg0", &[Some(900)]), tunnel("wg1", &[Some(900)])];
+    tunnels[0].handshake_timeout = Some(Duration::from_secs(60));
+    let health = Health::assess(&tunnels, &check(None), now);
+    assert_eq!((health.up, health.stale), (vec!["wg1"], vec!["wg0"]));
+
+    // A tunnel with a longer cadence stays up past the global threshold
+    tunnels[0].handshake_timeout = Some(Duration::from_secs(600));
+    let strict = HealthCheck {
+      threshold: None,
+      stale_after: Duration::from_secs(60),
+    };
+    let health = Health::assess(&tunnels, &strict, now);
+    assert_eq!((health.up, health.stale), (vec!["wg0"], vec!["wg1"]));
+  }
+
+  #[test]
+  fn fail_if_down_honours_the_threshold() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    let tunnels = mixed_tunnels();
+
+    assert!(check_health(&mut Vec::new(), &tunnels, &check(Some(0)), now, false).is_ok());
+    assert!(check_health(&mut Vec::new(), &tunnels, &check(Some(1)), now, false).is_ok());
+    assert!(matches!(
+      check_health(&mut Vec::new(), &tunnels, &check(Some(2)), now, false),
+      Err(CliError::TunnelsDown { up: 1, required: 2 })
+    ));
+  }
+
+  /// Define an event source replaying simulated network changes.
+  struct SimulatedChanges(Vec<NetworkChange>);
+
+  impl NetworkEvents for SimulatedChanges {
+    fn next_change(&mut self) -> Option<NetworkChange> {
+      (!self.0.is_empty()).then(|| self.0.remove(0))
+    }
+  }
+
+  #[test]
+  fn network_changes_reconnect_the_active_tunnels() {
+    let paths = [PathBuf::from("/etc/wireguard/wg0.conf"), PathBuf::from("/etc/wireguard/wg1.conf")];
+    let mut events = SimulatedChanges(vec![NetworkChange::Routes, NetworkChange::Resumed(Duration::from_secs(600))]);
+    let reconnected = std::cell::RefCell::new(Vec::new());
+    // wg0 is active, wg1 is down and fails the second time
+    let reconnect = |path: &Path| {
+      reconnected.borrow_mut().push(path.to_path_buf());
+      match path.ends_with("wg0.conf") {
+        true => Ok(true),
+        false if reconnected.borrow().len() > 2 => Err(WgError::Timeout("wg-quick up".to_string())),
+        false => Ok(false),
+      }
+    };
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    watch_network_with(&mut out, &mut err, &paths, &mut events, &reconnect).unwrap();
+
+    assert_eq!(reconnected.into_inner(), [paths.clone(), paths.clone()].concat());
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "Watching the network for 2 tunnel(s)\n\
+       Network change: the default routes changed\n\
+       Reconnected /etc/wireguard/wg0.conf\n\
+       Network change: the system resumed after 600s\n\
+       Reconnected /etc/wireguard/wg0.conf\n"
+    );
+    assert!(String::from_utf8(err).unwrap().starts_with("Failed to reconnect /etc/wireguard/wg1.conf: "));
+  }
+
+  /// Function to build the WireGuard configuration of a tunnel, with the
+  /// given allowed IPs for each peer of `tunnel`.
+  fn config(allowed_ips: &[&[&str]]) -> WgConfig {
+    let mut content = format!("[Interface]\nPrivateKey = {KEY}\n");
+    for (index, allowed_ips) in allowed_ips.iter().enumerate() {
+      content.push_str(&format!("[Peer]\nPublicKey = {}\n", peer_key(index)));
+      content.push_str(&format!("AllowedIPs = {}\n", allowed_ips.join(", ")));
+    }
+    WgConfig::parse_str(&content).unwrap()
+  }
+
+  /// Function to give a peer a valid key, so that it can be configured.
+  fn peer_key(index: usize) -> String {
+    ["3p7bfXt9wbTTW2HC7OQ1Nz+DQ8hbeGdNrfx+FG+IK08=", "GtL7fZc/bLnqZldpVofMCD6hDjrK28SsdLxevJ+qtKU="][index].to_string()
+  }
+
+  fn tunnel_with_config(live: &[&[&str]], configured: &[&[&str]]) -> Tunnel {
+    let mut tunnel = tunnel("wg0", &vec![Some(900); live.len()]);
+    for (index, (peer, allowed_ips)) in tunnel.status.peers.iter_mut().zip(live).enumerate() {
+      peer.public_key = peer_key(index);
+      peer.allowed_ips = allowed_ips.iter().map(|ip| ip.to_string()).collect();
+    }
+    tunnel.config = Some(config(configured));
+    tunnel
+  }
+
+  #[test]
+  fn allowed_ips_are_summarized_with_a_count() {
+    let allowed_ips: Vec<String> = (0..5).map(|index| format!("10.{index}.0.0/16")).collect();
+
+    assert_eq!(format_allowed_ips(&[], false), "-");
+    assert_eq!(format_allowed_ips(&allowed_ips[..1], false), "10.0.0.0/16");
+    assert_eq!(format_allowed_ips(&allowed_ips[..2], true), "10.0.0.0/16, 10.1.0.0/16 *");
+    assert_eq!(format_allowed_ips(&allowed_ips, false), "10.0.0.0/16, 10.1.0.0/16 (+3)");
+  }
+
+  #[test]
+  fn allowed_ips_drift_is_detected() {
+    let same = tunnel_with_config(&[&["10.0.0.0/24", "fd00::/64"]], &[&["fd00::1/64", "10.0.0.7/24"]]);
+    assert!(!same.drifted(&same.status.peers[0]));
+
+    let changed = tunnel_with_config(&[&["10.0.0.0/24", "10.9.0.0/16"]], &[&["10.0.0.0/24"]]);
+    assert!(changed.drifted(&changed.status.peers[0]));
+
+    let unknown_peer = tunnel_with_config(&[&["10.0.0.0/24"], &["10.1.0.0/24"]], &[&["10.0.0.0/24"]]);
+    assert!(!unknown_peer.drifted(&unknown_peer.status.peers[0]));
+    assert!(unknown_peer.drifted(&unknown_peer.status.peers[1]));
+
+    // Without the configuration file, nothing is reported
+    let unread = tunnel("wg0", &[Some(900)]);
+    assert!(!unread.drifted(&unread.status.peers[0]));
+  }
+
+  #[test]
+  fn status_marks_drifted_allowed_ips() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    let tunnels = vec![tunnel_with_config(&[&["10.0.0.0/24", "10.9.0.0/16", "10.8.0.0/16"]], &[&["10.0.0.0/24"]])];
+
+    let output = printed(|out| print_status(out, &tunnels, false, now, false));
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[1].ends_with("10.0.0.0/24, 10.9.0.0/16 (+1) *"), "{output}");
+    assert_eq!(lines[2], "* allowed IPs differ from the WireGuard configuration file");
+
+    let output = printed(|out| print_status(out, &tunnels, true, now, false));
+    let entries: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(entries[0]["peers"][0]["allowed_ips"].as_array().unwrap().len(), 3);
+    assert_eq!(entries[0]["peers"][0]["allowed_ips_drift"], true);
+  }
+
+  #[test]
+  fn roaming_is_detected_between_two_status() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    let mut tunnel = tunnel_with_config(&[&["10.0.0.0/24"]], &[&["10.0.0.0/24"]]);
+    let key = tunnel.status.peers[0].public_key.clone();
+    assert_eq!(tunnel.roamed_from(&tunnel.status.peers[0]), None);
+
+    // The first status records the endpoint, the next one sees it change
+    tunnel.previous = Some(Snapshot::of(&tunnel.status, now));
+    assert_eq!(tunnel.roamed_from(&tunnel.status.peers[0]), None);
+    tunnel.status.peers[0].endpoint = Some("203.0.113.7:40000".to_string());
+    assert_eq!(tunnel.roamed_from(&tunnel.status.peers[0]).as_deref(), Some("198.51.100.0:51820"));
+    let output = printed(|out| print_status(out, std::slice::from_ref(&tunnel), false, now, false));
+    let note = format!("wg0: peer {key} roamed to 203.0.113.7:40000 from 198.51.100.0:51820");
+    assert_eq!(output.lines().last(), Some(note.as_str()), "{output}");
+    let output = printed(|out| print_status(out, std::slice::from_ref(&tunnel), true, now, false));
+    let entries: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(entries[0]["peers"][0]["roamed_from"], "198.51.100.0:51820");
+
+    // Without a snapshot, the endpoint is compared with the configured address
+    tunnel.previous = None;
+    let configured = format!("[Interface]\nPrivateKey = {key}\n[Peer]\nPublicKey = {key}\nEndpoint = 198.51.100.9:51820\n");
+    tunnel.config = Some(WgConfig::parse_str(&configured).unwrap());
+    assert_eq!(tunnel.roamed_from(&tunnel.status.peers[0]).as_deref(), Some("198.51.100.9:51820"));
+    tunnel.config = Some(WgConfig::parse_str(&configured.replace("198.51.100.9", "vpn.example.com")).unwrap());
+    assert_eq!(tunnel.roamed_from(&tunnel.status.peers[0]), None);
+  }
+
+  /// Function to write a WireGuard configuration for each private key, in a
+  /// directory of its own, and to build the users pointing at them.
+  fn users_with_keys(keys: &[&str]) -> (TempDir, Vec<UserConfig>) {
+    let dir = temp_dir();
+    let users = keys
+        .iter()
+        .enumerate()
+        .map(|(index, key)| {
+          let path = dir.path().join(format!("wg{index}.conf"));
+          fs::write(&path, format!("[Interface]\nPrivateKey = {key}\n")).unwrap();
+          UserConfig {
+            config_path: path.display().to_string(),
+            ..Default::default()
+          }
+        })
+        .collect();
+    (dir, users)
+  }
+
+  /// Define a prober answering each endpoint with a canned outcome, the
+  /// unlisted ones being unknown.
+  struct StubProber(Vec<(&'static str, Reachability)>);
+
+  impl Prober for StubProber {
+    fn probe(&self, endpoint: &str) -> Reachability {
+      self.0
+          .iter()
+          .find(|(known, _)| *known == endpoint)
+          .map_or(Reachability::Unknown, |(_, reachability)| reachability.clone())
+    }
+  }
+
+  #[test]
+  fn probe_stops_at_unreachable_endpoints() {
+    let key = wg::generate_keypair().0.as_str().to_string();
+    let (_dir, users) = users_with_keys(&[&key]);
+    let path = PathBuf::from(&users[0].config_path);
+    let content = fs::read_to_string(&path).unwrap();
+    fs::write(
+      &path,
+      format!(
+        "{content}\n[Peer]\nPublicKey = {}\nEndpoint = 192.0.2.1:51820\n\n\
+         [Peer]\nPublicKey = {}\nEndpoint = 192.0.2.2:51820\n",
+        wg::generate_keypair().1,
+        wg::generate_keypair().1
+      ),
+    )
+    .unwrap();
+    let prober = StubProber(vec![
+      ("192.0.2.1:51820", Reachability::Reachable),
+      ("192.0.2.2:51820", Reachability::Unreachable("port closed".to_string())),
+    ]);
+
+    let mut out = Vec::new();
+    let result = probe_tunnel(&mut out, &path, &prober);
+    assert!(matches!(result, Err(CliError::UnreachableEndpoint { endpoint, .. }) if endpoint == "192.0.2.2:51820"));
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "Endpoint 192.0.2.1:51820: reachable\nEndpoint 192.0.2.2:51820: unreachable (port closed)\n"
+    );
+    let unknown = StubProber(vec![("192.0.2.1:51820", Reachability::Reachable)]);
+    assert!(printed(|out| probe_tunnel(out, &path, &unknown)).ends_with("192.0.2.2:51820: unknown (no answer, as usual for UDP)\n"));
+
+    let mut out = Vec::new();
+    let result = check_users(&mut out, &users, &FreePorts::default(), Some(&prober));
+    assert!(matches!(result, Err(CliError::DoctorProblems(1))));
+    assert!(String::from_utf8(out).unwrap().contains("Endpoint 192.0.2.2:51820 is unreachable: port closed"));
+    assert!(check_users(&mut Vec::new(), &users, &FreePorts::default(), Some(&unknown)).is_ok());
+  }
+
+  /// Define a port checker seeing the given ports bound and interfaces up.
+  #[derive(Default)]
+  struct FreePorts {
+    bound: Vec<u16>,
+    up: Vec<&'static str>,
+  }
+
+  impl PortChecker for FreePorts {
+    fn in_use(&self, port: u16) -> bool {
+      self.bound.contains(&port)
+    }
+
+    fn interface_exists(&self, interface: &str) -> bool {
+      self.up.contains(&interface)
+    }
+  }
+
+  #[test]
+  fn doctor_flags_conflicting_listen_ports() {
+    let keys: Vec<String> = (0..4).map(|_| wg::generate_keypair().0.as_str().to_string()).collect();
+    let (_dir, users) = users_with_keys(&keys.iter().map(String::as_str).collect::<Vec<_>>());
+    for (user, port) in users.iter().zip(["51820", "51820", "0", "0"]) {
+      let content = fs::read_to_string(&user.config_path).unwrap();
+      fs::write(&user.config_path, format!("{content}ListenPort = {port}\n")).unwrap();
+    }
+
+    let mut out = Vec::new();
+    let result = check_users(&mut out, &users, &FreePorts::default(), None);
+    assert!(matches!(result, Err(CliError::DoctorProblems(1))));
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      format!("The same ListenPort is used by {}, {}\n", users[0].config_path, users[1].config_path)
+    );
+  }
+
+  #[test]
+  fn doctor_flags_listen_ports_bound_by_other_programs() {
+    let keys: Vec<String> = (0..2).map(|_| wg::generate_keypair().0.as_str().to_string()).collect();
+    let (_dir, users) = users_with_keys(&keys.iter().map(String::as_str).collect::<Vec<_>>());
+    for (user, port) in users.iter().zip(["51820", "51821"]) {
+      let content = fs::read_to_string(&user.config_path).unwrap();
+      fs::write(&user.config_path, format!("{content}ListenPort = {port}\n")).unwrap();
+    }
+    let ports = FreePorts {
+      bound: vec![51820, 51821],
+      up: vec!["wg1"],
+    };
+
+    let mut out = Vec::new();
+    let result = check_users(&mut out, &users, &ports, None);
+    assert!(matches!(result, Err(CliError::DoctorProblems(1))));
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      format!("{}: ListenPort 51820 is already in use on this host\n", users[0].config_path)
+    );
+  }
+
+  #[test]
+  fn doctor_passes_with_unique_keys() {
+    let keys: Vec<String> = (0..3).map(|_| wg::generate_keypair().0.as_str().to_string()).collect();
+    let (_dir, users) = users_with_keys(&keys.iter().map(String::as_str).collect::<Vec<_>>());
+    assert_eq!(printed(|out| check_users(out, &users, &FreePorts::default(), None)), "No problems found in 3 configuration(s)\n");
+  }
+
+  #[test]
+  fn doctor_flags_duplicate_keys() {
+    let (private, _) = wg::generate_keypair();
+    let (other, _) = wg::generate_keypair();
+    // The same key with the low bits of its first byte set, which X25519
+    // clears: the base64 differs, the public key does not.
+    let mut bytes = STANDARD.decode(private.as_str()).unwrap();
+    bytes[0] |= 7;
+    let unclamped = STANDARD.encode(&bytes);
+    assert_ne!(unclamped, private.as_str());
+    let keys = [private.as_str(), other.as_str(), &unclamped, "not a key"];
+    let (_dir, users) = users_with_keys(&keys);
+
+    let mut out = Vec::new();
+    let result = check_users(&mut out, &users, &FreePorts::default(), None);
+    assert!(matches!(result, Err(CliError::DoctorProblems(2))));
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with(&format!("{}: cannot be checked", users[3].config_path)));
+    assert_eq!(
+      lines[1],
+      format!("The same private key is used by {}, {}", users[0].config_path, users[2].config_path)
+    );
+  }
+
+  #[test]
+  fn save_config_is_stripped_only_when_accepted() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wg0.conf");
+    let content = format!("[Interface]\nPrivateKey = {KEY}\nSaveConfig = true\n");
+    fs::write(&path, &content).unwrap();
+
+    let mut out = Vec::new();
+    offer_strip_save_config(&mut "n\n".as_bytes(), &mut out, &path).unwrap();
+    assert!(String::from_utf8(out).unwrap().contains("sets SaveConfig"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+    let mut out = Vec::new();
+    offer_strip_save_config(&mut "y\n".as_bytes(), &mut out, &path).unwrap();
+    assert!(String::from_utf8(out).unwrap().ends_with(&format!("Removed SaveConfig from {}\n", path.display())));
+    assert!(!WgConfig::parse(&path).unwrap().interface.save_config);
+
+    let mut out = Vec::new();
+    offer_strip_save_config(&mut "y\n".as_bytes(), &mut out, &path).unwrap();
+    assert!(out.is_empty());
+  }
+
+  /// Function to print the `list` output of the given users.
+  fn listed(users: &[UserConfig], json: bool, tag: Option<&str>) -> String {
+    let entries: Vec<ListEntry> = tagged(users, tag).map(ListEntry::from).collect();
+    printed(|out| print_list(out, &entries, json, tag))
+  }
+
+  #[test]
+  fn list_prints_the_users_in_configured_order() {
+    let users = [
+      UserConfig {
+        config_path: "/etc/wireguard/work.conf".to_string(),
+        otp: true,
+        otp_uri: "otpauth://totp/wgb:alice?secret=JBSWY3DPEHPK3PXP".to_string(),
+        otp_encrypted: true,
+        tags: vec!["eu".to_string(), "work".to_string()],
+        ..Default::default()
+      },
+      UserConfig {
+        config_path: "/etc/wireguard/wg0.conf".to_string(),
+        frozen: true,
+        ..Default::default()
+      },
+    ];
+
+    // The columns fit the longest value, and the users are not reordered
+    assert_eq!(
+      listed(&users, false, None),
+      "\
+CONFIG PATH               OTP  OTP URI          FROZEN  TAGS
+/etc/wireguard/work.conf  yes  set (encrypted)  no      eu,work
+/etc/wireguard/wg0.conf   no   -                yes     -
+"
+    );
+    assert_eq!(listed(&users, false, Some("eu")).lines().count(), 2);
+    let json: serde_json::Value = serde_json::from_str(&listed(&users, true, None)).unwrap();
+    assert_eq!(
+      json[0],
+      serde_json::json!({
+        "config_path": "/etc/wireguard/work.conf",
+        "otp": true,
+        "otp_uri_set": true,
+        "otp_encrypted": true,
+        "tags": ["eu", "work"],
+        "frozen": false,
+      })
+    );
+    assert_eq!(json[1]["config_path"], "/etc/wireguard/wg0.conf");
+  }
+
+  #[test]
+  fn empty_lists_say_so() {
+    assert_eq!(listed(&[], false, None), "No users configured, add one with `wgb add-user`\n");
+    let users = [UserConfig {
+      config_path: "/etc/wireguard/wg0.conf".to_string(),
+      ..Default::default()
+    }];
+    assert_eq!(listed(&users, false, Some("lab")), "No users tagged lab, add one with `wgb tag add`\n");
+    assert_eq!(listed(&[], true, None), "[]\n");
+  }
+
+  #[test]
+  fn confirmations_default_to_no() {
+    for (answer, confirmed) in [
+      ("y\n", true),
+      ("Y\n", true),
+      ("yes\n", true),
+      (" YES \r\n", true),
+      ("\n", false),
+      ("n\n", false),
+      ("no\n", false),
+      ("yep\n", false),
+      // The end of the input, e.g. a closed stdin
+      ("", false),
+    ] {
+      let mut out = Vec::new();
+      assert_eq!(confirm(&mut answer.as_bytes(), &mut out, "Remove user wg0.conf?").unwrap(), confirmed, "{answer:?}");
+      assert_eq!(String::from_utf8(out).unwrap(), "Remove user wg0.conf? [y/N] ");
+    }
+    // Only the first line is read
+    let mut input = "n\ny\n".as_bytes();
+    assert!(!confirm(&mut input, &mut Vec::new(), "Continue?").unwrap());
+    assert_eq!(input, b"y\n");
+  }
+
+  #[test]
+  fn interface_templates_expand_per_tunnel() {
+    let user = |config_path: &str, profile: Option<&str>| UserConfig {
+      config_path: config_path.to_string(),
+      profile: profile.map(str::to_string),
+      ..Default::default()
+    };
+    let users = [
+      user("/etc/wireguard/alice.conf", Some("prod")),
+      user("/etc/wireguard/bob.conf", Some("staging")),
+      user("/etc/wireguard/carol.conf", Some("prod")),
+    ];
+    let names = interface_names(&users, "wg-{profile}-{index}").unwrap();
+    assert_eq!(names.len(), 3);
+    assert_eq!(names[Path::new("/etc/wireguard/alice.conf")], "wg-prod-1");
+    assert_eq!(names[Path::new("/etc/wireguard/bob.conf")], "wg-staging-1");
+    assert_eq!(names[Path::new("/etc/wireguard/carol.conf")], "wg-prod-2");
+    let names = interface_names(&users[..2], "{user}.{profile}").unwrap();
+    assert_eq!(names[Path::new("/etc/wireguard/bob.conf")], "bob.staging");
+
+    // 16 bytes, one over the kernel limit, are rejected rather than cut
+    assert_eq!(interface_names(&users[..1], "wg-{profile}-{user}-x").unwrap()[Path::new("/etc/wireguard/alice.conf")], "wg-prod-alice-x");
+    let error = interface_names(&users[..1], "wg-{profile}-{user}-xy").unwrap_err();
+    assert!(matches!(&error, CliError::InvalidInterfaceName { name, .. } if name.len() == 16), "{error}");
+    assert!(matches!(interface_names(&users[..1], "wg {user}"), Err(CliError::InvalidInterfaceName { .. })));
+    assert!(matches!(interface_names(&users[..1], ""), Err(CliError::InvalidInterfaceName { .. })));
+
+    let error = interface_names(&users, "wg-{profile}").unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "interface_template gives /etc/wireguard/alice.conf and /etc/wireguard/carol.conf the same interface name wg-prod"
+    );
+    let unprofiled = [user("/etc/wireguard/dave.conf", None)];
+    assert!(matches!(interface_names(&unprofiled, "wg-{profile}"), Err(CliError::MissingProfile(_))));
+    assert_eq!(interface_names(&unprofiled, "wg-{user}").unwrap()[Path::new("/etc/wireguard/dave.conf")], "wg-dave");
+    assert!(matches!(interface_names(&unprofiled, "wg-{name}"), Err(CliError::Template(_))));
+  }
+}
+
@@ -0,0 +1,162 @@
+// template.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use thiserror::Error;
+
+/// Define the errors raised while parsing an output template.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TemplateError {
+  #[error("Unknown placeholder {{{field}}}, valid fields: {}", .valid.join(", "))]
+  UnknownField { field: String, valid: Vec<&'static str> },
+  #[error("Unclosed placeholder at character {0}, write {{{{ for a literal brace")]
+  Unclosed(usize),
+  #[error("Unmatched }} at character {0}, write }}}} for a literal brace")]
+  Unmatched(usize),
+}
+
+/// Define a part of a template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+  /// Text printed as it is.
+  Text(String),
+  /// A placeholder, by index in the fields of the template.
+  Field(usize),
+}
+
+/// Define a template shaping each item of a command output, e.g.
+/// `{name}\t{up}`.
+///
+/// A placeholder is a field name in braces, `{{` and `}}` are literal braces,
+/// and `\t`, `\n` and `\\` are a tab, a line break and a backslash, since a
+/// shell passes them as they are.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+  /// The parts of the template, in order.
+  parts: Vec<Part>,
+}
+
+/// Implements the logic of the output templates
+impl Template {
+  /// Function to parse a template.
+  ///
+  /// # Arguments
+  /// * `text`: The template.
+  /// * `fields`: The names of the fields the placeholders may refer to.
+  ///
+  /// # Returns
+  /// * `Result<Template, TemplateError>`: The template, or the error raised
+  ///   for an unknown field or an unbalanced brace.
+  pub fn parse(text: &str, fields: &[&'static str]) -> Result<Self, TemplateError> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = text.chars().enumerate().peekable();
+    while let Some((position, c)) = chars.next() {
+      match c {
+        '{' if chars.next_if(|(_, next)| *next == '{').is_some() => literal.push('{'),
+        '}' if chars.next_if(|(_, next)| *next == '}').is_some() => literal.push('}'),
+        '{' => {
+          let mut field = String::new();
+          loop {
+            match chars.next() {
+              Some((_, '}')) => break,
+              Some((_, c)) => field.push(c),
+              None => return Err(TemplateError::Unclosed(position)),
+            }
+          }
+          let Some(index) = fields.iter().position(|known| *known == field.trim()) else {
+            return Err(TemplateError::UnknownField {
+              field,
+              valid: fields.to_vec(),
+            });
+          };
+          if !literal.is_empty() {
+            parts.push(Part::Text(std::mem::take(&mut literal)));
+          }
+          parts.push(Part::Field(index));
+        }
+        '}' => return Err(TemplateError::Unmatched(position)),
+        '\\' => match chars.next_if(|(_, next)| matches!(next, 't' | 'n' | '\\')) {
+          Some((_, 't')) => literal.push('\t'),
+          Some((_, 'n')) => literal.push('\n'),
+          Some(_) | None => literal.push('\\'),
+        },
+        c => literal.push(c),
+      }
+    }
+    if !literal.is_empty() {
+      parts.push(Part::Text(literal));
+    }
+    Ok(Template { parts })
+  }
+
+  /// Function to tell whether a placeholder of the template refers to a
+  /// field.
+  ///
+  /// # Arguments
+  /// * `field`: The index of the field, in the fields the template was
+  ///   parsed with.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the field is used.
+  pub fn uses(&self, field: usize) -> bool {
+    self.parts.contains(&Part::Field(field))
+  }
+
+  /// Function to render an item.
+  ///
+  /// # Arguments
+  /// * `values`: The values of the fields of the item, in the order of the
+  ///   fields the template was parsed with.
+  ///
+  /// # Returns
+  /// * `String`: The rendered item, without a trailing line break.
+  pub fn render(&self, values: &[String]) -> String {
+    let mut rendered = String::new();
+    for part in &self.parts {
+      match part {
+        Part::Text(text) => rendered.push_str(text),
+        Part::Field(index) => rendered.push_str(values.get(*index).map_or("", String::as_str)),
+      }
+    }
+    rendered
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FIELDS: [&str; 3] = ["name", "up", "handshake"];
+
+  fn values(name: &str, up: &str, handshake: &str) -> Vec<String> {
+    vec![name.to_string(), up.to_string(), handshake.to_string()]
+  }
+
+  #[test]
+  fn templates_are_rendered_per_item() {
+    let template = Template::parse(r"{name}\t{up}\t{handshake}", &FIELDS).unwrap();
+    assert_eq!(template.render(&values("wg0", "true", "1m 40s ago")), "wg0\ttrue\t1m 40s ago");
+    assert_eq!(template.render(&values("wg1", "false", "never")), "wg1\tfalse\tnever");
+
+    let template = Template::parse(r"{{{ name }}} is {up}\n\\", &FIELDS).unwrap();
+    assert_eq!(template.render(&values("wg0", "true", "")), "{wg0} is true\n\\");
+    assert_eq!(Template::parse(r"C:\wg", &FIELDS).unwrap().render(&[]), r"C:\wg");
+  }
+
+  #[test]
+  fn invalid_placeholders_are_rejected() {
+    let error = Template::parse("{name} {rx}", &FIELDS).unwrap_err();
+    assert_eq!(
+      error,
+      TemplateError::UnknownField {
+        field: "rx".to_string(),
+        valid: FIELDS.to_vec()
+      }
+    );
+    assert_eq!(error.to_string(), "Unknown placeholder {rx}, valid fields: name, up, handshake");
+    assert_eq!(Template::parse("{name", &FIELDS), Err(TemplateError::Unclosed(0)));
+    assert_eq!(Template::parse("name}", &FIELDS), Err(TemplateError::Unmatched(4)));
+  }
+}
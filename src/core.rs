@@ -3,4 +3,26 @@
 // This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
 // See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
 
+pub mod audit;
+pub mod bench;
+pub mod clock;
+pub mod color;
+pub mod config;
+pub mod crypto;
+pub mod dns;
+pub mod grep;
+pub mod lint;
+pub mod lock;
 pub mod logger;
+pub mod monitor;
+pub mod mtu;
+pub mod netwatch;
+pub mod otp;
+pub mod pool;
+pub mod session;
+pub mod snapshot;
+#[cfg(test)]
+pub mod testing;
+pub mod update;
+pub mod wg;
+pub mod wipe;
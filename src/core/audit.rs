@@ -0,0 +1,286 @@
+// audit.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use chrono::Local;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use thiserror::Error;
+
+/// Hash used as the previous link of the first entry of the chain.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Define the errors raised while writing or verifying the audit log.
+#[derive(Debug, Error)]
+pub enum AuditError {
+  #[error("Failed to access audit log: {0}")]
+  Io(#[from] io::Error),
+  #[error("Audit log line {0} is malformed")]
+  Malformed(usize),
+  #[error("Audit log hash chain is broken at line {0}")]
+  BrokenChain(usize),
+  #[error("Audit log already initialized")]
+  AlreadyInitialized,
+}
+
+/// Define a struct to be used for writing the tamper-evident audit log.
+///
+/// Every entry is a tab-separated line holding the timestamp, the operator,
+/// the operation, its details, the hash of the previous entry and the hash
+/// of the entry itself. Altering, removing or reordering a line breaks the
+/// chain, which `verify` detects.
+#[derive(Debug)]
+pub struct AuditLog {
+  state: Mutex<AuditState>,
+}
+
+/// Define the mutable state of the audit log, guarded by a mutex so that
+/// concurrent entries are chained in the order they are written.
+#[derive(Debug)]
+struct AuditState {
+  file: File,
+  last_hash: String,
+}
+
+/// Define a variable to enable the Singleton pattern.
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// Implements the logic to write the audit log
+#[allow(dead_code)]
+impl AuditLog {
+  /// Function to initialize the audit log and set the AUDIT_LOG singleton.
+  ///
+  /// The file is opened in append mode. If it already holds entries, the
+  /// chain is resumed from the hash of the last one.
+  ///
+  /// # Arguments
+  /// * `audit_file`: The path to the audit log file.
+  ///
+  /// # Returns
+  /// * `Result<(), AuditError>`: The error, if any, raised while opening the
+  ///   file or because the audit log is already initialized.
+  pub fn init(audit_file: &Path) -> Result<(), AuditError> {
+    if AUDIT_LOG.get().is_some() {
+      return Err(AuditError::AlreadyInitialized);
+    }
+    let audit = Self::open(audit_file)?;
+    AUDIT_LOG.set(audit).map_err(|_| AuditError::AlreadyInitialized)
+  }
+
+  /// Function to open the audit log, resuming the chain of the entries it
+  /// already holds.
+  ///
+  /// # Arguments
+  /// * `audit_file`: The path to the audit log file.
+  ///
+  /// # Returns
+  /// * `Result<AuditLog, AuditError>`: The audit log, or the error raised
+  ///   while opening the file.
+  fn open(audit_file: &Path) -> Result<AuditLog, AuditError> {
+    let last_hash = match File::open(audit_file) {
+      Ok(file) => last_hash(file)?,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => GENESIS_HASH.to_string(),
+      Err(e) => return Err(e.into()),
+    };
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_file)?;
+
+    Ok(AuditLog {
+      state: Mutex::new(AuditState { file, last_hash }),
+    })
+  }
+
+  /// Retrieves a reference to the initialized `AuditLog` instance, if any.
+  ///
+  /// # Returns
+  /// * `Option<&'static AuditLog>`: The singleton `AuditLog`, or `None` when
+  ///   auditing has not been enabled.
+  pub fn get() -> Option<&'static AuditLog> {
+    AUDIT_LOG.get()
+  }
+
+  /// Function to record a privileged operation.
+  ///
+  /// The entry is written synchronously, so that it is on disk before the
+  /// operation it describes takes place.
+  ///
+  /// # Arguments
+  /// * `operation`: The operation performed (e.g., "connect", "route").
+  /// * `details`: What the operation acted on (e.g., the config path).
+  ///
+  /// # Returns
+  /// * `Result<(), AuditError>`: The error, if any, raised while writing.
+  pub fn record(&self, operation: &str, details: &str) -> Result<(), AuditError> {
+    let mut guard = self.state.lock().unwrap_or_else(|e| e.into_inner());
+    let state = &mut *guard;
+    let timestamp = Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string();
+    let record = [
+      timestamp.as_str(),
+      &sanitize(&operator()),
+      &sanitize(operation),
+      &sanitize(details),
+    ]
+    .join("\t");
+    let hash = chain_hash(&state.last_hash, &record);
+
+    writeln!(state.file, "{record}\t{}\t{hash}", state.last_hash)?;
+    state.file.sync_data()?;
+    state.last_hash = hash;
+    Ok(())
+  }
+}
+
+/// Function to verify the hash chain of an audit log file.
+///
+/// # Arguments
+/// * `audit_file`: The path to the audit log file.
+///
+/// # Returns
+/// * `Result<usize, AuditError>`: The number of valid entries, or the line at
+///   which the file is malformed or the chain is broken.
+#[allow(dead_code)]
+pub fn verify(audit_file: &Path) -> Result<usize, AuditError> {
+  let reader = BufReader::new(File::open(audit_file)?);
+  let mut expected_prev = GENESIS_HASH.to_string();
+  let mut count = 0;
+
+  for (index, line) in reader.lines().enumerate() {
+    let line = line?;
+    let line_number = index + 1;
+    let (record, prev, hash) = split_entry(&line).ok_or(AuditError::Malformed(line_number))?;
+    if prev != expected_prev || chain_hash(prev, record) != hash {
+      return Err(AuditError::BrokenChain(line_number));
+    }
+    expected_prev = hash.to_string();
+    count += 1;
+  }
+  Ok(count)
+}
+
+/// Function to retrieve the hash of the last entry of an audit log.
+///
+/// # Arguments
+/// * `file`: The audit log file, opened for reading.
+///
+/// # Returns
+/// * `Result<String, AuditError>`: The hash of the last entry, or the genesis
+///   hash for an empty file.
+fn last_hash(file: File) -> Result<String, AuditError> {
+  let mut last = GENESIS_HASH.to_string();
+  for (index, line) in BufReader::new(file).lines().enumerate() {
+    let line = line?;
+    let (_, _, hash) = split_entry(&line).ok_or(AuditError::Malformed(index + 1))?;
+    last = hash.to_string();
+  }
+  Ok(last)
+}
+
+/// Function to split an entry into its record, previous hash and own hash.
+///
+/// # Arguments
+/// * `line`: The entry line.
+///
+/// # Returns
+/// * `Option<(&str, &str, &str)>`: The three parts, or `None` when malformed.
+fn split_entry(line: &str) -> Option<(&str, &str, &str)> {
+  let (rest, hash) = line.rsplit_once('\t')?;
+  let (record, prev) = rest.rsplit_once('\t')?;
+  Some((record, prev, hash))
+}
+
+/// Function to compute the hash linking a record to the previous entry.
+///
+/// # Arguments
+/// * `prev`: The hash of the previous entry.
+/// * `record`: The record of the current entry.
+///
+/// # Returns
+/// * `String`: The hex-encoded SHA-256 of the previous hash and the record.
+fn chain_hash(prev: &str, record: &str) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(prev.as_bytes());
+  hasher.update(b"\t");
+  hasher.update(record.as_bytes());
+  format!("{:x}", hasher.finalize())
+}
+
+/// Function to retrieve the name of the user running the operation.
+///
+/// When running through sudo the invoking user is reported, since that is
+/// the person accountable for the operation.
+///
+/// # Returns
+/// * `String`: The operator name, or "unknown" when it cannot be determined.
+fn operator() -> String {
+  std::env::var("SUDO_USER")
+      .or_else(|_| std::env::var("USER"))
+      .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Function to strip field and line separators from a record field.
+///
+/// # Arguments
+/// * `field`: The field to be sanitized.
+///
+/// # Returns
+/// * `String`: The field with tabs and line breaks replaced by spaces.
+fn sanitize(field: &str) -> String {
+  field.replace(['\t', '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::temp_dir;
+  use std::fs;
+
+  #[test]
+  fn init_twice_is_an_error() {
+    let temp = temp_dir();
+    let path = temp.path().join("audit.log");
+    let _ = AuditLog::init(&path);
+    assert!(matches!(AuditLog::init(&path), Err(AuditError::AlreadyInitialized)));
+    assert!(AuditLog::get().is_some());
+  }
+
+  #[test]
+  fn recorded_entries_verify_and_resume_the_chain() {
+    let temp = temp_dir();
+    let path = temp.path().join("audit.log");
+    let audit = AuditLog::open(&path).unwrap();
+    audit.record("connect", "/etc/wireguard/wg0.conf").unwrap();
+    audit.record("disconnect", "/etc/wireguard/wg0.conf").unwrap();
+    drop(audit);
+
+    let audit = AuditLog::open(&path).unwrap();
+    audit.record("connect", "tab\tand\nnewline").unwrap();
+    assert_eq!(verify(&path).unwrap(), 3);
+  }
+
+  #[test]
+  fn altered_entries_break_the_chain() {
+    let temp = temp_dir();
+    let path = temp.path().join("audit.log");
+    let audit = AuditLog::open(&path).unwrap();
+    for operation in ["connect", "disconnect", "connect"] {
+      audit.record(operation, "wg0").unwrap();
+    }
+    let content = fs::read_to_string(&path).unwrap();
+
+    fs::write(&path, content.replacen("disconnect", "connect", 1)).unwrap();
+    assert!(matches!(verify(&path), Err(AuditError::BrokenChain(2))));
+
+    let lines: Vec<&str> = content.lines().collect();
+    fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+    assert!(matches!(verify(&path), Err(AuditError::BrokenChain(2))));
+
+    fs::write(&path, "not an entry\n").unwrap();
+    assert!(matches!(verify(&path), Err(AuditError::Malformed(1))));
+  }
+}
@@ -0,0 +1,248 @@
+// bench.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::logger::wgb_debug;
+use super::wg::CommandRunner;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use thiserror::Error;
+
+/// The program measuring the throughput to an iperf3 server.
+const IPERF: &str = "iperf3";
+
+/// The program measuring the throughput of an HTTP download.
+const CURL: &str = "curl";
+
+/// Define the errors raised while measuring the throughput of a tunnel.
+#[derive(Debug, Error)]
+pub enum BenchError {
+  #[error("{0} is not installed, install it or pick the other benchmark")]
+  NotInstalled(&'static str),
+  #[error("Failed to run {0}: {1}")]
+  Spawn(&'static str, #[source] io::Error),
+  #[error("`{command}` failed ({status}): {reason}")]
+  Failed { command: String, status: String, reason: String },
+  #[error("Unexpected output from {program}: {reason}")]
+  UnexpectedOutput { program: &'static str, reason: String },
+}
+
+/// Define how the throughput through a tunnel is measured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Benchmark {
+  /// A TCP test against an iperf3 server reached through the tunnel.
+  Iperf {
+    /// The host of the iperf3 server.
+    server: String,
+    /// The seconds the test lasts.
+    seconds: u64,
+  },
+  /// A download from an HTTP server reached through the tunnel.
+  Download {
+    /// The URL of the file downloaded, discarded afterwards.
+    url: String,
+  },
+}
+
+/// Define a throughput, in bits per second.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Throughput(pub f64);
+
+impl fmt::Display for Throughput {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let units = ["bit/s", "kbit/s", "Mbit/s", "Gbit/s"];
+    let mut value = self.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < units.len() - 1 {
+      value /= 1000.0;
+      unit += 1;
+    }
+    write!(f, "{value:.1} {}", units[unit])
+  }
+}
+
+/// Implements the logic to measure the throughput
+impl Benchmark {
+  /// Function to run the benchmark through the given runner.
+  ///
+  /// # Arguments
+  /// * `runner`: The runner of `iperf3` or `curl`.
+  ///
+  /// # Returns
+  /// * `Result<Throughput, BenchError>`: The achieved throughput, or the
+  ///   error raised because the program is missing, failed or printed an
+  ///   unexpected output.
+  pub fn run_with(&self, runner: &dyn CommandRunner) -> Result<Throughput, BenchError> {
+    match self {
+      Benchmark::Iperf { server, seconds } => {
+        let seconds = seconds.to_string();
+        let args = ["--client", server, "--time", &seconds, "--json"];
+        parse_iperf(&run(runner, IPERF, &args)?)
+      }
+      Benchmark::Download { url } => {
+        let args = ["--fail", "--silent", "--show-error", "--location", "--output", "/dev/null"];
+        let args: Vec<&str> = args.into_iter().chain(["--write-out", "%{speed_download}", "--", url]).collect();
+        parse_download(&run(runner, CURL, &args)?)
+      }
+    }
+  }
+}
+
+/// Function to run a benchmark program and capture its output.
+///
+/// # Arguments
+/// * `runner`: The runner of the program.
+/// * `program`: The program to be run.
+/// * `args`: The arguments of the program.
+///
+/// # Returns
+/// * `Result<String, BenchError>`: The stdout of the program, or the error
+///   raised because it is missing or failed.
+fn run(runner: &dyn CommandRunner, program: &'static str, args: &[&str]) -> Result<String, BenchError> {
+  let command = args.iter().fold(program.to_string(), |command, arg| format!("{command} {arg}"));
+  wgb_debug!("Running {command}");
+  let args: Vec<&OsStr> = args.iter().map(OsStr::new).collect();
+  let output = runner.run(program, &args).map_err(|e| match e.kind() {
+    io::ErrorKind::NotFound => BenchError::NotInstalled(program),
+    _ => BenchError::Spawn(program, e),
+  })?;
+  let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+  if output.status.success() {
+    return Ok(stdout);
+  }
+  // iperf3 reports its errors in the JSON output
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  let json_error = serde_json::from_str::<serde_json::Value>(&stdout)
+      .ok()
+      .and_then(|report| report["error"].as_str().map(str::to_string));
+  let reason = json_error
+      .or_else(|| stderr.lines().rev().find(|line| !line.trim().is_empty()).map(str::to_string))
+      .unwrap_or_else(|| "no error message".to_string());
+  Err(BenchError::Failed {
+    command,
+    status: output.status.to_string(),
+    reason,
+  })
+}
+
+/// Function to read the throughput from the JSON report of `iperf3`.
+///
+/// # Arguments
+/// * `output`: The output of `iperf3 --json`.
+///
+/// # Returns
+/// * `Result<Throughput, BenchError>`: The throughput received by the server,
+///   or the error raised because the report is not as expected.
+pub fn parse_iperf(output: &str) -> Result<Throughput, BenchError> {
+  let unexpected = |reason: &str| BenchError::UnexpectedOutput {
+    program: IPERF,
+    reason: reason.to_string(),
+  };
+  let report: serde_json::Value = serde_json::from_str(output).map_err(|e| unexpected(&e.to_string()))?;
+  if let Some(error) = report["error"].as_str() {
+    return Err(unexpected(error));
+  }
+  // A TCP test reports what the server received, a UDP one its sum
+  let end = &report["end"];
+  end["sum_received"]["bits_per_second"]
+      .as_f64()
+      .or_else(|| end["sum"]["bits_per_second"].as_f64())
+      .map(Throughput)
+      .ok_or_else(|| unexpected("no bits_per_second in the summary"))
+}
+
+/// Function to read the throughput from the `--write-out %{speed_download}`
+/// output of `curl`.
+///
+/// # Arguments
+/// * `output`: The output of `curl`, the average speed in bytes per second.
+///
+/// # Returns
+/// * `Result<Throughput, BenchError>`: The throughput, or the error raised
+///   because the output is not a number.
+pub fn parse_download(output: &str) -> Result<Throughput, BenchError> {
+  let bytes_per_second: f64 = output.trim().parse().map_err(|_| BenchError::UnexpectedOutput {
+    program: CURL,
+    reason: format!("{:?} is not a speed", output.trim()),
+  })?;
+  Ok(Throughput(bytes_per_second * 8.0))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::init_logger;
+  use std::os::unix::process::ExitStatusExt;
+  use std::process::{ExitStatus, Output};
+
+  /// The summary of a TCP `iperf3 --json` run, trimmed to what is read.
+  const IPERF_REPORT: &str = r#"{
+    "start": {"connecting_to": {"host": "10.0.0.1", "port": 5201}},
+    "intervals": [],
+    "end": {
+      "sum_sent": {"seconds": 10.0, "bytes": 117964800, "bits_per_second": 94371840.0},
+      "sum_received": {"seconds": 10.04, "bytes": 116391936, "bits_per_second": 92741566.9}
+    }
+  }"#;
+
+  /// Define a runner answering every program with the same exit code and
+  /// output, or as if it was not installed when there is none.
+  #[derive(Debug)]
+  struct CannedRunner(Option<(i32, &'static str)>);
+
+  impl CommandRunner for CannedRunner {
+    fn run(&self, _program: &str, _args: &[&OsStr]) -> io::Result<Output> {
+      let (code, stdout) = self.0.ok_or(io::ErrorKind::NotFound)?;
+      Ok(Output {
+        status: ExitStatus::from_raw(code << 8),
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: Vec::new(),
+      })
+    }
+  }
+
+  #[test]
+  fn iperf_reports_give_the_received_throughput() {
+    let throughput = parse_iperf(IPERF_REPORT).unwrap();
+    assert_eq!(throughput, Throughput(92741566.9));
+    assert_eq!(throughput.to_string(), "92.7 Mbit/s");
+    let udp = r#"{"end": {"sum": {"bits_per_second": 1048576.0, "lost_percent": 0.1}}}"#;
+    assert_eq!(parse_iperf(udp).unwrap().to_string(), "1.0 Mbit/s");
+
+    let error = parse_iperf(r#"{"error": "unable to connect to server: Connection refused"}"#).unwrap_err();
+    assert_eq!(error.to_string(), "Unexpected output from iperf3: unable to connect to server: Connection refused");
+    assert!(parse_iperf("iperf3: error").is_err());
+  }
+
+  #[test]
+  fn download_speeds_are_converted_to_bits() {
+    assert_eq!(parse_download("1250000.000\n").unwrap(), Throughput(10_000_000.0));
+    assert_eq!(Throughput(2.5e9).to_string(), "2.5 Gbit/s");
+    assert_eq!(Throughput(512.0).to_string(), "512.0 bit/s");
+    assert!(matches!(parse_download("fast"), Err(BenchError::UnexpectedOutput { program: CURL, .. })));
+  }
+
+  #[test]
+  fn missing_or_failing_programs_are_reported() {
+    init_logger();
+    let iperf = Benchmark::Iperf {
+      server: "10.0.0.1".to_string(),
+      seconds: 5,
+    };
+    let missing = iperf.run_with(&CannedRunner(None)).unwrap_err();
+    assert_eq!(missing.to_string(), "iperf3 is not installed, install it or pick the other benchmark");
+
+    let refused = r#"{"error": "unable to connect to server: Connection refused"}"#;
+    let failed = iperf.run_with(&CannedRunner(Some((1, refused)))).unwrap_err();
+    assert!(failed.to_string().ends_with("unable to connect to server: Connection refused"), "{failed}");
+    assert!(matches!(failed, BenchError::Failed { command, .. } if command == "iperf3 --client 10.0.0.1 --time 5 --json"));
+
+    let download = Benchmark::Download {
+      url: "http://10.0.0.1/1G.bin".to_string(),
+    };
+    let throughput = download.run_with(&CannedRunner(Some((0, "125000")))).unwrap();
+    assert_eq!(throughput.to_string(), "1.0 Mbit/s");
+  }
+}
@@ -0,0 +1,100 @@
+// clock.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use chrono::{DateTime, Local};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Define a source of the current time.
+///
+/// Code depending on the wall clock takes a `Clock` instead of calling
+/// `Local::now` directly, so that time can be controlled deterministically.
+pub trait Clock: Debug + Send + Sync {
+  /// Function to retrieve the current local time.
+  ///
+  /// # Returns
+  /// * `DateTime<Local>`: The current local time.
+  fn now(&self) -> DateTime<Local>;
+}
+
+/// Define the clock backed by the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> DateTime<Local> {
+    Local::now()
+  }
+}
+
+/// Define a clock stopped at a given time, e.g. for the commands computing a
+/// one-time password at the time given with `--at`.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub DateTime<Local>);
+
+impl Clock for FixedClock {
+  fn now(&self) -> DateTime<Local> {
+    self.0
+  }
+}
+
+/// Define a point in time after which an operation must be aborted, e.g. to
+/// bound the total duration of a command.
+#[derive(Clone, Debug)]
+pub struct Deadline {
+  /// The time the operation must be over by.
+  at: DateTime<Local>,
+  /// The clock the remaining time is measured with.
+  clock: Arc<dyn Clock>,
+}
+
+/// Implements the logic to measure the time left before a deadline
+#[allow(dead_code)]
+impl Deadline {
+  /// Function to create a deadline a given time from now.
+  ///
+  /// # Arguments
+  /// * `budget`: The time the operation may take.
+  /// * `clock`: The clock the remaining time is measured with.
+  ///
+  /// # Returns
+  /// * `Deadline`: The deadline, capped at a century from now.
+  pub fn after(budget: Duration, clock: Arc<dyn Clock>) -> Self {
+    let now = clock.now();
+    let century = chrono::Duration::days(36_525);
+    let budget = chrono::Duration::from_std(budget).map_or(century, |budget| budget.min(century));
+    let at = now + budget;
+    Deadline { at, clock }
+  }
+
+  /// Function to retrieve the time left before the deadline.
+  ///
+  /// # Returns
+  /// * `Option<Duration>`: The time left, or `None` once the deadline has
+  ///   passed.
+  pub fn remaining(&self) -> Option<Duration> {
+    (self.at - self.clock.now()).to_std().ok().filter(|remaining| !remaining.is_zero())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::ManualClock;
+
+  #[test]
+  fn deadline_expires_with_the_clock() {
+    let clock = ManualClock::at(Local::now());
+    let deadline = Deadline::after(Duration::from_secs(10), clock.clone());
+    assert_eq!(deadline.remaining(), Some(Duration::from_secs(10)));
+
+    clock.advance(chrono::Duration::seconds(4));
+    assert_eq!(deadline.remaining(), Some(Duration::from_secs(6)));
+    clock.advance(chrono::Duration::seconds(6));
+    assert_eq!(deadline.remaining(), None);
+    assert!(Deadline::after(Duration::MAX, clock).remaining().is_some());
+  }
+}
@@ -0,0 +1,136 @@
+// color.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use std::ffi::OsStr;
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+
+/// Environment variable disabling colors when set to a non-empty value,
+/// following the <https://no-color.org> convention.
+pub const NO_COLOR_ENV: &str = "NO_COLOR";
+
+/// Environment variable enabling colors when set to a value other than `0`,
+/// even when the output is not a terminal (e.g. piped to a pager).
+pub const FORCE_COLOR_ENV: &str = "CLICOLOR_FORCE";
+
+/// Define the colors used in the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+  Red,
+  Green,
+  Yellow,
+  Dim,
+}
+
+/// Define the decision taken once for the whole process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Choice {
+  /// Whether colors may be used at all.
+  allowed: bool,
+  /// Whether colors are used even when the output is not a terminal.
+  forced: bool,
+}
+
+/// Define a variable holding the color decision of the process.
+static CHOICE: OnceLock<Choice> = OnceLock::new();
+
+/// Function to decide, once for the whole process, whether colors are used.
+///
+/// `--no-color` and `NO_COLOR` disable colors everywhere, taking precedence
+/// over `CLICOLOR_FORCE`. Calls after the first one are ignored.
+///
+/// # Arguments
+/// * `no_color`: Whether `--no-color` was given.
+pub fn init(no_color: bool) {
+  let _ = CHOICE.set(choose(
+    no_color,
+    std::env::var_os(NO_COLOR_ENV).as_deref(),
+    std::env::var_os(FORCE_COLOR_ENV).as_deref(),
+  ));
+}
+
+/// Function to tell whether colors may be used, regardless of the output.
+///
+/// # Returns
+/// * `bool`: Whether colors are allowed, `true` before `init`.
+pub fn allowed() -> bool {
+  CHOICE.get().is_none_or(|choice| choice.allowed)
+}
+
+/// Function to tell whether the output printed to stdout is colored.
+///
+/// # Returns
+/// * `bool`: Whether colors are allowed and stdout is a terminal, or colors
+///   are forced.
+pub fn stdout() -> bool {
+  let choice = CHOICE.get().copied().unwrap_or(Choice {
+    allowed: true,
+    forced: false,
+  });
+  choice.allowed && (choice.forced || io::stdout().is_terminal())
+}
+
+/// Function to wrap a text in an ANSI color.
+///
+/// # Arguments
+/// * `text`: The text to be colored.
+/// * `color`: The color.
+/// * `enabled`: Whether colors are used; the text is returned as it is
+///   otherwise.
+///
+/// # Returns
+/// * `String`: The colored text.
+pub fn paint(text: &str, color: Color, enabled: bool) -> String {
+  if !enabled {
+    return text.to_string();
+  }
+  let code = match color {
+    Color::Red => "31",
+    Color::Green => "32",
+    Color::Yellow => "33",
+    Color::Dim => "2",
+  };
+  format!("\x1b[{code}m{text}\x1b[0m")
+}
+
+/// Function to take the color decision from the flag and the environment.
+///
+/// # Arguments
+/// * `no_color`: Whether `--no-color` was given.
+/// * `no_color_env`: The value of `NO_COLOR`, if set.
+/// * `force_env`: The value of `CLICOLOR_FORCE`, if set.
+///
+/// # Returns
+/// * `Choice`: Whether colors are allowed and whether they are forced.
+fn choose(no_color: bool, no_color_env: Option<&OsStr>, force_env: Option<&OsStr>) -> Choice {
+  let allowed = !no_color && no_color_env.is_none_or(OsStr::is_empty);
+  let forced = force_env.is_some_and(|value| !value.is_empty() && value != "0");
+  Choice { allowed, forced }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn no_color_takes_precedence() {
+    let choice = |no_color, env: Option<&str>, force: Option<&str>| {
+      choose(no_color, env.map(OsStr::new), force.map(OsStr::new))
+    };
+    assert!(choice(false, None, None).allowed);
+    assert!(choice(false, Some(""), None).allowed);
+    assert!(!choice(true, None, None).allowed);
+    assert!(!choice(false, Some("1"), None).allowed);
+    assert!(!choice(false, Some("1"), Some("1")).allowed);
+    assert!(choice(false, None, Some("1")).forced);
+    assert!(!choice(false, None, Some("0")).forced);
+  }
+
+  #[test]
+  fn paint_only_when_enabled() {
+    assert_eq!(paint("up", Color::Green, true), "\x1b[32mup\x1b[0m");
+    assert_eq!(paint("up", Color::Green, false), "up");
+  }
+}
@@ -0,0 +1,2194 @@
+// config.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::crypto::{self, CryptoError};
+use super::dns::DnsBackend;
+use super::logger::{LogFilePermissions, wgb_error, wgb_info, wgb_warn};
+use super::netwatch::TrustedNetwork;
+use super::wg::{self, AddressFamily, AddressPool, Decryptors};
+use super::wipe::WipeGuard;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Name of the configuration file, located in the user's home directory.
+pub const CONFIG_FILE_NAME: &str = ".wgbconf.json";
+
+/// Name of the TOML configuration file, preferred to the JSON one when both
+/// exist in the user's home directory.
+pub const TOML_CONFIG_FILE_NAME: &str = ".wgbconf.toml";
+
+/// Directory of the configuration fragments, in the configuration directory
+/// of the user (`$XDG_CONFIG_HOME` or `~/.config`).
+const FRAGMENT_DIR: &str = "wgbridge/conf.d";
+
+/// Environment variable used to override the path of the configuration file.
+pub const CONFIG_ENV: &str = "WGB_CONFIG";
+
+/// Time without further changes after which a changed configuration file is
+/// reloaded, so that an editor saving in several steps triggers one reload.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Version of the configuration schema written by this release. Files
+/// without a `schema_version` are version 0, the `conf_path`/`confs` shape.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Issuer of the OTP URIs built from a bare secret, shown by authenticator
+/// apps next to the label.
+const OTP_ISSUER: &str = "WG-Bridge";
+
+/// Define the errors raised while loading, saving or changing the configuration.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+  #[error("Failed to access configuration file: {0}")]
+  Io(#[from] io::Error),
+  #[error("Invalid configuration file: {0}")]
+  Parse(#[from] serde_json::Error),
+  #[error("Invalid configuration file: {0}")]
+  ParseToml(#[from] toml::de::Error),
+  #[error("Failed to serialize configuration: {0}")]
+  SerializeToml(#[from] toml::ser::Error),
+  #[error("Unable to determine the home directory")]
+  NoHomeDir,
+  #[error("A user with config path {0} already exists")]
+  DuplicateUser(String),
+  #[error("Path {0} is already configured")]
+  DuplicatePath(String),
+  #[error("Configuration already initialized")]
+  AlreadyInitialized,
+  #[error("Configuration schema version {0} is newer than the supported one")]
+  UnsupportedVersion(u64),
+  #[error("Encrypted otp_uri of {0}: {1}")]
+  Secret(String, #[source] CryptoError),
+  #[error("Configuration already unlocked")]
+  AlreadyUnlocked,
+  #[error("Configuration not initialized")]
+  NotInitialized,
+  #[error("Failed to watch configuration file: {0}")]
+  Watch(#[from] notify::Error),
+  #[error("Undefined environment variable {0} in {1}")]
+  UndefinedVariable(String, String),
+  #[error("Invalid log file mode {0}, expected octal permission bits (e.g. 0640)")]
+  InvalidLogMode(String),
+  #[error("Unknown configuration format {0}, expected json or toml")]
+  UnknownFormat(String),
+  #[error("Invalid tag {0:?}, expected a non-empty word without spaces or commas")]
+  InvalidTag(String),
+  #[error("Invalid configuration fragment {}: {source}", .path.display())]
+  Fragment { path: PathBuf, source: serde_json::Error },
+  #[error("{config_path} is defined twice in the configuration fragment {}", .path.display())]
+  DuplicateInFragment { config_path: String, path: PathBuf },
+  #[error("Interface {interface} is defined by both {first} and {second}")]
+  ConflictingInterface {
+    interface: String,
+    first: String,
+    second: String,
+  },
+  #[error("{config_path} is defined by the configuration fragment {}, edit it there", .path.display())]
+  FragmentUser { config_path: String, path: PathBuf },
+}
+
+/// Define the formats a configuration file can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+  Json,
+  Toml,
+}
+
+/// Define the problems found while validating the configuration. Users are
+/// numbered from 1, in the order they appear in the file.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum ValidationError {
+  #[error("User {0}: config_path is empty")]
+  EmptyConfigPath(usize),
+  #[error("User {index}: config file {path} does not exist")]
+  MissingConfigFile { index: usize, path: String },
+  #[error("User {index}: {reason}")]
+  UnresolvedConfigPath { index: usize, reason: String },
+  #[error("User {index}: invalid otp_uri: {reason}")]
+  InvalidOtpUri { index: usize, reason: String },
+  #[error("User {index}: invalid tag {tag:?}")]
+  InvalidTag { index: usize, tag: String },
+}
+
+/// Define the configuration of WG-Bridge, stored in `~/.wgbconf.json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+  /// Version of the schema of the file, used to migrate older files.
+  pub schema_version: u64,
+  /// Path to the tamper-evident audit log of the privileged operations (see
+  /// `AuditLog`), expanded like `config_path`. `None` disables auditing.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub audit_log: Option<String>,
+  /// The address family endpoint hostnames are resolved to on connect, when
+  /// they have both IPv4 and IPv6 addresses. `None` leaves it to `wg`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub endpoint_family: Option<AddressFamily>,
+  /// The backend given the DNS of the tunnels, `auto` detecting the one
+  /// installed. `None` leaves it to the `resolvconf` run by `wg-quick`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub dns_backend: Option<DnsBackend>,
+  /// The network `add-user --assign-address` picks the `Address` of a
+  /// configuration from (e.g. `10.9.0.0/24`).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub address_pool: Option<String>,
+  /// The template the interfaces are named after instead of their files
+  /// (e.g. `wg-{profile}-{index}`). `None` names them after the files.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub interface_template: Option<String>,
+  /// The directories where WireGuard configuration files are saved, as
+  /// written by the user (i.e. before `expand_path`). Plain values come
+  /// before `user`, since TOML requires them ahead of the arrays of tables.
+  #[serde(default)]
+  pub paths: Vec<String>,
+  /// The WireGuard configurations managed by WG-Bridge.
+  #[serde(default, deserialize_with = "deserialize_users")]
+  pub user: Vec<UserConfig>,
+  /// Ownership and permissions of the log file. Tables come after `user`,
+  /// since an empty `user` is written as a plain value.
+  #[serde(default, skip_serializing_if = "LogSettings::is_empty")]
+  pub log: LogSettings,
+  /// The commands decrypting the WireGuard configuration files encrypted
+  /// with age or sops. `None` refuses the encrypted files.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub decrypt: Option<Decryptors>,
+  /// The passphrase of the encrypted OTP URIs, kept while they are decrypted
+  /// in memory.
+  #[serde(skip)]
+  passphrase: Option<Passphrase>,
+  /// The configurations of the main file overridden by a fragment, written
+  /// back in their place on save.
+  #[serde(skip)]
+  shadowed: Vec<UserConfig>,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      schema_version: SCHEMA_VERSION,
+      audit_log: None,
+      endpoint_family: None,
+      dns_backend: None,
+      address_pool: None,
+      interface_template: None,
+      paths: Vec::new(),
+      user: Vec::new(),
+      log: LogSettings::default(),
+      decrypt: None,
+      passphrase: None,
+      shadowed: Vec::new(),
+    }
+  }
+}
+
+/// Define a passphrase held in memory, wiped when dropped and never printed.
+#[derive(Clone, PartialEq, Eq)]
+struct Passphrase(Zeroizing<String>);
+
+impl fmt::Debug for Passphrase {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("Passphrase(***)")
+  }
+}
+
+/// Define a configuration fragment, adding WireGuard configurations to the
+/// main file.
+#[derive(Debug, Deserialize)]
+struct Fragment {
+  /// The WireGuard configurations of the fragment.
+  #[serde(default, deserialize_with = "deserialize_users")]
+  user: Vec<UserConfig>,
+}
+
+/// Define the ownership and permissions of the log file, applied to the
+/// rotated files too. Unset values leave the file untouched.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogSettings {
+  /// Permission bits of the log file, in octal (e.g. "0640").
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub mode: Option<String>,
+  /// User id of the log file owner.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub owner: Option<u32>,
+  /// Group id of the log file group.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub group: Option<u32>,
+}
+
+/// Implements the logic of the log file settings
+impl LogSettings {
+  /// Function to tell whether no log file setting is given.
+  ///
+  /// # Returns
+  /// * `bool`: Whether every setting is unset.
+  pub fn is_empty(&self) -> bool {
+    *self == LogSettings::default()
+  }
+
+  /// Function to convert the settings to the permissions of the Logger.
+  ///
+  /// # Returns
+  /// * `Result<LogFilePermissions, ConfigError>`: The permissions, or the
+  ///   error raised when `mode` is not octal permission bits.
+  pub fn permissions(&self) -> Result<LogFilePermissions, ConfigError> {
+    let mode = match &self.mode {
+      Some(mode) => Some(parse_mode(mode)?),
+      None => None,
+    };
+    Ok(LogFilePermissions {
+      mode,
+      owner: self.owner,
+      group: self.group,
+    })
+  }
+}
+
+/// Define the settings of a single WireGuard configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserConfig {
+  /// Full path to the WireGuard configuration file.
+  #[serde(deserialize_with = "deserialize_config_path")]
+  pub config_path: String,
+  /// Whether connecting requires a one-time password.
+  #[serde(default)]
+  pub otp: bool,
+  /// The `otpauth://` URI used to generate the one-time passwords.
+  #[serde(default, deserialize_with = "deserialize_otp_uri")]
+  pub otp_uri: String,
+  /// Whether `otp_uri` is stored encrypted with a passphrase. It is then
+  /// only usable once the configuration is unlocked.
+  #[serde(default)]
+  pub otp_encrypted: bool,
+  /// The command handing the one-time password, given on its stdin, to the
+  /// gateway before the tunnel is brought up. It exits with 77 when the
+  /// gateway rejects the code.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub otp_command: Option<String>,
+  /// Times a rejected one-time password is retried with the code of the
+  /// next period. `None` uses the default of 2.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub otp_retries: Option<u32>,
+  /// Tags grouping the configuration with others (e.g. `work`), selected by
+  /// the `--tag` option of the commands acting on several of them.
+  #[serde(default, deserialize_with = "deserialize_tags", skip_serializing_if = "Vec::is_empty")]
+  pub tags: Vec<String>,
+  /// The environment the configuration belongs to (e.g. `staging`), for the
+  /// `{profile}` of the `interface_template`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub profile: Option<String>,
+  /// Seconds without a handshake after which the tunnel is stale, for links
+  /// with their own cadence. `None` uses the global `--stale-after`.
+  #[serde(default, deserialize_with = "deserialize_handshake_timeout", skip_serializing_if = "Option::is_none")]
+  pub handshake_timeout: Option<u64>,
+  /// Seconds `connect` waits for a handshake after bringing the tunnel up,
+  /// bringing it down again when none takes place. `None` does not wait.
+  #[serde(default, deserialize_with = "deserialize_connect_timeout", skip_serializing_if = "Option::is_none")]
+  pub connect_timeout: Option<u64>,
+  /// Whether `watch-network` reconnects the tunnel, when active, once the
+  /// network changes or the system resumes.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub reconnect_on_network_change: bool,
+  /// Whether the tunnel is frozen, e.g. while its file is being fixed:
+  /// `connect`, `rotate-keys`, `wg-set` and `watch-network` leave it alone
+  /// until it is unfrozen.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub frozen: bool,
+  /// The networks `connect --all` leaves the tunnel down on, e.g. the home
+  /// Wi-Fi, given by SSID, gateway MAC address or subnet.
+  #[serde(default, deserialize_with = "deserialize_trusted_networks", skip_serializing_if = "Vec::is_empty")]
+  pub trusted_networks: Vec<TrustedNetwork>,
+  /// The configuration fragment defining the configuration, `None` for the
+  /// main file. Configurations of fragments are never written back.
+  #[serde(skip)]
+  pub fragment: Option<PathBuf>,
+}
+
+/// Implements the logic of a single WireGuard configuration
+#[allow(dead_code)]
+impl UserConfig {
+  /// Function to resolve the path to the WireGuard configuration file.
+  ///
+  /// The path is expanded by `expand_path`. Every access to the WireGuard
+  /// configuration file goes through this function.
+  ///
+  /// # Returns
+  /// * `Result<PathBuf, ConfigError>`: The resolved path, or the error raised
+  ///   for an undefined variable or an unknown home directory.
+  pub fn resolved_path(&self) -> Result<PathBuf, ConfigError> {
+    expand_path(&self.config_path)
+  }
+
+  /// Function to describe where the configuration comes from, for the error
+  /// messages.
+  ///
+  /// # Returns
+  /// * `String`: The path, followed by the fragment defining it, if any.
+  pub fn origin(&self) -> String {
+    match &self.fragment {
+      Some(fragment) => format!("{} (from {})", self.config_path, fragment.display()),
+      None => self.config_path.clone(),
+    }
+  }
+
+  /// Function to tell whether the configuration has a tag.
+  ///
+  /// # Arguments
+  /// * `tag`: The tag, compared exactly.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the configuration has the tag.
+  pub fn has_tag(&self, tag: &str) -> bool {
+    self.tags.iter().any(|t| t == tag)
+  }
+
+  /// Function to add a tag to the configuration.
+  ///
+  /// The change is made in memory only; call `save` to persist it.
+  ///
+  /// # Arguments
+  /// * `tag`: The tag to be added.
+  ///
+  /// # Returns
+  /// * `Result<bool, ConfigError>`: Whether the tag was added, `false` when
+  ///   the configuration already has it, or the error raised for an invalid
+  ///   tag.
+  pub fn add_tag(&mut self, tag: &str) -> Result<bool, ConfigError> {
+    check_tag(tag)?;
+    if self.has_tag(tag) {
+      return Ok(false);
+    }
+    self.tags.push(tag.to_string());
+    Ok(true)
+  }
+
+  /// Function to remove a tag from the configuration.
+  ///
+  /// The change is made in memory only; call `save` to persist it.
+  ///
+  /// # Arguments
+  /// * `tag`: The tag to be removed.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the tag was removed.
+  pub fn remove_tag(&mut self, tag: &str) -> bool {
+    let before = self.tags.len();
+    self.tags.retain(|t| t != tag);
+    self.tags.len() != before
+  }
+}
+
+/// Define a variable to enable the Singleton pattern.
+static CONFIG: OnceLock<Mutex<Config>> = OnceLock::new();
+
+/// Define a variable holding the path the CONFIG singleton was loaded from.
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Define a variable holding the format forced on the configuration file,
+/// regardless of its extension.
+static CONFIG_FORMAT: OnceLock<ConfigFormat> = OnceLock::new();
+
+/// Implements the logic to detect the format of a configuration file
+#[allow(dead_code)]
+impl ConfigFormat {
+  /// Function to detect the format of a configuration file from its
+  /// extension.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the configuration file.
+  ///
+  /// # Returns
+  /// * `ConfigFormat`: TOML for the `.toml` extension, JSON for any other.
+  pub fn detect(path: &Path) -> ConfigFormat {
+    if path.extension().is_some_and(|extension| extension == "toml") {
+      ConfigFormat::Toml
+    } else {
+      ConfigFormat::Json
+    }
+  }
+
+  /// Function to force the format of the configuration file, for every
+  /// following load and save. It must be called before `Config::init`.
+  ///
+  /// # Arguments
+  /// * `format`: The format the configuration file is written in.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error raised when a format is already
+  ///   forced.
+  pub fn force(format: ConfigFormat) -> Result<(), ConfigError> {
+    CONFIG_FORMAT.set(format).map_err(|_| ConfigError::AlreadyInitialized)
+  }
+
+  /// Function to retrieve the format of a configuration file: the forced
+  /// one, if any, or the one detected from its extension.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the configuration file.
+  ///
+  /// # Returns
+  /// * `ConfigFormat`: The format the file is read and written in.
+  pub fn of(path: &Path) -> ConfigFormat {
+    CONFIG_FORMAT.get().copied().unwrap_or_else(|| ConfigFormat::detect(path))
+  }
+}
+
+impl FromStr for ConfigFormat {
+  type Err = ConfigError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim().to_ascii_lowercase().as_str() {
+      "json" => Ok(ConfigFormat::Json),
+      "toml" => Ok(ConfigFormat::Toml),
+      _ => Err(ConfigError::UnknownFormat(s.to_string())),
+    }
+  }
+}
+
+/// Implements the logic to load, change and save the configuration
+#[allow(dead_code)]
+impl Config {
+  /// Function to load the configuration from the path returned by
+  /// `resolve_path` and set the CONFIG singleton variable.
+  ///
+  /// A missing configuration file is not an error: an empty configuration is
+  /// used instead, and written on the first save. The loaded configuration
+  /// is validated and every problem found is logged as a warning; it is used
+  /// regardless, so that it can still be fixed through WG-Bridge.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error, if any, raised while reading the
+  ///   file or because the configuration is already initialized.
+  pub fn init() -> Result<(), ConfigError> {
+    Self::init_with_path(None)
+  }
+
+  /// Function to load the configuration from the given path and set the
+  /// CONFIG singleton variable.
+  ///
+  /// This function behaves like `init`, but allows overriding the path (e.g.
+  /// from the command line). The path is remembered, so that `save` and
+  /// `watch` act on the same file.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the configuration file, or `None` to use the one
+  ///   returned by `resolve_path`.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error, if any, raised while reading the
+  ///   file or because the configuration is already initialized.
+  pub fn init_with_path(path: Option<PathBuf>) -> Result<(), ConfigError> {
+    let path = match path {
+      Some(path) => path,
+      None => Self::resolve_path()?,
+    };
+    let mut config = Self::load_config(&path)?;
+    if let Some(dir) = fragment_dir() {
+      config.merge_fragments(&dir)?;
+    }
+    if let Err(errors) = config.validate() {
+      for error in errors {
+        wgb_warn!("{error}");
+      }
+    }
+    CONFIG.set(Mutex::new(config)).map_err(|_| ConfigError::AlreadyInitialized)?;
+    let _ = CONFIG_PATH.set(path);
+    Ok(())
+  }
+
+  /// Retrieves a lock on the initialized `Config` instance.
+  ///
+  /// The lock must be released before calling `get` again on the same thread.
+  /// If the `Config` has not been initialized, it panics with the message
+  /// "Config not initialized".
+  ///
+  /// # Returns
+  /// * `MutexGuard<'static, Config>`: The locked singleton `Config`.
+  pub fn get() -> MutexGuard<'static, Config> {
+    CONFIG
+        .get()
+        .expect("Config not initialized")
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Function to retrieve the path of the loaded configuration file.
+  ///
+  /// # Returns
+  /// * `Result<PathBuf, ConfigError>`: The path the CONFIG singleton was
+  ///   loaded from, or the one returned by `resolve_path` before `init`.
+  pub fn loaded_path() -> Result<PathBuf, ConfigError> {
+    match CONFIG_PATH.get() {
+      Some(path) => Ok(path.clone()),
+      None => Self::resolve_path(),
+    }
+  }
+
+  /// Function to retrieve the path of the configuration file.
+  ///
+  /// # Returns
+  /// * `Result<PathBuf, ConfigError>`: The path set in `WGB_CONFIG`, if any,
+  ///   `~/.wgbconf.toml` when it exists, `~/.wgbconf.json` otherwise, or the
+  ///   error raised when the home directory is unknown.
+  pub fn resolve_path() -> Result<PathBuf, ConfigError> {
+    resolve_path_from(&|name| std::env::var_os(name), std::env::home_dir())
+  }
+
+  /// Function to read a configuration file, migrating it to the current
+  /// schema version.
+  ///
+  /// The file is read in the forced format, if any, otherwise files with the
+  /// `.toml` extension are read as TOML, any other as JSON.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the configuration file.
+  ///
+  /// # Returns
+  /// * `Result<Config, ConfigError>`: The configuration, an empty one when
+  ///   the file does not exist, or the error raised reading or parsing it.
+  pub fn load_config(path: &Path) -> Result<Config, ConfigError> {
+    Self::load_config_as(path, ConfigFormat::of(path))
+  }
+
+  /// Function to read a configuration file in the given format, migrating
+  /// it to the current schema version.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the configuration file.
+  /// * `format`: The format the file is written in.
+  ///
+  /// # Returns
+  /// * `Result<Config, ConfigError>`: The configuration, an empty one when
+  ///   the file does not exist, or the error raised reading or parsing it,
+  ///   e.g. for a field of a user holding an invalid value.
+  pub fn load_config_as(path: &Path, format: ConfigFormat) -> Result<Config, ConfigError> {
+    match fs::read_to_string(path) {
+      Ok(content) => match format {
+        ConfigFormat::Json => Self::migrate(serde_json::from_str(&content)?),
+        ConfigFormat::Toml => Self::migrate(toml::from_str(&content)?),
+      },
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  /// Function to upgrade a configuration of an older schema version to the
+  /// current one.
+  ///
+  /// Each step upgrades the raw document by one version (renaming moved
+  /// fields, filling new ones with their defaults) before the final
+  /// deserialization.
+  ///
+  /// # Arguments
+  /// * `raw`: The configuration, as read from the file.
+  ///
+  /// # Returns
+  /// * `Result<Config, ConfigError>`: The configuration, or the error raised
+  ///   when it is newer than supported or does not match the schema.
+  pub fn migrate(mut raw: Value) -> Result<Config, ConfigError> {
+    let version = raw.get("schema_version").and_then(Value::as_u64).unwrap_or(0);
+    if version > SCHEMA_VERSION {
+      return Err(ConfigError::UnsupportedVersion(version));
+    }
+    if let Some(object) = raw.as_object_mut() {
+      if version < 1 {
+        migrate_v0(object);
+      }
+      object.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    }
+    Ok(serde_json::from_value(raw)?)
+  }
+
+  /// Function to write the configuration to a file.
+  ///
+  /// The configuration is written to a temporary file in the same directory,
+  /// which is then renamed over the target: the rename is atomic on the same
+  /// filesystem, so a crash or a full disk never leaves a truncated file.
+  /// On failure the temporary file, which may hold OTP secrets, is wiped.
+  ///
+  /// On Unix the file and its backup are created readable and writable by
+  /// their owner only (mode `0600`), since `otp_uri` holds TOTP secrets.
+  ///
+  /// The file is always written with the current schema version, in the
+  /// forced format, if any, otherwise as TOML when it has the `.toml`
+  /// extension and as JSON otherwise. Encrypted OTP
+  /// URIs are written encrypted, even while the configuration is unlocked.
+  ///
+  /// The file being replaced, if any, is first copied to `<name>.bak`, so
+  /// that the previous configuration can be restored in one step. Only the
+  /// most recent backup is kept.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the configuration file.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error, if any, raised while writing.
+  pub fn save_config(&self, path: &Path) -> Result<(), ConfigError> {
+    let content = self.to_content(ConfigFormat::of(path))?;
+    let temp = WipeGuard::new(temp_path(path));
+
+    let mut file = create_private(temp.path())?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    if path.exists() {
+      io::copy(&mut File::open(path)?, &mut create_private(&backup_path(path))?)?;
+    }
+    fs::rename(temp.path(), path)?;
+    temp.disarm();
+    Ok(())
+  }
+
+  /// Function to write the configuration as `save_config` writes it to a
+  /// file, i.e. with the current schema version, the encrypted OTP URIs
+  /// encrypted and without the configurations of the fragments.
+  ///
+  /// # Arguments
+  /// * `format`: The format the configuration is written in.
+  ///
+  /// # Returns
+  /// * `Result<String, ConfigError>`: The content of the file, or the error
+  ///   raised encrypting or serializing it.
+  pub fn to_content(&self, format: ConfigFormat) -> Result<String, ConfigError> {
+    let mut config = Config {
+      schema_version: SCHEMA_VERSION,
+      ..self.clone()
+    };
+    config.lock()?;
+    config.user = config
+        .user
+        .into_iter()
+        .filter_map(|user| match user.fragment {
+          None => Some(user),
+          Some(_) => self.shadowed.iter().find(|shadowed| shadowed.config_path == user.config_path).cloned(),
+        })
+        .collect();
+    Ok(match format {
+      ConfigFormat::Json => serde_json::to_string_pretty(&config)?,
+      ConfigFormat::Toml => toml::to_string_pretty(&config)?,
+    })
+  }
+
+  /// Function to rewrite the configuration in its canonical form, so that
+  /// hand-edited files diff cleanly: the paths are tidied with
+  /// `normalize_path`, duplicates dropped, and the WireGuard configurations
+  /// sorted by path, with their tags sorted. The fields are written in a
+  /// fixed order by `save_config` anyway.
+  ///
+  /// The configurations of the fragments are left as they are, since they
+  /// are never written back.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error raised when two configurations
+  ///   of the main file have the same path once tidied.
+  pub fn normalize(&mut self) -> Result<(), ConfigError> {
+    let mut paths = BTreeSet::new();
+    self.paths = self.paths.iter().map(|path| normalize_path(path)).filter(|path| paths.insert(path.clone())).collect();
+    let mut config_paths = BTreeSet::new();
+    for user in self.user.iter_mut().filter(|user| user.fragment.is_none()) {
+      user.config_path = normalize_path(&user.config_path);
+      if !config_paths.insert(user.config_path.clone()) {
+        return Err(ConfigError::DuplicateUser(user.config_path.clone()));
+      }
+      user.tags.sort();
+    }
+    self.user.sort_by(|a, b| a.config_path.cmp(&b.config_path));
+    Ok(())
+  }
+
+  /// Function to write the configuration back to the path returned by
+  /// `loaded_path`.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error, if any, raised while writing.
+  pub fn save(&self) -> Result<(), ConfigError> {
+    self.save_config(&Self::loaded_path()?)
+  }
+
+  /// Function to apply several changes atomically, writing the configuration
+  /// back to the path returned by `loaded_path` once they all succeeded.
+  ///
+  /// # Arguments
+  /// * `mutate`: The changes, made on a copy of the configuration.
+  ///
+  /// # Returns
+  /// * `Result<T, E>`: The result of the changes, or the error raised by them
+  ///   or while writing, in which case nothing is changed.
+  pub fn with_transaction<T, E: From<ConfigError>>(
+    &mut self,
+    mutate: impl FnOnce(&mut Config) -> Result<T, E>,
+  ) -> Result<T, E> {
+    self.with_transaction_at(&Self::loaded_path()?, mutate)
+  }
+
+  /// Function to apply several changes atomically, writing the configuration
+  /// to the given path once they all succeeded.
+  ///
+  /// The changes are made on a copy, which replaces the configuration only
+  /// once written: when a change or the write fails, both the configuration
+  /// in memory and the file are left as they were.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the configuration file.
+  /// * `mutate`: The changes, made on a copy of the configuration.
+  ///
+  /// # Returns
+  /// * `Result<T, E>`: The result of the changes, or the error raised by them
+  ///   or while writing, in which case nothing is changed.
+  pub fn with_transaction_at<T, E: From<ConfigError>>(
+    &mut self,
+    path: &Path,
+    mutate: impl FnOnce(&mut Config) -> Result<T, E>,
+  ) -> Result<T, E> {
+    let mut draft = self.clone();
+    let value = mutate(&mut draft)?;
+    draft.save_config(path)?;
+    *self = draft;
+    Ok(value)
+  }
+
+  /// Function to check every WireGuard configuration for problems.
+  ///
+  /// Each configuration must have a non-empty `config_path` pointing to an
+  /// existing file and, when `otp` is set, an `otp_uri` that is a valid
+  /// `otpauth://` URI holding a secret. Tags must pass `check_tag`.
+  ///
+  /// # Returns
+  /// * `Result<(), Vec<ValidationError>>`: Every problem found, so that they
+  ///   can all be fixed at once.
+  ///
+  /// Encrypted OTP URIs are only checked while the configuration is unlocked.
+  pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+    let mut errors = Vec::new();
+    for (index, user) in (1..).zip(&self.user) {
+      if user.config_path.is_empty() {
+        errors.push(ValidationError::EmptyConfigPath(index));
+      } else {
+        match user.resolved_path() {
+          Ok(path) if !path.exists() => errors.push(ValidationError::MissingConfigFile {
+            index,
+            path: path.display().to_string(),
+          }),
+          Ok(_) => {}
+          Err(e) => errors.push(ValidationError::UnresolvedConfigPath {
+            index,
+            reason: e.to_string(),
+          }),
+        }
+      }
+      for tag in user.tags.iter().filter(|tag| check_tag(tag).is_err()) {
+        errors.push(ValidationError::InvalidTag {
+          index,
+          tag: tag.clone(),
+        });
+      }
+      let readable = !user.otp_encrypted || self.is_unlocked();
+      if user.otp && readable && let Err(reason) = check_otp_uri(&user.otp_uri) {
+        errors.push(ValidationError::InvalidOtpUri { index, reason });
+      }
+    }
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+  }
+
+  /// Function to decrypt the encrypted OTP URIs in memory.
+  ///
+  /// Either every URI is decrypted or, on failure, the configuration is left
+  /// untouched. The passphrase is kept until `lock` is called, so that new or
+  /// changed URIs marked `otp_encrypted` can be encrypted on save.
+  ///
+  /// # Arguments
+  /// * `passphrase`: The passphrase the URIs were encrypted with.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error, if any, naming the configuration
+  ///   whose URI could not be decrypted, e.g. because of a wrong passphrase.
+  pub fn unlock(&mut self, passphrase: &str) -> Result<(), ConfigError> {
+    if self.is_unlocked() {
+      return Err(ConfigError::AlreadyUnlocked);
+    }
+    let mut decrypted = Vec::new();
+    for (index, user) in self.user.iter().enumerate() {
+      if user.otp_encrypted {
+        let otp_uri = crypto::decrypt(passphrase, &user.otp_uri)
+            .map_err(|e| ConfigError::Secret(user.config_path.clone(), e))?;
+        decrypted.push((index, otp_uri));
+      }
+    }
+    for (index, mut otp_uri) in decrypted {
+      self.user[index].otp_uri = std::mem::take(&mut *otp_uri);
+    }
+    self.passphrase = Some(Passphrase(Zeroizing::new(passphrase.to_string())));
+    Ok(())
+  }
+
+  /// Function to encrypt again the OTP URIs marked `otp_encrypted` and forget
+  /// the passphrase.
+  ///
+  /// Every URI is encrypted with a fresh salt and nonce. Nothing happens when
+  /// the configuration is not unlocked.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error, if any, raised while encrypting.
+  pub fn lock(&mut self) -> Result<(), ConfigError> {
+    let Some(passphrase) = self.passphrase.take() else {
+      return Ok(());
+    };
+    let mut encrypted = Vec::new();
+    for (index, user) in self.user.iter().enumerate() {
+      if user.otp_encrypted {
+        match crypto::encrypt(&passphrase.0, &user.otp_uri) {
+          Ok(otp_uri) => encrypted.push((index, otp_uri)),
+          Err(e) => {
+            let config_path = user.config_path.clone();
+            self.passphrase = Some(passphrase);
+            return Err(ConfigError::Secret(config_path, e));
+          }
+        }
+      }
+    }
+    for (index, otp_uri) in encrypted {
+      std::mem::replace(&mut self.user[index].otp_uri, otp_uri).zeroize();
+    }
+    Ok(())
+  }
+
+  /// Function to tell whether the encrypted OTP URIs are decrypted in memory.
+  ///
+  /// # Returns
+  /// * `bool`: Whether `unlock` succeeded and `lock` was not called since.
+  pub fn is_unlocked(&self) -> bool {
+    self.passphrase.is_some()
+  }
+
+  /// Function to reload the configuration whenever its file changes.
+  ///
+  /// Changes are debounced, then the file is parsed and validated and, when
+  /// valid, swapped into the CONFIG singleton. An unlocked configuration is
+  /// unlocked again with the same passphrase. A file that fails to parse,
+  /// validate or unlock is logged as an error and the previous configuration
+  /// is kept.
+  ///
+  /// The directory holding the file is watched, rather than the file itself,
+  /// so that atomic saves replacing it are picked up too.
+  ///
+  /// # Returns
+  /// * `Result<ConfigWatcher, ConfigError>`: The watcher, reloading until it
+  ///   is stopped or dropped, or the error raised setting it up.
+  pub fn watch() -> Result<ConfigWatcher, ConfigError> {
+    let config = CONFIG.get().ok_or(ConfigError::NotInitialized)?;
+    Self::watch_at(config, Self::loaded_path()?)
+  }
+
+  /// Function to reload the given configuration whenever the given file
+  /// changes, as `watch` does for the CONFIG singleton.
+  ///
+  /// # Arguments
+  /// * `config`: The configuration to be replaced.
+  /// * `path`: The path to the configuration file.
+  ///
+  /// # Returns
+  /// * `Result<ConfigWatcher, ConfigError>`: The watcher, reloading until it
+  ///   is stopped or dropped, or the error raised setting it up.
+  fn watch_at(config: &'static Mutex<Config>, path: PathBuf) -> Result<ConfigWatcher, ConfigError> {
+    let directory = match path.parent() {
+      Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+      _ => PathBuf::from("."),
+    };
+    let file_name = path.file_name().map(|name| name.to_owned());
+
+    let (tx, rx) = mpsc::channel::<WatchMessage>();
+    let events = tx.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+      let Ok(event) = event else {
+        return;
+      };
+      let changed = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+          && event.paths.iter().any(|changed| changed.file_name() == file_name.as_deref());
+      if changed {
+        let _ = events.send(WatchMessage::Changed);
+      }
+    })?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+
+    let handle = std::thread::spawn(move || {
+      while let Ok(WatchMessage::Changed) = rx.recv() {
+        // Wait for the changes to settle before reloading
+        loop {
+          match rx.recv_timeout(WATCH_DEBOUNCE) {
+            Ok(WatchMessage::Changed) => continue,
+            Ok(WatchMessage::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+            Err(RecvTimeoutError::Timeout) => break,
+          }
+        }
+        reload(config, &path);
+      }
+    });
+
+    Ok(ConfigWatcher {
+      watcher: Some(watcher),
+      stop: tx,
+      handle: Some(handle),
+    })
+  }
+
+  /// Function to add a WireGuard configuration.
+  ///
+  /// The change is made in memory only; call `save` to persist it.
+  ///
+  /// # Arguments
+  /// * `user`: The configuration to be added.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error raised when a configuration with
+  ///   the same `config_path` already exists.
+  pub fn add_user(&mut self, user: UserConfig) -> Result<(), ConfigError> {
+    if self.user.iter().any(|u| u.config_path == user.config_path) {
+      return Err(ConfigError::DuplicateUser(user.config_path));
+    }
+    self.user.push(user);
+    Ok(())
+  }
+
+  /// Function to remove a WireGuard configuration.
+  ///
+  /// The path is compared exactly, case included. The change is made in
+  /// memory only; call `save` to persist it.
+  ///
+  /// # Arguments
+  /// * `config_path`: The path of the configuration to be removed.
+  ///
+  /// # Returns
+  /// * `bool`: Whether a configuration was removed.
+  pub fn remove_user(&mut self, config_path: &str) -> bool {
+    let before = self.user.len();
+    self.user.retain(|u| u.config_path != config_path);
+    self.user.len() != before
+  }
+
+  /// Function to add a directory where WireGuard configuration files are
+  /// saved.
+  ///
+  /// The change is made in memory only; call `save` to persist it.
+  ///
+  /// # Arguments
+  /// * `path`: The directory, as written by the user.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error raised when the path is already
+  ///   configured.
+  pub fn add_path(&mut self, path: String) -> Result<(), ConfigError> {
+    if self.paths.contains(&path) {
+      return Err(ConfigError::DuplicatePath(path));
+    }
+    self.paths.push(path);
+    Ok(())
+  }
+
+  /// Function to remove a directory where WireGuard configuration files are
+  /// saved.
+  ///
+  /// The path is compared exactly. The change is made in memory only; call
+  /// `save` to persist it.
+  ///
+  /// # Arguments
+  /// * `path`: The directory to be removed.
+  ///
+  /// # Returns
+  /// * `bool`: Whether a path was removed.
+  pub fn remove_path(&mut self, path: &str) -> bool {
+    let before = self.paths.len();
+    self.paths.retain(|p| p != path);
+    self.paths.len() != before
+  }
+
+  /// Function to retrieve a WireGuard configuration by path.
+  ///
+  /// # Arguments
+  /// * `config_path`: The exact path of the configuration.
+  ///
+  /// # Returns
+  /// * `Option<&UserConfig>`: The configuration, or `None` when it is missing.
+  pub fn get_user(&self, config_path: &str) -> Option<&UserConfig> {
+    self.user.iter().find(|u| u.config_path == config_path)
+  }
+
+  /// Function to check that a WireGuard configuration can be changed, i.e.
+  /// that it is not defined by a configuration fragment.
+  ///
+  /// # Arguments
+  /// * `config_path`: The exact path of the configuration.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error naming the fragment defining the
+  ///   configuration, if any.
+  pub fn check_editable(&self, config_path: &str) -> Result<(), ConfigError> {
+    match self.get_user(config_path).and_then(|user| user.fragment.as_ref()) {
+      Some(fragment) => Err(ConfigError::FragmentUser {
+        config_path: config_path.to_string(),
+        path: fragment.clone(),
+      }),
+      None => Ok(()),
+    }
+  }
+
+  /// Function to merge the configuration fragments of a directory, i.e. its
+  /// `*.json` files holding a `user` array, in the order of their names.
+  ///
+  /// A configuration with the same `config_path` as an earlier one, from the
+  /// main file or a fragment, replaces it in place. Configurations of
+  /// fragments are kept in memory only: saving writes the main file back as
+  /// it was read.
+  ///
+  /// # Arguments
+  /// * `dir`: The directory of the fragments, ignored when missing.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error, if any, raised reading or
+  ///   parsing a fragment, for a `config_path` defined twice in a fragment,
+  ///   or for two configurations bringing up the same interface.
+  pub fn merge_fragments(&mut self, dir: &Path) -> Result<(), ConfigError> {
+    let mut files = match fs::read_dir(dir) {
+      Ok(entries) => entries.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()?,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+      Err(e) => return Err(e.into()),
+    };
+    files.retain(|file| file.extension().is_some_and(|extension| extension == "json") && file.is_file());
+    files.sort();
+
+    for file in files {
+      let fragment: Fragment = serde_json::from_str(&fs::read_to_string(&file)?).map_err(|source| ConfigError::Fragment {
+        path: file.clone(),
+        source,
+      })?;
+      let mut defined = BTreeSet::new();
+      for mut user in fragment.user {
+        if !defined.insert(user.config_path.clone()) {
+          return Err(ConfigError::DuplicateInFragment {
+            config_path: user.config_path,
+            path: file,
+          });
+        }
+        user.fragment = Some(file.clone());
+        match self.user.iter_mut().find(|u| u.config_path == user.config_path) {
+          Some(earlier) => {
+            let earlier = std::mem::replace(earlier, user);
+            if earlier.fragment.is_none() {
+              self.shadowed.push(earlier);
+            }
+          }
+          None => self.user.push(user),
+        }
+      }
+    }
+    self.check_interfaces()
+  }
+
+  /// Function to check that no two configurations, one of them from a
+  /// fragment, bring up the same interface.
+  ///
+  /// # Returns
+  /// * `Result<(), ConfigError>`: The error naming both configurations, if
+  ///   any. Paths which cannot be resolved are left to `validate`.
+  fn check_interfaces(&self) -> Result<(), ConfigError> {
+    let mut interfaces: BTreeMap<String, &UserConfig> = BTreeMap::new();
+    for user in &self.user {
+      let Ok(path) = user.resolved_path() else {
+        continue;
+      };
+      let Some(interface) = wg::interface_name(&path) else {
+        continue;
+      };
+      match interfaces.get(interface) {
+        Some(first) if first.fragment.is_some() || user.fragment.is_some() => {
+          return Err(ConfigError::ConflictingInterface {
+            interface: interface.to_string(),
+            first: first.origin(),
+            second: user.origin(),
+          });
+        }
+        Some(_) => {}
+        None => {
+          interfaces.insert(interface.to_string(), user);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Function to retrieve a mutable WireGuard configuration by path.
+  ///
+  /// # Arguments
+  /// * `config_path`: The exact path of the configuration.
+  ///
+  /// # Returns
+  /// * `Option<&mut UserConfig>`: The configuration, or `None` when it is
+  ///   missing.
+  pub fn get_user_mut(&mut self, config_path: &str) -> Option<&mut UserConfig> {
+    self.user.iter_mut().find(|u| u.config_path == config_path)
+  }
+}
+
+/// Define the messages sent to the configuration watcher thread.
+#[derive(Debug)]
+enum WatchMessage {
+  /// The configuration file changed.
+  Changed,
+  /// The watcher is being stopped.
+  Stop,
+}
+
+/// Define a handle to the watcher reloading the configuration on changes.
+///
+/// Dropping the handle stops the watcher, like `stop`.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+  watcher: Option<RecommendedWatcher>,
+  stop: Sender<WatchMessage>,
+  handle: Option<JoinHandle<()>>,
+}
+
+/// Implements the logic to stop the configuration watcher
+#[allow(dead_code)]
+impl ConfigWatcher {
+  /// Function to stop watching the configuration file, waiting for a reload
+  /// in progress to complete.
+  pub fn stop(mut self) {
+    self.shutdown();
+  }
+
+  /// Function to stop the file notifications and the reload thread.
+  fn shutdown(&mut self) {
+    drop(self.watcher.take());
+    let _ = self.stop.send(WatchMessage::Stop);
+    if let Some(handle) = self.handle.take() {
+      let _ = handle.join();
+    }
+  }
+}
+
+impl Drop for ConfigWatcher {
+  fn drop(&mut self) {
+    self.shutdown();
+  }
+}
+
+/// Function to reload the configuration from its file, keeping the current
+/// one when the file is not usable.
+///
+/// # Arguments
+/// * `config`: The configuration to be replaced.
+/// * `path`: The path to the configuration file.
+fn reload(config: &Mutex<Config>, path: &Path) {
+  let loaded = Config::load_config(path).and_then(|mut loaded| {
+    if let Some(dir) = fragment_dir() {
+      loaded.merge_fragments(&dir)?;
+    }
+    Ok(loaded)
+  });
+  let mut loaded = match loaded {
+    Ok(loaded) => loaded,
+    Err(e) => {
+      wgb_error!("{e}, keeping the previous configuration");
+      return;
+    }
+  };
+
+  let passphrase = config
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .passphrase
+      .clone();
+  if let Some(passphrase) = passphrase
+    && let Err(e) = loaded.unlock(&passphrase.0)
+  {
+    wgb_error!("{e}, keeping the previous configuration");
+    return;
+  }
+  if let Err(errors) = loaded.validate() {
+    for error in errors {
+      wgb_error!("{error}");
+    }
+    wgb_error!("Invalid configuration, keeping the previous one");
+    return;
+  }
+
+  let mut current = config.lock().unwrap_or_else(|e| e.into_inner());
+  if *current != loaded {
+    *current = loaded;
+    wgb_info!("Configuration reloaded from {}", path.display());
+  }
+}
+
+/// Function to upgrade a version 0 configuration to version 1.
+///
+/// Version 0 listed the configurations under `confs`, as `path`, `token` and
+/// `uri`, along with the unused `conf_path` and `error_codes`.
+///
+/// # Arguments
+/// * `object`: The configuration document to be upgraded.
+fn migrate_v0(object: &mut serde_json::Map<String, Value>) {
+  let confs = object.remove("confs");
+  object.remove("conf_path");
+  object.remove("error_codes");
+  if object.contains_key("user") {
+    return;
+  }
+  let users = confs
+      .as_ref()
+      .and_then(Value::as_array)
+      .into_iter()
+      .flatten()
+      .map(|conf| {
+        json!({
+          "config_path": conf["path"].as_str().unwrap_or_default(),
+          "otp": conf["token"].as_bool().unwrap_or_default(),
+          "otp_uri": conf["uri"].as_str().unwrap_or_default(),
+        })
+      })
+      .collect();
+  object.insert("user".to_string(), Value::Array(users));
+}
+
+/// Function to retrieve the path of the configuration file, as
+/// `Config::resolve_path` does, from the given environment.
+///
+/// # Arguments
+/// * `env`: The function reading an environment variable.
+/// * `home`: The home directory, if known.
+///
+/// # Returns
+/// * `Result<PathBuf, ConfigError>`: The path set in `WGB_CONFIG`, if any,
+///   `~/.wgbconf.toml` when it exists, `~/.wgbconf.json` otherwise, or the
+///   error raised when the home directory is unknown.
+fn resolve_path_from(env: &dyn Fn(&str) -> Option<OsString>, home: Option<PathBuf>) -> Result<PathBuf, ConfigError> {
+  if let Some(path) = env(CONFIG_ENV).filter(|path| !path.is_empty()) {
+    return Ok(PathBuf::from(path));
+  }
+  let home = home.ok_or(ConfigError::NoHomeDir)?;
+  let toml_path = home.join(TOML_CONFIG_FILE_NAME);
+  if toml_path.exists() {
+    return Ok(toml_path);
+  }
+  Ok(home.join(CONFIG_FILE_NAME))
+}
+
+/// Function to retrieve the directory of the configuration fragments.
+///
+/// # Returns
+/// * `Option<PathBuf>`: `$XDG_CONFIG_HOME/wgbridge/conf.d`, defaulting to
+///   `~/.config/wgbridge/conf.d`, or `None` when the home directory is
+///   unknown.
+pub fn fragment_dir() -> Option<PathBuf> {
+  let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+      .filter(|dir| !dir.is_empty())
+      .map(PathBuf::from)
+      .or_else(|| std::env::home_dir().map(|home| home.join(".config")))?;
+  Some(config_dir.join(FRAGMENT_DIR))
+}
+
+/// Function to expand a path as written in the configuration file.
+///
+/// A leading `~` is expanded to the home directory, and `$VAR` and `${VAR}`
+/// are replaced with the value of the environment variable. `~user` is not
+/// supported and kept as is.
+///
+/// # Arguments
+/// * `path`: The path to be expanded.
+///
+/// # Returns
+/// * `Result<PathBuf, ConfigError>`: The expanded path, or the error raised
+///   for an undefined variable or an unknown home directory.
+pub fn expand_path(path: &str) -> Result<PathBuf, ConfigError> {
+  let mut resolved = String::with_capacity(path.len());
+  let mut rest = path;
+
+  if let Some(after) = rest.strip_prefix('~')
+    && (after.is_empty() || after.starts_with('/'))
+  {
+    let home = std::env::home_dir().ok_or(ConfigError::NoHomeDir)?;
+    resolved.push_str(&home.to_string_lossy());
+    rest = after;
+  }
+
+  while let Some(start) = rest.find('$') {
+    resolved.push_str(&rest[..start]);
+    let after = &rest[start + 1..];
+    let (name, remaining) = match after.strip_prefix('{') {
+      Some(braced) => match braced.find('}') {
+        Some(end) => (&braced[..end], &braced[end + 1..]),
+        None => ("", after),
+      },
+      None => {
+        let end = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        (&after[..end], &after[end..])
+      }
+    };
+    if name.is_empty() {
+      // Not a variable reference, e.g. a lone `$`
+      resolved.push('$');
+      rest = after;
+      continue;
+    }
+    let value = std::env::var(name)
+        .map_err(|_| ConfigError::UndefinedVariable(name.to_string(), path.to_string()))?;
+    resolved.push_str(&value);
+    rest = remaining;
+  }
+  resolved.push_str(rest);
+  Ok(PathBuf::from(resolved))
+}
+
+/// Function to tidy a path as written by the user, without changing the file
+/// it names: repeated and trailing slashes and `.` components are dropped,
+/// while `..`, `~` and environment variables are kept, since symbolic links
+/// and the environment decide where they lead.
+///
+/// # Arguments
+/// * `path`: The path, before `expand_path`.
+///
+/// # Returns
+/// * `String`: The tidied path, e.g. `~/wireguard/wg0.conf` for
+///   `~//wireguard/./wg0.conf`.
+fn normalize_path(path: &str) -> String {
+  let components: Vec<&str> = path.split('/').filter(|component| !component.is_empty() && *component != ".").collect();
+  match (path.starts_with('/'), components.is_empty()) {
+    (true, _) => format!("/{}", components.join("/")),
+    (false, true) => ".".to_string(),
+    (false, false) => components.join("/"),
+  }
+}
+
+/// Function to parse octal permission bits, with or without a leading `0`
+/// or `0o`.
+///
+/// # Arguments
+/// * `mode`: The permission bits, as written in the configuration file.
+///
+/// # Returns
+/// * `Result<u32, ConfigError>`: The permission bits, or the error raised
+///   when they are not octal or exceed `07777`.
+fn parse_mode(mode: &str) -> Result<u32, ConfigError> {
+  let digits = mode.trim();
+  let digits = digits.strip_prefix("0o").unwrap_or(digits);
+  let octal = !digits.is_empty() && digits.bytes().all(|b| matches!(b, b'0'..=b'7'));
+  match u32::from_str_radix(digits, 8) {
+    Ok(bits) if octal && bits <= 0o7777 => Ok(bits),
+    _ => Err(ConfigError::InvalidLogMode(mode.to_string())),
+  }
+}
+
+/// Function to check that a tag can be given to `--tag` and printed in a
+/// comma-separated list.
+///
+/// # Arguments
+/// * `tag`: The tag to be checked.
+///
+/// # Returns
+/// * `Result<(), ConfigError>`: The error raised for an empty tag or one
+///   holding spaces or commas.
+pub fn check_tag(tag: &str) -> Result<(), ConfigError> {
+  if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c == ',') {
+    return Err(ConfigError::InvalidTag(tag.to_string()));
+  }
+  Ok(())
+}
+
+/// Function to check that an OTP URI is a usable `otpauth://` URI.
+///
+/// # Arguments
+/// * `otp_uri`: The URI to be checked.
+///
+/// # Returns
+/// * `Result<(), String>`: The reason why the URI is not usable, if any.
+pub fn check_otp_uri(otp_uri: &str) -> Result<(), String> {
+  if otp_uri.trim().is_empty() {
+    return Err("it is empty".to_string());
+  }
+  let url = Url::parse(otp_uri).map_err(|e| e.to_string())?;
+  if url.scheme() != "otpauth" {
+    return Err(format!("scheme must be otpauth, not {}", url.scheme()));
+  }
+  if !matches!(url.host_str(), Some("totp" | "hotp")) {
+    return Err("type must be totp or hotp".to_string());
+  }
+  if !url.query_pairs().any(|(key, value)| key == "secret" && !value.is_empty()) {
+    return Err("the secret parameter is missing".to_string());
+  }
+  Ok(())
+}
+
+/// Function to turn the OTP secret given by a provider into the URI stored
+/// in `otp_uri`.
+///
+/// Some providers only give the base32 secret: it is then written into an
+/// `otpauth://totp/` URI with the usual defaults (SHA1, 6 digits, 30
+/// seconds), spaces and padding removed.
+///
+/// # Arguments
+/// * `otp`: An `otpauth://` URI, or a bare base32 secret.
+/// * `label`: The account of the URI built from a bare secret, e.g. the
+///   interface name.
+///
+/// # Returns
+/// * `Result<String, String>`: The URI, or the reason why it is not usable or
+///   the secret is not base32.
+pub fn canonical_otp_uri(otp: &str, label: &str) -> Result<String, String> {
+  if otp.contains(':') {
+    check_otp_uri(otp)?;
+    return Ok(otp.to_string());
+  }
+  let secret: String = otp
+      .chars()
+      .filter(|c| !c.is_whitespace())
+      .collect::<String>()
+      .trim_end_matches('=')
+      .to_ascii_uppercase();
+  if secret.is_empty() {
+    return Err("it is empty".to_string());
+  }
+  if !secret.bytes().all(|c| matches!(c, b'A'..=b'Z' | b'2'..=b'7')) {
+    return Err("the secret is neither an otpauth:// URI nor base32".to_string());
+  }
+  // Any other length leaves bits which make no whole byte
+  if matches!(secret.len() % 8, 1 | 3 | 6) {
+    return Err("the secret is truncated base32".to_string());
+  }
+  let mut url = Url::parse(&format!("otpauth://totp/{OTP_ISSUER}:{label}")).map_err(|e| e.to_string())?;
+  url.query_pairs_mut()
+      .append_pair("secret", &secret)
+      .append_pair("issuer", OTP_ISSUER)
+      .append_pair("algorithm", "SHA1")
+      .append_pair("digits", "6")
+      .append_pair("period", "30");
+  Ok(url.to_string())
+}
+
+/// Function to deserialize the WireGuard configurations, naming the one
+/// holding an invalid field, since the error of a field does not tell which
+/// entry of `user` it belongs to.
+///
+/// # Arguments
+/// * `deserializer`: The deserializer of the `user` array.
+///
+/// # Returns
+/// * `Result<Vec<UserConfig>, D::Error>`: The configurations, or the error
+///   of the first invalid one, numbered from 1 like `ValidationError`.
+fn deserialize_users<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<UserConfig>, D::Error> {
+  let users = Vec::<Value>::deserialize(deserializer)?;
+  (1..)
+      .zip(users)
+      .map(|(index, user)| serde_json::from_value(user).map_err(|e| de::Error::custom(format!("user {index}: {e}"))))
+      .collect()
+}
+
+/// Function to deserialize `config_path`, rejecting an empty path.
+///
+/// # Arguments
+/// * `deserializer`: The deserializer of the field.
+///
+/// # Returns
+/// * `Result<String, D::Error>`: The path, or the error raised when it is
+///   empty.
+fn deserialize_config_path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+  let config_path = String::deserialize(deserializer)?;
+  if config_path.trim().is_empty() {
+    return Err(de::Error::custom("config_path is empty"));
+  }
+  Ok(config_path)
+}
+
+/// Function to deserialize `otp_uri`, which is either empty, an `otpauth://`
+/// URI passing `check_otp_uri` or a secret encrypted by `crypto::encrypt`.
+///
+/// Whether the URI is required is left to `validate`, which knows `otp`.
+///
+/// # Arguments
+/// * `deserializer`: The deserializer of the field.
+///
+/// # Returns
+/// * `Result<String, D::Error>`: The URI, or the error telling why it is
+///   unusable.
+fn deserialize_otp_uri<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+  let otp_uri = String::deserialize(deserializer)?;
+  // Base64 never holds a colon, while every URI does
+  if otp_uri.contains(':') {
+    check_otp_uri(&otp_uri).map_err(|reason| de::Error::custom(format!("invalid otp_uri: {reason}")))?;
+  } else if !otp_uri.is_empty() && !crypto::is_encrypted(&otp_uri) {
+    return Err(de::Error::custom("invalid otp_uri: expected an otpauth:// URI or an encrypted secret"));
+  }
+  Ok(otp_uri)
+}
+
+/// Function to deserialize `handshake_timeout`, rejecting zero, which would
+/// make the tunnel stale right after every handshake.
+///
+/// # Arguments
+/// * `deserializer`: The deserializer of the field.
+///
+/// # Returns
+/// * `Result<Option<u64>, D::Error>`: The timeout, or the error raised when
+///   it is zero.
+fn deserialize_handshake_timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+  match Option::<u64>::deserialize(deserializer)? {
+    Some(0) => Err(de::Error::custom("handshake_timeout must be at least 1 second")),
+    timeout => Ok(timeout),
+  }
+}
+
+/// Function to deserialize `connect_timeout`, rejecting zero, which would
+/// bring every tunnel down before its first handshake.
+///
+/// # Arguments
+/// * `deserializer`: The deserializer of the field.
+///
+/// # Returns
+/// * `Result<Option<u64>, D::Error>`: The timeout, or the error raised when
+///   it is zero.
+fn deserialize_connect_timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+  match Option::<u64>::deserialize(deserializer)? {
+    Some(0) => Err(de::Error::custom("connect_timeout must be at least 1 second")),
+    timeout => Ok(timeout),
+  }
+}
+
+/// Function to deserialize `trusted_networks`, rejecting an empty SSID, a
+/// MAC address which is not six hexadecimal pairs and an invalid subnet.
+///
+/// # Arguments
+/// * `deserializer`: The deserializer of the field.
+///
+/// # Returns
+/// * `Result<Vec<TrustedNetwork>, D::Error>`: The networks, or the error
+///   naming the first invalid one.
+fn deserialize_trusted_networks<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<TrustedNetwork>, D::Error> {
+  let networks = Vec::<TrustedNetwork>::deserialize(deserializer)?;
+  for network in &networks {
+    let valid = match network {
+      TrustedNetwork::Ssid(ssid) => !ssid.is_empty(),
+      TrustedNetwork::GatewayMac(mac) => {
+        let pairs: Vec<&str> = mac.split(':').collect();
+        pairs.len() == 6 && pairs.iter().all(|pair| pair.len() == 2 && pair.chars().all(|c| c.is_ascii_hexdigit()))
+      }
+      TrustedNetwork::Subnet(subnet) => subnet.parse::<AddressPool>().is_ok(),
+    };
+    if !valid {
+      return Err(de::Error::custom(format!("invalid trusted network {network}")));
+    }
+  }
+  Ok(networks)
+}
+
+/// Function to deserialize `tags`, rejecting the tags failing `check_tag`.
+///
+/// # Arguments
+/// * `deserializer`: The deserializer of the field.
+///
+/// # Returns
+/// * `Result<Vec<String>, D::Error>`: The tags, or the error naming the first
+///   invalid one.
+fn deserialize_tags<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+  let tags = Vec::<String>::deserialize(deserializer)?;
+  match tags.iter().find(|tag| check_tag(tag).is_err()) {
+    Some(tag) => Err(de::Error::custom(format!(
+      "invalid tag {tag:?}, expected a non-empty word without spaces or commas"
+    ))),
+    None => Ok(tags),
+  }
+}
+
+/// Function to build the path of the backup of a configuration file.
+///
+/// # Arguments
+/// * `path`: The path to the configuration file.
+///
+/// # Returns
+/// * `PathBuf`: The path `<name>.bak`, in the same directory.
+fn backup_path(path: &Path) -> PathBuf {
+  let mut backup = path.as_os_str().to_owned();
+  backup.push(".bak");
+  PathBuf::from(backup)
+}
+
+/// Function to build the path of the temporary file used to save a
+/// configuration file.
+///
+/// # Arguments
+/// * `path`: The path to the configuration file.
+///
+/// # Returns
+/// * `PathBuf`: The path `<name>.<pid>.tmp`, in the same directory.
+fn temp_path(path: &Path) -> PathBuf {
+  let mut temp = path.file_name().unwrap_or(CONFIG_FILE_NAME.as_ref()).to_owned();
+  temp.push(format!(".{}.tmp", std::process::id()));
+  path.with_file_name(temp)
+}
+
+/// Function to create a configuration file, or to truncate it, readable and
+/// writable by its owner only.
+///
+/// A new file is created with mode `0600`, so that its content is never
+/// readable by others, and an existing one is restricted before anything is
+/// written to it. Permission bits are a Unix concept, so other platforms
+/// keep their defaults.
+///
+/// # Arguments
+/// * `path`: The path to the configuration file.
+///
+/// # Returns
+/// * `io::Result<File>`: The empty file opened for writing, or the error
+///   raised creating it or changing its permissions.
+fn create_private(path: &Path) -> io::Result<File> {
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create(true).truncate(true);
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    options.mode(0o600);
+    let file = options.open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+  }
+  #[cfg(not(unix))]
+  options.open(path)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::{init_logger, temp_dir};
+
+  #[test]
+  fn config_format_is_detected_or_forced() {
+    assert_eq!(ConfigFormat::detect(Path::new("/home/user/.wgbconf.toml")), ConfigFormat::Toml);
+    assert_eq!(ConfigFormat::detect(Path::new("/home/user/.wgbconf.json")), ConfigFormat::Json);
+    assert_eq!(ConfigFormat::detect(Path::new("/etc/wgbconf")), ConfigFormat::Json);
+    assert_eq!(" TOML ".parse::<ConfigFormat>().unwrap(), ConfigFormat::Toml);
+    assert!(matches!("yaml".parse::<ConfigFormat>(), Err(ConfigError::UnknownFormat(_))));
+
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wgbconf.json");
+    fs::write(&path, "schema_version = 1\npaths = [\"/etc/wireguard\"]\n").unwrap();
+
+    assert!(matches!(Config::load_config_as(&path, ConfigFormat::Json), Err(ConfigError::Parse(_))));
+    let config = Config::load_config_as(&path, ConfigFormat::Toml).unwrap();
+    assert_eq!(config.paths, ["/etc/wireguard"]);
+  }
+
+  #[test]
+  fn failed_transaction_changes_nothing() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wgbconf.json");
+    let user = |config_path: &str| UserConfig {
+      config_path: config_path.to_string(),
+      ..Default::default()
+    };
+    let mut config = Config::default();
+    config.add_user(user("/etc/wireguard/wg0.conf")).unwrap();
+    config.save_config(&path).unwrap();
+    let saved = fs::read_to_string(&path).unwrap();
+
+    let result = config.with_transaction_at(&path, |draft| {
+      draft.add_user(user("/etc/wireguard/wg1.conf"))?;
+      assert!(draft.remove_user("/etc/wireguard/wg0.conf"));
+      draft.add_user(user("/etc/wireguard/wg1.conf"))
+    });
+    assert!(matches!(result, Err(ConfigError::DuplicateUser(_))));
+    assert_eq!(fs::read_to_string(&path).unwrap(), saved);
+    assert_eq!(config.user, [user("/etc/wireguard/wg0.conf")]);
+
+    let removed = config
+        .with_transaction_at(&path, |draft| {
+          draft.add_user(user("/etc/wireguard/wg1.conf"))?;
+          draft.add_path("/etc/wireguard".to_string())?;
+          Ok::<_, ConfigError>(draft.remove_user("/etc/wireguard/wg0.conf"))
+        })
+        .unwrap();
+    assert!(removed);
+    assert_eq!(Config::load_config(&path).unwrap().user, [user("/etc/wireguard/wg1.conf")]);
+    assert_eq!(config.paths, ["/etc/wireguard"]);
+  }
+
+  #[test]
+  fn saved_files_are_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = temp_dir();
+    let path = temp.path().join("wgbconf.json");
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    // A file written by hand, readable by everyone
+    fs::write(&path, "{}").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+    Config::default().save_config(&path).unwrap();
+    assert_eq!(mode(&path), 0o600);
+    assert_eq!(mode(&backup_path(&path)), 0o600);
+    assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "{}");
+    Config::default().save_config(&path).unwrap();
+    assert_eq!(mode(&backup_path(&path)), 0o600);
+    assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 2);
+  }
+
+  #[test]
+  fn bare_otp_secrets_become_uris() {
+    let otp_uri = canonical_otp_uri("jbsw y3dp ehpk 3pxp", "wg0").unwrap();
+    assert_eq!(otp_uri, "otpauth://totp/WG-Bridge:wg0?secret=JBSWY3DPEHPK3PXP&issuer=WG-Bridge&algorithm=SHA1&digits=6&period=30");
+    check_otp_uri(&otp_uri).unwrap();
+    let user: UserConfig = serde_json::from_value(json!({ "config_path": "/etc/wireguard/wg0.conf", "otp_uri": otp_uri })).unwrap();
+    assert_eq!(user.otp_uri, otp_uri);
+
+    assert_eq!(canonical_otp_uri("JBSWY3DPEHPK3PXP====", "wg0").unwrap(), otp_uri);
+    let full = "otpauth://totp/wgb:alice?secret=JBSWY3DPEHPK3PXP";
+    assert_eq!(canonical_otp_uri(full, "wg0").unwrap(), full);
+    assert!(canonical_otp_uri("JBSWY3DPEHPK3PX1", "wg0").unwrap_err().ends_with("nor base32"));
+    assert!(canonical_otp_uri("JBSWY3DPE", "wg0").unwrap_err().ends_with("truncated base32"));
+    assert!(canonical_otp_uri("otpauth://totp/wgb:alice", "wg0").is_err());
+  }
+
+  #[test]
+  fn normalization_is_idempotent() {
+    let user = |config_path: &str, tags: &[&str]| UserConfig {
+      config_path: config_path.to_string(),
+      tags: tags.iter().map(|tag| tag.to_string()).collect(),
+      ..Default::default()
+    };
+    let mut config = Config {
+      paths: vec!["/etc/wireguard/".to_string(), "~//vpn/.".to_string(), "/etc/wireguard".to_string()],
+      user: vec![user("~/vpn//wg1.conf", &["work", "eu"]), user("/etc/./wireguard/wg0.conf", &[]), user("$VPN/../wg2.conf", &[])],
+      ..Default::default()
+    };
+    config.normalize().unwrap();
+    assert_eq!(config.paths, ["/etc/wireguard", "~/vpn"]);
+    let config_paths: Vec<&str> = config.user.iter().map(|user| user.config_path.as_str()).collect();
+    assert_eq!(config_paths, ["$VPN/../wg2.conf", "/etc/wireguard/wg0.conf", "~/vpn/wg1.conf"]);
+    assert_eq!(config.user[2].tags, ["eu", "work"]);
+
+    for format in [ConfigFormat::Json, ConfigFormat::Toml] {
+      let content = config.to_content(format).unwrap();
+      let mut again = config.clone();
+      again.normalize().unwrap();
+      assert_eq!(again.to_content(format).unwrap(), content);
+    }
+    assert_eq!(normalize_path("./"), ".");
+    assert_eq!(normalize_path("/"), "/");
+
+    config.user.push(user("/etc/wireguard//wg0.conf", &[]));
+    assert!(matches!(config.normalize(), Err(ConfigError::DuplicateUser(path)) if path == "/etc/wireguard/wg0.conf"));
+  }
+
+  #[test]
+  fn tags_are_saved_and_validated() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wgbconf.toml");
+    let mut config = Config::default();
+    config
+        .add_user(UserConfig {
+          config_path: "/etc/wireguard/wg0.conf".to_string(),
+          ..Default::default()
+        })
+        .unwrap();
+    config.save_config(&path).unwrap();
+    assert!(!fs::read_to_string(&path).unwrap().contains("tags"));
+
+    let user = config.get_user_mut("/etc/wireguard/wg0.conf").unwrap();
+    assert!(user.add_tag("work").unwrap());
+    assert!(user.add_tag("eu").unwrap());
+    assert!(!user.add_tag("work").unwrap());
+    for tag in ["", "two words", "a,b"] {
+      assert!(matches!(user.add_tag(tag), Err(ConfigError::InvalidTag(_))), "{tag:?}");
+    }
+    config.save_config(&path).unwrap();
+    let loaded = Config::load_config(&path).unwrap();
+    assert_eq!(loaded.user[0].tags, ["work", "eu"]);
+
+    let user = config.get_user_mut("/etc/wireguard/wg0.conf").unwrap();
+    assert!(user.remove_tag("work"));
+    assert!(!user.remove_tag("work"));
+    config.save_config(&path).unwrap();
+    assert_eq!(Config::load_config(&path).unwrap().user[0].tags, ["eu"]);
+  }
+
+  #[test]
+  fn invalid_fields_are_rejected_at_load() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wgbconf.json");
+    let load = |users: Value| {
+      fs::write(&path, json!({ "schema_version": 1, "user": users }).to_string()).unwrap();
+      Config::load_config(&path)
+    };
+    let otp_uri = "otpauth://totp/wgb:alice?secret=JBSWY3DPEHPK3PXP";
+    let encrypted = crypto::encrypt("passphrase", otp_uri).unwrap();
+    // Unknown fields are still ignored, as they always were
+    let loaded = load(json!([
+      { "config_path": "/etc/wireguard/wg0.conf", "otp": true, "otp_uri": otp_uri, "comment": "home" },
+      { "config_path": "/etc/wireguard/wg1.conf", "otp_uri": encrypted, "otp_encrypted": true, "tags": ["work"] },
+      { "config_path": "/etc/wireguard/wg2.conf", "trusted_networks": [{ "ssid": "Home" }, { "subnet": "192.168.1.0/24" }] },
+    ]))
+    .unwrap();
+    assert_eq!(loaded.user.len(), 3);
+    assert_eq!(loaded.user[2].trusted_networks[0], TrustedNetwork::Ssid("Home".to_string()));
+
+    for (user, message) in [
+      (json!({ "config_path": " " }), "user 2: config_path is empty"),
+      (
+        json!({ "config_path": "/etc/wireguard/wg1.conf", "otp_uri": "https://example.com/?secret=A" }),
+        "user 2: invalid otp_uri: scheme must be otpauth, not https",
+      ),
+      (
+        json!({ "config_path": "/etc/wireguard/wg1.conf", "otp_uri": "JBSWY3DPEHPK3PXP" }),
+        "user 2: invalid otp_uri: expected an otpauth:// URI or an encrypted secret",
+      ),
+      (
+        json!({ "config_path": "/etc/wireguard/wg1.conf", "handshake_timeout": 0 }),
+        "user 2: handshake_timeout must be at least 1 second",
+      ),
+      (
+        json!({ "config_path": "/etc/wireguard/wg1.conf", "connect_timeout": 0 }),
+        "user 2: connect_timeout must be at least 1 second",
+      ),
+      (
+        json!({ "config_path": "/etc/wireguard/wg1.conf", "tags": ["work", "two words"] }),
+        "user 2: invalid tag \"two words\", expected a non-empty word without spaces or commas",
+      ),
+      (
+        json!({ "config_path": "/etc/wireguard/wg1.conf", "trusted_networks": [{ "gateway_mac": "aa:bb:cc" }] }),
+        "user 2: invalid trusted network gateway MAC aa:bb:cc",
+      ),
+    ] {
+      let error = load(json!([{ "config_path": "/etc/wireguard/wg0.conf" }, user])).unwrap_err();
+      assert!(matches!(error, ConfigError::Parse(_)));
+      assert_eq!(error.to_string(), format!("Invalid configuration file: {message}"));
+    }
+  }
+
+  #[test]
+  fn fragments_override_in_name_order() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let fragments = dir.join("conf.d");
+    fs::create_dir_all(&fragments).unwrap();
+    let user = |config_path: &str, tags: &[&str]| json!({ "config_path": config_path, "tags": tags });
+    let write = |name: &str, users: Value| fs::write(fragments.join(name), json!({ "user": users }).to_string()).unwrap();
+    write("20-work.json", json!([user("/etc/wireguard/wg0.conf", &["work"]), user("/etc/wireguard/wg2.conf", &["work"])]));
+    write("10-home.json", json!([user("/etc/wireguard/wg2.conf", &["home"])]));
+    write("notes.txt", json!([user("/etc/wireguard/wg9.conf", &[])]));
+
+    let path = dir.join("wgbconf.json");
+    let mut config = Config::default();
+    config.add_user(serde_json::from_value(user("/etc/wireguard/wg0.conf", &["main"])).unwrap()).unwrap();
+    config.add_user(serde_json::from_value(user("/etc/wireguard/wg1.conf", &[])).unwrap()).unwrap();
+    config.save_config(&path).unwrap();
+
+    let mut merged = Config::load_config(&path).unwrap();
+    merged.merge_fragments(&fragments).unwrap();
+    let users: Vec<(&str, &[String])> = merged.user.iter().map(|u| (u.config_path.as_str(), u.tags.as_slice())).collect();
+    assert_eq!(
+      users,
+      [
+        ("/etc/wireguard/wg0.conf", &["work".to_string()][..]),
+        ("/etc/wireguard/wg1.conf", &[][..]),
+        ("/etc/wireguard/wg2.conf", &["work".to_string()][..]),
+      ]
+    );
+    assert_eq!(merged.user[2].fragment, Some(fragments.join("20-work.json")));
+    assert!(matches!(merged.check_editable("/etc/wireguard/wg0.conf"), Err(ConfigError::FragmentUser { .. })));
+    assert!(merged.check_editable("/etc/wireguard/wg1.conf").is_ok());
+
+    // Saving writes the main file back as it was read
+    merged.save_config(&path).unwrap();
+    assert_eq!(Config::load_config(&path).unwrap().user, config.user);
+  }
+
+  #[test]
+  fn conflicting_fragments_are_rejected() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let write = |users: Value| fs::write(dir.join("work.json"), json!({ "user": users }).to_string()).unwrap();
+    let merged = || Config::default().merge_fragments(dir);
+
+    write(json!([{ "config_path": "/etc/wireguard/wg0.conf" }, { "config_path": "/srv/wg0.conf" }]));
+    let error = merged().unwrap_err();
+    assert!(matches!(&error, ConfigError::ConflictingInterface { interface, .. } if interface == "wg0"));
+    assert_eq!(
+      error.to_string(),
+      format!(
+        "Interface wg0 is defined by both /etc/wireguard/wg0.conf (from {0}) and /srv/wg0.conf (from {0})",
+        dir.join("work.json").display()
+      )
+    );
+    write(json!([{ "config_path": "/etc/wireguard/wg0.conf" }, { "config_path": "/etc/wireguard/wg0.conf" }]));
+    assert!(matches!(merged(), Err(ConfigError::DuplicateInFragment { .. })));
+    write(json!([{ "config_path": "" }]));
+    assert!(matches!(merged(), Err(ConfigError::Fragment { .. })));
+    assert!(Config::default().merge_fragments(&dir.join("missing")).is_ok());
+  }
+
+  #[test]
+  fn log_modes_are_parsed_as_octal() {
+    for (mode, bits) in [("0640", 0o640), ("640", 0o640), ("0o600", 0o600), (" 2750 ", 0o2750)] {
+      assert_eq!(parse_mode(mode).unwrap(), bits, "{mode}");
+    }
+    for mode in ["", "0o", "rw-r-----", "0649", "+640", "17777"] {
+      assert!(matches!(parse_mode(mode), Err(ConfigError::InvalidLogMode(_))), "{mode}");
+    }
+  }
+
+  #[test]
+  fn log_settings_are_read_and_omitted_when_unset() {
+    let config = Config::migrate(json!({
+      "schema_version": 1,
+      "log": { "mode": "0640", "group": 4 },
+    }))
+    .unwrap();
+    let permissions = config.log.permissions().unwrap();
+    assert_eq!((permissions.mode, permissions.owner, permissions.group), (Some(0o640), None, Some(4)));
+
+    let written = serde_json::to_value(Config::default()).unwrap();
+    assert!(written.get("log").is_none());
+
+    let mut config = config;
+    for user in [None, Some("/etc/wireguard/wg0.conf")] {
+      if let Some(config_path) = user {
+        config.add_user(UserConfig { config_path: config_path.to_string(), ..Default::default() }).unwrap();
+      }
+      let written = toml::to_string(&config).unwrap();
+      assert_eq!(Config::migrate(toml::from_str(&written).unwrap()).unwrap(), config);
+    }
+  }
+
+  #[test]
+  fn removing_a_user_keeps_the_others() {
+    let user = |config_path: &str| UserConfig {
+      config_path: config_path.to_string(),
+      ..Default::default()
+    };
+    let mut config = Config {
+      paths: vec!["/etc/wireguard".to_string()],
+      ..Default::default()
+    };
+    config.add_user(user("/etc/wireguard/wg0.conf")).unwrap();
+    config.add_user(user("/etc/wireguard/wg1.conf")).unwrap();
+
+    assert!(config.remove_user("/etc/wireguard/wg0.conf"));
+    assert_eq!(config.user, [user("/etc/wireguard/wg1.conf")]);
+    // Missing users, the removed one or one differing in case, are reported
+    let unchanged = config.clone();
+    for missing in ["/etc/wireguard/wg0.conf", "/etc/wireguard/WG1.conf"] {
+      assert!(!config.remove_user(missing), "{missing}");
+    }
+    assert_eq!(config, unchanged);
+    assert_eq!(config.paths, ["/etc/wireguard"]);
+  }
+
+  #[test]
+  fn users_are_looked_up_by_exact_path() {
+    let mut config = Config::default();
+    config
+        .add_user(UserConfig {
+          config_path: "/etc/wireguard/wg0.conf".to_string(),
+          ..Default::default()
+        })
+        .unwrap();
+
+    assert_eq!(config.get_user("/etc/wireguard/wg0.conf").unwrap().config_path, "/etc/wireguard/wg0.conf");
+    for missing in ["/etc/wireguard/WG0.conf", "/etc/wireguard/wg0.conf ", "/etc/wireguard/wg1.conf"] {
+      assert!(config.get_user(missing).is_none(), "{missing:?}");
+      assert!(config.get_user_mut(missing).is_none(), "{missing:?}");
+    }
+    config.get_user_mut("/etc/wireguard/wg0.conf").unwrap().otp = true;
+    assert!(config.user[0].otp);
+  }
+
+  /// Function to retrieve the path of a configuration file of `tests/fixtures`.
+  fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+  }
+
+  #[test]
+  fn v0_files_are_migrated_and_saved_as_current() {
+    let temp = temp_dir();
+    let path = temp.path().join("wgbconf.json");
+    fs::copy(fixture("v0.wgbconf.json"), &path).unwrap();
+
+    let config = Config::load_config(&path).unwrap();
+    assert_eq!(config.schema_version, SCHEMA_VERSION);
+    let users: Vec<(&str, bool, &str)> =
+      config.user.iter().map(|user| (user.config_path.as_str(), user.otp, user.otp_uri.as_str())).collect();
+    assert_eq!(
+      users,
+      [
+        ("/etc/wireguard/wg0.conf", true, "otpauth://totp/wgb:alice?secret=JBSWY3DPEHPK3PXP"),
+        // The fields missing from v0 get their default
+        ("/etc/wireguard/wg1.conf", false, ""),
+      ]
+    );
+
+    config.save_config(&path).unwrap();
+    let saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved["schema_version"], SCHEMA_VERSION);
+    for field in ["conf_path", "confs", "error_codes"] {
+      assert!(saved.get(field).is_none(), "{field}");
+    }
+    assert_eq!(Config::load_config(&path).unwrap(), config);
+    assert_eq!(Config::load_config(&fixture("v1.wgbconf.json")).unwrap().user.len(), 1);
+    assert!(matches!(
+      Config::migrate(json!({ "schema_version": SCHEMA_VERSION + 1 })),
+      Err(ConfigError::UnsupportedVersion(_))
+    ));
+  }
+
+  #[test]
+  fn sample_configs_round_trip_through_toml() {
+    let temp = temp_dir();
+    let path = temp.path().join("wgbconf.toml");
+    let sample = Path::new(env!("CARGO_MANIFEST_DIR")).join(CONFIG_FILE_NAME);
+    for json_path in [sample, fixture("v0.wgbconf.json"), fixture("v1.wgbconf.json")] {
+      let config = Config::load_config(&json_path).unwrap();
+      config.save_config(&path).unwrap();
+
+      let content = fs::read_to_string(&path).unwrap();
+      assert!(content.starts_with(&format!("schema_version = {SCHEMA_VERSION}\n")), "{content}");
+      assert_eq!(Config::load_config(&path).unwrap(), config, "{}", json_path.display());
+    }
+  }
+
+  #[test]
+  fn config_path_is_resolved_from_the_environment() {
+    let temp = temp_dir();
+    let home = temp.path();
+    let env = |value: &'static str| move |name: &str| (name == CONFIG_ENV).then(|| OsString::from(value));
+
+    assert_eq!(resolve_path_from(&env("/srv/wgb.json"), None).unwrap(), Path::new("/srv/wgb.json"));
+    // An empty override is ignored
+    assert_eq!(resolve_path_from(&env(""), Some(home.to_path_buf())).unwrap(), home.join(CONFIG_FILE_NAME));
+    assert_eq!(resolve_path_from(&|_| None, Some(home.to_path_buf())).unwrap(), home.join(CONFIG_FILE_NAME));
+    fs::write(home.join(TOML_CONFIG_FILE_NAME), "").unwrap();
+    assert_eq!(resolve_path_from(&|_| None, Some(home.to_path_buf())).unwrap(), home.join(TOML_CONFIG_FILE_NAME));
+    assert!(matches!(resolve_path_from(&|_| None, None), Err(ConfigError::NoHomeDir)));
+  }
+
+  #[test]
+  fn watched_configs_reload_valid_changes_only() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wgbconf.json");
+    let config_with = |name: &str| {
+      let config_path = dir.join(name);
+      fs::write(&config_path, "").unwrap();
+      let mut config = Config::default();
+      config
+          .add_user(UserConfig {
+            config_path: config_path.display().to_string(),
+            ..Default::default()
+          })
+          .unwrap();
+      config
+    };
+    let first = config_with("wg0.conf");
+    first.save_config(&path).unwrap();
+    let config: &'static Mutex<Config> = Box::leak(Box::new(Mutex::new(first.clone())));
+    let current = || config.lock().unwrap().clone();
+    // Waits past the debounce for the reload, if any
+    let settle = |expected: &Config| {
+      let deadline = std::time::Instant::now() + Duration::from_secs(5);
+      while current() != *expected && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+      }
+      std::thread::sleep(WATCH_DEBOUNCE * 2);
+    };
+    let watcher = Config::watch_at(config, path.clone()).unwrap();
+
+    let second = config_with("wg1.conf");
+    second.save_config(&path).unwrap();
+    settle(&second);
+    assert_eq!(current(), second);
+
+    // Neither a broken file nor an invalid configuration replaces it
+    fs::write(&path, "{ \"user\": [").unwrap();
+    settle(&second);
+    assert_eq!(current(), second);
+    let mut invalid = second.clone();
+    invalid.user[0].config_path = dir.join("missing.conf").display().to_string();
+    invalid.save_config(&path).unwrap();
+    settle(&second);
+    assert_eq!(current(), second);
+
+    watcher.stop();
+    first.save_config(&path).unwrap();
+    settle(&second);
+    assert_eq!(current(), second);
+  }
+
+  #[test]
+  fn backups_hold_the_previous_save() {
+    let temp = temp_dir();
+    let path = temp.path().join("wgbconf.json");
+    let config_with = |config_path: &str| {
+      let mut config = Config::default();
+      config
+          .add_user(UserConfig {
+            config_path: config_path.to_string(),
+            ..Default::default()
+          })
+          .unwrap();
+      config
+    };
+    let first = config_with("/etc/wireguard/wg0.conf");
+    let second = config_with("/etc/wireguard/wg1.conf");
+
+    first.save_config(&path).unwrap();
+    assert!(!backup_path(&path).exists());
+    let first_content = fs::read_to_string(&path).unwrap();
+    second.save_config(&path).unwrap();
+    assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), first_content);
+    // Only the most recent backup is kept
+    second.save_config(&path).unwrap();
+    assert_eq!(Config::load_config(&backup_path(&path)).unwrap(), second);
+
+    // Restoring is copying the backup back
+    first.save_config(&path).unwrap();
+    fs::copy(backup_path(&path), &path).unwrap();
+    assert_eq!(Config::load_config(&path).unwrap(), second);
+    assert_eq!(backup_path(&path), temp.path().join("wgbconf.json.bak"));
+  }
+
+  #[test]
+  fn paths_expand_home_and_variables() {
+    let home = std::env::home_dir().unwrap().display().to_string();
+    let home_var = std::env::var("HOME").unwrap();
+
+    assert_eq!(expand_path("~").unwrap(), Path::new(&home));
+    assert_eq!(expand_path("~/vpn/wg0.conf").unwrap(), Path::new(&format!("{home}/vpn/wg0.conf")));
+    assert_eq!(expand_path("$HOME/vpn/${HOME}.conf").unwrap(), Path::new(&format!("{home_var}/vpn/{home_var}.conf")));
+    // Relative paths, `~user`, and `$` not starting a variable are kept as is
+    for kept in ["vpn/wg0.conf", "./wg0.conf", "../wg0.conf", "~alice/wg0.conf", "/etc/wire$/wg0.conf", "/etc/${/wg0.conf"] {
+      assert_eq!(expand_path(kept).unwrap(), Path::new(kept), "{kept}");
+    }
+    assert!(matches!(
+      expand_path("$WGB_TEST_UNDEFINED/wg0.conf"),
+      Err(ConfigError::UndefinedVariable(name, path)) if name == "WGB_TEST_UNDEFINED" && path == "$WGB_TEST_UNDEFINED/wg0.conf"
+    ));
+  }
+}
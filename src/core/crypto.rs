@@ -0,0 +1,233 @@
+// crypto.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// Version of the encrypted format, stored as its first byte.
+const FORMAT_VERSION: u8 = 1;
+
+/// Length, in bytes, of the random salt used to derive the key.
+const SALT_LEN: usize = 16;
+
+/// Length, in bytes, of the ChaCha20-Poly1305 nonce.
+const NONCE_LEN: usize = 12;
+
+/// Length, in bytes, of the Poly1305 authentication tag ending the ciphertext.
+const TAG_LEN: usize = 16;
+
+/// Argon2id memory cost, in KiB. The parameters are pinned, rather than taken
+/// from the crate defaults, so that secrets stay readable across upgrades.
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+
+/// Argon2id number of iterations.
+const ARGON2_ITERATIONS: u32 = 2;
+
+/// Argon2id degree of parallelism.
+const ARGON2_PARALLELISM: u32 = 1;
+
+/// Define the errors raised while encrypting or decrypting a secret.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CryptoError {
+  #[error("Failed to derive the key from the passphrase: {0}")]
+  KeyDerivation(String),
+  #[error("Failed to encrypt the secret")]
+  Encrypt,
+  #[error("Wrong passphrase or corrupted secret")]
+  Decrypt,
+  #[error("Malformed encrypted secret")]
+  Malformed,
+}
+
+/// Function to encrypt a secret with a key derived from a passphrase.
+///
+/// The key is derived with Argon2id from the passphrase and a random salt,
+/// and the secret is encrypted with ChaCha20-Poly1305 under a random nonce,
+/// so that encrypting the same secret twice gives unrelated outputs.
+///
+/// # Arguments
+/// * `passphrase`: The passphrase protecting the secret.
+/// * `secret`: The secret to be encrypted.
+///
+/// # Returns
+/// * `Result<String, CryptoError>`: The base64 encoding of the format
+///   version, the salt, the nonce and the ciphertext, in this order.
+pub fn encrypt(passphrase: &str, secret: &str) -> Result<String, CryptoError> {
+  let mut salt = [0u8; SALT_LEN];
+  OsRng.fill_bytes(&mut salt);
+  let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+  let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+  let ciphertext = cipher
+      .encrypt(&nonce, secret.as_bytes())
+      .map_err(|_| CryptoError::Encrypt)?;
+
+  let mut encoded = Vec::with_capacity(1 + SALT_LEN + NONCE_LEN + ciphertext.len());
+  encoded.push(FORMAT_VERSION);
+  encoded.extend_from_slice(&salt);
+  encoded.extend_from_slice(&nonce);
+  encoded.extend_from_slice(&ciphertext);
+  Ok(STANDARD.encode(encoded))
+}
+
+/// Function to decrypt a secret encrypted by `encrypt`.
+///
+/// # Arguments
+/// * `passphrase`: The passphrase protecting the secret.
+/// * `encrypted`: The output of `encrypt`.
+///
+/// # Returns
+/// * `Result<Zeroizing<String>, CryptoError>`: The secret, wiped from memory
+///   when dropped, or `Decrypt` when the passphrase is wrong.
+pub fn decrypt(passphrase: &str, encrypted: &str) -> Result<Zeroizing<String>, CryptoError> {
+  let encoded = STANDARD.decode(encrypted.trim()).map_err(|_| CryptoError::Malformed)?;
+  let (salt, rest) = payload(&encoded)?.split_at(SALT_LEN);
+  let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+  let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+  let secret = cipher
+      .decrypt(Nonce::from_slice(nonce), ciphertext)
+      .map_err(|_| CryptoError::Decrypt)?;
+  match String::from_utf8(secret) {
+    Ok(secret) => Ok(Zeroizing::new(secret)),
+    Err(e) => {
+      drop(Zeroizing::new(e.into_bytes()));
+      Err(CryptoError::Malformed)
+    }
+  }
+}
+
+/// Function to tell whether a value has the shape of a secret encrypted by
+/// `encrypt`, without decrypting it.
+///
+/// # Arguments
+/// * `encrypted`: The value to be checked.
+///
+/// # Returns
+/// * `bool`: Whether the value is base64 of the current format, with room
+///   for the salt, the nonce and the authentication tag.
+pub fn is_encrypted(encrypted: &str) -> bool {
+  STANDARD.decode(encrypted.trim()).is_ok_and(|encoded| payload(&encoded).is_ok())
+}
+
+/// Function to strip the format version of a decoded secret.
+///
+/// # Arguments
+/// * `encoded`: The decoded output of `encrypt`.
+///
+/// # Returns
+/// * `Result<&[u8], CryptoError>`: The salt, the nonce and the ciphertext,
+///   or `Malformed` for another version or a value too short to hold them.
+fn payload(encoded: &[u8]) -> Result<&[u8], CryptoError> {
+  let Some((&FORMAT_VERSION, rest)) = encoded.split_first() else {
+    return Err(CryptoError::Malformed);
+  };
+  if rest.len() < SALT_LEN + NONCE_LEN + TAG_LEN {
+    return Err(CryptoError::Malformed);
+  }
+  Ok(rest)
+}
+
+/// Function to derive the encryption key from a passphrase.
+///
+/// # Arguments
+/// * `passphrase`: The passphrase.
+/// * `salt`: The salt stored along with the ciphertext.
+///
+/// # Returns
+/// * `Result<Key, CryptoError>`: The 256-bit key.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, CryptoError> {
+  let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, ARGON2_PARALLELISM, Some(32))
+      .map_err(|e| CryptoError::KeyDerivation(e.to_string()))?;
+  let mut key = Key::default();
+  Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+      .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+      .map_err(|e| CryptoError::KeyDerivation(e.to_string()))?;
+  Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SECRET: &str = "otpauth://totp/wgb:alice?secret=JBSWY3DPEHPK3PXP&issuer=wgb";
+
+  /// Passphrase of the known-answer vector.
+  const KNOWN_PASSPHRASE: &str = "correct horse battery staple";
+
+  /// `SECRET` encrypted with `KNOWN_PASSPHRASE`, the salt `00..0f` and the
+  /// nonce `10..1b`, by an independent implementation (Python's
+  /// `cryptography`) using the pinned Argon2id parameters.
+  const KNOWN_ENCRYPTED: &str = "AQABAgMEBQYHCAkKCwwNDg8QERITFBUWFxgZGhtDulN+/vWXATHEkAwC0nr1ukLOsAYZJaVyw6Lw3Ti1DgQhj00Xft+mb44RLT4fYRHP1v6IBhesiAd2+cFb855vJOlpVhEg/ugs1Os=";
+
+  /// Key of the known-answer vector, as also derived by `openssl kdf ARGON2ID`.
+  const KNOWN_KEY: [u8; 32] = [
+    0x81, 0x82, 0x59, 0xb6, 0x31, 0x00, 0x26, 0xa8,
+    0xe0, 0xdb, 0xac, 0x5d, 0x2e, 0x69, 0x27, 0xab,
+    0xcf, 0xdb, 0x07, 0xb3, 0x22, 0x58, 0xfa, 0xc4,
+    0xf6, 0x1b, 0x18, 0xb8, 0x0f, 0x92, 0x90, 0x85,
+  ];
+
+  #[test]
+  fn decrypt_round_trips_encrypt() {
+    let first = encrypt("passphrase", SECRET).unwrap();
+    let second = encrypt("passphrase", SECRET).unwrap();
+
+    assert_ne!(first, second);
+    assert!(!first.contains("JBSWY3DPEHPK3PXP"));
+    assert_eq!(*decrypt("passphrase", &first).unwrap(), SECRET);
+    assert_eq!(*decrypt("passphrase", &format!(" {second}\n")).unwrap(), SECRET);
+    assert_eq!(*decrypt("", &encrypt("", "").unwrap()).unwrap(), "");
+  }
+
+  #[test]
+  fn decrypt_rejects_a_wrong_passphrase() {
+    let encrypted = encrypt("passphrase", SECRET).unwrap();
+    assert_eq!(decrypt("Passphrase", &encrypted), Err(CryptoError::Decrypt));
+  }
+
+  #[test]
+  fn decrypt_rejects_a_tampered_ciphertext() {
+    let mut encoded = STANDARD.decode(encrypt("passphrase", SECRET).unwrap()).unwrap();
+    let last = encoded.len() - 1;
+    encoded[last] ^= 1;
+    assert_eq!(decrypt("passphrase", &STANDARD.encode(&encoded)), Err(CryptoError::Decrypt));
+  }
+
+  #[test]
+  fn decrypt_rejects_malformed_input() {
+    let mut unknown_version = vec![FORMAT_VERSION + 1];
+    unknown_version.extend_from_slice(&[0; SALT_LEN + NONCE_LEN + TAG_LEN]);
+    let truncated = [FORMAT_VERSION; 1 + SALT_LEN + NONCE_LEN + TAG_LEN];
+
+    for encrypted in [
+      String::new(),
+      "not base64!".to_string(),
+      STANDARD.encode(unknown_version),
+      STANDARD.encode(&truncated[..truncated.len() - 1]),
+    ] {
+      assert_eq!(decrypt("passphrase", &encrypted), Err(CryptoError::Malformed), "{encrypted}");
+      assert!(!is_encrypted(&encrypted), "{encrypted}");
+    }
+    assert!(is_encrypted(&STANDARD.encode(truncated)));
+    // Well formed, but not encrypted with this passphrase
+    assert_eq!(decrypt("passphrase", &STANDARD.encode(truncated)), Err(CryptoError::Decrypt));
+  }
+
+  #[test]
+  fn known_answer_vector_decrypts() {
+    let salt: Vec<u8> = (0x00..0x10).collect();
+    assert_eq!(derive_key(KNOWN_PASSPHRASE, &salt).unwrap().as_slice(), KNOWN_KEY);
+    assert_eq!(*decrypt(KNOWN_PASSPHRASE, KNOWN_ENCRYPTED).unwrap(), SECRET);
+    assert!(is_encrypted(KNOWN_ENCRYPTED));
+    assert_eq!(decrypt("correct horse battery stapler", KNOWN_ENCRYPTED), Err(CryptoError::Decrypt));
+  }
+}
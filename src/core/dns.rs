@@ -0,0 +1,301 @@
+// dns.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::logger::{wgb_debug, wgb_info};
+use super::wg::CommandRunner;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use thiserror::Error;
+
+/// The program of resolvconf and openresolv, and of the resolvconf
+/// compatibility of systemd-resolved.
+const RESOLVCONF: &str = "resolvconf";
+
+/// The program configuring the links of systemd-resolved.
+const RESOLVECTL: &str = "resolvectl";
+
+/// Define the errors raised while applying the DNS of a tunnel.
+#[derive(Debug, Error)]
+pub enum DnsError {
+  #[error("No DNS backend found, install resolvconf, openresolv or systemd-resolved, or pick one in dns_backend")]
+  NotFound,
+  #[error("Failed to run {program}: {source}")]
+  Spawn { program: &'static str, source: io::Error },
+  #[error("`{command}` failed ({status}): {reason}")]
+  Failed { command: String, status: String, reason: String },
+}
+
+/// Define the subsystem given the DNS servers and search domains of the
+/// tunnels, in place of the `resolvconf` run by `wg-quick`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DnsBackend {
+  /// The one installed, detected on each connect.
+  Auto,
+  /// The `resolvconf` of Debian, whose records of the tunnels are named
+  /// `tun.<interface>` so that `interface-order` ranks them first.
+  Resolvconf,
+  /// openresolv, whose records are exclusive, as `wg-quick` makes them.
+  Openresolv,
+  /// systemd-resolved, configured per link with `resolvectl`.
+  SystemdResolved,
+}
+
+impl fmt::Display for DnsBackend {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      DnsBackend::Auto => "auto",
+      DnsBackend::Resolvconf => "resolvconf",
+      DnsBackend::Openresolv => "openresolv",
+      DnsBackend::SystemdResolved => "systemd-resolved",
+    })
+  }
+}
+
+/// Define a command run by a DNS backend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsCommand {
+  /// The program to be run.
+  pub program: &'static str,
+  /// The arguments of the program.
+  pub args: Vec<String>,
+  /// The input written to the program, if any.
+  pub input: Option<String>,
+}
+
+/// Implements the logic of the DNS backends
+impl DnsBackend {
+  /// Function to tell which backend is installed, for `Auto`.
+  ///
+  /// `resolvconf --version` names openresolv and the compatibility of
+  /// systemd-resolved, while the `resolvconf` of Debian has no such option.
+  /// Without `resolvconf`, systemd-resolved is used when `resolvectl` runs.
+  ///
+  /// # Arguments
+  /// * `runner`: The runner of `resolvconf` and `resolvectl`.
+  ///
+  /// # Returns
+  /// * `Result<DnsBackend, DnsError>`: The backend itself unless `Auto`, the
+  ///   detected one, or `DnsError::NotFound` when none is installed.
+  pub fn detect(self, runner: &dyn CommandRunner) -> Result<DnsBackend, DnsError> {
+    if self != DnsBackend::Auto {
+      return Ok(self);
+    }
+    let detected = match runner.run(RESOLVCONF, &[OsStr::new("--version")]) {
+      Ok(output) => {
+        let version = String::from_utf8_lossy(&output.stdout).to_ascii_lowercase();
+        if version.contains("openresolv") {
+          DnsBackend::Openresolv
+        } else if version.contains("systemd") {
+          DnsBackend::SystemdResolved
+        } else {
+          DnsBackend::Resolvconf
+        }
+      }
+      Err(e) if e.kind() == io::ErrorKind::NotFound => match runner.run(RESOLVECTL, &[OsStr::new("--version")]) {
+        Ok(output) if output.status.success() => DnsBackend::SystemdResolved,
+        _ => return Err(DnsError::NotFound),
+      },
+      Err(source) => {
+        return Err(DnsError::Spawn {
+          program: RESOLVCONF,
+          source,
+        });
+      }
+    };
+    wgb_debug!("Detected the {detected} DNS backend");
+    Ok(detected)
+  }
+
+  /// Function to build the commands giving the DNS of a tunnel to the
+  /// backend.
+  ///
+  /// Like `wg-quick`, the DNS is exclusive: systemd-resolved routes every
+  /// query through the tunnel with the `~.` domain.
+  ///
+  /// # Arguments
+  /// * `interface`: The name of the interface.
+  /// * `servers`: The DNS servers.
+  /// * `domains`: The search domains.
+  ///
+  /// # Returns
+  /// * `Vec<DnsCommand>`: The commands, in order; none for `Auto`, which is
+  ///   detected first.
+  pub fn apply_commands(self, interface: &str, servers: &[IpAddr], domains: &[&str]) -> Vec<DnsCommand> {
+    let record = || {
+      let mut record: String = servers.iter().map(|server| format!("nameserver {server}\n")).collect();
+      if !domains.is_empty() {
+        record.push_str(&format!("search {}\n", domains.join(" ")));
+      }
+      Some(record)
+    };
+    match self {
+      DnsBackend::Auto => Vec::new(),
+      DnsBackend::Resolvconf => vec![command(RESOLVCONF, &["-a", &format!("tun.{interface}")], record())],
+      DnsBackend::Openresolv => vec![command(RESOLVCONF, &["-a", interface, "-m", "0", "-x"], record())],
+      DnsBackend::SystemdResolved => {
+        let servers: Vec<String> = servers.iter().map(IpAddr::to_string).collect();
+        let dns: Vec<&str> = ["dns", interface].into_iter().chain(servers.iter().map(String::as_str)).collect();
+        let domain: Vec<&str> = ["domain", interface].into_iter().chain(domains.iter().copied()).chain(["~."]).collect();
+        vec![command(RESOLVECTL, &dns, None), command(RESOLVECTL, &domain, None)]
+      }
+    }
+  }
+
+  /// Function to build the commands taking the DNS of a tunnel back from
+  /// the backend.
+  ///
+  /// # Arguments
+  /// * `interface`: The name of the interface.
+  ///
+  /// # Returns
+  /// * `Vec<DnsCommand>`: The commands, in order; none for `Auto`.
+  pub fn remove_commands(self, interface: &str) -> Vec<DnsCommand> {
+    match self {
+      DnsBackend::Auto => Vec::new(),
+      DnsBackend::Resolvconf => vec![command(RESOLVCONF, &["-d", &format!("tun.{interface}"), "-f"], None)],
+      DnsBackend::Openresolv => vec![command(RESOLVCONF, &["-d", interface, "-f"], None)],
+      DnsBackend::SystemdResolved => vec![command(RESOLVECTL, &["revert", interface], None)],
+    }
+  }
+}
+
+/// Function to run the commands of a DNS backend, stopping at the first
+/// failing one.
+///
+/// # Arguments
+/// * `runner`: The runner of the commands.
+/// * `commands`: The commands (see `DnsBackend::apply_commands`).
+///
+/// # Returns
+/// * `Result<(), DnsError>`: The error, if any, raised because a program is
+///   missing or failed.
+pub fn run_all(runner: &dyn CommandRunner, commands: &[DnsCommand]) -> Result<(), DnsError> {
+  for dns in commands {
+    let line = dns.args.iter().fold(dns.program.to_string(), |line, arg| format!("{line} {arg}"));
+    wgb_info!("Running {line}");
+    let args: Vec<&OsStr> = dns.args.iter().map(OsStr::new).collect();
+    let output = match &dns.input {
+      Some(input) => runner.run_with_input(dns.program, &args, input.as_bytes()),
+      None => runner.run(dns.program, &args),
+    }
+    .map_err(|source| DnsError::Spawn {
+      program: dns.program,
+      source,
+    })?;
+    if !output.status.success() {
+      let stderr = String::from_utf8_lossy(&output.stderr);
+      return Err(DnsError::Failed {
+        command: line,
+        status: output.status.to_string(),
+        reason: stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("no error message").to_string(),
+      });
+    }
+  }
+  Ok(())
+}
+
+/// Function to build a command of a DNS backend.
+///
+/// # Arguments
+/// * `program`: The program to be run.
+/// * `args`: The arguments of the program.
+/// * `input`: The input written to the program, if any.
+///
+/// # Returns
+/// * `DnsCommand`: The command.
+fn command(program: &'static str, args: &[&str], input: Option<String>) -> DnsCommand {
+  DnsCommand {
+    program,
+    args: args.iter().map(|arg| arg.to_string()).collect(),
+    input,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::init_logger;
+  use std::os::unix::process::ExitStatusExt;
+  use std::process::{ExitStatus, Output};
+
+  /// Define a runner answering `--version` with the given output, or as if
+  /// the program was not installed.
+  #[derive(Debug)]
+  struct VersionRunner {
+    resolvconf: Option<&'static str>,
+    resolvectl: bool,
+  }
+
+  impl CommandRunner for VersionRunner {
+    fn run(&self, program: &str, _args: &[&OsStr]) -> io::Result<Output> {
+      let stdout = match program {
+        RESOLVCONF => self.resolvconf.ok_or(io::ErrorKind::NotFound)?,
+        _ if self.resolvectl => "systemd 255 (255.4-1)\n",
+        _ => return Err(io::ErrorKind::NotFound.into()),
+      };
+      Ok(Output {
+        status: ExitStatus::from_raw(0),
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: Vec::new(),
+      })
+    }
+  }
+
+  fn lines(commands: &[DnsCommand]) -> Vec<String> {
+    commands
+        .iter()
+        .map(|dns| {
+          let line = dns.args.iter().fold(dns.program.to_string(), |line, arg| format!("{line} {arg}"));
+          match &dns.input {
+            Some(input) => format!("{line} < {input:?}"),
+            None => line,
+          }
+        })
+        .collect()
+  }
+
+  #[test]
+  fn each_backend_runs_its_own_commands() {
+    let servers: [IpAddr; 2] = ["10.0.0.1".parse().unwrap(), "fd00::1".parse().unwrap()];
+    let domains = ["example.internal"];
+    assert_eq!(
+      lines(&DnsBackend::Resolvconf.apply_commands("wg0", &servers, &domains)),
+      [r#"resolvconf -a tun.wg0 < "nameserver 10.0.0.1\nnameserver fd00::1\nsearch example.internal\n""#]
+    );
+    assert_eq!(lines(&DnsBackend::Resolvconf.remove_commands("wg0")), ["resolvconf -d tun.wg0 -f"]);
+    assert_eq!(
+      lines(&DnsBackend::Openresolv.apply_commands("wg0", &servers[..1], &[])),
+      [r#"resolvconf -a wg0 -m 0 -x < "nameserver 10.0.0.1\n""#]
+    );
+    assert_eq!(lines(&DnsBackend::Openresolv.remove_commands("wg0")), ["resolvconf -d wg0 -f"]);
+    assert_eq!(
+      lines(&DnsBackend::SystemdResolved.apply_commands("wg0", &servers, &domains)),
+      ["resolvectl dns wg0 10.0.0.1 fd00::1", "resolvectl domain wg0 example.internal ~."]
+    );
+    assert_eq!(lines(&DnsBackend::SystemdResolved.remove_commands("wg0")), ["resolvectl revert wg0"]);
+    assert!(DnsBackend::Auto.apply_commands("wg0", &servers, &domains).is_empty());
+  }
+
+  #[test]
+  fn the_installed_backend_is_detected() {
+    init_logger();
+    let detect = |resolvconf, resolvectl| DnsBackend::Auto.detect(&VersionRunner { resolvconf, resolvectl });
+    assert_eq!(detect(Some("openresolv 3.12.0\n"), false).unwrap(), DnsBackend::Openresolv);
+    assert_eq!(detect(Some("systemd 255 (255.4-1)\n"), true).unwrap(), DnsBackend::SystemdResolved);
+    assert_eq!(detect(Some(""), false).unwrap(), DnsBackend::Resolvconf);
+    assert_eq!(detect(None, true).unwrap(), DnsBackend::SystemdResolved);
+    assert!(matches!(detect(None, false), Err(DnsError::NotFound)));
+    // A backend picked in the configuration is not detected
+    let runner = VersionRunner {
+      resolvconf: None,
+      resolvectl: false,
+    };
+    assert_eq!(DnsBackend::Openresolv.detect(&runner).unwrap(), DnsBackend::Openresolv);
+  }
+}
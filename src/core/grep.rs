@@ -0,0 +1,281 @@
+// grep.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use crate::core::logger::LogLevel;
+use flate2::read::GzDecoder;
+use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Define the criteria of a search through the log files.
+#[derive(Clone, Debug)]
+pub struct GrepOptions {
+  /// The pattern a line must match.
+  pub pattern: Regex,
+  /// The minimum level of a matching line, if any; lines without a level
+  /// never match then.
+  pub min_level: Option<LogLevel>,
+  /// The number of lines printed before and after each match.
+  pub context: usize,
+}
+
+/// Define a search through one or more log files, printing the matching
+/// lines as `file:line:text` and the context lines as `file-line-text`, the
+/// way `grep` does.
+#[derive(Debug)]
+pub struct LogGrep<'a> {
+  /// The criteria of the search.
+  options: &'a GrepOptions,
+  /// Whether a line was printed, so that groups of lines are separated by
+  /// `--` when context is requested.
+  printed: bool,
+}
+
+/// Implements the logic of the search through the log files
+impl<'a> LogGrep<'a> {
+  /// Function to start a search.
+  ///
+  /// # Arguments
+  /// * `options`: The criteria of the search.
+  pub fn new(options: &'a GrepOptions) -> Self {
+    LogGrep { options, printed: false }
+  }
+
+  /// Function to search a log file, read line by line so that large files
+  /// are never loaded in memory. Lines which are not valid UTF-8 are read
+  /// lossily.
+  ///
+  /// # Arguments
+  /// * `out`: The writer the lines are printed to (e.g. stdout).
+  /// * `name`: The name of the file, printed before each line.
+  /// * `reader`: The content of the file.
+  ///
+  /// # Returns
+  /// * `io::Result<usize>`: The number of matching lines, or the error raised
+  ///   reading or printing.
+  pub fn search(&mut self, out: &mut impl Write, name: &str, mut reader: impl BufRead) -> io::Result<usize> {
+    let context = self.options.context;
+    let mut before: VecDeque<(usize, String)> = VecDeque::with_capacity(context);
+    let mut after = 0;
+    let mut last_printed: Option<usize> = None;
+    let mut matches = 0;
+    let mut buffer = Vec::new();
+    let mut number = 0;
+
+    loop {
+      buffer.clear();
+      if reader.read_until(b'\n', &mut buffer)? == 0 {
+        return Ok(matches);
+      }
+      number += 1;
+      let line = String::from_utf8_lossy(&buffer);
+      let line = line.trim_end_matches(['\n', '\r']);
+
+      if self.matches(line) {
+        let first = before.front().map_or(number, |(first, _)| *first);
+        let contiguous = last_printed.is_some_and(|last| last + 1 >= first);
+        if context > 0 && self.printed && !contiguous {
+          writeln!(out, "--")?;
+        }
+        for (before_number, before_line) in before.drain(..) {
+          writeln!(out, "{name}-{before_number}-{before_line}")?;
+        }
+        writeln!(out, "{name}:{number}:{line}")?;
+        self.printed = true;
+        last_printed = Some(number);
+        after = context;
+        matches += 1;
+      } else if after > 0 {
+        writeln!(out, "{name}-{number}-{line}")?;
+        last_printed = Some(number);
+        after -= 1;
+      } else if context > 0 {
+        if before.len() == context {
+          before.pop_front();
+        }
+        before.push_back((number, line.to_string()));
+      }
+    }
+  }
+
+  /// Function to tell whether a line matches the criteria of the search.
+  ///
+  /// # Arguments
+  /// * `line`: The line, without its line break.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the line matches the pattern and the minimum level.
+  fn matches(&self, line: &str) -> bool {
+    let level_matches = match self.options.min_level {
+      Some(min_level) => line_level(line).is_some_and(|level| level >= min_level),
+      None => true,
+    };
+    level_matches && self.options.pattern.is_match(line)
+  }
+}
+
+/// Function to list the dated log files of a directory, i.e.
+/// `YYYY-MM-DD.log` and their rotated, possibly compressed, copies
+/// `YYYY-MM-DD.log.N[.gz]`.
+///
+/// # Arguments
+/// * `dir`: The directory holding the log files.
+///
+/// # Returns
+/// * `io::Result<Vec<PathBuf>>`: The log files from the oldest to the
+///   newest, or the error raised reading the directory.
+pub fn log_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+  static NAME: OnceLock<Regex> = OnceLock::new();
+  let name = NAME.get_or_init(|| Regex::new(r"^(\d{4}-\d{2}-\d{2})\.log(?:\.(\d+))?(?:\.gz)?$").expect("valid regex"));
+
+  let mut files = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    if let Some(captures) = name.captures(&file_name) {
+      let date = captures[1].to_string();
+      // A higher rotation index is an older part of the same day
+      let index: usize = captures.get(2).map_or(0, |index| index.as_str().parse().unwrap_or(usize::MAX));
+      files.push(((date, Reverse(index)), path));
+    }
+  }
+  files.sort();
+  Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Function to open a log file, decompressing it when gzip-compressed.
+///
+/// # Arguments
+/// * `path`: The path to the log file.
+///
+/// # Returns
+/// * `io::Result<Box<dyn BufRead>>`: The reader of the lines of the file, or
+///   the error raised opening it.
+pub fn open_log(path: &Path) -> io::Result<Box<dyn BufRead>> {
+  let file = File::open(path)?;
+  if path.extension().is_some_and(|extension| extension == "gz") {
+    Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+  } else {
+    Ok(Box::new(BufReader::new(file)))
+  }
+}
+
+/// Function to read the level of a log line, in text or JSON format.
+///
+/// # Arguments
+/// * `line`: The log line.
+///
+/// # Returns
+/// * `Option<LogLevel>`: The level, or `None` for a line without one.
+fn line_level(line: &str) -> Option<LogLevel> {
+  if line.starts_with('{') {
+    let entry: serde_json::Value = serde_json::from_str(line).ok()?;
+    return entry["level"].as_str()?.parse().ok();
+  }
+  let (_, rest) = line.split_once(" - ")?;
+  rest.split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::temp_dir;
+  use flate2::Compression;
+  use flate2::write::GzEncoder;
+
+  /// A log in the text format, with a line of each kind of level.
+  const LOG: &str = "\
+2025-03-01 10:00:00.000 - INFO      Starting
+2025-03-01 10:00:01.000 - DEBUG     [core::wg] Running wg-quick up wg0.conf
+2025-03-01 10:00:02.000 - ERROR     [core::wg] wg-quick: Address already in use
+2025-03-01 10:00:03.000 - INFO      Retrying
+2025-03-01 10:00:04.000 - WARN      [core::wg] Handshake for wg0 is stale
+2025-03-01 10:00:05.000 - INFO      Stopping
+";
+
+  /// Function to search a log and collect the printed lines.
+  fn grep(pattern: &str, min_level: Option<LogLevel>, context: usize, logs: &[(&str, &str)]) -> (usize, String) {
+    let options = GrepOptions {
+      pattern: Regex::new(pattern).unwrap(),
+      min_level,
+      context,
+    };
+    let mut search = LogGrep::new(&options);
+    let mut out = Vec::new();
+    let matches = logs
+        .iter()
+        .map(|(name, log)| search.search(&mut out, name, log.as_bytes()).unwrap())
+        .sum();
+    (matches, String::from_utf8(out).unwrap())
+  }
+
+  #[test]
+  fn matches_are_printed_with_their_line() {
+    let (matches, out) = grep("wg0", None, 0, &[("today.log", LOG)]);
+    assert_eq!(matches, 2);
+    let numbers: Vec<&str> = out.lines().map(|line| line.split(':').nth(1).unwrap()).collect();
+    assert_eq!(numbers, ["2", "5"]);
+    assert!(out.starts_with("today.log:2:2025-03-01 10:00:01.000 - DEBUG"));
+  }
+
+  #[test]
+  fn context_lines_surround_the_matches() {
+    let (_, out) = grep("Starting|Stopping", None, 1, &[("a.log", LOG)]);
+    let prefixes: Vec<&str> = out.lines().map(|line| &line[..line.len().min(7)]).collect();
+    assert_eq!(prefixes, ["a.log:1", "a.log-2", "--", "a.log-5", "a.log:6"]);
+
+    // Overlapping context is printed once, without a separator
+    let (_, out) = grep("ERROR|WARN", None, 1, &[("a.log", LOG)]);
+    assert_eq!(out.lines().count(), 5);
+    assert!(!out.contains("--\n"));
+
+    // Groups of different files are separated as well
+    let (matches, out) = grep("Stopping", None, 1, &[("a.log", LOG), ("b.log", LOG)]);
+    assert_eq!(matches, 2);
+    assert_eq!(out.lines().nth(2), Some("--"));
+  }
+
+  #[test]
+  fn levels_below_the_minimum_are_skipped() {
+    let (matches, out) = grep("wg", Some(LogLevel::Warn), 0, &[("a.log", LOG)]);
+    assert_eq!(matches, 2);
+    assert!(out.lines().all(|line| line.contains("ERROR") || line.contains("WARN")));
+
+    let json = "{\"level\":\"ERROR\",\"message\":\"wg-quick failed\"}\n{\"level\":\"INFO\",\"message\":\"wg-quick up\"}\n";
+    assert_eq!(grep("wg-quick", Some(LogLevel::Error), 0, &[("a.log", json)]).0, 1);
+    assert_eq!(grep("wg-quick", None, 0, &[("a.log", json)]).0, 2);
+    assert_eq!(grep("panicked", Some(LogLevel::Trace), 0, &[("a.log", "thread panicked\n")]).0, 0);
+  }
+
+  #[test]
+  fn log_files_are_listed_from_the_oldest() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    for name in ["2025-03-02.log", "2025-03-01.log", "2025-03-01.log.1", "notes.txt", "2025-03-01.log.2"] {
+      fs::write(dir.join(name), LOG).unwrap();
+    }
+    let mut encoder = GzEncoder::new(File::create(dir.join("2025-03-01.log.3.gz")).unwrap(), Compression::default());
+    encoder.write_all(LOG.as_bytes()).unwrap();
+    encoder.finish().unwrap();
+
+    let files = log_files(dir).unwrap();
+    let names: Vec<String> = files.iter().map(|path| path.file_name().unwrap().to_string_lossy().into_owned()).collect();
+    assert_eq!(
+      names,
+      ["2025-03-01.log.3.gz", "2025-03-01.log.2", "2025-03-01.log.1", "2025-03-01.log", "2025-03-02.log"]
+    );
+    let options = GrepOptions {
+      pattern: Regex::new("stale").unwrap(),
+      min_level: None,
+      context: 0,
+    };
+    let mut out = Vec::new();
+    assert_eq!(LogGrep::new(&options).search(&mut out, &names[0], open_log(&files[0]).unwrap()).unwrap(), 1);
+  }
+}
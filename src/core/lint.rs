@@ -0,0 +1,213 @@
+// lint.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use crate::core::wg::{Peer, WgConfig};
+use serde::Serialize;
+use std::fmt;
+
+/// The lowest MTU allowed for IPv6 traffic, below which a tunnel may drop it.
+const MIN_MTU: u16 = 1280;
+
+/// Define the severity of a lint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  /// A suggestion which does not affect the tunnel.
+  Info,
+  /// An issue which may break the tunnel or leak traffic.
+  Warning,
+}
+
+/// Define a best-practice issue found in a WireGuard configuration.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Lint {
+  /// The short identifier of the check.
+  pub code: &'static str,
+  /// The severity of the issue.
+  pub severity: Severity,
+  /// The section the issue was found in, e.g. `Interface` or `Peer 2`.
+  pub section: String,
+  /// The explanation of the issue and of how to fix it.
+  pub message: String,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Severity::Info => write!(f, "info"),
+      Severity::Warning => write!(f, "warning"),
+    }
+  }
+}
+
+impl fmt::Display for Lint {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {}: {} [{}]", self.severity, self.section, self.message, self.code)
+  }
+}
+
+/// Function to check a WireGuard configuration against best practices.
+///
+/// # Arguments
+/// * `config`: The configuration to be checked.
+///
+/// # Returns
+/// * `Vec<Lint>`: The issues found, the interface ones first and then the
+///   ones of each peer in file order.
+pub fn lint(config: &WgConfig) -> Vec<Lint> {
+  let interface = &config.interface;
+  let mut lints = Vec::new();
+  let mut push = |code, severity, section: &str, message: String| {
+    lints.push(Lint { code, severity, section: section.to_string(), message });
+  };
+
+  if let Some(mtu) = interface.mtu
+      && mtu < MIN_MTU
+  {
+    push(
+      "low-mtu",
+      Severity::Warning,
+      "Interface",
+      format!("MTU {mtu} is below {MIN_MTU}, the minimum required by IPv6: leave it unset unless the path needs it"),
+    );
+  }
+  if interface.save_config {
+    push(
+      "save-config",
+      Severity::Warning,
+      "Interface",
+      "SaveConfig is set, so wg-quick overwrites the file with the live state when the tunnel goes down".to_string(),
+    );
+  }
+  if interface.dns_servers().is_empty()
+      && let Some(route) = config.peers.iter().find_map(default_route)
+  {
+    push(
+      "dns-leak",
+      Severity::Warning,
+      "Interface",
+      format!("all traffic is routed through the tunnel ({route}) but no DNS server is set, so queries leak to the local resolver"),
+    );
+  }
+
+  for (index, peer) in config.peers.iter().enumerate() {
+    let section = format!("Peer {}", index + 1);
+    if peer.endpoint.is_some() && peer.persistent_keepalive.is_none() && interface.listen_port.is_none() {
+      push(
+        "missing-keepalive",
+        Severity::Warning,
+        &section,
+        "no PersistentKeepalive is set, so a NAT in front of this host may drop the tunnel when idle: 25 is a common value"
+            .to_string(),
+      );
+    }
+    if peer.preshared_key.is_none() {
+      push(
+        "missing-preshared-key",
+        Severity::Info,
+        &section,
+        "no PresharedKey is set: one adds a symmetric layer of protection against future quantum attacks".to_string(),
+      );
+    }
+    if peer.allowed_ips.is_empty() {
+      push(
+        "no-allowed-ips",
+        Severity::Warning,
+        &section,
+        "no AllowedIPs are set, so no traffic is ever routed to or accepted from this peer".to_string(),
+      );
+    }
+  }
+  lints
+}
+
+/// Function to find the default route among the allowed IPs of a peer.
+///
+/// # Arguments
+/// * `peer`: The peer to be checked.
+///
+/// # Returns
+/// * `Option<&str>`: The default route, if the peer takes all the traffic.
+fn default_route(peer: &Peer) -> Option<&str> {
+  peer.allowed_ips.iter().map(String::as_str).find(|ip| matches!(*ip, "0.0.0.0/0" | "::/0"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::KEY;
+
+  /// Function to collect the codes of the lints of a configuration.
+  fn codes(config: &str) -> Vec<&'static str> {
+    lint(&WgConfig::parse_str(config).unwrap()).into_iter().map(|lint| lint.code).collect()
+  }
+
+  #[test]
+  fn clean_config_has_no_lints() {
+    let config = format!(
+      "[Interface]\nPrivateKey = {KEY}\nDNS = 1.1.1.1\n\n[Peer]\nPublicKey = {KEY}\nPresharedKey = {KEY}\n\
+       Endpoint = vpn.example.com:51820\nAllowedIPs = 0.0.0.0/0\nPersistentKeepalive = 25\n"
+    );
+    assert!(codes(&config).is_empty());
+  }
+
+  #[test]
+  fn full_tunnel_without_dns_leaks() {
+    let config = format!("[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\nPresharedKey = {KEY}\nAllowedIPs = ::/0\n");
+    let lints = lint(&WgConfig::parse_str(&config).unwrap());
+    assert_eq!(lints.len(), 1);
+    assert_eq!(lints[0].code, "dns-leak");
+    assert_eq!(lints[0].severity, Severity::Warning);
+    assert!(lints[0].message.contains("::/0"));
+    // Search domains alone resolve nothing
+    let config = config.replace("\n\n[Peer]", "\nDNS = corp.example\n\n[Peer]");
+    assert_eq!(codes(&config), ["dns-leak"]);
+  }
+
+  #[test]
+  fn client_behind_nat_needs_keepalive() {
+    let config = format!(
+      "[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\nPresharedKey = {KEY}\n\
+       Endpoint = vpn.example.com:51820\nAllowedIPs = 10.0.0.0/24\n"
+    );
+    assert_eq!(codes(&config), ["missing-keepalive"]);
+    // A server listening on a fixed port is reached by its peers instead.
+    assert!(codes(&config.replace("[Peer]", "ListenPort = 51820\n\n[Peer]")).is_empty());
+  }
+
+  #[test]
+  fn missing_preshared_key_is_info() {
+    let config = format!("[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\nAllowedIPs = 10.0.0.2/32\n");
+    let lints = lint(&WgConfig::parse_str(&config).unwrap());
+    assert_eq!(lints.len(), 1);
+    assert_eq!(lints[0].code, "missing-preshared-key");
+    assert_eq!(lints[0].severity, Severity::Info);
+    assert_eq!(lints[0].section, "Peer 1");
+  }
+
+  #[test]
+  fn interface_lints() {
+    let config = format!("[Interface]\nPrivateKey = {KEY}\nMTU = 1000\nSaveConfig = true\n");
+    assert_eq!(codes(&config), ["low-mtu", "save-config"]);
+    assert!(codes(&config.replace("1000", "1420")).iter().all(|code| *code != "low-mtu"));
+  }
+
+  #[test]
+  fn peer_without_allowed_ips() {
+    let config = format!("[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\nPresharedKey = {KEY}\n");
+    assert_eq!(codes(&config), ["no-allowed-ips"]);
+  }
+
+  #[test]
+  fn lint_display() {
+    let lint = Lint {
+      code: "low-mtu",
+      severity: Severity::Warning,
+      section: "Interface".to_string(),
+      message: "MTU is low".to_string(),
+    };
+    assert_eq!(lint.to_string(), "warning: Interface: MTU is low [low-mtu]");
+  }
+}
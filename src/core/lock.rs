@@ -0,0 +1,253 @@
+// lock.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Directory holding the lock files, shared by every user on Debian.
+const LOCK_DIR: &str = "/run/lock";
+
+/// Name of the lock taken shared by the operations on a single tunnel and
+/// exclusively by the ones on every tunnel.
+const GLOBAL_LOCK: &str = "wgb.lock";
+
+/// Time between two attempts to take a lock held by another process.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Define the errors raised while taking a tunnel lock.
+#[derive(Debug, Error)]
+pub enum LockError {
+  #[error("Failed to lock {}: {source}", .path.display())]
+  Io { path: PathBuf, source: io::Error },
+  #[error("Another wgb command is managing {what}, gave up waiting after {:.1}s", .waited.as_secs_f32())]
+  Busy { what: String, waited: Duration },
+}
+
+/// Define a lock serializing the operations on a tunnel across processes,
+/// so that two `connect` of the same interface never interleave and leave
+/// half-applied routes.
+///
+/// The locks are advisory `flock` locks on files of `/run/lock`, released by
+/// the kernel when the guard is dropped or the process dies, so that a crash
+/// never leaves a stale lock behind.
+#[derive(Debug)]
+pub struct TunnelLock {
+  /// The locked files, unlocked when closed.
+  _files: Vec<File>,
+}
+
+/// Implements the logic to take the tunnel locks
+impl TunnelLock {
+  /// Function to lock a single tunnel, waiting for the commands holding it
+  /// or locking every tunnel.
+  ///
+  /// # Arguments
+  /// * `interface`: The name of the interface of the tunnel.
+  /// * `wait`: The longest time to wait for the lock.
+  ///
+  /// # Returns
+  /// * `Result<TunnelLock, LockError>`: The lock, released when dropped, or
+  ///   the error raised when it could not be taken in time.
+  pub fn interface(interface: &str, wait: Duration) -> Result<Self, LockError> {
+    Self::interface_in(&lock_dir(), interface, wait)
+  }
+
+  /// Function to lock every tunnel, e.g. for `disconnect --all`, waiting for
+  /// the commands holding any of them.
+  ///
+  /// # Arguments
+  /// * `wait`: The longest time to wait for the lock.
+  ///
+  /// # Returns
+  /// * `Result<TunnelLock, LockError>`: The lock, released when dropped, or
+  ///   the error raised when it could not be taken in time.
+  pub fn all(wait: Duration) -> Result<Self, LockError> {
+    Self::all_in(&lock_dir(), wait)
+  }
+
+  /// Function to lock a single tunnel with the lock files of a directory.
+  ///
+  /// # Arguments
+  /// * `dir`: The directory holding the lock files.
+  /// * `interface`: The name of the interface of the tunnel.
+  /// * `wait`: The longest time to wait for the lock.
+  ///
+  /// # Returns
+  /// * `Result<TunnelLock, LockError>`: The lock, or the error raised when it
+  ///   could not be taken in time.
+  fn interface_in(dir: &Path, interface: &str, wait: Duration) -> Result<Self, LockError> {
+    let deadline = Instant::now() + wait;
+    let global = lock_file(&dir.join(GLOBAL_LOCK), Mode::Shared, deadline, interface)?;
+    let tunnel = lock_file(&dir.join(format!("wgb-{interface}.lock")), Mode::Exclusive, deadline, interface)?;
+    Ok(TunnelLock {
+      _files: vec![global, tunnel],
+    })
+  }
+
+  /// Function to lock every tunnel with the lock files of a directory.
+  ///
+  /// # Arguments
+  /// * `dir`: The directory holding the lock files.
+  /// * `wait`: The longest time to wait for the lock.
+  ///
+  /// # Returns
+  /// * `Result<TunnelLock, LockError>`: The lock, or the error raised when it
+  ///   could not be taken in time.
+  fn all_in(dir: &Path, wait: Duration) -> Result<Self, LockError> {
+    let global = lock_file(&dir.join(GLOBAL_LOCK), Mode::Exclusive, Instant::now() + wait, "every tunnel")?;
+    Ok(TunnelLock { _files: vec![global] })
+  }
+}
+
+/// Define how a lock file is locked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+  /// Along with other shared holders.
+  Shared,
+  /// Alone.
+  Exclusive,
+}
+
+/// Function to retrieve the directory holding the lock files.
+///
+/// # Returns
+/// * `PathBuf`: `/run/lock`, or the temporary directory when it is missing.
+fn lock_dir() -> PathBuf {
+  let dir = Path::new(LOCK_DIR);
+  if dir.is_dir() { dir.to_path_buf() } else { std::env::temp_dir() }
+}
+
+/// Function to lock a file, polling until the lock is free or the deadline
+/// passes.
+///
+/// # Arguments
+/// * `path`: The path to the lock file, created when missing.
+/// * `mode`: How the file is locked.
+/// * `deadline`: The time after which the lock is given up.
+/// * `what`: The locked tunnels, for the error message.
+///
+/// # Returns
+/// * `Result<File, LockError>`: The locked file, or the error raised opening
+///   it or because it is still held at the deadline.
+fn lock_file(path: &Path, mode: Mode, deadline: Instant, what: &str) -> Result<File, LockError> {
+  let io_error = |source| LockError::Io {
+    path: path.to_path_buf(),
+    source,
+  };
+  let started = Instant::now();
+  // A lock file created by another user can still be locked read-only
+  let file = OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(path)
+      .or_else(|e| match e.kind() {
+        io::ErrorKind::PermissionDenied => File::open(path),
+        _ => Err(e),
+      })
+      .map_err(io_error)?;
+  loop {
+    match try_lock(&file, mode) {
+      Ok(true) => return Ok(file),
+      Ok(false) if Instant::now() >= deadline => {
+        return Err(LockError::Busy {
+          what: what.to_string(),
+          waited: started.elapsed(),
+        });
+      }
+      Ok(false) => thread::sleep(POLL_INTERVAL),
+      Err(e) => return Err(io_error(e)),
+    }
+  }
+}
+
+/// Function to try to lock a file without waiting.
+///
+/// # Arguments
+/// * `file`: The lock file.
+/// * `mode`: How the file is locked.
+///
+/// # Returns
+/// * `io::Result<bool>`: Whether the file was locked, `false` when another
+///   holder prevents it, or the error raised locking it.
+#[cfg(unix)]
+fn try_lock(file: &File, mode: Mode) -> io::Result<bool> {
+  use std::os::fd::AsRawFd;
+
+  let operation = match mode {
+    Mode::Shared => libc::LOCK_SH,
+    Mode::Exclusive => libc::LOCK_EX,
+  };
+  // SAFETY: the descriptor belongs to `file`, which outlives the call
+  if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+    return Ok(true);
+  }
+  let error = io::Error::last_os_error();
+  match error.raw_os_error() {
+    Some(libc::EWOULDBLOCK) => Ok(false),
+    _ => Err(error),
+  }
+}
+
+/// Function to try to lock a file without waiting.
+///
+/// File locks are left to Unix, so this always succeeds on other platforms.
+#[cfg(not(unix))]
+fn try_lock(_file: &File, _mode: Mode) -> io::Result<bool> {
+  Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::temp_dir;
+  use std::sync::{Arc, Mutex};
+
+  #[test]
+  fn connects_of_the_same_interface_serialize() {
+    let temp = temp_dir();
+    let dir = Arc::new(temp.path().to_path_buf());
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let connect = |id: usize| {
+      let (dir, events) = (Arc::clone(&dir), Arc::clone(&events));
+      thread::spawn(move || {
+        let _lock = TunnelLock::interface_in(&dir, "wg0", Duration::from_secs(10)).unwrap();
+        events.lock().unwrap().push(format!("start {id}"));
+        thread::sleep(Duration::from_millis(100));
+        events.lock().unwrap().push(format!("end {id}"));
+      })
+    };
+    let threads = [connect(1), connect(2)];
+    threads.into_iter().for_each(|thread| thread.join().unwrap());
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 4);
+    for pair in events.chunks(2) {
+      assert_eq!(pair[0].replace("start", "end"), pair[1], "{events:?}");
+    }
+  }
+
+  #[test]
+  fn busy_locks_time_out() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let held = TunnelLock::interface_in(dir, "wg0", Duration::ZERO).unwrap();
+    // Another interface is independent, the whole set is not
+    let other = TunnelLock::interface_in(dir, "wg1", Duration::ZERO).unwrap();
+    let error = TunnelLock::interface_in(dir, "wg0", Duration::from_millis(50)).unwrap_err();
+    assert!(matches!(&error, LockError::Busy { what, .. } if what == "wg0"));
+    assert!(matches!(TunnelLock::all_in(dir, Duration::ZERO), Err(LockError::Busy { .. })));
+
+    drop((held, other));
+    let all = TunnelLock::all_in(dir, Duration::ZERO).unwrap();
+    assert!(matches!(TunnelLock::interface_in(dir, "wg0", Duration::ZERO), Err(LockError::Busy { .. })));
+    drop(all);
+    assert!(TunnelLock::interface_in(dir, "wg0", Duration::ZERO).is_ok());
+  }
+}
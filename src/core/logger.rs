@@ -3,16 +3,796 @@
 // This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
 // See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
 
-use chrono::Local;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::sync::OnceLock;
+use super::clock::{Clock, SystemClock};
+use super::color::{self, Color};
+use chrono::{DateTime, Local, NaiveDate, SecondsFormat};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use regex::Regex;
+use std::backtrace::Backtrace;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, LazyLock, Mutex, OnceLock};
 use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Default maximum length, in bytes, of a single log message.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/// Default size, in bytes, after which the log file is rotated.
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Default number of rotated log files kept on disk.
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+/// Default number of lines the queue to the writer thread holds before the
+/// `OnFull` policy applies.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 8192;
+
+/// Environment variable used to set the minimum log level.
+pub const LOG_LEVEL_ENV: &str = "WGB_LOG_LEVEL";
+
+/// Environment variable used to set per-target minimum log levels, e.g.
+/// `core::wg=debug,cli=warn`.
+pub const LOG_TARGETS_ENV: &str = "WGB_LOG";
+
+/// Define the severity levels of the log messages, from the least to the most
+/// severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+  Trace,
+  Debug,
+  Info,
+  Warn,
+  Error,
+}
+
+/// Define the errors raised while initializing the Logger.
+#[derive(Debug, Error)]
+pub enum LoggerError {
+  #[error("Failed to open log file: {0}")]
+  Open(#[source] io::Error),
+  #[error("Failed to start the logger thread: {0}")]
+  Spawn(#[source] io::Error),
+  #[error("Logger already initialized")]
+  AlreadyInitialized,
+}
+
+/// Define the error raised when parsing an unknown log level.
+#[derive(Debug, Error)]
+#[error("Unknown log level: {0}")]
+pub struct ParseLogLevelError(String);
+
+/// Define the errors raised when parsing per-target log level directives.
+#[derive(Debug, Error)]
+pub enum ParseDirectiveError {
+  #[error("Invalid log directive: {0}")]
+  Malformed(String),
+  #[error(transparent)]
+  Level(#[from] ParseLogLevelError),
+}
+
+/// Implements the conversions of the log levels
+#[allow(dead_code)]
+impl LogLevel {
+  /// Function to retrieve the token written in the log file for the level.
+  ///
+  /// # Returns
+  /// * `&'static str`: The level token (e.g., "DEBUG", "INFO", "WARN", "ERROR").
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LogLevel::Trace => "TRACE",
+      LogLevel::Debug => "DEBUG",
+      LogLevel::Info => "INFO",
+      LogLevel::Warn => "WARN",
+      LogLevel::Error => "ERROR",
+    }
+  }
+
+  /// Function to read the minimum log level from the `WGB_LOG_LEVEL`
+  /// environment variable.
+  ///
+  /// # Returns
+  /// * `Option<LogLevel>`: The configured level, or `None` when the variable is
+  ///   unset or holds an unknown level.
+  pub fn from_env() -> Option<LogLevel> {
+    std::env::var(LOG_LEVEL_ENV).ok()?.parse().ok()
+  }
+}
+
+/// Function to parse per-target log level directives.
+///
+/// The directives are a comma-separated list of `target=level` pairs, such as
+/// `core::wg=debug,cli=warn`. Blank entries are ignored.
+///
+/// # Arguments
+/// * `directives`: The directives to be parsed.
+///
+/// # Returns
+/// * `Result<HashMap<String, LogLevel>, ParseDirectiveError>`: The level of each
+///   target, or the error raised by the first malformed directive.
+pub fn parse_directives(directives: &str) -> Result<HashMap<String, LogLevel>, ParseDirectiveError> {
+  let mut targets = HashMap::new();
+  for directive in directives.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+    let (target, level) = directive
+        .split_once('=')
+        .ok_or_else(|| ParseDirectiveError::Malformed(directive.to_string()))?;
+    let target = target.trim();
+    if target.is_empty() {
+      return Err(ParseDirectiveError::Malformed(directive.to_string()));
+    }
+    targets.insert(target.to_string(), level.parse()?);
+  }
+  Ok(targets)
+}
+
+/// Function to read the per-target log levels from the `WGB_LOG` environment
+/// variable.
+///
+/// Malformed directives are reported on stderr and ignored as a whole.
+///
+/// # Returns
+/// * `HashMap<String, LogLevel>`: The level of each target.
+fn targets_from_env() -> HashMap<String, LogLevel> {
+  let Ok(directives) = std::env::var(LOG_TARGETS_ENV) else {
+    return HashMap::new();
+  };
+  parse_directives(&directives).unwrap_or_else(|e| {
+    eprintln!("Ignoring {LOG_TARGETS_ENV}: {e}");
+    HashMap::new()
+  })
+}
+
+impl fmt::Display for LogLevel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl FromStr for LogLevel {
+  type Err = ParseLogLevelError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim().to_ascii_lowercase().as_str() {
+      "trace" => Ok(LogLevel::Trace),
+      "debug" => Ok(LogLevel::Debug),
+      "info" => Ok(LogLevel::Info),
+      "warn" | "warning" => Ok(LogLevel::Warn),
+      "error" => Ok(LogLevel::Error),
+      _ => Err(ParseLogLevelError(s.to_string())),
+    }
+  }
+}
+
+/// Define the options used to tune the behaviour of the Logger.
+#[derive(Clone, Debug)]
+pub struct LoggerOptions {
+  /// Maximum length, in bytes, of a single log message. Longer messages are
+  /// truncated and marked with a `...[truncated N bytes]` suffix.
+  pub max_message_len: usize,
+  /// Ownership and permissions applied to the log file once opened.
+  pub permissions: LogFilePermissions,
+  /// Minimum level of the messages written; less severe messages are dropped.
+  pub min_level: LogLevel,
+  /// Minimum level of the messages written for specific targets, overriding
+  /// `min_level`. A target also applies to its submodules, so `core` covers
+  /// `core::wg`; the longest matching target wins.
+  pub targets: HashMap<String, LogLevel>,
+  /// Size, in bytes, after which the log file is rotated to `<name>.1`, the
+  /// previous `<name>.1` to `<name>.2`, and so on. `None` disables rotation.
+  pub max_bytes: Option<u64>,
+  /// Number of rotated log files kept on disk; older ones are deleted.
+  pub max_files: usize,
+  /// Whether rotated log files are gzip-compressed to `<name>.<N>.gz`. The
+  /// compression runs on a helper thread, so writing is not held up by it.
+  pub compress_rotated: bool,
+  /// Format of the written lines.
+  pub format: LogFormat,
+  /// Whether every written line is also echoed to stderr, e.g. to follow the
+  /// log live when running interactively.
+  pub mirror_stderr: bool,
+  /// Whether the level token of the stderr mirror is colorized. Colors are
+  /// only used when stderr is a terminal; the log file is always plain text.
+  pub color: bool,
+  /// Policy deciding when the writer thread flushes the log file.
+  pub flush_policy: FlushPolicy,
+  /// Number of lines the queue to the writer thread holds, bounding the
+  /// memory used when messages are logged faster than they are written.
+  pub queue_capacity: usize,
+  /// Policy applied when a line is logged while the queue is full.
+  pub on_full: OnFull,
+  /// Clock used by the writer thread to detect when the local date changes
+  /// and when a flush interval has elapsed.
+  pub clock: Arc<dyn Clock>,
+}
+
+impl Default for LoggerOptions {
+  /// The minimum level is read from `WGB_LOG_LEVEL`, falling back to `Info`,
+  /// and the per-target levels from `WGB_LOG`.
+  fn default() -> Self {
+    LoggerOptions {
+      max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+      permissions: LogFilePermissions::default(),
+      min_level: LogLevel::from_env().unwrap_or(LogLevel::Info),
+      targets: targets_from_env(),
+      max_bytes: Some(DEFAULT_MAX_BYTES),
+      max_files: DEFAULT_MAX_FILES,
+      compress_rotated: false,
+      format: LogFormat::Text,
+      mirror_stderr: false,
+      color: true,
+      flush_policy: FlushPolicy::EveryLine,
+      queue_capacity: DEFAULT_QUEUE_CAPACITY,
+      on_full: OnFull::Block,
+      clock: Arc::new(SystemClock),
+    }
+  }
+}
+
+/// Define the ownership and permissions to enforce on the log file.
+///
+/// These are useful when running as a service or as root, where the log file
+/// would otherwise be readable only by root. They are applied on Unix only,
+/// and only when the process is permitted to; unset values leave the file
+/// untouched.
+#[derive(Clone, Debug, Default)]
+pub struct LogFilePermissions {
+  /// Permission bits of the log file (e.g. `0o640`).
+  pub mode: Option<u32>,
+  /// User id of the log file owner.
+  pub owner: Option<u32>,
+  /// Group id of the log file group.
+  pub group: Option<u32>,
+}
+
+/// Define the format of the written lines.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+  /// Human readable lines: `<timestamp> - <LEVEL>  <message>`.
+  Text,
+  /// One JSON object per line, with the `timestamp`, `level` and `message`
+  /// fields, plus `error` when the entry carries one.
+  Json,
+}
+
+/// Define when the writer thread flushes the log file to disk.
+///
+/// Flushing less often trades durability for throughput: on a crash, the
+/// lines not flushed yet are lost. Explicit flushes and the Logger shutdown
+/// always flush, regardless of the policy.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+  /// Flush after every line.
+  EveryLine,
+  /// Flush once the given number of lines has been written.
+  EveryN(usize),
+  /// Flush once the given time has elapsed since the last flush. A zero
+  /// interval flushes every line, and shorter intervals than
+  /// `MIN_FLUSH_INTERVAL` are raised to it.
+  Interval(Duration),
+}
+
+/// Implements the logic to sanitize a flush policy
+impl FlushPolicy {
+  /// Function to normalize the policy, so that the writer thread never
+  /// wakes up in a busy loop.
+  ///
+  /// # Returns
+  /// * `FlushPolicy`: `EveryLine` for a zero interval, the interval raised
+  ///   to `MIN_FLUSH_INTERVAL` when shorter, or the policy itself.
+  fn normalized(self) -> FlushPolicy {
+    match self {
+      FlushPolicy::Interval(interval) if interval.is_zero() => FlushPolicy::EveryLine,
+      FlushPolicy::Interval(interval) => FlushPolicy::Interval(interval.max(MIN_FLUSH_INTERVAL)),
+      policy => policy,
+    }
+  }
+}
+
+/// Define the policy applied when a line is logged while the queue to the
+/// writer thread is full.
+///
+/// With the `Drop*` policies, the number of dropped lines is reported in the
+/// log file, at most once every `DROPPED_REPORT_INTERVAL`.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnFull {
+  /// Wait on the calling thread until the writer thread frees a slot.
+  Block,
+  /// Drop the oldest queued line to make room for the new one.
+  DropOldest,
+  /// Drop the new line.
+  DropNewest,
+}
+
+/// Shortest interval of `FlushPolicy::Interval`, bounding how often the idle
+/// writer thread wakes up.
+const MIN_FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Name of the background writer thread.
+const WRITER_THREAD_NAME: &str = "wgb-logger";
+
+/// Maximum time spent waiting for the writer thread to acknowledge a flush.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Minimum time between two reports of the lines dropped because the queue
+/// to the writer thread was full.
+const DROPPED_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Define the messages exchanged with the background writer thread.
+#[derive(Debug)]
+enum Message {
+  /// A formatted line, along with its level, to be appended to the log file.
+  Line(LogLevel, String),
+  /// A request to acknowledge once every previous line has been written.
+  Flush(Sender<()>),
+  /// A request to write every previous line and stop the writer thread.
+  Shutdown,
+}
+
+/// Define the bounded queue of messages sent to the background writer thread.
+///
+/// Only lines count towards the capacity: flush and shutdown requests are
+/// always queued, so that they are never dropped nor blocked behind a full
+/// queue. Once closed, e.g. after the writer thread stopped, every message is
+/// discarded.
+#[derive(Debug)]
+struct LogQueue {
+  state: Mutex<QueueState>,
+  readable: Condvar,
+  writable: Condvar,
+  capacity: usize,
+  on_full: OnFull,
+}
+
+/// Define the state of the queue, guarded by its mutex.
+#[derive(Debug, Default)]
+struct QueueState {
+  messages: VecDeque<Message>,
+  lines: usize,
+  dropped: u64,
+  closed: bool,
+}
+
+/// Implements the logic of the queue to the background writer thread
+impl LogQueue {
+  /// Function to create an open queue.
+  ///
+  /// # Arguments
+  /// * `capacity`: The number of lines the queue holds; at least one.
+  /// * `on_full`: The policy applied when a line is pushed to a full queue.
+  ///
+  /// # Returns
+  /// * `LogQueue`: The empty queue.
+  fn new(capacity: usize, on_full: OnFull) -> LogQueue {
+    LogQueue {
+      state: Mutex::new(QueueState::default()),
+      readable: Condvar::new(),
+      writable: Condvar::new(),
+      capacity: capacity.max(1),
+      on_full,
+    }
+  }
+
+  /// Function to create a closed queue, discarding every message.
+  ///
+  /// # Returns
+  /// * `LogQueue`: The closed queue.
+  fn closed() -> LogQueue {
+    let queue = LogQueue::new(1, OnFull::DropNewest);
+    queue.close();
+    queue
+  }
+
+  /// Function to lock the state of the queue, recovering it when a thread
+  /// panicked while holding the lock.
+  ///
+  /// # Returns
+  /// * `MutexGuard<'_, QueueState>`: The locked state.
+  fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+    self.state.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Function to push a message, applying the `OnFull` policy to lines.
+  ///
+  /// # Arguments
+  /// * `message`: The message to be queued.
+  fn push(&self, message: Message) {
+    self.push_with(message, self.on_full);
+  }
+
+  /// Function to push a message without ever waiting for room, dropping it
+  /// when the queue is full under the `Block` policy.
+  ///
+  /// This is meant for callers which may run on the writer thread itself
+  /// (e.g. the panic hook), where waiting for the queue to drain would never
+  /// end.
+  ///
+  /// # Arguments
+  /// * `message`: The message to be queued.
+  fn try_push(&self, message: Message) {
+    let on_full = match self.on_full {
+      OnFull::Block => OnFull::DropNewest,
+      on_full => on_full,
+    };
+    self.push_with(message, on_full);
+  }
+
+  /// Function to push a message, applying the given policy to lines.
+  ///
+  /// # Arguments
+  /// * `message`: The message to be queued.
+  /// * `on_full`: The policy applied when the queue is full.
+  fn push_with(&self, message: Message, on_full: OnFull) {
+    let mut state = self.lock();
+    let is_line = matches!(message, Message::Line(..));
+    if is_line {
+      while !state.closed && state.lines >= self.capacity {
+        match on_full {
+          OnFull::Block => {
+            state = self.writable.wait(state).unwrap_or_else(|e| e.into_inner());
+          }
+          OnFull::DropOldest => {
+            let oldest = state.messages.iter().position(|m| matches!(m, Message::Line(..)));
+            if let Some(index) = oldest {
+              state.messages.remove(index);
+            }
+            state.lines -= 1;
+            state.dropped += 1;
+          }
+          OnFull::DropNewest => {
+            state.dropped += 1;
+            return;
+          }
+        }
+      }
+    }
+    if state.closed {
+      return;
+    }
+    if is_line {
+      state.lines += 1;
+    }
+    state.messages.push_back(message);
+    self.readable.notify_one();
+  }
+
+  /// Function to pop the oldest message, waiting for one to be queued.
+  ///
+  /// # Arguments
+  /// * `timeout`: The maximum time to wait; `Duration::MAX` waits forever.
+  ///
+  /// # Returns
+  /// * `Option<Message>`: The message, or `None` when the timeout elapsed.
+  fn pop(&self, timeout: Duration) -> Option<Message> {
+    let mut state = self.lock();
+    let deadline = Instant::now().checked_add(timeout);
+    while state.messages.is_empty() {
+      state = match deadline {
+        Some(deadline) => {
+          let remaining = deadline.saturating_duration_since(Instant::now());
+          if remaining.is_zero() {
+            return None;
+          }
+          self.readable.wait_timeout(state, remaining).unwrap_or_else(|e| e.into_inner()).0
+        }
+        None => self.readable.wait(state).unwrap_or_else(|e| e.into_inner()),
+      };
+    }
+    let message = state.messages.pop_front();
+    if let Some(Message::Line(..)) = message {
+      state.lines -= 1;
+      self.writable.notify_one();
+    }
+    message
+  }
+
+  /// Function to retrieve and reset the number of dropped lines.
+  ///
+  /// # Returns
+  /// * `u64`: The lines dropped since the last call.
+  fn take_dropped(&self) -> u64 {
+    std::mem::take(&mut self.lock().dropped)
+  }
+
+  /// Function to close the queue, discarding the queued messages and waking
+  /// up the blocked producers.
+  fn close(&self) {
+    let mut state = self.lock();
+    state.closed = true;
+    state.messages.clear();
+    state.lines = 0;
+    self.writable.notify_all();
+  }
+}
+
+/// Define a guard closing the queue when the writer thread stops, even by
+/// panicking, so that producers never block on a queue nobody drains.
+struct CloseOnDrop(Arc<LogQueue>);
+
+impl Drop for CloseOnDrop {
+  fn drop(&mut self) {
+    self.0.close();
+  }
+}
+
+/// Define the state of the background writer thread.
+#[derive(Debug)]
+struct Writer {
+  pattern: PathBuf,
+  date: NaiveDate,
+  path: PathBuf,
+  file: BufWriter<File>,
+  written: u64,
+  unflushed: usize,
+  last_flush: DateTime<Local>,
+  warnings: usize,
+  errors: usize,
+  compressor: Option<JoinHandle<()>>,
+  options: LoggerOptions,
+}
+
+/// Implements the logic of the background writer thread
+impl Writer {
+  /// Function to open the log file and set up the writer.
+  ///
+  /// The size of an already existing file is taken into account, so that a
+  /// restarted process keeps rotating at the configured size.
+  ///
+  /// # Arguments
+  /// * `pattern`: The path to the log file, possibly holding date specifiers.
+  /// * `options`: The options used to configure the Logger.
+  ///
+  /// # Returns
+  /// * `io::Result<Writer>`: The writer, or the error raised opening the file.
+  fn open(pattern: PathBuf, mut options: LoggerOptions) -> io::Result<Writer> {
+    options.flush_policy = options.flush_policy.normalized();
+    let now = options.clock.now();
+    let path = dated_path(&pattern, &now);
+    let file = open_log_file(&path, &options.permissions)?;
+    let written = file.metadata()?.len();
+    Ok(Writer {
+      pattern,
+      date: now.date_naive(),
+      path,
+      file: BufWriter::new(file),
+      written,
+      unflushed: 0,
+      last_flush: now,
+      warnings: 0,
+      errors: 0,
+      compressor: None,
+      options,
+    })
+  }
+
+  /// Function to switch to a new log file when the local date has changed
+  /// since the last write and the path depends on the date.
+  ///
+  /// # Returns
+  /// * `io::Result<()>`: The error, if any, raised opening the new file.
+  fn roll_date(&mut self) -> io::Result<()> {
+    let now = self.options.clock.now();
+    if now.date_naive() == self.date {
+      return Ok(());
+    }
+    self.date = now.date_naive();
+
+    let path = dated_path(&self.pattern, &now);
+    if path != self.path {
+      self.flush();
+      let file = open_log_file(&path, &self.options.permissions)?;
+      self.written = file.metadata()?.len();
+      self.file = BufWriter::new(file);
+      self.path = path;
+    }
+    Ok(())
+  }
+
+  /// Function to append an entry to the log file, counting the warnings and
+  /// errors written during the session.
+  ///
+  /// # Arguments
+  /// * `level`: The log level of the entry.
+  /// * `line`: The formatted line to be written.
+  fn write_entry(&mut self, level: LogLevel, line: &str) {
+    match level {
+      LogLevel::Warn => self.warnings += 1,
+      LogLevel::Error => self.errors += 1,
+      _ => {}
+    }
+    self.write_line(line);
+  }
+
+  /// Function to append the session summary, reporting how many warnings
+  /// and errors were logged, to the log file.
+  fn write_summary(&mut self) {
+    let summary = format!(
+      "session ended: {} {}, {} {}",
+      self.warnings,
+      if self.warnings == 1 { "warning" } else { "warnings" },
+      self.errors,
+      if self.errors == 1 { "error" } else { "errors" },
+    );
+    let now = self.options.clock.now();
+    let entry = Entry {
+      level: LogLevel::Info,
+      target: None,
+      message: &summary,
+      error: None,
+    };
+    let line = format_entry(self.options.format, &now, &entry);
+    self.write_line(&line);
+  }
+
+  /// Function to append a warning reporting how many lines were dropped
+  /// because the queue to the writer thread was full.
+  ///
+  /// # Arguments
+  /// * `dropped`: The number of dropped lines.
+  fn write_dropped(&mut self, dropped: u64) {
+    let report = format!(
+      "dropped {dropped} log {} because the queue was full",
+      if dropped == 1 { "message" } else { "messages" },
+    );
+    let now = self.options.clock.now();
+    let entry = Entry {
+      level: LogLevel::Warn,
+      target: None,
+      message: &report,
+      error: None,
+    };
+    let line = format_entry(self.options.format, &now, &entry);
+    self.write_line(&line);
+  }
+
+  /// Function to append a line to the log file, rotating it first when the
+  /// line would exceed the configured size.
+  ///
+  /// # Arguments
+  /// * `line`: The formatted line to be written.
+  fn write_line(&mut self, line: &str) {
+    if let Err(e) = self.roll_date() {
+      eprintln!("Failed to switch log file: {e}");
+    }
+
+    let len = line.len() as u64 + 1;
+    if let Some(max_bytes) = self.options.max_bytes
+      && self.written > 0
+      && self.written + len > max_bytes
+      && let Err(e) = self.rotate()
+    {
+      eprintln!("Failed to rotate log: {e}");
+    }
+
+    match writeln!(self.file, "{line}") {
+      Ok(()) => {
+        self.written += len;
+        self.unflushed += 1;
+      }
+      Err(e) => eprintln!("Failed to write log: {e}"),
+    }
+    self.flush_if_due();
+  }
+
+  /// Function to flush the log file.
+  fn flush(&mut self) {
+    if let Err(e) = self.file.flush() {
+      eprintln!("Failed to flush log: {e}");
+    }
+    self.unflushed = 0;
+    self.last_flush = self.options.clock.now();
+  }
+
+  /// Function to flush the log file when the flush policy requires it.
+  fn flush_if_due(&mut self) {
+    if self.unflushed == 0 {
+      return;
+    }
+    let due = match self.options.flush_policy {
+      FlushPolicy::EveryLine => true,
+      FlushPolicy::EveryN(lines) => self.unflushed >= lines,
+      FlushPolicy::Interval(interval) => {
+        let elapsed = self.options.clock.now() - self.last_flush;
+        elapsed.to_std().is_ok_and(|elapsed| elapsed >= interval)
+      }
+    };
+    if due {
+      self.flush();
+    }
+  }
+
+  /// Function to retrieve how long the writer thread may wait for a message
+  /// before checking whether a flush is due.
+  ///
+  /// # Returns
+  /// * `Duration`: The flush interval, or `Duration::MAX` for policies that
+  ///   only flush on writes.
+  fn idle_timeout(&self) -> Duration {
+    match self.options.flush_policy {
+      FlushPolicy::Interval(interval) => interval,
+      _ => Duration::MAX,
+    }
+  }
+
+  /// Function to rotate the log file.
+  ///
+  /// The rotated files are shifted from the oldest to the newest, so that an
+  /// interruption at any step leaves every line in one of the files: the
+  /// current file is only ever renamed, never truncated, and a fresh one is
+  /// created in its place.
+  ///
+  /// Both the plain and the compressed rotated files are shifted and pruned,
+  /// so that switching `compress_rotated` on or off keeps the retention. A
+  /// compression still running from the previous rotation is waited for
+  /// first, so that it never reads a file being shifted.
+  ///
+  /// # Returns
+  /// * `io::Result<()>`: The error, if any, raised while rotating.
+  fn rotate(&mut self) -> io::Result<()> {
+    self.flush();
+    self.wait_compressor();
+    let max_files = self.options.max_files;
+
+    if max_files == 0 {
+      fs::remove_file(&self.path)?;
+    } else {
+      for index in (1..=max_files).rev() {
+        for suffix in ["", ".gz"] {
+          let from = rotated_path(&self.path, index, suffix);
+          if index == max_files {
+            remove_if_exists(&from)?;
+          } else if from.exists() {
+            fs::rename(&from, rotated_path(&self.path, index + 1, suffix))?;
+          }
+        }
+      }
+      let rotated = rotated_path(&self.path, 1, "");
+      fs::rename(&self.path, &rotated)?;
+      if self.options.compress_rotated {
+        let permissions = self.options.permissions.clone();
+        self.compressor = Some(thread::spawn(move || {
+          if let Err(e) = compress_file(&rotated, &permissions) {
+            eprintln!("Failed to compress {}: {e}", rotated.display());
+          }
+        }));
+      }
+    }
+
+    self.file = BufWriter::new(open_log_file(&self.path, &self.options.permissions)?);
+    self.written = 0;
+    Ok(())
+  }
+
+  /// Function to wait for the compression of the last rotated file, if any.
+  fn wait_compressor(&mut self) {
+    if let Some(handle) = self.compressor.take() {
+      let _ = handle.join();
+    }
+  }
+}
 
 /// Define a struct to be used for multithreaded writing to a log file.
 #[derive(Clone, Debug)]
 pub struct Logger {
-  sender: Sender<String>,
+  queue: Arc<LogQueue>,
+  writer: Arc<Mutex<Option<JoinHandle<()>>>>,
+  max_message_len: usize,
+  min_level: LogLevel,
+  targets: HashMap<String, LogLevel>,
+  format: LogFormat,
+  mirror_stderr: bool,
+  color: bool,
 }
 
 /// Define a variable to enable the Singleton pattern.
@@ -26,50 +806,353 @@ impl Logger {
   ///
   /// This function creates a background logging thread that listens for messages
   /// sent via a channel. It appends the messages to the specified log file.
-  /// If the logger has not been initialized, it will panic with "Logger already initialized".
+  /// The log file is opened before returning, so that a failure can be handled
+  /// by the caller, e.g. by falling back to `init_stderr`.
+  ///
+  /// The path may hold `chrono` date specifiers (e.g. `./%Y-%m-%d.log`): the
+  /// file name then follows the local date, and the writer switches to a new
+  /// file when the date changes, without dropping any queued message.
   ///
   /// # Arguments
   /// * `log_file`: The path to the log file where log messages will be written.
-  pub fn init(log_file: &str) {
-    // Create a channel to send logs to the logging thread
-    let (tx, rx) = mpsc::channel::<String>();
-    let log_file = log_file.to_string();
+  ///
+  /// # Returns
+  /// * `Result<(), LoggerError>`: The error, if any, raised while opening the
+  ///   log file or because the Logger is already initialized.
+  pub fn init(log_file: &str) -> Result<(), LoggerError> {
+    Self::init_with_options(log_file, LoggerOptions::default())
+  }
+
+  /// Function to initialize the Logger, panicking on failure.
+  ///
+  /// This is a thin wrapper over `init` for callers that cannot run without
+  /// a log file.
+  ///
+  /// # Arguments
+  /// * `log_file`: The path to the log file where log messages will be written.
+  pub fn init_or_panic(log_file: &str) {
+    if let Err(e) = Self::init(log_file) {
+      panic!("{e}");
+    }
+  }
+
+  /// Function to initialize the Logger with custom options.
+  ///
+  /// This function behaves like `init`, but allows tuning the Logger through
+  /// the given `LoggerOptions`.
+  ///
+  /// # Arguments
+  /// * `log_file`: The path to the log file where log messages will be written.
+  /// * `options`: The options used to configure the Logger.
+  ///
+  /// # Returns
+  /// * `Result<(), LoggerError>`: The error, if any, raised while opening the
+  ///   log file or because the Logger is already initialized.
+  pub fn init_with_options(log_file: &str, options: LoggerOptions) -> Result<(), LoggerError> {
+    if LOGGER.get().is_some() {
+      return Err(LoggerError::AlreadyInitialized);
+    }
+    Self::install(Self::start(log_file, options)?)
+  }
+
+  /// Function to open the log file and spawn the writer thread of a Logger,
+  /// without installing it as the global one.
+  ///
+  /// # Arguments
+  /// * `log_file`: The path to the log file where log messages will be written.
+  /// * `options`: The options used to configure the Logger.
+  ///
+  /// # Returns
+  /// * `Result<Logger, LoggerError>`: The Logger, or the error raised while
+  ///   opening the log file or spawning the thread.
+  fn start(log_file: &str, options: LoggerOptions) -> Result<Logger, LoggerError> {
+    // Create a queue to send logs to the logging thread
+    let queue = Arc::new(LogQueue::new(options.queue_capacity, options.on_full));
+    let color = options.color && io::stderr().is_terminal();
+    let logger_options = options.clone();
+    let mut writer = Writer::open(PathBuf::from(log_file), options).map_err(LoggerError::Open)?;
 
     // Spawn a background logging thread
-    std::thread::spawn(move || {
-      let mut file = OpenOptions::new()
-          .create(true)
-          .append(true)
-          .open(&log_file)
-          .expect("Failed to open log file");
-
-      for message in rx {
-        if let Err(e) = writeln!(file, "{message}") {
-          eprintln!("Failed to write log: {e}");
+    let closer = CloseOnDrop(Arc::clone(&queue));
+    let handle = thread::Builder::new().name(WRITER_THREAD_NAME.to_string()).spawn(move || {
+      let queue = &closer.0;
+      let mut last_report: Option<Instant> = None;
+      loop {
+        // Wake up in time to report dropped lines even when no line follows
+        let timeout = match last_report {
+          Some(_) => writer.idle_timeout().min(DROPPED_REPORT_INTERVAL),
+          None => writer.idle_timeout(),
+        };
+        match queue.pop(timeout) {
+          Some(Message::Line(level, line)) => writer.write_entry(level, &line),
+          Some(Message::Flush(ack)) => {
+            writer.flush();
+            let _ = ack.send(());
+          }
+          Some(Message::Shutdown) => break,
+          None => writer.flush_if_due(),
         }
-        let _ = file.flush();
+        if last_report.is_none_or(|at| at.elapsed() >= DROPPED_REPORT_INTERVAL) {
+          let dropped = queue.take_dropped();
+          last_report = (dropped > 0).then(Instant::now);
+          if dropped > 0 {
+            writer.write_dropped(dropped);
+          }
+        }
+      }
+      queue.close();
+      let dropped = queue.take_dropped();
+      if dropped > 0 {
+        writer.write_dropped(dropped);
       }
+      writer.write_summary();
+      writer.flush();
+      writer.wait_compressor();
     });
+    let handle = handle.map_err(LoggerError::Spawn)?;
+
+    Ok(Logger {
+      queue,
+      writer: Arc::new(Mutex::new(Some(handle))),
+      max_message_len: logger_options.max_message_len,
+      min_level: logger_options.min_level,
+      targets: logger_options.targets,
+      format: logger_options.format,
+      mirror_stderr: logger_options.mirror_stderr,
+      color,
+    })
+  }
+
+  /// Function to initialize a Logger writing to stderr only.
+  ///
+  /// This is the fallback used when the log file cannot be opened: messages
+  /// are formatted and filtered as usual, then printed to stderr instead of
+  /// being sent to a writer thread.
+  ///
+  /// # Arguments
+  /// * `options`: The options used to configure the Logger; file related
+  ///   options are ignored.
+  ///
+  /// # Returns
+  /// * `Result<(), LoggerError>`: The error raised when the Logger is already
+  ///   initialized.
+  pub fn init_stderr(options: LoggerOptions) -> Result<(), LoggerError> {
+    // Nobody drains the queue, so it is closed and queuing a message is a no-op
+    let logger = Logger {
+      queue: Arc::new(LogQueue::closed()),
+      writer: Arc::new(Mutex::new(None)),
+      max_message_len: options.max_message_len,
+      min_level: options.min_level,
+      targets: options.targets,
+      format: options.format,
+      mirror_stderr: true,
+      color: options.color && io::stderr().is_terminal(),
+    };
+    Self::install(logger)
+  }
 
-    let logger = Logger { sender: tx };
-    LOGGER.set(logger).expect("Logger already initialized");
+  /// Function to set the LOGGER singleton variable and install the panic hook.
+  ///
+  /// # Arguments
+  /// * `logger`: The Logger to be installed.
+  ///
+  /// # Returns
+  /// * `Result<(), LoggerError>`: The error raised when the Logger is already
+  ///   initialized, in which case the given Logger is shut down.
+  fn install(logger: Logger) -> Result<(), LoggerError> {
+    if let Err(logger) = LOGGER.set(logger) {
+      logger.shutdown();
+      return Err(LoggerError::AlreadyInitialized);
+    }
+    install_panic_hook();
+    Ok(())
   }
 
   /// Function to send log messages to the background thread.
   ///
   /// This method formats the log message with a timestamp and log level.
-  /// The formatted message is then sent to the background thread for writing to the log file.
+  /// The formatted message is then sent to the background thread for writing to the log file,
+  /// and echoed to stderr when mirroring is enabled.
+  /// Messages below the minimum level are dropped here, on the calling thread.
+  ///
+  /// # Arguments
+  /// * `level`: The log level of the message.
+  /// * `message`: The log message to be logged.
+  fn log(&self, level: LogLevel, message: &str) {
+    self.emit(level, None, message, None);
+  }
+
+  /// Function to send log messages on behalf of a target module.
+  ///
+  /// This method behaves like `log`, but filters the message against the
+  /// level configured for the target, and writes the target in the entry.
+  ///
+  /// # Arguments
+  /// * `level`: The log level of the message.
+  /// * `target`: The module the message comes from (e.g., "core::wg").
+  /// * `message`: The log message to be logged.
+  pub fn log_for(&self, level: LogLevel, target: &str, message: &str) {
+    self.emit(level, Some(target), message, None);
+  }
+
+  /// Function to send log messages along with the error that caused them.
+  ///
+  /// This method behaves like `log`, but also records the given error: it is
+  /// appended to the message in text format and written to the `error` field
+  /// in JSON format.
+  ///
+  /// # Arguments
+  /// * `level`: The log level of the message.
+  /// * `message`: The log message to be logged.
+  /// * `error`: The error to be logged with the message.
+  pub fn log_error(&self, level: LogLevel, message: &str, error: &dyn Error) {
+    self.emit(level, None, message, Some(error));
+  }
+
+  /// Function to format a log entry and send it to the background thread.
+  ///
+  /// # Arguments
+  /// * `level`: The log level of the message.
+  /// * `target`: The module, if any, the message comes from.
+  /// * `message`: The log message to be logged.
+  /// * `error`: The error, if any, to be logged with the message.
+  fn emit(&self, level: LogLevel, target: Option<&str>, message: &str, error: Option<&dyn Error>) {
+    if let Some(line) = self.format_line(level, target, message, error) {
+      self.queue.push(Message::Line(level, line));
+    }
+  }
+
+  /// Function to log a panic from the panic hook.
+  ///
+  /// The entry is queued without waiting for room, since the panicking
+  /// thread may be the writer thread, the only one draining the queue.
+  ///
+  /// # Arguments
+  /// * `message`: The panic message.
+  fn emit_panic(&self, message: &str) {
+    if let Some(line) = self.format_line(LogLevel::Error, None, message, None) {
+      self.queue.try_push(Message::Line(LogLevel::Error, line));
+    }
+  }
+
+  /// Function to format a log entry, echoing it to stderr when mirroring.
+  ///
+  /// Secrets are redacted from the message and the error before anything is
+  /// formatted, so that they never reach the log file or stderr.
   ///
   /// # Arguments
-  /// * `level`: The log level (e.g., "DEBUG", "INFO", "WARN", "ERROR").
+  /// * `level`: The log level of the message.
+  /// * `target`: The module, if any, the message comes from.
   /// * `message`: The log message to be logged.
-  fn log(&self, level: &str, message: &str) {
-    // Format timestamp with milliseconds
-    let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string();
-    // The timestamp and level are left-aligned with 20 and 8 padding spaces,
-    // respectively.
-    let log_message = format!("{timestamp:<20} - {level:<8}  {message}");
-    let _ = self.sender.send(log_message);
+  /// * `error`: The error, if any, to be logged with the message.
+  ///
+  /// # Returns
+  /// * `Option<String>`: The formatted line, or `None` when the message is
+  ///   below the level of its target.
+  fn format_line(
+    &self,
+    level: LogLevel,
+    target: Option<&str>,
+    message: &str,
+    error: Option<&dyn Error>,
+  ) -> Option<String> {
+    if level < self.threshold(target) {
+      return None;
+    }
+    let message = redact(message);
+    let message = truncate(&message, self.max_message_len);
+    let error = error.map(|error| redact(&error.to_string()));
+    let entry = Entry {
+      level,
+      target,
+      message: &message,
+      error: error.as_deref(),
+    };
+    let log_message = format_entry(self.format, &Local::now(), &entry);
+
+    if self.mirror_stderr {
+      if self.color && self.format == LogFormat::Text {
+        // The timestamp holds no letters, so the first match is the level token
+        let token = level.as_str();
+        eprintln!("{}", log_message.replacen(token, &colorize(token), 1));
+      } else {
+        eprintln!("{log_message}");
+      }
+    }
+    Some(log_message)
+  }
+
+  /// Function to retrieve the minimum level of the messages of a target.
+  ///
+  /// # Arguments
+  /// * `target`: The module, if any, the message comes from.
+  ///
+  /// # Returns
+  /// * `LogLevel`: The level of the longest configured target matching the
+  ///   module or one of its parents, or the global minimum level.
+  fn threshold(&self, target: Option<&str>) -> LogLevel {
+    let Some(target) = target else {
+      return self.min_level;
+    };
+    self
+        .targets
+        .iter()
+        .filter(|(prefix, _)| {
+          target
+              .strip_prefix(prefix.as_str())
+              .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or(self.min_level, |(_, level)| *level)
+  }
+
+  /// Function to wait until every message sent so far is written to the file.
+  ///
+  /// The wait is bounded, so that a stuck or dead writer thread (e.g. after a
+  /// panic while writing) cannot block the caller forever.
+  pub fn flush(&self) {
+    let (ack_tx, ack_rx) = mpsc::channel();
+    self.queue.push(Message::Flush(ack_tx));
+    // A closed queue drops the request along with its sender, ending the wait
+    let _ = ack_rx.recv_timeout(FLUSH_TIMEOUT);
+  }
+
+  /// Function to stop the Logger, guaranteeing that every queued message is
+  /// written to the log file.
+  ///
+  /// The background thread drains the messages sent before the call, writes a
+  /// summary line counting the warnings and errors of the session, flushes
+  /// the file and exits; this method returns once it has been joined. It is
+  /// meant to be called right before the process exits: messages logged
+  /// afterwards are discarded.
+  pub fn shutdown(&self) {
+    self.queue.push(Message::Shutdown);
+    let writer = self.writer.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(writer) = writer {
+      let _ = writer.join();
+    }
+  }
+
+  /// Function to write trace messages.
+  ///
+  /// This method writes messages with the "TRACE" log level, the most verbose
+  /// one. Unlike `debug`, it is available in release builds too: trace
+  /// messages are dropped by the minimum level filter unless enabled, e.g.
+  /// with `WGB_LOG_LEVEL=trace`.
+  ///
+  /// # Arguments
+  /// * `message`: The trace message to be logged.
+  pub fn trace(&self, message: &str) {
+    self.log(LogLevel::Trace, message);
+  }
+
+  /// Function to write trace messages along with the error that caused them.
+  ///
+  /// # Arguments
+  /// * `message`: The trace message to be logged.
+  /// * `error`: The error to be logged with the message.
+  pub fn trace_error(&self, message: &str, error: &dyn Error) {
+    self.log_error(LogLevel::Trace, message, error);
   }
 
   /// Function to write debug messages (only in non-release versions).
@@ -81,7 +1164,7 @@ impl Logger {
   /// * `message`: The debug message to be logged.
   #[cfg(debug_assertions)]
   pub fn debug(&self, message: &str) {
-    self.log("DEBUG", message);
+    self.log(LogLevel::Debug, message);
   }
 
   /// Function to write info messages.
@@ -91,7 +1174,7 @@ impl Logger {
   /// # Arguments
   /// * `message`: The info message to be logged.
   pub fn info(&self, message: &str) {
-    self.log("INFO", message);
+    self.log(LogLevel::Info, message);
   }
 
   /// Function to write warning messages.
@@ -101,7 +1184,7 @@ impl Logger {
   /// # Arguments
   /// * `message`: The warning message to be logged.
   pub fn warn(&self, message: &str) {
-    self.log("WARN", message);
+    self.log(LogLevel::Warn, message);
   }
 
   /// Function to write error messages.
@@ -111,7 +1194,62 @@ impl Logger {
   /// # Arguments
   /// * `message`: The error message to be logged.
   pub fn error(&self, message: &str) {
-    self.log("ERROR", message);
+    self.log(LogLevel::Error, message);
+  }
+
+  /// Function to write trace messages on behalf of a target module.
+  ///
+  /// # Arguments
+  /// * `target`: The module the message comes from (e.g., "core::wg").
+  /// * `message`: The trace message to be logged.
+  pub fn trace_for(&self, target: &str, message: &str) {
+    self.log_for(LogLevel::Trace, target, message);
+  }
+
+  /// Function to write debug messages on behalf of a target module (only in
+  /// non-release versions).
+  ///
+  /// # Arguments
+  /// * `target`: The module the message comes from (e.g., "core::wg").
+  /// * `message`: The debug message to be logged.
+  #[cfg(debug_assertions)]
+  pub fn debug_for(&self, target: &str, message: &str) {
+    self.log_for(LogLevel::Debug, target, message);
+  }
+
+  /// Function to write info messages on behalf of a target module.
+  ///
+  /// # Arguments
+  /// * `target`: The module the message comes from (e.g., "core::wg").
+  /// * `message`: The info message to be logged.
+  pub fn info_for(&self, target: &str, message: &str) {
+    self.log_for(LogLevel::Info, target, message);
+  }
+
+  /// Function to write warning messages on behalf of a target module.
+  ///
+  /// # Arguments
+  /// * `target`: The module the message comes from (e.g., "core::wg").
+  /// * `message`: The warning message to be logged.
+  pub fn warn_for(&self, target: &str, message: &str) {
+    self.log_for(LogLevel::Warn, target, message);
+  }
+
+  /// Function to write error messages on behalf of a target module.
+  ///
+  /// # Arguments
+  /// * `target`: The module the message comes from (e.g., "core::wg").
+  /// * `message`: The error message to be logged.
+  pub fn error_for(&self, target: &str, message: &str) {
+    self.log_for(LogLevel::Error, target, message);
+  }
+
+  /// Function to tell whether the written lines are echoed to stderr.
+  ///
+  /// # Returns
+  /// * `bool`: Whether mirroring is enabled, as it is for a stderr-only Logger.
+  pub fn mirrors_stderr(&self) -> bool {
+    self.mirror_stderr
   }
 
   /// Retrieves a reference to the initialized `Logger` instance.
@@ -125,4 +1263,841 @@ impl Logger {
   pub fn get() -> &'static Logger {
     LOGGER.get().expect("Logger not initialized")
   }
+
+  /// Function to create a logger handle bound to an operation context.
+  ///
+  /// Every message logged through the returned handle is prefixed with the
+  /// given context (e.g. the interface or user name of a tunnel), so that
+  /// the messages of different tunnels sharing the same file can be told
+  /// apart without threading the name into every message.
+  ///
+  /// # Arguments
+  /// * `context`: The name of the operation context.
+  ///
+  /// # Returns
+  /// * `ScopedLogger`: A handle writing prefixed messages through this Logger.
+  pub fn scope(&self, context: &str) -> ScopedLogger<'_> {
+    ScopedLogger {
+      logger: self,
+      prefix: format!("[{context}]"),
+    }
+  }
+}
+
+/// Define a logger handle that prefixes its messages with an operation context.
+#[derive(Clone, Debug)]
+pub struct ScopedLogger<'a> {
+  logger: &'a Logger,
+  prefix: String,
+}
+
+/// Implements the logging methods of the scoped handle
+#[allow(dead_code)]
+impl ScopedLogger<'_> {
+  /// Function to send a prefixed log message to the underlying Logger.
+  ///
+  /// # Arguments
+  /// * `level`: The log level of the message.
+  /// * `message`: The log message to be logged.
+  fn log(&self, level: LogLevel, message: &str) {
+    self.logger.log(level, &format!("{} {message}", self.prefix));
+  }
+
+  /// Function to send a prefixed log message along with the error that
+  /// caused it to the underlying Logger.
+  ///
+  /// # Arguments
+  /// * `level`: The log level of the message.
+  /// * `message`: The log message to be logged.
+  /// * `error`: The error to be logged with the message.
+  pub fn log_error(&self, level: LogLevel, message: &str, error: &dyn Error) {
+    self.logger.log_error(level, &format!("{} {message}", self.prefix), error);
+  }
+
+  /// Function to write prefixed trace messages.
+  ///
+  /// # Arguments
+  /// * `message`: The trace message to be logged.
+  pub fn trace(&self, message: &str) {
+    self.log(LogLevel::Trace, message);
+  }
+
+  /// Function to write prefixed trace messages along with the error that
+  /// caused them.
+  ///
+  /// # Arguments
+  /// * `message`: The trace message to be logged.
+  /// * `error`: The error to be logged with the message.
+  pub fn trace_error(&self, message: &str, error: &dyn Error) {
+    self.log_error(LogLevel::Trace, message, error);
+  }
+
+  /// Function to write prefixed debug messages (only in non-release versions).
+  ///
+  /// # Arguments
+  /// * `message`: The debug message to be logged.
+  #[cfg(debug_assertions)]
+  pub fn debug(&self, message: &str) {
+    self.log(LogLevel::Debug, message);
+  }
+
+  /// Function to write prefixed info messages.
+  ///
+  /// # Arguments
+  /// * `message`: The info message to be logged.
+  pub fn info(&self, message: &str) {
+    self.log(LogLevel::Info, message);
+  }
+
+  /// Function to write prefixed warning messages.
+  ///
+  /// # Arguments
+  /// * `message`: The warning message to be logged.
+  pub fn warn(&self, message: &str) {
+    self.log(LogLevel::Warn, message);
+  }
+
+  /// Function to write prefixed error messages.
+  ///
+  /// # Arguments
+  /// * `message`: The error message to be logged.
+  pub fn error(&self, message: &str) {
+    self.log(LogLevel::Error, message);
+  }
+}
+
+/// Macro to write a message on behalf of the calling module, prefixed with
+/// the `[file:line]` of the call site, e.g.
+/// `wgb_log!(LogLevel::Info, "Running {command}")`. The message takes
+/// `format!` arguments, and the target is the module path without the crate
+/// name (e.g. `core::wg`), so that `WGB_LOG` filters it. The `@to` form
+/// writes through the given Logger instead of the global one.
+macro_rules! wgb_log {
+  (@to $logger:expr, $level:expr, $($arg:tt)+) => {
+    $logger.log_for(
+      $level,
+      $crate::core::logger::module_target(module_path!()),
+      &format!("[{}:{}] {}", file!(), line!(), format_args!($($arg)+)),
+    )
+  };
+  ($level:expr, $($arg:tt)+) => {
+    $crate::core::logger::wgb_log!(@to $crate::core::logger::Logger::get(), $level, $($arg)+)
+  };
+}
+
+/// Macro to write debug messages prefixed with the call site. Unlike
+/// `Logger::debug`, it is available in release builds too, the messages
+/// being dropped by the level filter unless enabled.
+macro_rules! wgb_debug {
+  ($($arg:tt)+) => { $crate::core::logger::wgb_log!($crate::core::logger::LogLevel::Debug, $($arg)+) };
+}
+
+/// Macro to write info messages prefixed with the call site.
+macro_rules! wgb_info {
+  ($($arg:tt)+) => { $crate::core::logger::wgb_log!($crate::core::logger::LogLevel::Info, $($arg)+) };
+}
+
+/// Macro to write warning messages prefixed with the call site.
+macro_rules! wgb_warn {
+  ($($arg:tt)+) => { $crate::core::logger::wgb_log!($crate::core::logger::LogLevel::Warn, $($arg)+) };
+}
+
+/// Macro to write error messages prefixed with the call site.
+macro_rules! wgb_error {
+  ($($arg:tt)+) => { $crate::core::logger::wgb_log!($crate::core::logger::LogLevel::Error, $($arg)+) };
+}
+
+pub(crate) use {wgb_debug, wgb_error, wgb_info, wgb_log, wgb_warn};
+
+/// Function to derive the target of the messages of a module from its path,
+/// as the logging macros do.
+///
+/// # Arguments
+/// * `module_path`: The path of the module, as given by `module_path!`.
+///
+/// # Returns
+/// * `&str`: The path without the crate name (e.g. `core::wg`), or the crate
+///   name for the root module.
+pub fn module_target(module_path: &str) -> &str {
+  module_path.split_once("::").map_or(module_path, |(_, target)| target)
+}
+
+/// Function to install a panic hook routing panics through the Logger.
+///
+/// The hook logs the panic message, the panicking thread and a backtrace at
+/// ERROR level and waits for the entry to reach the log file, then hands over
+/// to the previously installed hook. Whether the panic then unwinds or aborts
+/// is left untouched. The hook never waits on the queue: when the writer
+/// thread itself panics, nobody is left to drain it.
+fn install_panic_hook() {
+  let default_hook = std::panic::take_hook();
+  std::panic::set_hook(Box::new(move |info| {
+    if let Some(logger) = LOGGER.get() {
+      let thread = std::thread::current();
+      let name = thread.name().unwrap_or("<unnamed>");
+      let backtrace = Backtrace::force_capture();
+      logger.emit_panic(&format!("thread '{name}' {info}\n{backtrace}"));
+      if thread.name() != Some(WRITER_THREAD_NAME) {
+        logger.flush();
+      }
+    }
+    default_hook(info);
+  }));
+}
+
+/// Function to open a log file in append mode, creating it when missing.
+///
+/// # Arguments
+/// * `path`: The path to the log file.
+/// * `permissions`: The ownership and permissions to apply to the file.
+///
+/// # Returns
+/// * `io::Result<File>`: The opened file, or the error raised opening it.
+fn open_log_file(path: &Path, permissions: &LogFilePermissions) -> io::Result<File> {
+  let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)?;
+  apply_permissions(path, permissions);
+  Ok(file)
+}
+
+/// Function to resolve the date specifiers of a log file path.
+///
+/// # Arguments
+/// * `pattern`: The path to the log file, possibly holding date specifiers.
+/// * `now`: The time used to resolve the specifiers.
+///
+/// # Returns
+/// * `PathBuf`: The resolved path, or the pattern itself when it holds no
+///   valid specifier.
+fn dated_path(pattern: &Path, now: &DateTime<Local>) -> PathBuf {
+  use std::fmt::Write as _;
+
+  let Some(pattern_str) = pattern.to_str().filter(|p| p.contains('%')) else {
+    return pattern.to_path_buf();
+  };
+  let mut resolved = String::new();
+  match write!(resolved, "{}", now.format(pattern_str)) {
+    Ok(()) => PathBuf::from(resolved),
+    Err(_) => pattern.to_path_buf(),
+  }
+}
+
+/// Function to build the path of a rotated log file.
+///
+/// # Arguments
+/// * `path`: The path to the current log file.
+/// * `index`: The index of the rotated file, starting from 1 for the newest.
+/// * `suffix`: The suffix appended after the index (e.g., ".gz"), if any.
+///
+/// # Returns
+/// * `PathBuf`: The path `<name>.<index><suffix>`.
+fn rotated_path(path: &Path, index: usize, suffix: &str) -> PathBuf {
+  let mut rotated = path.as_os_str().to_owned();
+  rotated.push(format!(".{index}{suffix}"));
+  PathBuf::from(rotated)
+}
+
+/// Function to gzip-compress a rotated log file to `<path>.gz`.
+///
+/// The archive is written to a temporary file and renamed into place before
+/// the original is removed, so an interruption never loses the rotated lines.
+///
+/// # Arguments
+/// * `path`: The path to the rotated log file.
+/// * `permissions`: The ownership and permissions to apply to the archive.
+///
+/// # Returns
+/// * `io::Result<()>`: The error, if any, raised while compressing.
+fn compress_file(path: &Path, permissions: &LogFilePermissions) -> io::Result<()> {
+  let mut archive = path.as_os_str().to_owned();
+  archive.push(".gz");
+  let archive = PathBuf::from(archive);
+  let mut partial = archive.as_os_str().to_owned();
+  partial.push(".tmp");
+  let partial = PathBuf::from(partial);
+
+  let mut input = File::open(path)?;
+  remove_if_exists(&partial)?;
+  let mut encoder = GzEncoder::new(open_log_file(&partial, permissions)?, Compression::default());
+  io::copy(&mut input, &mut encoder)?;
+  encoder.finish()?.sync_all()?;
+  fs::rename(&partial, &archive)?;
+  fs::remove_file(path)
+}
+
+/// Function to remove a file, ignoring it when it does not exist.
+///
+/// # Arguments
+/// * `path`: The path to the file to remove.
+///
+/// # Returns
+/// * `io::Result<()>`: The error, if any, raised removing an existing file.
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+  match fs::remove_file(path) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+    _ => Ok(()),
+  }
+}
+
+/// Function to apply the configured ownership and permissions to the log file.
+///
+/// Failures (e.g. changing the owner without the required privileges) are
+/// reported on stderr and do not stop the Logger.
+///
+/// # Arguments
+/// * `log_file`: The path to the log file.
+/// * `permissions`: The ownership and permissions to apply.
+#[cfg(unix)]
+fn apply_permissions(log_file: &Path, permissions: &LogFilePermissions) {
+  use std::fs::Permissions;
+  use std::os::unix::fs::{PermissionsExt, chown};
+
+  if (permissions.owner.is_some() || permissions.group.is_some())
+    && let Err(e) = chown(log_file, permissions.owner, permissions.group)
+  {
+    eprintln!("Failed to change log file owner: {e}");
+  }
+  if let Some(mode) = permissions.mode
+    && let Err(e) = fs::set_permissions(log_file, Permissions::from_mode(mode))
+  {
+    eprintln!("Failed to change log file permissions: {e}");
+  }
+}
+
+/// Function to apply the configured ownership and permissions to the log file.
+///
+/// Ownership and permission bits are Unix concepts, so this is a no-op on
+/// other platforms.
+#[cfg(not(unix))]
+fn apply_permissions(_log_file: &Path, _permissions: &LogFilePermissions) {}
+
+/// Define the fields of a log entry.
+#[derive(Debug)]
+struct Entry<'a> {
+  level: LogLevel,
+  target: Option<&'a str>,
+  message: &'a str,
+  error: Option<&'a str>,
+}
+
+/// Function to format a log entry according to the given format.
+///
+/// # Arguments
+/// * `format`: The format of the entry.
+/// * `now`: The time of the entry.
+/// * `entry`: The fields of the entry.
+///
+/// # Returns
+/// * `String`: The formatted entry, without the trailing newline.
+fn format_entry(format: LogFormat, now: &DateTime<Local>, entry: &Entry) -> String {
+  let level = entry.level.as_str();
+  let message = entry.message;
+  match format {
+    LogFormat::Text => {
+      // Format timestamp with milliseconds
+      let timestamp = now.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+      let target = entry.target.map(|target| format!("[{target}] ")).unwrap_or_default();
+      let error = entry.error.map(|error| format!(": {error}")).unwrap_or_default();
+      // The timestamp and level are left-aligned with 20 and 8 padding spaces,
+      // respectively.
+      format!("{timestamp:<20} - {level:<8}  {target}{message}{error}")
+    }
+    LogFormat::Json => {
+      let mut json = serde_json::json!({
+        "timestamp": now.to_rfc3339_opts(SecondsFormat::Millis, false),
+        "level": level,
+        "message": message,
+      });
+      if let Some(target) = entry.target {
+        json["target"] = serde_json::Value::String(target.to_string());
+      }
+      if let Some(error) = entry.error {
+        json["error"] = serde_json::Value::String(error.to_string());
+      }
+      json.to_string()
+    }
+  }
+}
+
+/// Function to wrap a level token in the ANSI color matching its severity.
+///
+/// ERROR is red, WARN yellow, INFO green, DEBUG and TRACE dim; unknown tokens
+/// are returned as they are.
+///
+/// # Arguments
+/// * `level`: The level token (e.g., "DEBUG", "INFO", "WARN", "ERROR").
+///
+/// # Returns
+/// * `String`: The colorized level token.
+pub fn colorize(level: &str) -> String {
+  let color = match level {
+    "ERROR" => Color::Red,
+    "WARN" => Color::Yellow,
+    "INFO" => Color::Green,
+    "DEBUG" | "TRACE" => Color::Dim,
+    _ => return level.to_string(),
+  };
+  color::paint(level, color, true)
+}
+
+/// Function to mask the secrets found in a message.
+///
+/// The value of `secret=` parameters (as found in `otpauth://` URIs) and every
+/// token shaped like a base64-encoded WireGuard key are replaced with `***`.
+/// Public keys share the shape of private keys, so they are masked as well.
+/// A key must stand on its own, not be part of a longer base64 run, so that
+/// e.g. hashes or certificates are not mangled.
+///
+/// # Arguments
+/// * `message`: The message to be redacted.
+///
+/// # Returns
+/// * `String`: The message with its secrets replaced with `***`.
+pub fn redact(message: &str) -> String {
+  static OTP_SECRET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)(\bsecret=)[^&\s]+").expect("Invalid OTP secret pattern"));
+  // The regex crate has no lookarounds, so the base64 runs are matched whole
+  // and only masked when they are shaped like a key
+  static BASE64_RUN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[A-Za-z0-9+/]+=*").expect("Invalid base64 pattern"));
+  static WG_KEY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z0-9+/]{42}[AEIMQUYcgkosw048]=$").expect("Invalid key pattern"));
+
+  let message = OTP_SECRET.replace_all(message, "${1}***");
+  BASE64_RUN
+      .replace_all(&message, |run: &regex::Captures| {
+        if WG_KEY.is_match(&run[0]) { "***".to_string() } else { run[0].to_string() }
+      })
+      .into_owned()
+}
+
+/// Function to truncate a message exceeding the maximum allowed length.
+///
+/// The message is cut on the nearest character boundary below `max_len` and
+/// a `...[truncated N bytes]` suffix is appended, where `N` is the number of
+/// bytes removed. Messages within the limit are returned untouched.
+///
+/// # Arguments
+/// * `message`: The message to be truncated.
+/// * `max_len`: The maximum length, in bytes, of the message.
+///
+/// # Returns
+/// * `Cow<str>`: The original message or its truncated copy.
+fn truncate(message: &str, max_len: usize) -> Cow<'_, str> {
+  if message.len() <= max_len {
+    return Cow::Borrowed(message);
+  }
+
+  let mut end = max_len;
+  while !message.is_char_boundary(end) {
+    end -= 1;
+  }
+  let removed = message.len() - end;
+  Cow::Owned(format!("{}...[truncated {removed} bytes]", &message[..end]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::{ManualClock, temp_dir};
+  use chrono::TimeZone;
+  use tempfile::TempDir;
+
+  /// Function to build the options of a Logger writing every level, whatever
+  /// the environment of the tests.
+  fn options() -> LoggerOptions {
+    LoggerOptions {
+      min_level: LogLevel::Trace,
+      targets: HashMap::new(),
+      ..Default::default()
+    }
+  }
+
+  /// Function to start a Logger writing to `wgb.log`, in a directory of its
+  /// own, apart from the global one.
+  fn file_logger(options: LoggerOptions) -> (TempDir, Logger) {
+    let dir = temp_dir();
+    let logger = Logger::start(&dir.path().join("wgb.log").display().to_string(), options).unwrap();
+    (dir, logger)
+  }
+
+  /// Function to open the writer of a log file following the given clock,
+  /// so that its lines are written without going through a Logger.
+  fn clocked_writer(pattern: &Path, clock: Arc<ManualClock>, options: LoggerOptions) -> Writer {
+    Writer::open(pattern.to_path_buf(), LoggerOptions { clock, ..options }).unwrap()
+  }
+
+  /// Function to read a log file, as a missing file reads as empty.
+  fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_default()
+  }
+
+  /// Function to stop a Logger and read the lines it wrote to `wgb.log`,
+  /// the session summary included.
+  fn written(dir: &TempDir, logger: &Logger) -> Vec<String> {
+    logger.shutdown();
+    fs::read_to_string(dir.path().join("wgb.log")).unwrap().lines().map(str::to_string).collect()
+  }
+
+  #[test]
+  fn macros_record_the_call_site_target_and_level() {
+    let (dir, logger) = file_logger(LoggerOptions {
+      targets: HashMap::from([("core::logger::tests".to_string(), LogLevel::Info)]),
+      ..options()
+    });
+    let line = line!() + 1;
+    wgb_log!(@to logger, LogLevel::Warn, "{} tunnels up", 2);
+    // Below the level of the target of the module
+    wgb_log!(@to logger, LogLevel::Debug, "not written");
+
+    let lines = written(&dir, &logger);
+    assert_eq!(lines.len(), 2, "{lines:?}");
+    assert!(lines[0].contains(" - WARN      [core::logger::tests] "), "{}", lines[0]);
+    assert!(lines[0].ends_with(&format!("] [src/core/logger.rs:{line}] 2 tunnels up")), "{}", lines[0]);
+    assert!(lines[1].ends_with("session ended: 1 warning, 0 errors"));
+    assert_eq!(module_target("wgb::core::wg"), "core::wg");
+    assert_eq!(module_target("wgb"), "wgb");
+  }
+
+  #[test]
+  fn zero_flush_interval_flushes_every_line() {
+    assert_eq!(FlushPolicy::Interval(Duration::ZERO).normalized(), FlushPolicy::EveryLine);
+  }
+
+  #[test]
+  fn short_flush_interval_is_raised_to_the_minimum() {
+    let policy = FlushPolicy::Interval(Duration::from_nanos(1)).normalized();
+    assert_eq!(policy, FlushPolicy::Interval(MIN_FLUSH_INTERVAL));
+    let policy = FlushPolicy::Interval(Duration::from_secs(5)).normalized();
+    assert_eq!(policy, FlushPolicy::Interval(Duration::from_secs(5)));
+  }
+
+  #[test]
+  fn try_push_never_blocks_on_a_full_queue() {
+    let queue = LogQueue::new(1, OnFull::Block);
+    queue.push(Message::Line(LogLevel::Info, "first".to_string()));
+    queue.try_push(Message::Line(LogLevel::Error, "panic".to_string()));
+
+    assert_eq!(queue.take_dropped(), 1);
+    match queue.pop(Duration::ZERO) {
+      Some(Message::Line(_, line)) => assert_eq!(line, "first"),
+      _ => panic!("expected the first line"),
+    }
+  }
+
+  #[test]
+  fn try_push_queues_when_there_is_room() {
+    let queue = LogQueue::new(2, OnFull::Block);
+    queue.try_push(Message::Line(LogLevel::Error, "panic".to_string()));
+
+    assert_eq!(queue.take_dropped(), 0);
+    assert!(matches!(queue.pop(Duration::ZERO), Some(Message::Line(LogLevel::Error, _))));
+  }
+
+  #[test]
+  fn oversized_messages_are_truncated() {
+    assert_eq!(truncate("tunnel up", 9), "tunnel up");
+    assert_eq!(truncate("tunnel up", 6), "tunnel...[truncated 3 bytes]");
+    // The cut falls back to the previous character boundary
+    assert_eq!(truncate("aé", 2), "a...[truncated 2 bytes]");
+
+    let (dir, logger) = file_logger(LoggerOptions {
+      max_message_len: 6,
+      ..options()
+    });
+    logger.info("tunnel up");
+    logger.info("short");
+    let lines = written(&dir, &logger);
+    assert!(lines[0].ends_with("  tunnel...[truncated 3 bytes]"), "{}", lines[0]);
+    assert!(lines[1].ends_with("  short"), "{}", lines[1]);
+  }
+
+  #[test]
+  fn scoped_messages_carry_their_context() {
+    let (dir, logger) = file_logger(options());
+    logger.scope("wg0").info("up");
+    logger.scope("wg1").log_error(LogLevel::Warn, "late handshake", &io::Error::other("timed out"));
+    logger.info("unscoped");
+
+    let lines = written(&dir, &logger);
+    assert!(lines[0].ends_with("  [wg0] up"), "{}", lines[0]);
+    assert!(lines[1].ends_with("  [wg1] late handshake: timed out"), "{}", lines[1]);
+    assert!(lines[2].ends_with("  unscoped"), "{}", lines[2]);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn log_files_get_the_configured_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (dir, logger) = file_logger(LoggerOptions {
+      permissions: LogFilePermissions {
+        mode: Some(0o640),
+        ..Default::default()
+      },
+      max_bytes: Some(1),
+      ..options()
+    });
+    logger.info("first");
+    // Rotated away, so that the second line lands in a fresh file
+    logger.info("second");
+    written(&dir, &logger);
+
+    for name in ["wgb.log", "wgb.log.1"] {
+      let mode = fs::metadata(dir.path().join(name)).unwrap().permissions().mode();
+      assert_eq!(mode & 0o777, 0o640, "{name}");
+    }
+  }
+
+  #[test]
+  fn panics_are_logged_at_error_level() {
+    const LOG_FILE_ENV: &str = "WGB_TEST_PANIC_LOG";
+    const TEST: &str = "core::logger::tests::panics_are_logged_at_error_level";
+
+    // The child process installs the global Logger and panics
+    if let Ok(log_file) = std::env::var(LOG_FILE_ENV) {
+      Logger::init_with_options(&log_file, options()).unwrap();
+      panic!("tunnel table corrupted");
+    }
+    let dir = temp_dir();
+    let log_file = dir.path().join("wgb.log");
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", TEST, "--nocapture"])
+        .env(LOG_FILE_ENV, &log_file)
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    // The default hook still runs after the Logger one
+    assert!(String::from_utf8_lossy(&output.stderr).contains("tunnel table corrupted"));
+    let log = fs::read_to_string(&log_file).unwrap();
+    let entry = format!(" - ERROR     thread '{TEST}' panicked at src/core/logger.rs:");
+    assert!(log.contains(&entry), "{log}");
+    assert!(log.contains("tunnel table corrupted\n"), "{log}");
+  }
+
+  #[test]
+  fn messages_below_the_minimum_level_are_dropped() {
+    let (dir, logger) = file_logger(LoggerOptions {
+      min_level: LogLevel::Warn,
+      ..options()
+    });
+    logger.trace("trace");
+    logger.log_for(LogLevel::Debug, "core::wg", "debug");
+    logger.info("info");
+    logger.warn("warn");
+    logger.error("error");
+
+    let lines = written(&dir, &logger);
+    assert_eq!(lines.len(), 3, "{lines:?}");
+    assert!(lines[0].ends_with(" - WARN      warn"), "{}", lines[0]);
+    assert!(lines[1].ends_with(" - ERROR     error"), "{}", lines[1]);
+    assert_eq!("Warning".parse::<LogLevel>().unwrap(), LogLevel::Warn);
+    assert!("loud".parse::<LogLevel>().is_err());
+  }
+
+  #[test]
+  fn oversized_log_files_are_rotated() {
+    let dir = temp_dir();
+    let path = dir.path().join("wgb.log");
+    let clock = ManualClock::at(Local::now());
+    let mut writer = clocked_writer(&path, clock, LoggerOptions {
+      max_bytes: Some(100),
+      max_files: 2,
+      ..options()
+    });
+    let lines: Vec<String> = (1..=4).map(|n| n.to_string().repeat(60)).collect();
+    for line in &lines {
+      writer.write_line(line);
+    }
+    writer.flush();
+
+    // Two lines never fit in the same file, and only two rotated files are
+    // kept, so the first line is gone
+    assert_eq!(read(&path), format!("{}\n", lines[3]));
+    assert_eq!(read(&rotated_path(&path, 1, "")), format!("{}\n", lines[2]));
+    assert_eq!(read(&rotated_path(&path, 2, "")), format!("{}\n", lines[1]));
+    assert!(!rotated_path(&path, 3, "").exists());
+  }
+
+  #[test]
+  fn dated_log_files_follow_the_local_date() {
+    let dir = temp_dir();
+    let clock = ManualClock::at(Local.with_ymd_and_hms(2026, 10, 14, 23, 59, 59).unwrap());
+    let mut writer = clocked_writer(&dir.path().join("%Y-%m-%d.log"), clock.clone(), options());
+    writer.write_line("before midnight");
+    clock.advance(chrono::Duration::seconds(1));
+    writer.write_line("after midnight");
+    writer.flush();
+
+    assert_eq!(read(&dir.path().join("2026-10-14.log")), "before midnight\n");
+    assert_eq!(read(&dir.path().join("2026-10-15.log")), "after midnight\n");
+  }
+
+  #[test]
+  fn every_n_policy_flushes_every_n_lines() {
+    let dir = temp_dir();
+    let path = dir.path().join("wgb.log");
+    let mut writer = clocked_writer(&path, ManualClock::at(Local::now()), LoggerOptions {
+      flush_policy: FlushPolicy::EveryN(3),
+      ..options()
+    });
+    writer.write_line("first");
+    writer.write_line("second");
+    assert_eq!(read(&path), "");
+    writer.write_line("third");
+    assert_eq!(read(&path), "first\nsecond\nthird\n");
+
+    // An explicit flush does not wait for the policy
+    writer.write_line("fourth");
+    writer.flush();
+    assert_eq!(read(&path), "first\nsecond\nthird\nfourth\n");
+  }
+
+  #[test]
+  fn interval_policy_flushes_once_the_interval_elapsed() {
+    let dir = temp_dir();
+    let path = dir.path().join("wgb.log");
+    let clock = ManualClock::at(Local::now());
+    let mut writer = clocked_writer(&path, clock.clone(), LoggerOptions {
+      flush_policy: FlushPolicy::Interval(Duration::from_secs(60)),
+      ..options()
+    });
+    writer.write_line("first");
+    clock.advance(chrono::Duration::seconds(59));
+    writer.flush_if_due();
+    assert_eq!(read(&path), "");
+    clock.advance(chrono::Duration::seconds(1));
+    writer.flush_if_due();
+    assert_eq!(read(&path), "first\n");
+  }
+
+  #[test]
+  fn shutdown_flushes_whatever_the_policy() {
+    let (dir, logger) = file_logger(LoggerOptions {
+      flush_policy: FlushPolicy::EveryN(100),
+      ..options()
+    });
+    logger.info("pending");
+    let lines = written(&dir, &logger);
+    assert!(lines[0].ends_with("  pending"), "{}", lines[0]);
+  }
+
+  #[test]
+  fn json_lines_parse_back() {
+    let (dir, logger) = file_logger(LoggerOptions {
+      format: LogFormat::Json,
+      ..options()
+    });
+    logger.log_error(LogLevel::Warn, "late handshake", &io::Error::other("timed out"));
+    logger.log_for(LogLevel::Info, "core::wg", "up");
+
+    let lines = written(&dir, &logger);
+    let entry: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+    assert_eq!(entry["level"], "WARN");
+    assert_eq!(entry["message"], "late handshake");
+    assert_eq!(entry["error"], "timed out");
+    assert!(DateTime::parse_from_rfc3339(entry["timestamp"].as_str().unwrap()).is_ok());
+    let entry: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+    assert_eq!(entry["target"], "core::wg");
+    assert!(entry.get("error").is_none());
+  }
+
+  #[test]
+  fn secrets_are_redacted() {
+    const KEY: &str = crate::core::testing::KEY;
+
+    assert_eq!(
+      redact("otpauth://totp/WG-Bridge:alice?secret=JBSWY3DPEHPK3PXP&issuer=WG-Bridge"),
+      "otpauth://totp/WG-Bridge:alice?secret=***&issuer=WG-Bridge"
+    );
+    assert_eq!(redact(&format!("peer {KEY}, endpoint down")), "peer ***, endpoint down");
+    assert_eq!(redact(&format!("PrivateKey={KEY}\nPublicKey = ({KEY})")), "PrivateKey=***\nPublicKey = (***)");
+    // Longer base64 runs merely holding a key are left alone
+    for token in [format!("A{KEY}"), format!("{KEY}="), format!("{}{KEY}", &KEY[..43])] {
+      assert_eq!(redact(&token), token);
+    }
+
+    let (dir, logger) = file_logger(options());
+    logger.log_error(LogLevel::Error, &format!("Invalid peer {KEY}"), &io::Error::other(KEY));
+    let lines = written(&dir, &logger);
+    assert!(lines[0].ends_with("  Invalid peer ***: ***"), "{}", lines[0]);
+  }
+
+  #[test]
+  fn session_summary_counts_warnings_and_errors() {
+    let (dir, logger) = file_logger(LoggerOptions {
+      min_level: LogLevel::Warn,
+      ..options()
+    });
+    logger.warn("first");
+    logger.log_for(LogLevel::Warn, "core::wg", "second");
+    logger.error("third");
+    logger.info("not written, nor counted");
+
+    let lines = written(&dir, &logger);
+    assert_eq!(lines.len(), 4, "{lines:?}");
+    assert!(lines[3].ends_with(" - INFO      session ended: 2 warnings, 1 error"), "{}", lines[3]);
+  }
+
+  #[test]
+  fn directives_map_targets_to_levels() {
+    let targets = parse_directives(" core::wg=debug, cli=WARN,,").unwrap();
+    assert_eq!(
+      targets,
+      HashMap::from([("core::wg".to_string(), LogLevel::Debug), ("cli".to_string(), LogLevel::Warn)])
+    );
+    assert!(parse_directives("").unwrap().is_empty());
+    assert!(matches!(parse_directives("core::wg"), Err(ParseDirectiveError::Malformed(d)) if d == "core::wg"));
+    assert!(matches!(parse_directives("=debug"), Err(ParseDirectiveError::Malformed(_))));
+    assert!(matches!(parse_directives("cli=warn,core=loud"), Err(ParseDirectiveError::Level(_))));
+  }
+
+  /// Function to pop the lines left in a queue.
+  fn drain(queue: &LogQueue) -> Vec<String> {
+    std::iter::from_fn(|| match queue.pop(Duration::ZERO) {
+      Some(Message::Line(_, line)) => Some(line),
+      _ => None,
+    })
+    .collect()
+  }
+
+  #[test]
+  fn full_queues_apply_their_drop_policy() {
+    for (on_full, kept) in [(OnFull::DropOldest, ["second", "third"]), (OnFull::DropNewest, ["first", "second"])] {
+      let queue = LogQueue::new(2, on_full);
+      for line in ["first", "second", "third"] {
+        queue.push(Message::Line(LogLevel::Info, line.to_string()));
+      }
+      // Flush requests do not count towards the capacity
+      queue.push(Message::Flush(mpsc::channel().0));
+
+      assert_eq!(queue.take_dropped(), 1, "{on_full:?}");
+      assert_eq!(queue.take_dropped(), 0, "{on_full:?}");
+      assert_eq!(drain(&queue), kept, "{on_full:?}");
+    }
+  }
+
+  #[test]
+  fn full_queues_block_until_a_line_is_written() {
+    let queue = Arc::new(LogQueue::new(1, OnFull::Block));
+    queue.push(Message::Line(LogLevel::Info, "first".to_string()));
+    let (pushed_tx, pushed_rx) = mpsc::channel();
+    let producer = {
+      let queue = Arc::clone(&queue);
+      thread::spawn(move || {
+        queue.push(Message::Line(LogLevel::Info, "second".to_string()));
+        pushed_tx.send(()).unwrap();
+      })
+    };
+
+    assert!(pushed_rx.recv_timeout(Duration::from_millis(100)).is_err());
+    assert!(matches!(queue.pop(Duration::ZERO), Some(Message::Line(_, line)) if line == "first"));
+    pushed_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    producer.join().unwrap();
+    assert_eq!(queue.take_dropped(), 0);
+    assert_eq!(drain(&queue), ["second"]);
+  }
 }
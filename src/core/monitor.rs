@@ -0,0 +1,319 @@
+// monitor.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use std::ffi::OsString;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Name of the directory holding the PID files of the background monitors,
+/// located in the user's home directory.
+const MONITOR_DIR_NAME: &str = ".wgbmonitors";
+
+/// Extension of the PID file of a monitor, named after its interface.
+const PID_EXTENSION: &str = "pid";
+
+/// Longest time a monitor is given to exit once asked to stop.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time between two checks that a stopped monitor has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Define the errors raised while starting or stopping a background monitor.
+#[derive(Debug, Error)]
+pub enum MonitorError {
+  #[error("Failed to access the monitors in {}: {source}", .dir.display())]
+  Io { dir: PathBuf, source: io::Error },
+  #[error("Invalid PID file {}", .0.display())]
+  InvalidPidFile(PathBuf),
+  #[error("A monitor of {interface} is running already (PID {pid}), stop it with `wgb stop` first")]
+  Running { interface: String, pid: u32 },
+  #[error("No monitor of {0} is running")]
+  NotRunning(String),
+  #[error("Failed to start the monitor: {0}")]
+  Spawn(io::Error),
+  #[error("Failed to stop the monitor of {interface} (PID {pid}): {source}")]
+  Signal { interface: String, pid: u32, source: io::Error },
+  #[error("The monitor of {interface} (PID {pid}) is still running after {}s", STOP_TIMEOUT.as_secs())]
+  StillRunning { interface: String, pid: u32 },
+}
+
+/// Function to retrieve the directory of the PID files.
+///
+/// # Returns
+/// * `Option<PathBuf>`: `~/.wgbmonitors`, or `None` when the home directory
+///   is unknown.
+pub fn default_dir() -> Option<PathBuf> {
+  std::env::home_dir().map(|home| home.join(MONITOR_DIR_NAME))
+}
+
+/// Function to start a background monitor, detached from the terminal.
+///
+/// The monitor is a new `wgb` process leading its own session, so that it
+/// has no controlling terminal and outlives the shell; its stdin is
+/// `/dev/null` and its stdout and stderr are appended to the log file.
+///
+/// # Arguments
+/// * `args`: The arguments of the monitor, given to the current executable.
+/// * `log_file`: The file the output of the monitor is appended to.
+///
+/// # Returns
+/// * `Result<Child, MonitorError>`: The monitor, left running when dropped,
+///   or the error raised opening the log file or starting the process.
+pub fn spawn(args: &[OsString], log_file: &Path) -> Result<Child, MonitorError> {
+  let log = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(log_file)
+      .map_err(MonitorError::Spawn)?;
+  let exe = std::env::current_exe().map_err(MonitorError::Spawn)?;
+  let mut command = Command::new(exe);
+  command
+      .args(args)
+      .stdin(Stdio::null())
+      .stdout(log.try_clone().map_err(MonitorError::Spawn)?)
+      .stderr(log);
+  // SAFETY: setsid is async-signal-safe and touches no memory of the parent
+  unsafe {
+    command.pre_exec(|| match libc::setsid() {
+      -1 => Err(io::Error::last_os_error()),
+      _ => Ok(()),
+    });
+  }
+  command.spawn().map_err(MonitorError::Spawn)
+}
+
+/// Function to record the PID of the monitor of an interface.
+///
+/// The PID is written to a temporary file which is then renamed over the
+/// previous one, so that a crash never leaves half a PID behind. A PID file
+/// left by a monitor which is gone is replaced.
+///
+/// # Arguments
+/// * `dir`: The directory of the PID files, created when missing.
+/// * `interface`: The name of the interface.
+/// * `pid`: The PID of the monitor.
+///
+/// # Returns
+/// * `Result<(), MonitorError>`: The error, if any, raised while writing, or
+///   because another monitor of the interface is running.
+pub fn record(dir: &Path, interface: &str, pid: u32) -> Result<(), MonitorError> {
+  if let Some(running) = running(dir, interface)?
+      && running != pid
+  {
+    return Err(MonitorError::Running {
+      interface: interface.to_string(),
+      pid: running,
+    });
+  }
+  let io_error = |source| MonitorError::Io {
+    dir: dir.to_path_buf(),
+    source,
+  };
+  DirBuilder::new().recursive(true).mode(0o700).create(dir).map_err(io_error)?;
+  let temp = dir.join(format!(".{interface}.{}.tmp", std::process::id()));
+  fs::write(&temp, format!("{pid}\n")).map_err(io_error)?;
+  fs::rename(&temp, pid_path(dir, interface)).map_err(|e| {
+    let _ = fs::remove_file(&temp);
+    io_error(e)
+  })
+}
+
+/// Function to find the running monitor of an interface.
+///
+/// A PID file whose process is gone, e.g. after a reboot, is removed.
+///
+/// # Arguments
+/// * `dir`: The directory of the PID files.
+/// * `interface`: The name of the interface.
+///
+/// # Returns
+/// * `Result<Option<u32>, MonitorError>`: The PID of the monitor, `None`
+///   when none is running, or the error raised reading the PID file.
+pub fn running(dir: &Path, interface: &str) -> Result<Option<u32>, MonitorError> {
+  let path = pid_path(dir, interface);
+  let content = match fs::read_to_string(&path) {
+    Ok(content) => content,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(source) => {
+      return Err(MonitorError::Io {
+        dir: dir.to_path_buf(),
+        source,
+      });
+    }
+  };
+  // A PID of 0 would signal the whole process group
+  let pid = content
+      .trim()
+      .parse()
+      .ok()
+      .filter(|pid| *pid > 0)
+      .ok_or(MonitorError::InvalidPidFile(path))?;
+  if is_alive(pid) {
+    return Ok(Some(pid));
+  }
+  forget(dir, interface)?;
+  Ok(None)
+}
+
+/// Function to stop the monitor of an interface, waiting for it to exit.
+///
+/// # Arguments
+/// * `dir`: The directory of the PID files.
+/// * `interface`: The name of the interface.
+///
+/// # Returns
+/// * `Result<u32, MonitorError>`: The PID of the stopped monitor, or the
+///   error raised because none is running, signalling it or because it is
+///   still running after `STOP_TIMEOUT`.
+pub fn stop(dir: &Path, interface: &str) -> Result<u32, MonitorError> {
+  let Some(pid) = running(dir, interface)? else {
+    return Err(MonitorError::NotRunning(interface.to_string()));
+  };
+  let signal_error = |source| MonitorError::Signal {
+    interface: interface.to_string(),
+    pid,
+    source,
+  };
+  let raw_pid = libc::pid_t::try_from(pid).map_err(|_| signal_error(io::ErrorKind::InvalidInput.into()))?;
+  // SAFETY: kill only sends a signal, the PID being checked alive above
+  if unsafe { libc::kill(raw_pid, libc::SIGTERM) } != 0 {
+    return Err(signal_error(io::Error::last_os_error()));
+  }
+  let deadline = Instant::now() + STOP_TIMEOUT;
+  while is_alive(pid) {
+    if Instant::now() >= deadline {
+      return Err(MonitorError::StillRunning {
+        interface: interface.to_string(),
+        pid,
+      });
+    }
+    thread::sleep(POLL_INTERVAL);
+  }
+  forget(dir, interface)?;
+  Ok(pid)
+}
+
+/// Function to forget the monitor of an interface, once stopped.
+///
+/// # Arguments
+/// * `dir`: The directory of the PID files.
+/// * `interface`: The name of the interface.
+///
+/// # Returns
+/// * `Result<(), MonitorError>`: The error, if any, raised while removing
+///   the PID file; none when there is no PID file.
+pub fn forget(dir: &Path, interface: &str) -> Result<(), MonitorError> {
+  match fs::remove_file(pid_path(dir, interface)) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(MonitorError::Io {
+      dir: dir.to_path_buf(),
+      source: e,
+    }),
+    _ => Ok(()),
+  }
+}
+
+/// Function to tell whether a process is running.
+///
+/// # Arguments
+/// * `pid`: The PID of the process.
+///
+/// # Returns
+/// * `bool`: Whether the process exists, even when owned by another user.
+fn is_alive(pid: u32) -> bool {
+  let Ok(pid) = libc::pid_t::try_from(pid) else {
+    return false;
+  };
+  // SAFETY: the signal 0 only checks that the process can be signalled
+  let signalled = unsafe { libc::kill(pid, 0) } == 0;
+  signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Function to build the path of the PID file of an interface.
+///
+/// # Arguments
+/// * `dir`: The directory of the PID files.
+/// * `interface`: The name of the interface.
+///
+/// # Returns
+/// * `PathBuf`: The path of the PID file.
+fn pid_path(dir: &Path, interface: &str) -> PathBuf {
+  dir.join(interface).with_extension(PID_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::temp_dir;
+  use std::os::unix::process::ExitStatusExt;
+
+  #[test]
+  fn pid_files_are_recorded_and_stale_ones_removed() {
+    let temp = temp_dir();
+    // The directory is created by the first PID recorded
+    let dir = temp.path().join("monitors");
+    assert_eq!(running(&dir, "wg0").unwrap(), None);
+    let own = std::process::id();
+    record(&dir, "wg0", own).unwrap();
+    record(&dir, "wg0", own).unwrap();
+    assert_eq!(running(&dir, "wg0").unwrap(), Some(own));
+    assert!(matches!(record(&dir, "wg0", own + 1), Err(MonitorError::Running { pid, .. }) if pid == own));
+
+    // The PID of a process which exited is stale
+    let mut exited = Command::new("true").spawn().unwrap();
+    exited.wait().unwrap();
+    record(&dir, "wg1", exited.id()).unwrap();
+    assert_eq!(running(&dir, "wg1").unwrap(), None);
+    assert!(!pid_path(&dir, "wg1").exists());
+    record(&dir, "wg1", exited.id()).unwrap();
+    record(&dir, "wg1", own).unwrap();
+    assert_eq!(running(&dir, "wg1").unwrap(), Some(own));
+
+    fs::write(pid_path(&dir, "wg2"), "0\n").unwrap();
+    assert!(matches!(running(&dir, "wg2"), Err(MonitorError::InvalidPidFile(_))));
+    forget(&dir, "wg0").unwrap();
+    forget(&dir, "wg0").unwrap();
+    assert_eq!(running(&dir, "wg0").unwrap(), None);
+  }
+
+  #[test]
+  fn stop_terminates_the_monitor() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    assert!(matches!(stop(dir, "wg0"), Err(MonitorError::NotRunning(interface)) if interface == "wg0"));
+
+    let mut monitor = Command::new("sleep").arg("30").spawn().unwrap();
+    let pid = monitor.id();
+    // Reaping the monitor as soon as it exits, as init does for a detached one
+    let waiter = thread::spawn(move || monitor.wait().unwrap());
+    record(dir, "wg0", pid).unwrap();
+
+    assert_eq!(stop(dir, "wg0").unwrap(), pid);
+    assert_eq!(waiter.join().unwrap().signal(), Some(libc::SIGTERM));
+    assert!(!pid_path(dir, "wg0").exists());
+    assert!(matches!(stop(dir, "wg0"), Err(MonitorError::NotRunning(_))));
+  }
+
+  #[test]
+  fn spawned_monitors_write_to_the_log_file() {
+    let temp = temp_dir();
+    let log_file = temp.path().join("wgb.log");
+    fs::write(&log_file, "previous\n").unwrap();
+    // The test binary itself, listing its tests instead of monitoring
+    let args = ["--list", "--exact", "core::monitor::tests::spawned_monitors_write_to_the_log_file"];
+    let mut monitor = spawn(&args.map(OsString::from), &log_file).unwrap();
+
+    assert!(monitor.wait().unwrap().success());
+    let log = fs::read_to_string(&log_file).unwrap();
+    assert!(log.starts_with("previous\n"), "{log}");
+    assert!(log.contains("spawned_monitors_write_to_the_log_file: test"), "{log}");
+  }
+}
@@ -0,0 +1,218 @@
+// mtu.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::wg::{self, CommandRunner, Resolver, WgConfig};
+use std::ffi::OsStr;
+use std::fmt;
+use std::net::IpAddr;
+
+/// The program probing the path MTU, with packets which must not be
+/// fragmented.
+const PING: &str = "ping";
+
+/// The largest path MTU probed, the one of Ethernet.
+const MAX_PATH_MTU: u16 = 1500;
+
+/// The smallest path MTU every IPv4 host must accept.
+const MIN_PATH_MTU_V4: u16 = 576;
+
+/// The smallest path MTU of IPv6.
+const MIN_PATH_MTU_V6: u16 = 1280;
+
+/// Define the probe of the path to a host, so that tests can replace the
+/// network.
+pub trait MtuProbe {
+  /// Function to tell whether an IP packet of the given size reaches a host
+  /// without being fragmented.
+  ///
+  /// # Arguments
+  /// * `host`: The address of the host.
+  /// * `size`: The size of the packet, headers included.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the packet got through, `false` when the probe
+  ///   failed.
+  fn fits(&self, host: IpAddr, size: u16) -> bool;
+}
+
+/// Define the probe sending a single `ping` forbidding fragmentation,
+/// through the given runner.
+#[derive(Debug)]
+pub struct PingProbe(pub Box<dyn CommandRunner>);
+
+impl MtuProbe for PingProbe {
+  fn fits(&self, host: IpAddr, size: u16) -> bool {
+    let (family, headers) = match host {
+      IpAddr::V4(_) => ("-4", 28),
+      IpAddr::V6(_) => ("-6", 48),
+    };
+    let payload = size.saturating_sub(headers).to_string();
+    let host = host.to_string();
+    let args = [family, "-c", "1", "-W", "1", "-M", "do", "-s", &payload, &host].map(OsStr::new);
+    self.0.run(PING, &args).is_ok_and(|output| output.status.success())
+  }
+}
+
+/// Define the MTU `connect --auto-mtu` picks for a tunnel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MtuChoice {
+  /// The file sets the MTU, which is kept.
+  Configured(u16),
+  /// The smallest path MTU to the endpoints, less the WireGuard overhead.
+  Probed(u16),
+  /// The path MTU could not be probed, the MTU is left to `wg-quick`.
+  Fallback,
+}
+
+/// Implements the logic of the MTU choice
+impl MtuChoice {
+  /// Function to retrieve the MTU to be set on the interface.
+  ///
+  /// # Returns
+  /// * `Option<u16>`: The probed MTU, or `None` when the one of the file or
+  ///   of `wg-quick` is kept.
+  pub fn mtu(&self) -> Option<u16> {
+    match self {
+      MtuChoice::Probed(mtu) => Some(*mtu),
+      MtuChoice::Configured(_) | MtuChoice::Fallback => None,
+    }
+  }
+}
+
+impl fmt::Display for MtuChoice {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      MtuChoice::Configured(mtu) => write!(f, "{mtu}, set in the file"),
+      MtuChoice::Probed(mtu) => write!(f, "{mtu}, probed"),
+      MtuChoice::Fallback => f.write_str("the default of wg-quick, the path MTU could not be probed"),
+    }
+  }
+}
+
+/// Function to pick the MTU of a tunnel from the path MTU to its endpoints,
+/// when the file sets none.
+///
+/// # Arguments
+/// * `config`: The WireGuard configuration.
+/// * `resolver`: The resolver of the endpoint hostnames.
+/// * `probe`: The probe of the paths.
+///
+/// # Returns
+/// * `MtuChoice`: The MTU of the file, the probed one, or `Fallback` when an
+///   endpoint cannot be resolved or probed, or there is none.
+pub fn choose(config: &WgConfig, resolver: &dyn Resolver, probe: &dyn MtuProbe) -> MtuChoice {
+  if let Some(mtu) = config.interface.mtu {
+    return MtuChoice::Configured(mtu);
+  }
+  let mut smallest: Option<u16> = None;
+  for endpoint in config.peers.iter().filter_map(|peer| peer.endpoint.as_deref()) {
+    let Some((host, port)) = wg::split_endpoint(endpoint) else {
+      return MtuChoice::Fallback;
+    };
+    let address = match host.parse::<IpAddr>() {
+      Ok(address) => Some(address),
+      Err(_) => resolver.resolve(host, port).ok().and_then(|addresses| addresses.first().copied()),
+    };
+    let Some(mtu) = address.and_then(|address| tunnel_mtu(probe, address)) else {
+      return MtuChoice::Fallback;
+    };
+    smallest = Some(smallest.map_or(mtu, |smallest| smallest.min(mtu)));
+  }
+  smallest.map_or(MtuChoice::Fallback, MtuChoice::Probed)
+}
+
+/// Function to find the MTU of a tunnel to a host, by searching the largest
+/// packet reaching it unfragmented.
+///
+/// # Arguments
+/// * `probe`: The probe of the path.
+/// * `host`: The address of the endpoint.
+///
+/// # Returns
+/// * `Option<u16>`: The path MTU less the headers WireGuard adds (60 bytes
+///   over IPv4, 80 over IPv6), or `None` when not even the smallest packet
+///   got through.
+fn tunnel_mtu(probe: &dyn MtuProbe, host: IpAddr) -> Option<u16> {
+  let (mut low, overhead) = match host {
+    IpAddr::V4(_) => (MIN_PATH_MTU_V4, 60),
+    IpAddr::V6(_) => (MIN_PATH_MTU_V6, 80),
+  };
+  if !probe.fits(host, low) {
+    return None;
+  }
+  // `low` always fits, and anything above `high` does not
+  let mut high = MAX_PATH_MTU;
+  while low < high {
+    let middle = low + (high - low).div_ceil(2);
+    if probe.fits(host, middle) {
+      low = middle;
+    } else {
+      high = middle - 1;
+    }
+  }
+  Some(low - overhead)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::KEY;
+  use std::cell::Cell;
+  use std::io;
+
+  /// Define a network whose paths carry packets up to a given size, or
+  /// nothing at all, counting the probes.
+  struct FakePath {
+    mtu: Option<u16>,
+    probes: Cell<usize>,
+  }
+
+  impl MtuProbe for FakePath {
+    fn fits(&self, _host: IpAddr, size: u16) -> bool {
+      self.probes.set(self.probes.get() + 1);
+      self.mtu.is_some_and(|mtu| size <= mtu)
+    }
+  }
+
+  /// Define a resolver knowing no hostname.
+  struct NoResolver;
+
+  impl Resolver for NoResolver {
+    fn resolve(&self, host: &str, _port: u16) -> io::Result<Vec<IpAddr>> {
+      Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown host {host}")))
+    }
+  }
+
+  fn config(mtu: &str, endpoints: &[&str]) -> WgConfig {
+    let mut content = format!("[Interface]\nPrivateKey = {KEY}\n{mtu}");
+    for endpoint in endpoints {
+      content.push_str(&format!("[Peer]\nPublicKey = {KEY}\nEndpoint = {endpoint}\n"));
+    }
+    WgConfig::parse_str(&content).unwrap()
+  }
+
+  #[test]
+  fn the_mtu_follows_the_path_or_falls_back() {
+    let path = |mtu| FakePath {
+      mtu,
+      probes: Cell::new(0),
+    };
+    let pppoe = path(Some(1492));
+    assert_eq!(choose(&config("", &["203.0.113.1:51820"]), &NoResolver, &pppoe), MtuChoice::Probed(1432));
+    assert!(pppoe.probes.get() <= 12, "a binary search, not a scan");
+    assert_eq!(choose(&config("", &["[2001:db8::1]:51820"]), &NoResolver, &pppoe), MtuChoice::Probed(1412));
+    assert_eq!(choose(&config("", &["203.0.113.1:51820"]), &NoResolver, &path(Some(1500))), MtuChoice::Probed(1440));
+
+    // The file decides, and a failed probe leaves the MTU to wg-quick
+    assert_eq!(choose(&config("MTU = 1380\n", &["203.0.113.1:51820"]), &NoResolver, &pppoe), MtuChoice::Configured(1380));
+    let unreachable = path(None);
+    assert_eq!(choose(&config("", &["203.0.113.1:51820"]), &NoResolver, &unreachable), MtuChoice::Fallback);
+    assert_eq!(unreachable.probes.get(), 1);
+    assert_eq!(choose(&config("", &["vpn.example.com:51820"]), &NoResolver, &pppoe), MtuChoice::Fallback);
+    assert_eq!(choose(&config("", &[]), &NoResolver, &pppoe), MtuChoice::Fallback);
+    assert_eq!(MtuChoice::Fallback.mtu(), None);
+    assert_eq!(MtuChoice::Probed(1432).mtu(), Some(1432));
+  }
+}
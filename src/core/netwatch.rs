@@ -0,0 +1,388 @@
+// netwatch.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::wg::{AddressPool, CommandRunner};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The routing table of IPv4, as exposed by Linux.
+const IPV4_ROUTES: &str = "/proc/net/route";
+
+/// The routing table of IPv6, as exposed by Linux.
+const IPV6_ROUTES: &str = "/proc/net/ipv6_route";
+
+/// The neighbour table of IPv4, as exposed by Linux.
+const ARP_TABLE: &str = "/proc/net/arp";
+
+/// The program printing the SSID of the Wi-Fi network joined.
+const IWGETID: &str = "iwgetid";
+
+/// How many polling intervals may pass between two polls before the system
+/// is deemed to have been suspended.
+const RESUME_FACTOR: u32 = 3;
+
+/// Define a change of the network the tunnels run over.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetworkChange {
+  /// The default routes changed, e.g. after a switch to another Wi-Fi.
+  Routes,
+  /// The system resumed from a suspend, after the given time.
+  Resumed(Duration),
+}
+
+impl fmt::Display for NetworkChange {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      NetworkChange::Routes => f.write_str("the default routes changed"),
+      NetworkChange::Resumed(after) => write!(f, "the system resumed after {}s", after.as_secs()),
+    }
+  }
+}
+
+/// Define a network a tunnel is not needed on, e.g. the home Wi-Fi.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustedNetwork {
+  /// The Wi-Fi network with the given SSID.
+  Ssid(String),
+  /// The network whose default gateway has the given MAC address.
+  GatewayMac(String),
+  /// The networks whose default gateway is in the given subnet, e.g.
+  /// `192.168.1.0/24`.
+  Subnet(String),
+}
+
+impl fmt::Display for TrustedNetwork {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TrustedNetwork::Ssid(ssid) => write!(f, "SSID {ssid}"),
+      TrustedNetwork::GatewayMac(mac) => write!(f, "gateway MAC {mac}"),
+      TrustedNetwork::Subnet(subnet) => write!(f, "subnet {subnet}"),
+    }
+  }
+}
+
+/// Implements the logic to match the networks the host is on
+impl TrustedNetwork {
+  /// Function to tell whether the host is on the network.
+  ///
+  /// # Arguments
+  /// * `network`: The network the host is on.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the network matches; an invalid subnet never does.
+  pub fn matches(&self, network: &CurrentNetwork) -> bool {
+    match self {
+      TrustedNetwork::Ssid(ssid) => network.ssids.contains(ssid),
+      TrustedNetwork::GatewayMac(mac) => network.gateway_macs.iter().any(|known| known.eq_ignore_ascii_case(mac)),
+      TrustedNetwork::Subnet(subnet) => subnet
+          .parse::<AddressPool>()
+          .is_ok_and(|subnet| network.gateways.iter().any(|gateway| subnet.contains(*gateway))),
+    }
+  }
+}
+
+/// Define the network the host is on, as far as `TrustedNetwork` tells.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CurrentNetwork {
+  /// The gateways of the default routes.
+  pub gateways: Vec<IpAddr>,
+  /// The MAC addresses of the IPv4 gateways, in lowercase.
+  pub gateway_macs: Vec<String>,
+  /// The SSIDs of the Wi-Fi networks joined.
+  pub ssids: Vec<String>,
+}
+
+/// Define the detector of the network the host is on, so that tests can
+/// stub it.
+pub trait NetworkDetector {
+  /// Function to detect the network the host is on.
+  ///
+  /// # Returns
+  /// * `CurrentNetwork`: The network; what cannot be detected is left empty.
+  fn detect(&self) -> CurrentNetwork;
+}
+
+/// Define the detector reading the routing and neighbour tables of Linux,
+/// and asking `iwgetid` for the SSID through the given runner.
+///
+/// The default routes of the tunnels go through their interface, without a
+/// gateway, so they never match.
+#[derive(Debug)]
+pub struct SystemNetwork<R: CommandRunner>(pub R);
+
+impl<R: CommandRunner> NetworkDetector for SystemNetwork<R> {
+  fn detect(&self) -> CurrentNetwork {
+    let ipv4 = fs::read_to_string(IPV4_ROUTES).unwrap_or_default();
+    let ipv6 = fs::read_to_string(IPV6_ROUTES).unwrap_or_default();
+    let gateways = default_gateways(&ipv4, &ipv6);
+    let arp = fs::read_to_string(ARP_TABLE).unwrap_or_default();
+    // `iwgetid` prints nothing and fails without Wi-Fi
+    let ssids = match self.0.run(IWGETID, &[OsStr::new("--raw")]) {
+      Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+          .lines()
+          .map(str::trim)
+          .filter(|ssid| !ssid.is_empty())
+          .map(str::to_string)
+          .collect(),
+      _ => Vec::new(),
+    };
+    CurrentNetwork {
+      gateway_macs: gateway_macs(&arp, &gateways),
+      gateways,
+      ssids,
+    }
+  }
+}
+
+/// Define a source of network changes, so that tests can replace the
+/// routing table with simulated events.
+pub trait NetworkEvents {
+  /// Function to wait for the next change of the network.
+  ///
+  /// # Returns
+  /// * `Option<NetworkChange>`: The change, or `None` when no more changes
+  ///   will come.
+  fn next_change(&mut self) -> Option<NetworkChange>;
+}
+
+/// Define the source of network changes polling the routing tables of
+/// Linux, since netlink would need a socket library for a single purpose.
+///
+/// The default routes of the WireGuard interfaces are left out, so that
+/// bringing a tunnel up or down is not taken for a change. A poll coming
+/// much later than expected tells that the system was suspended.
+#[derive(Debug)]
+pub struct RoutePoller {
+  /// The time between two polls.
+  interval: Duration,
+  /// The interfaces whose routes are left out.
+  ignored: Vec<String>,
+  /// The files of the IPv4 and IPv6 routing tables.
+  tables: [PathBuf; 2],
+  /// The default routes seen by the previous poll.
+  routes: BTreeSet<String>,
+  /// The time of the previous poll.
+  polled: SystemTime,
+}
+
+/// Implements the logic of the routing table polling
+impl RoutePoller {
+  /// Function to start polling the routing tables.
+  ///
+  /// # Arguments
+  /// * `interval`: The time between two polls.
+  /// * `ignored`: The interfaces whose routes are left out, i.e. the ones of
+  ///   the tunnels.
+  ///
+  /// # Returns
+  /// * `RoutePoller`: The poller, holding the current default routes.
+  pub fn new(interval: Duration, ignored: Vec<String>) -> Self {
+    let mut poller = RoutePoller {
+      interval,
+      ignored,
+      tables: [PathBuf::from(IPV4_ROUTES), PathBuf::from(IPV6_ROUTES)],
+      routes: BTreeSet::new(),
+      polled: SystemTime::now(),
+    };
+    poller.routes = poller.default_routes();
+    poller
+  }
+
+  /// Function to read the default routes of the routing tables.
+  ///
+  /// # Returns
+  /// * `BTreeSet<String>`: The default routes, as `interface via gateway`;
+  ///   none for a table which cannot be read.
+  fn default_routes(&self) -> BTreeSet<String> {
+    let [ipv4, ipv6] = &self.tables;
+    let ipv4 = fs::read_to_string(ipv4).unwrap_or_default();
+    let ipv6 = fs::read_to_string(ipv6).unwrap_or_default();
+    default_routes(&ipv4, &ipv6, &self.ignored)
+  }
+}
+
+impl NetworkEvents for RoutePoller {
+  fn next_change(&mut self) -> Option<NetworkChange> {
+    loop {
+      thread::sleep(self.interval);
+      let now = SystemTime::now();
+      let elapsed = now.duration_since(self.polled).unwrap_or_default();
+      self.polled = now;
+      let routes = self.default_routes();
+      let changed = routes != self.routes;
+      self.routes = routes;
+      if elapsed > self.interval * RESUME_FACTOR {
+        return Some(NetworkChange::Resumed(elapsed));
+      }
+      if changed {
+        return Some(NetworkChange::Routes);
+      }
+    }
+  }
+}
+
+/// Function to read the default routes of the Linux routing tables.
+///
+/// # Arguments
+/// * `ipv4`: The content of `/proc/net/route`.
+/// * `ipv6`: The content of `/proc/net/ipv6_route`.
+/// * `ignored`: The interfaces whose routes are left out.
+///
+/// # Returns
+/// * `BTreeSet<String>`: The default routes, as `interface via gateway`, the
+///   gateway written as in the table.
+pub fn default_routes(ipv4: &str, ipv6: &str, ignored: &[String]) -> BTreeSet<String> {
+  let kept = |interface: &str| !ignored.iter().any(|ignored| ignored == interface);
+  // Iface Destination Gateway Flags RefCnt Use Metric Mask ...
+  let ipv4 = ipv4.lines().skip(1).filter_map(|line| {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+      [interface, "00000000", gateway, _, _, _, _, "00000000", ..] if kept(interface) => {
+        Some(format!("{interface} via {gateway}"))
+      }
+      _ => None,
+    }
+  });
+  // Destination PrefixLength Source SourcePrefix NextHop Metric RefCnt Use Flags Iface
+  let ipv6 = ipv6.lines().filter_map(|line| {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+      [destination, "00", _, _, next_hop, _, _, _, _, interface]
+        if destination.bytes().all(|byte| byte == b'0') && *interface != "lo" && kept(interface) =>
+      {
+        Some(format!("{interface} via {next_hop}"))
+      }
+      _ => None,
+    }
+  });
+  ipv4.chain(ipv6).collect()
+}
+
+/// Function to read the gateways of the default routes of the Linux
+/// routing tables.
+///
+/// # Arguments
+/// * `ipv4`: The content of `/proc/net/route`.
+/// * `ipv6`: The content of `/proc/net/ipv6_route`.
+///
+/// # Returns
+/// * `Vec<IpAddr>`: The gateways, without the unspecified ones of the
+///   routes through an interface, e.g. the ones of the tunnels.
+pub fn default_gateways(ipv4: &str, ipv6: &str) -> Vec<IpAddr> {
+  let gateways: BTreeSet<IpAddr> = default_routes(ipv4, ipv6, &[])
+      .iter()
+      .filter_map(|route| route.rsplit_once(" via "))
+      .filter_map(|(_, gateway)| match gateway.len() {
+        // Written in the byte order of the host, i.e. little-endian
+        8 => u32::from_str_radix(gateway, 16)
+            .ok()
+            .map(|gateway| IpAddr::V4(Ipv4Addr::from(gateway.swap_bytes()))),
+        32 => u128::from_str_radix(gateway, 16).ok().map(|gateway| IpAddr::V6(Ipv6Addr::from(gateway))),
+        _ => None,
+      })
+      .filter(|gateway| !gateway.is_unspecified())
+      .collect();
+  gateways.into_iter().collect()
+}
+
+/// Function to find the MAC addresses of gateways in the Linux neighbour
+/// table.
+///
+/// # Arguments
+/// * `arp`: The content of `/proc/net/arp`.
+/// * `gateways`: The gateways.
+///
+/// # Returns
+/// * `Vec<String>`: The MAC addresses of the gateways found, in lowercase.
+pub fn gateway_macs(arp: &str, gateways: &[IpAddr]) -> Vec<String> {
+  // IP address HW type Flags HW address Mask Device
+  arp.lines()
+      .skip(1)
+      .filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+          [address, _, _, mac, ..] if *mac != "00:00:00:00:00:00" => {
+            let address: IpAddr = address.parse().ok()?;
+            gateways.contains(&address).then(|| mac.to_ascii_lowercase())
+          }
+          _ => None,
+        }
+      })
+      .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A routing table with a default route over Wi-Fi and one over a tunnel.
+  const ROUTES: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+wg0\t00000000\t00000000\t0001\t0\t0\t0\t00000000\t0\t0\t0
+";
+
+  /// An IPv6 routing table with a default route over Ethernet, the loopback
+  /// route and a prefix route.
+  const ROUTES6: &str = "\
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00450003  eth0
+fd000000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001  eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200    lo
+";
+
+  #[test]
+  fn default_routes_leave_out_the_tunnels() {
+    let routes = default_routes(ROUTES, ROUTES6, &["wg0".to_string()]);
+    let expected = ["eth0 via fe800000000000000000000000000001", "wlan0 via 0101A8C0"];
+    assert_eq!(routes.into_iter().collect::<Vec<_>>(), expected);
+
+    let with_tunnel = default_routes(ROUTES, "", &[]);
+    assert!(with_tunnel.contains("wg0 via 00000000"));
+    // Another Wi-Fi with another gateway is another set of routes
+    let switched = ROUTES.replace("0101A8C0", "012BA8C0");
+    assert_ne!(default_routes(&switched, "", &[]), with_tunnel);
+    assert!(default_routes("", "", &[]).is_empty());
+  }
+
+  #[test]
+  fn the_current_network_is_matched() {
+    let arp = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         AA:BB:CC:00:11:22     *        wlan0
+192.168.1.7      0x1         0x2         aa:bb:cc:33:44:55     *        wlan0
+";
+    let gateways = default_gateways(ROUTES, ROUTES6);
+    assert_eq!(gateways, ["192.168.1.1".parse::<IpAddr>().unwrap(), "fe80::1".parse().unwrap()]);
+    let network = CurrentNetwork {
+      gateway_macs: gateway_macs(arp, &gateways),
+      gateways,
+      ssids: vec!["Home".to_string()],
+    };
+    assert_eq!(network.gateway_macs, ["aa:bb:cc:00:11:22"]);
+
+    let trusted = [
+      TrustedNetwork::Ssid("Home".to_string()),
+      TrustedNetwork::GatewayMac("AA:BB:CC:00:11:22".to_string()),
+      TrustedNetwork::Subnet("192.168.1.0/24".to_string()),
+    ];
+    assert!(trusted.iter().all(|trusted| trusted.matches(&network)));
+    let untrusted = [
+      TrustedNetwork::Ssid("home".to_string()),
+      TrustedNetwork::GatewayMac("aa:bb:cc:33:44:55".to_string()),
+      TrustedNetwork::Subnet("192.168.2.0/24".to_string()),
+    ];
+    assert!(!untrusted.iter().any(|trusted| trusted.matches(&network)));
+    assert!(!trusted.iter().any(|trusted| trusted.matches(&CurrentNetwork::default())));
+  }
+}
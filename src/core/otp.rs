@@ -0,0 +1,533 @@
+// otp.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::clock::Clock;
+use super::logger::wgb_warn;
+use super::wg::CommandRunner;
+use sha1::Sha1;
+use sha2::digest::core_api::BlockSizeUser;
+use sha2::{Digest, Sha256, Sha512};
+use std::ffi::OsStr;
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+use zeroize::Zeroizing;
+
+/// Number of digits of a code when the URI gives none.
+const DEFAULT_DIGITS: u32 = 6;
+
+/// Seconds a code is valid for when the URI gives no period.
+const DEFAULT_PERIOD: u64 = 30;
+
+/// Exit status of an `otp_command` telling that the gateway rejected the
+/// code, as opposed to failing for another reason.
+pub const REJECTED_STATUS: i32 = 77;
+
+/// Times a rejected code is retried when the configuration sets no
+/// `otp_retries`.
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// Define the errors raised while reading an OTP URI.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum OtpError {
+  #[error("Invalid OTP URI: {0}")]
+  InvalidUri(String),
+  #[error("Unsupported OTP algorithm {0}, expected SHA1, SHA256 or SHA512")]
+  UnsupportedAlgorithm(String),
+  #[error("Only time-based one-time passwords (otpauth://totp/) can be generated")]
+  NotTimeBased,
+  #[error("The gateway rejected the one-time password, {attempts} attempt(s) made")]
+  Rejected { attempts: u32 },
+  #[error("The OTP command `{command}` failed: {reason}")]
+  CommandFailed { command: String, reason: String },
+}
+
+/// Define the hash function a one-time password is computed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+  Sha1,
+  Sha256,
+  Sha512,
+}
+
+/// Define the generator of the time-based one-time passwords of an
+/// `otpauth://totp/` URI, as specified by RFC 6238.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Totp {
+  /// The shared secret, decoded from base32.
+  secret: Zeroizing<Vec<u8>>,
+  /// The hash function of the HMAC.
+  algorithm: Algorithm,
+  /// The number of digits of a code.
+  digits: u32,
+  /// The seconds a code is valid for.
+  period: u64,
+}
+
+impl fmt::Debug for Totp {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Totp")
+        .field("secret", &"***")
+        .field("algorithm", &self.algorithm)
+        .field("digits", &self.digits)
+        .field("period", &self.period)
+        .finish()
+  }
+}
+
+/// Implements the logic to generate and verify the one-time passwords
+impl Totp {
+  /// Function to read the generator of an `otpauth://totp/` URI.
+  ///
+  /// The algorithm, digits and period default to SHA1, 6 and 30 seconds.
+  ///
+  /// # Arguments
+  /// * `otp_uri`: The URI, decrypted when stored encrypted.
+  ///
+  /// # Returns
+  /// * `Result<Totp, OtpError>`: The generator, or the error raised for a
+  ///   HOTP or invalid URI.
+  pub fn from_uri(otp_uri: &str) -> Result<Self, OtpError> {
+    let invalid = |reason: &str| OtpError::InvalidUri(reason.to_string());
+    let url = Url::parse(otp_uri).map_err(|e| OtpError::InvalidUri(e.to_string()))?;
+    if url.scheme() != "otpauth" {
+      return Err(invalid("the scheme must be otpauth"));
+    }
+    if url.host_str() != Some("totp") {
+      return Err(OtpError::NotTimeBased);
+    }
+    let mut totp = Totp {
+      secret: Zeroizing::new(Vec::new()),
+      algorithm: Algorithm::Sha1,
+      digits: DEFAULT_DIGITS,
+      period: DEFAULT_PERIOD,
+    };
+    for (key, value) in url.query_pairs() {
+      match key.as_ref() {
+        "secret" => totp.secret = decode_base32(&value).ok_or_else(|| invalid("the secret is not base32"))?,
+        "algorithm" => {
+          totp.algorithm = match value.to_ascii_uppercase().as_str() {
+            "SHA1" => Algorithm::Sha1,
+            "SHA256" => Algorithm::Sha256,
+            "SHA512" => Algorithm::Sha512,
+            _ => return Err(OtpError::UnsupportedAlgorithm(value.into_owned())),
+          }
+        }
+        "digits" => {
+          totp.digits = value
+              .parse()
+              .ok()
+              .filter(|digits| (6..=8).contains(digits))
+              .ok_or_else(|| invalid("digits must be 6, 7 or 8"))?;
+        }
+        "period" => {
+          totp.period = value
+              .parse()
+              .ok()
+              .filter(|period| *period > 0)
+              .ok_or_else(|| invalid("the period must be a positive number of seconds"))?;
+        }
+        _ => {}
+      }
+    }
+    if totp.secret.is_empty() {
+      return Err(invalid("the secret parameter is missing"));
+    }
+    Ok(totp)
+  }
+
+  /// Function to compute the code valid at the current time of a clock.
+  ///
+  /// # Arguments
+  /// * `clock`: The clock, e.g. stopped at the time given with `--at`.
+  ///
+  /// # Returns
+  /// * `String`: The code, padded with leading zeros.
+  pub fn now(&self, clock: &dyn Clock) -> String {
+    self.code_at(unix_time(clock))
+  }
+
+  /// Function to compute the code valid at a point in time.
+  ///
+  /// # Arguments
+  /// * `timestamp`: The time, in seconds since the Unix epoch.
+  ///
+  /// # Returns
+  /// * `String`: The code, padded with leading zeros.
+  pub fn code_at(&self, timestamp: u64) -> String {
+    self.code_for_step(timestamp / self.period)
+  }
+
+  /// Function to check a code against the current time of a clock,
+  /// accepting the codes of the previous and next periods as well, for the
+  /// clock drift between the two sides.
+  ///
+  /// # Arguments
+  /// * `code`: The code to be checked.
+  /// * `clock`: The clock, e.g. stopped at the time given with `--at`.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the code is valid.
+  pub fn verify(&self, code: &str, clock: &dyn Clock) -> bool {
+    let step = unix_time(clock) / self.period;
+    let code = code.trim();
+    // Every step is compared, so that the time taken tells nothing
+    [step.saturating_sub(1), step, step + 1]
+        .iter()
+        .fold(false, |valid, step| constant_time_eq(&self.code_for_step(*step), code) | valid)
+  }
+
+  /// Function to compute how long the code generated at a point in time
+  /// stays the current one, i.e. the time until the next period.
+  ///
+  /// # Arguments
+  /// * `generated`: The time the code was generated, in seconds since the
+  ///   Unix epoch.
+  /// * `now`: The current time, in seconds since the Unix epoch.
+  ///
+  /// # Returns
+  /// * `Duration`: The time until the next period, zero once it started.
+  pub fn until_next_period(&self, generated: u64, now: u64) -> Duration {
+    let next = (generated / self.period + 1) * self.period;
+    Duration::from_secs(next.saturating_sub(now))
+  }
+
+  /// Function to compute the code of a time step, i.e. the HOTP of the
+  /// step with the dynamic truncation of RFC 4226.
+  ///
+  /// # Arguments
+  /// * `step`: The number of periods since the Unix epoch.
+  ///
+  /// # Returns
+  /// * `String`: The code, padded with leading zeros.
+  fn code_for_step(&self, step: u64) -> String {
+    let message = step.to_be_bytes();
+    let mac = match self.algorithm {
+      Algorithm::Sha1 => hmac::<Sha1>(&self.secret, &message),
+      Algorithm::Sha256 => hmac::<Sha256>(&self.secret, &message),
+      Algorithm::Sha512 => hmac::<Sha512>(&self.secret, &message),
+    };
+    let offset = usize::from(mac[mac.len() - 1] & 0x0f);
+    let binary = u32::from_be_bytes([mac[offset], mac[offset + 1], mac[offset + 2], mac[offset + 3]]) & 0x7fff_ffff;
+    let code = binary % 10u32.pow(self.digits);
+    format!("{code:0width$}", width = self.digits as usize)
+  }
+}
+
+/// Function to hand the current code to the gateway with an `otp_command`,
+/// run by `sh -c` with the code on its stdin, before the tunnel is brought
+/// up.
+///
+/// When the command exits with `REJECTED_STATUS`, e.g. for a code sent at
+/// the very end of its period, the next period is waited for and its code
+/// is sent instead, up to `retries` times. Any other failure is not retried.
+///
+/// # Arguments
+/// * `runner`: The runner of the command.
+/// * `command`: The `otp_command` of the configuration.
+/// * `totp`: The generator of the codes.
+/// * `clock`: The clock the codes are generated at.
+/// * `retries`: The times a rejected code is retried.
+/// * `sleep`: The function waiting for the next period.
+///
+/// # Returns
+/// * `Result<u32, OtpError>`: The number of codes sent until one was
+///   accepted, or the error raised because every code was rejected or the
+///   command failed.
+pub fn authenticate_with(
+  runner: &dyn CommandRunner,
+  command: &str,
+  totp: &Totp,
+  clock: &dyn Clock,
+  retries: u32,
+  sleep: &dyn Fn(Duration),
+) -> Result<u32, OtpError> {
+  let failed = |reason: String| OtpError::CommandFailed {
+    command: command.to_string(),
+    reason,
+  };
+  let mut attempts = 0;
+  loop {
+    let generated = unix_time(clock);
+    let code = Zeroizing::new(format!("{}\n", totp.code_at(generated)));
+    attempts += 1;
+    let output = runner
+        .run_with_input("sh", &[OsStr::new("-c"), OsStr::new(command)], code.as_bytes())
+        .map_err(|e| failed(e.to_string()))?;
+    match output.status.code() {
+      Some(0) => return Ok(attempts),
+      Some(REJECTED_STATUS) if attempts <= retries => {
+        let wait = totp.until_next_period(generated, unix_time(clock));
+        wgb_warn!("The one-time password was rejected, retrying with the next one in {}s", wait.as_secs());
+        sleep(wait);
+      }
+      Some(REJECTED_STATUS) => return Err(OtpError::Rejected { attempts }),
+      _ => {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(failed(if stderr.is_empty() { output.status.to_string() } else { stderr }));
+      }
+    }
+  }
+}
+
+/// Function to read the current time of a clock in seconds since the Unix
+/// epoch.
+///
+/// # Arguments
+/// * `clock`: The clock.
+///
+/// # Returns
+/// * `u64`: The seconds, 0 before the epoch.
+pub fn unix_time(clock: &dyn Clock) -> u64 {
+  u64::try_from(clock.now().timestamp()).unwrap_or(0)
+}
+
+/// Function to compute the HMAC of a message, as specified by RFC 2104.
+///
+/// # Arguments
+/// * `key`: The key.
+/// * `message`: The message.
+///
+/// # Returns
+/// * `Zeroizing<Vec<u8>>`: The MAC, as long as a digest of `D`.
+fn hmac<D: Digest + BlockSizeUser>(key: &[u8], message: &[u8]) -> Zeroizing<Vec<u8>> {
+  let block_size = D::block_size();
+  // A key longer than a block is hashed first
+  let mut block = Zeroizing::new(match key.len() > block_size {
+    true => D::digest(key).to_vec(),
+    false => key.to_vec(),
+  });
+  block.resize(block_size, 0);
+  let pad = |byte: u8| Zeroizing::new(block.iter().map(|key| key ^ byte).collect::<Vec<u8>>());
+  let inner = D::new().chain_update(pad(0x36).as_slice()).chain_update(message).finalize();
+  Zeroizing::new(D::new().chain_update(pad(0x5c).as_slice()).chain_update(inner).finalize().to_vec())
+}
+
+/// Function to decode a base32 secret, as specified by RFC 4648.
+///
+/// Letters may be of either case, and spaces and padding are ignored.
+///
+/// # Arguments
+/// * `encoded`: The base32 text.
+///
+/// # Returns
+/// * `Option<Zeroizing<Vec<u8>>>`: The decoded bytes, or `None` when the
+///   text is not base32.
+fn decode_base32(encoded: &str) -> Option<Zeroizing<Vec<u8>>> {
+  let mut decoded = Zeroizing::new(Vec::with_capacity(encoded.len() * 5 / 8));
+  let mut buffer: u64 = 0;
+  let mut bits = 0;
+  for c in encoded.chars().filter(|c| !c.is_whitespace()).take_while(|c| *c != '=') {
+    let value = match c.to_ascii_uppercase() {
+      c @ 'A'..='Z' => c as u64 - 'A' as u64,
+      c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+      _ => return None,
+    };
+    buffer = (buffer << 5) | value;
+    bits += 5;
+    if bits >= 8 {
+      bits -= 8;
+      decoded.push((buffer >> bits) as u8);
+      buffer &= (1 << bits) - 1;
+    }
+  }
+  Some(decoded)
+}
+
+/// Function to compare two codes in a time independent of where they
+/// differ.
+///
+/// # Arguments
+/// * `expected`: The valid code.
+/// * `given`: The code to be checked.
+///
+/// # Returns
+/// * `bool`: Whether the codes are equal.
+fn constant_time_eq(expected: &str, given: &str) -> bool {
+  expected.len() == given.len() && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::clock::FixedClock;
+  use crate::core::testing::{ManualClock, init_logger};
+  use chrono::{Local, TimeZone};
+  use std::io;
+  use std::os::unix::process::ExitStatusExt;
+  use std::process::{ExitStatus, Output};
+  use std::sync::Mutex;
+
+  /// Define a gateway answering each code with the next exit status, and
+  /// recording the codes it is given.
+  #[derive(Debug)]
+  struct Gateway {
+    statuses: Mutex<Vec<i32>>,
+    codes: Mutex<Vec<String>>,
+  }
+
+  impl Gateway {
+    fn answering(statuses: &[i32]) -> Self {
+      Gateway {
+        statuses: Mutex::new(statuses.iter().rev().copied().collect()),
+        codes: Mutex::new(Vec::new()),
+      }
+    }
+
+    fn codes(&self) -> Vec<String> {
+      self.codes.lock().unwrap().clone()
+    }
+  }
+
+  impl CommandRunner for Gateway {
+    fn run(&self, program: &str, _args: &[&OsStr]) -> io::Result<Output> {
+      Err(io::Error::other(format!("{program} needs an input")))
+    }
+
+    fn run_with_input(&self, program: &str, args: &[&OsStr], input: &[u8]) -> io::Result<Output> {
+      assert_eq!((program, args), ("sh", &[OsStr::new("-c"), OsStr::new("submit-otp")][..]));
+      self.codes.lock().unwrap().push(String::from_utf8(input.to_vec()).unwrap());
+      let status = self.statuses.lock().unwrap().pop().expect("one status per code");
+      Ok(Output {
+        status: ExitStatus::from_raw(status << 8),
+        stdout: Vec::new(),
+        stderr: if status == 0 { Vec::new() } else { b"gateway says no\n".to_vec() },
+      })
+    }
+  }
+
+  /// The secrets of the test vectors of RFC 6238, in base32.
+  const SHA1_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+  const SHA256_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA";
+  const SHA512_SECRET: &str =
+      "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA";
+
+  fn totp(secret: &str, algorithm: &str) -> Totp {
+    Totp::from_uri(&format!("otpauth://totp/WG-Bridge:wg0?secret={secret}&algorithm={algorithm}&digits=8")).unwrap()
+  }
+
+  fn at(timestamp: i64) -> FixedClock {
+    FixedClock(Local.timestamp_opt(timestamp, 0).unwrap())
+  }
+
+  #[test]
+  fn codes_match_the_rfc_6238_vectors() {
+    let vectors: [(u64, [&str; 3]); 6] = [
+      (59, ["94287082", "46119246", "90693936"]),
+      (1_111_111_109, ["07081804", "68084774", "25091201"]),
+      (1_111_111_111, ["14050471", "67062674", "99943326"]),
+      (1_234_567_890, ["89005924", "91819424", "93441116"]),
+      (2_000_000_000, ["69279037", "90698825", "38618901"]),
+      (20_000_000_000, ["65353130", "77737706", "47863826"]),
+    ];
+    let generators = [totp(SHA1_SECRET, "SHA1"), totp(SHA256_SECRET, "SHA256"), totp(SHA512_SECRET, "SHA512")];
+    for (timestamp, codes) in vectors {
+      for (generator, code) in generators.iter().zip(codes) {
+        assert_eq!(generator.code_at(timestamp), code, "{generator:?} at {timestamp}");
+      }
+    }
+    // The clock gives the same codes
+    assert_eq!(generators[0].now(&at(1_111_111_109)), "07081804");
+  }
+
+  #[test]
+  fn uris_default_to_six_digits_every_thirty_seconds() {
+    let totp = Totp::from_uri(&format!("otpauth://totp/WG-Bridge:wg0?secret={}", SHA1_SECRET.to_lowercase())).unwrap();
+    assert_eq!(totp.code_at(59), "287082");
+    assert_eq!(totp.code_at(1_111_111_109), "081804");
+    assert_ne!(totp.code_at(29), totp.code_at(30));
+    assert!(!format!("{totp:?}").contains(SHA1_SECRET));
+
+    let uri = |query: &str| Totp::from_uri(&format!("otpauth://totp/wg0?{query}"));
+    let minutely = uri("secret=GEZDGNBV&period=60").unwrap();
+    assert_eq!(minutely.code_at(30), minutely.code_at(59));
+    assert!(matches!(uri("secret=GEZDGNBV&algorithm=MD5"), Err(OtpError::UnsupportedAlgorithm(_))));
+    assert!(matches!(uri("secret=GEZDGNB1"), Err(OtpError::InvalidUri(_))));
+    assert!(matches!(uri("secret=GEZDGNBV&digits=4"), Err(OtpError::InvalidUri(_))));
+    assert!(matches!(uri("secret=GEZDGNBV&period=0"), Err(OtpError::InvalidUri(_))));
+    assert!(matches!(uri("issuer=WG-Bridge"), Err(OtpError::InvalidUri(_))));
+    assert_eq!(Totp::from_uri("otpauth://hotp/wg0?secret=GEZDGNBV&counter=1"), Err(OtpError::NotTimeBased));
+  }
+
+  #[test]
+  fn codes_are_verified_within_one_period() {
+    let totp = totp(SHA1_SECRET, "SHA1");
+    let clock = at(1_111_111_111);
+    assert!(totp.verify("14050471", &clock));
+    assert!(totp.verify(" 14050471\n", &clock));
+    // The previous and next periods are accepted for the drift of the clocks
+    assert!(totp.verify(&totp.code_at(1_111_111_111 - 30), &clock));
+    assert!(totp.verify(&totp.code_at(1_111_111_111 + 30), &clock));
+    assert!(!totp.verify(&totp.code_at(1_111_111_111 - 60), &clock));
+    assert!(!totp.verify("14050472", &clock));
+    assert!(!totp.verify("1405047", &clock));
+  }
+
+  #[test]
+  fn long_hmac_keys_are_hashed_first() {
+    // RFC 4231, test case 6: a 131-byte key, longer than a SHA-256 block
+    let mac = hmac::<Sha256>(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First");
+    let hex: String = mac.iter().map(|byte| format!("{byte:02x}")).collect();
+    assert_eq!(hex, "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+  }
+
+  #[test]
+  fn rejected_codes_are_retried_with_the_next_one() {
+    init_logger();
+    let totp = totp(SHA1_SECRET, "SHA1");
+    let clock = ManualClock::at(Local.timestamp_opt(59, 0).unwrap());
+    let waits = Mutex::new(Vec::new());
+    let sleep = |wait: Duration| {
+      waits.lock().unwrap().push(wait);
+      clock.advance(chrono::Duration::from_std(wait).unwrap());
+    };
+
+    // The code sent at the end of its period is rejected, the next one is not
+    let gateway = Gateway::answering(&[REJECTED_STATUS, 0]);
+    assert_eq!(authenticate_with(&gateway, "submit-otp", &totp, &*clock, 2, &sleep), Ok(2));
+    assert_eq!(gateway.codes(), ["94287082\n".to_string(), format!("{}\n", totp.code_at(60))]);
+    assert_ne!(gateway.codes()[0], gateway.codes()[1]);
+    assert_eq!(*waits.lock().unwrap(), [Duration::from_secs(1)]);
+
+    // Every code being rejected gives up after the retries
+    let gateway = Gateway::answering(&[REJECTED_STATUS; 3]);
+    assert_eq!(
+      authenticate_with(&gateway, "submit-otp", &totp, &*clock, 2, &sleep),
+      Err(OtpError::Rejected { attempts: 3 })
+    );
+    assert_eq!(gateway.codes().len(), 3);
+    let gateway = Gateway::answering(&[REJECTED_STATUS]);
+    assert_eq!(
+      authenticate_with(&gateway, "submit-otp", &totp, &*clock, 0, &sleep),
+      Err(OtpError::Rejected { attempts: 1 })
+    );
+  }
+
+  #[test]
+  fn other_failures_are_not_retried() {
+    let totp = totp(SHA1_SECRET, "SHA1");
+    let clock = at(59);
+    let gateway = Gateway::answering(&[1]);
+    let result = authenticate_with(&gateway, "submit-otp", &totp, &clock, 2, &|_| panic!("no retry expected"));
+    assert_eq!(
+      result,
+      Err(OtpError::CommandFailed {
+        command: "submit-otp".to_string(),
+        reason: "gateway says no".to_string()
+      })
+    );
+    assert_eq!(gateway.codes(), ["94287082\n"]);
+  }
+
+  #[test]
+  fn the_next_period_is_waited_for_from_the_code_sent() {
+    let totp = totp(SHA1_SECRET, "SHA1");
+    assert_eq!(totp.until_next_period(59, 59), Duration::from_secs(1));
+    assert_eq!(totp.until_next_period(30, 35), Duration::from_secs(25));
+    // The period may be over already, e.g. after a slow gateway
+    assert_eq!(totp.until_next_period(59, 61), Duration::ZERO);
+  }
+}
@@ -0,0 +1,77 @@
+// pool.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Function to apply a function to every item with a bounded number of
+/// worker threads, for the commands acting on several tunnels.
+///
+/// Each worker takes the next item left until there is none, and the results
+/// are kept in the order of the items, whichever finishes first. A single
+/// worker applies the function in the calling thread.
+///
+/// # Arguments
+/// * `items`: The items, e.g. the paths to the WireGuard configuration files.
+/// * `workers`: The most items processed at once, at least one.
+/// * `f`: The function applied to each item.
+///
+/// # Returns
+/// * `Vec<R>`: The result of each item, in the order of the items.
+pub fn map_bounded<T: Sync, R: Send>(items: &[T], workers: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+  let workers = workers.clamp(1, items.len().max(1));
+  if workers == 1 {
+    return items.iter().map(f).collect();
+  }
+  let next = AtomicUsize::new(0);
+  let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+  thread::scope(|scope| {
+    for _ in 0..workers {
+      scope.spawn(|| {
+        loop {
+          let index = next.fetch_add(1, Ordering::Relaxed);
+          let Some(item) = items.get(index) else {
+            break;
+          };
+          let result = f(item);
+          results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+        }
+      });
+    }
+  });
+  results
+      .into_inner()
+      .unwrap_or_else(|e| e.into_inner())
+      .into_iter()
+      .map(|result| result.expect("every item is processed once the workers are done"))
+      .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  #[test]
+  fn results_keep_the_order_of_the_items() {
+    let items: Vec<u64> = (0..20).collect();
+    let running = AtomicUsize::new(0);
+    let peak = AtomicUsize::new(0);
+    let results = map_bounded(&items, 4, |item| {
+      let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+      peak.fetch_max(now, Ordering::SeqCst);
+      // The first items take the longest, so they finish last
+      thread::sleep(Duration::from_millis(20 - item));
+      running.fetch_sub(1, Ordering::SeqCst);
+      item * 2
+    });
+    assert_eq!(results, items.iter().map(|item| item * 2).collect::<Vec<_>>());
+    assert!((2..=4).contains(&peak.load(Ordering::SeqCst)), "{peak:?}");
+
+    assert_eq!(map_bounded(&items, 1, |item| *item), items);
+    assert_eq!(map_bounded(&[] as &[u64], 8, |item| *item), Vec::<u64>::new());
+  }
+}
@@ -0,0 +1,274 @@
+// session.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Name of the directory tracking the interfaces brought up by WG-Bridge,
+/// located in the user's home directory.
+const SESSION_DIR_NAME: &str = ".wgbsessions";
+
+/// Extension of the file tracking an interface, named after it.
+const SESSION_EXTENSION: &str = "json";
+
+/// Define the errors raised while reading or writing the sessions.
+#[derive(Debug, Error)]
+pub enum SessionError {
+  #[error("Failed to access the sessions in {}: {source}", .dir.display())]
+  Io { dir: PathBuf, source: io::Error },
+  #[error("Invalid session {}: {source}", .path.display())]
+  Parse { path: PathBuf, source: serde_json::Error },
+}
+
+/// Define the record of an interface brought up by WG-Bridge, kept until it
+/// is brought down, so that an interface left up after its configuration
+/// was removed can be told apart from the ones of other tools.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+  /// The WireGuard configuration file the interface was brought up from.
+  pub config_path: PathBuf,
+  /// The time the interface was brought up, in seconds since the Unix epoch.
+  pub started: u64,
+}
+
+/// Define why an interface brought up by WG-Bridge is orphaned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orphaned {
+  /// Its configuration is no longer configured.
+  Unconfigured,
+  /// Its WireGuard configuration file no longer exists.
+  MissingFile,
+}
+
+impl fmt::Display for Orphaned {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Orphaned::Unconfigured => f.write_str("it is no longer configured"),
+      Orphaned::MissingFile => f.write_str("its WireGuard configuration file no longer exists"),
+    }
+  }
+}
+
+/// Function to retrieve the directory of the sessions.
+///
+/// # Returns
+/// * `Option<PathBuf>`: `~/.wgbsessions`, or `None` when the home directory
+///   is unknown.
+pub fn default_dir() -> Option<PathBuf> {
+  std::env::home_dir().map(|home| home.join(SESSION_DIR_NAME))
+}
+
+/// Function to record that an interface was brought up.
+///
+/// The record is written to a temporary file which is then renamed over the
+/// previous one, so that a crash never leaves half a record behind.
+///
+/// # Arguments
+/// * `dir`: The directory of the sessions, created when missing.
+/// * `interface`: The name of the interface.
+/// * `config_path`: The WireGuard configuration file it was brought up from.
+///
+/// # Returns
+/// * `Result<(), SessionError>`: The error, if any, raised while writing.
+pub fn record(dir: &Path, interface: &str, config_path: &Path) -> Result<(), SessionError> {
+  let io_error = |source| SessionError::Io {
+    dir: dir.to_path_buf(),
+    source,
+  };
+  DirBuilder::new().recursive(true).mode(0o700).create(dir).map_err(io_error)?;
+  let session = Session {
+    config_path: config_path.to_path_buf(),
+    started: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+  };
+  let content = serde_json::to_string_pretty(&session).map_err(|source| SessionError::Parse {
+    path: session_path(dir, interface),
+    source,
+  })?;
+  let temp = dir.join(format!(".{interface}.{}.tmp", std::process::id()));
+  fs::write(&temp, content).map_err(io_error)?;
+  fs::rename(&temp, session_path(dir, interface)).map_err(|e| {
+    let _ = fs::remove_file(&temp);
+    io_error(e)
+  })
+}
+
+/// Function to forget an interface, once brought down.
+///
+/// # Arguments
+/// * `dir`: The directory of the sessions.
+/// * `interface`: The name of the interface.
+///
+/// # Returns
+/// * `Result<(), SessionError>`: The error, if any, raised while removing
+///   the record; none when there is no record.
+pub fn forget(dir: &Path, interface: &str) -> Result<(), SessionError> {
+  match fs::remove_file(session_path(dir, interface)) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(SessionError::Io {
+      dir: dir.to_path_buf(),
+      source: e,
+    }),
+    _ => Ok(()),
+  }
+}
+
+/// Function to read the recorded sessions.
+///
+/// # Arguments
+/// * `dir`: The directory of the sessions.
+///
+/// # Returns
+/// * `Result<BTreeMap<String, Session>, SessionError>`: The sessions by
+///   interface name, none when the directory does not exist, or the error
+///   raised reading or parsing a record.
+pub fn load(dir: &Path) -> Result<BTreeMap<String, Session>, SessionError> {
+  let io_error = |source| SessionError::Io {
+    dir: dir.to_path_buf(),
+    source,
+  };
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+    Err(e) => return Err(io_error(e)),
+  };
+  let mut sessions = BTreeMap::new();
+  for entry in entries {
+    let path = entry.map_err(io_error)?.path();
+    let interface = match (path.file_stem(), path.extension()) {
+      (Some(stem), Some(extension)) if extension == SESSION_EXTENSION => stem.to_string_lossy().into_owned(),
+      _ => continue,
+    };
+    let content = fs::read_to_string(&path).map_err(io_error)?;
+    let session = serde_json::from_str(&content).map_err(|source| SessionError::Parse {
+      path: path.clone(),
+      source,
+    })?;
+    sessions.insert(interface, session);
+  }
+  Ok(sessions)
+}
+
+/// Function to find the active interfaces brought up by WG-Bridge whose
+/// configuration is gone, e.g. removed while the tunnel was up.
+///
+/// # Arguments
+/// * `sessions`: The recorded sessions, by interface name.
+/// * `active`: The names of the active interfaces.
+/// * `configured`: The resolved paths of the configured WireGuard
+///   configuration files.
+///
+/// # Returns
+/// * `Vec<(&str, &Session, Orphaned)>`: The orphaned interfaces, their
+///   session and why they are orphaned, by interface name.
+pub fn orphans<'a>(
+  sessions: &'a BTreeMap<String, Session>,
+  active: &[String],
+  configured: &[PathBuf],
+) -> Vec<(&'a str, &'a Session, Orphaned)> {
+  sessions
+      .iter()
+      .filter(|(interface, _)| active.contains(interface))
+      .filter_map(|(interface, session)| {
+        let orphaned = if !configured.contains(&session.config_path) {
+          Orphaned::Unconfigured
+        } else if !session.config_path.exists() {
+          Orphaned::MissingFile
+        } else {
+          return None;
+        };
+        Some((interface.as_str(), session, orphaned))
+      })
+      .collect()
+}
+
+/// Function to find the sessions whose interface is no longer active, e.g.
+/// after a reboot or a `wg-quick down` run by hand.
+///
+/// # Arguments
+/// * `sessions`: The recorded sessions, by interface name.
+/// * `active`: The names of the active interfaces.
+///
+/// # Returns
+/// * `Vec<&str>`: The interfaces whose session is stale.
+pub fn stale<'a>(sessions: &'a BTreeMap<String, Session>, active: &[String]) -> Vec<&'a str> {
+  sessions
+      .keys()
+      .filter(|interface| !active.contains(interface))
+      .map(String::as_str)
+      .collect()
+}
+
+/// Function to build the path of the record of an interface.
+///
+/// # Arguments
+/// * `dir`: The directory of the sessions.
+/// * `interface`: The name of the interface.
+///
+/// # Returns
+/// * `PathBuf`: The path of the record.
+fn session_path(dir: &Path, interface: &str) -> PathBuf {
+  dir.join(interface).with_extension(SESSION_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::temp_dir;
+
+  #[test]
+  fn sessions_are_recorded_and_forgotten() {
+    let temp = temp_dir();
+    // The directory is created by the first session recorded
+    let dir = temp.path().join("sessions");
+    assert!(load(&dir).unwrap().is_empty());
+    record(&dir, "wg0", Path::new("/etc/wireguard/wg0.conf")).unwrap();
+    record(&dir, "wg1", Path::new("/etc/wireguard/wg1.conf")).unwrap();
+    let sessions = load(&dir).unwrap();
+    assert_eq!(sessions.keys().collect::<Vec<_>>(), ["wg0", "wg1"]);
+    assert_eq!(sessions["wg1"].config_path, Path::new("/etc/wireguard/wg1.conf"));
+
+    forget(&dir, "wg0").unwrap();
+    forget(&dir, "wg0").unwrap();
+    assert_eq!(load(&dir).unwrap().keys().collect::<Vec<_>>(), ["wg1"]);
+    fs::write(dir.join("wg1.json"), "{").unwrap();
+    assert!(matches!(load(&dir), Err(SessionError::Parse { .. })));
+  }
+
+  #[test]
+  fn orphans_are_told_apart_from_managed_interfaces() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let kept = dir.join("wg0.conf");
+    fs::write(&kept, "[Interface]\n").unwrap();
+    let deleted = dir.join("wg1.conf");
+    let removed = dir.join("wg2.conf");
+    let session = |config_path: &Path| Session {
+      config_path: config_path.to_path_buf(),
+      started: 1_000,
+    };
+    let sessions = BTreeMap::from([
+      ("wg0".to_string(), session(&kept)),
+      ("wg1".to_string(), session(&deleted)),
+      ("wg2".to_string(), session(&removed)),
+      ("wg3".to_string(), session(&kept)),
+    ]);
+    // wg3 went down, wg9 was brought up by another tool
+    let active = ["wg0", "wg1", "wg2", "wg9"].map(String::from);
+    let configured = [kept.clone(), deleted.clone()];
+
+    let orphans = orphans(&sessions, &active, &configured);
+    assert_eq!(
+      orphans,
+      [("wg1", &sessions["wg1"], Orphaned::MissingFile), ("wg2", &sessions["wg2"], Orphaned::Unconfigured)]
+    );
+    assert_eq!(stale(&sessions, &active), ["wg3"]);
+  }
+}
@@ -0,0 +1,311 @@
+// snapshot.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::wg::TunnelStatus;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Name of the file caching the latest snapshot of each interface, located
+/// in the user's home directory.
+const SNAPSHOT_FILE_NAME: &str = ".wgbstatus.json";
+
+/// Define the errors raised while reading or writing the snapshots.
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+  #[error("Failed to access status snapshots: {0}")]
+  Io(#[from] io::Error),
+  #[error("Invalid status snapshots: {0}")]
+  Parse(#[from] serde_json::Error),
+}
+
+/// Define the counters of a peer when a snapshot was taken.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerCounters {
+  /// The latest handshake, in seconds since the Unix epoch, if any.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub latest_handshake: Option<u64>,
+  /// The bytes received from the peer.
+  pub rx_bytes: u64,
+  /// The bytes sent to the peer.
+  pub tx_bytes: u64,
+  /// The endpoint the peer was last seen at, if any, to tell when it roams.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub endpoint: Option<String>,
+}
+
+/// Define the counters of the peers of an interface at a point in time, so
+/// that a later `status --since` can tell what changed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+  /// The time the snapshot was taken, in seconds since the Unix epoch.
+  pub taken: u64,
+  /// The counters of each peer, by public key.
+  pub peers: BTreeMap<String, PeerCounters>,
+}
+
+/// Define what changed for a peer since a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerDelta {
+  /// The base64 public key of the peer.
+  pub public_key: String,
+  /// Whether a handshake took place after the requested time.
+  pub new_handshake: bool,
+  /// The bytes received since the snapshot, `None` when the peer is not in
+  /// it.
+  pub rx_bytes: Option<u64>,
+  /// The bytes sent since the snapshot, `None` when the peer is not in it.
+  pub tx_bytes: Option<u64>,
+  /// Whether the counters went back since the snapshot, e.g. because the
+  /// interface was recreated. The bytes are then counted from zero.
+  pub reset: bool,
+}
+
+/// Implements the logic of the status snapshots
+impl Snapshot {
+  /// Function to take the snapshot of an interface.
+  ///
+  /// # Arguments
+  /// * `status`: The live state of the interface.
+  /// * `taken`: The time the state was read.
+  ///
+  /// # Returns
+  /// * `Snapshot`: The counters of every peer of the interface.
+  pub fn of(status: &TunnelStatus, taken: SystemTime) -> Self {
+    let peers = status
+        .peers
+        .iter()
+        .map(|peer| {
+          let counters = PeerCounters {
+            latest_handshake: peer.latest_handshake.map(seconds),
+            rx_bytes: peer.rx_bytes,
+            tx_bytes: peer.tx_bytes,
+            endpoint: peer.endpoint.clone(),
+          };
+          (peer.public_key.clone(), counters)
+        })
+        .collect();
+    Snapshot {
+      taken: seconds(taken),
+      peers,
+    }
+  }
+
+  /// Function to retrieve the time the snapshot was taken.
+  ///
+  /// # Returns
+  /// * `SystemTime`: The time the snapshot was taken.
+  pub fn taken_at(&self) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(self.taken)
+  }
+
+  /// Function to retrieve the endpoint a peer was seen at in the snapshot.
+  ///
+  /// # Arguments
+  /// * `public_key`: The base64 public key of the peer.
+  ///
+  /// # Returns
+  /// * `Option<&str>`: The endpoint, `None` when the peer is not in the
+  ///   snapshot or had no endpoint.
+  pub fn endpoint(&self, public_key: &str) -> Option<&str> {
+    self.peers.get(public_key)?.endpoint.as_deref()
+  }
+
+  /// Function to compare the live state of an interface with the snapshot.
+  ///
+  /// A peer whose counters are lower than in the snapshot had them reset, so
+  /// its bytes are the live counters, i.e. counted from the reset.
+  ///
+  /// # Arguments
+  /// * `current`: The live state of the interface.
+  /// * `since`: The time after which a handshake is new.
+  ///
+  /// # Returns
+  /// * `Vec<PeerDelta>`: The changes of each live peer, in the live order.
+  pub fn delta(&self, current: &TunnelStatus, since: SystemTime) -> Vec<PeerDelta> {
+    current
+        .peers
+        .iter()
+        .map(|peer| {
+          let previous = self.peers.get(&peer.public_key);
+          let reset = previous.is_some_and(|previous| peer.rx_bytes < previous.rx_bytes || peer.tx_bytes < previous.tx_bytes);
+          let bytes = |current: u64, previous: u64| if reset { current } else { current - previous };
+          PeerDelta {
+            public_key: peer.public_key.clone(),
+            new_handshake: peer.latest_handshake.is_some_and(|handshake| handshake > since),
+            rx_bytes: previous.map(|previous| bytes(peer.rx_bytes, previous.rx_bytes)),
+            tx_bytes: previous.map(|previous| bytes(peer.tx_bytes, previous.tx_bytes)),
+            reset,
+          }
+        })
+        .collect()
+  }
+}
+
+/// Function to retrieve the path of the snapshot file.
+///
+/// # Returns
+/// * `Option<PathBuf>`: `~/.wgbstatus.json`, or `None` when the home
+///   directory is unknown.
+pub fn default_path() -> Option<PathBuf> {
+  std::env::home_dir().map(|home| home.join(SNAPSHOT_FILE_NAME))
+}
+
+/// Function to read the latest snapshot of each interface.
+///
+/// # Arguments
+/// * `path`: The path to the snapshot file.
+///
+/// # Returns
+/// * `Result<BTreeMap<String, Snapshot>, SnapshotError>`: The snapshots by
+///   interface name, none when the file does not exist, or the error raised
+///   reading or parsing it.
+pub fn load(path: &Path) -> Result<BTreeMap<String, Snapshot>, SnapshotError> {
+  match fs::read_to_string(path) {
+    Ok(content) => Ok(serde_json::from_str(&content)?),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+    Err(e) => Err(e.into()),
+  }
+}
+
+/// Function to write the latest snapshot of each interface.
+///
+/// The snapshots are written to a temporary file which is then renamed over
+/// the target, so that two `status` running at once never mix their writes.
+///
+/// # Arguments
+/// * `path`: The path to the snapshot file.
+/// * `snapshots`: The snapshots by interface name.
+///
+/// # Returns
+/// * `Result<(), SnapshotError>`: The error, if any, raised while writing.
+pub fn save(path: &Path, snapshots: &BTreeMap<String, Snapshot>) -> Result<(), SnapshotError> {
+  let mut temp = path.as_os_str().to_owned();
+  temp.push(format!(".{}.tmp", std::process::id()));
+  fs::write(&temp, serde_json::to_string_pretty(snapshots)?)?;
+  fs::rename(&temp, path).inspect_err(|_| {
+    let _ = fs::remove_file(&temp);
+  })?;
+  Ok(())
+}
+
+/// Function to convert a time to seconds since the Unix epoch.
+///
+/// # Arguments
+/// * `time`: The time.
+///
+/// # Returns
+/// * `u64`: The seconds since the Unix epoch, zero for earlier times.
+fn seconds(time: SystemTime) -> u64 {
+  time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::temp_dir;
+  use crate::core::wg::PeerStatus;
+
+  fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+  }
+
+  fn tunnel(peers: &[(&str, Option<u64>, u64, u64)]) -> TunnelStatus {
+    let peers = peers
+        .iter()
+        .map(|&(public_key, handshake, rx_bytes, tx_bytes)| PeerStatus {
+          public_key: public_key.to_string(),
+          endpoint: None,
+          allowed_ips: Vec::new(),
+          latest_handshake: handshake.map(at),
+          rx_bytes,
+          tx_bytes,
+        })
+        .collect();
+    TunnelStatus {
+      interface: "wg0".to_string(),
+      up: true,
+      peers,
+    }
+  }
+
+  #[test]
+  fn deltas_are_counted_from_the_snapshot() {
+    let first = tunnel(&[("alice", Some(1_000), 4096, 1024), ("bob", None, 0, 0)]);
+    let snapshot = Snapshot::of(&first, at(1_100));
+    assert_eq!(snapshot.taken_at(), at(1_100));
+
+    let second = tunnel(&[("alice", Some(1_150), 6144, 1536), ("bob", None, 0, 0), ("carol", Some(1_120), 512, 256)]);
+    let deltas = snapshot.delta(&second, at(1_100));
+    assert_eq!(
+      deltas,
+      [
+        PeerDelta {
+          public_key: "alice".to_string(),
+          new_handshake: true,
+          rx_bytes: Some(2048),
+          tx_bytes: Some(512),
+          reset: false,
+        },
+        PeerDelta {
+          public_key: "bob".to_string(),
+          new_handshake: false,
+          rx_bytes: Some(0),
+          tx_bytes: Some(0),
+          reset: false,
+        },
+        PeerDelta {
+          public_key: "carol".to_string(),
+          new_handshake: true,
+          rx_bytes: None,
+          tx_bytes: None,
+          reset: false,
+        },
+      ]
+    );
+    // A handshake before the requested time is not new
+    assert!(!snapshot.delta(&second, at(1_200))[0].new_handshake);
+  }
+
+  #[test]
+  fn reset_counters_are_counted_from_zero() {
+    let snapshot = Snapshot::of(&tunnel(&[("alice", Some(1_000), 4096, 1024)]), at(1_100));
+    let recreated = tunnel(&[("alice", Some(1_300), 300, 2048)]);
+    let delta = &snapshot.delta(&recreated, at(1_100))[0];
+    assert!(delta.reset);
+    assert_eq!((delta.rx_bytes, delta.tx_bytes), (Some(300), Some(2048)));
+  }
+
+  #[test]
+  fn endpoints_are_recorded_to_detect_roaming() {
+    let mut status = tunnel(&[("alice", Some(1_000), 1, 0), ("bob", None, 0, 0)]);
+    status.peers[0].endpoint = Some("198.51.100.1:51820".to_string());
+    let first = Snapshot::of(&status, at(1_100));
+    status.peers[0].endpoint = Some("203.0.113.7:40000".to_string());
+    let second = Snapshot::of(&status, at(1_300));
+    assert_eq!(first.endpoint("alice"), Some("198.51.100.1:51820"));
+    assert_eq!(second.endpoint("alice"), Some("203.0.113.7:40000"));
+    assert_eq!((first.endpoint("bob"), first.endpoint("carol")), (None, None));
+  }
+
+  #[test]
+  fn snapshots_are_saved_and_loaded() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join(SNAPSHOT_FILE_NAME);
+    assert!(load(&path).unwrap().is_empty());
+
+    let snapshots = BTreeMap::from([("wg0".to_string(), Snapshot::of(&tunnel(&[("alice", None, 1, 2)]), at(5)))]);
+    save(&path, &snapshots).unwrap();
+    assert_eq!(load(&path).unwrap(), snapshots);
+    assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+    fs::write(&path, "[").unwrap();
+    assert!(matches!(load(&path), Err(SnapshotError::Parse(_))));
+  }
+}
@@ -0,0 +1,62 @@
+// testing.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::clock::Clock;
+use super::logger::{LogLevel, Logger, LoggerOptions};
+use chrono::{DateTime, Local};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+/// A valid WireGuard key, used wherever the key itself does not matter.
+pub const KEY: &str = "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=";
+
+/// Function to create an empty directory for a test.
+///
+/// # Returns
+/// * `TempDir`: The directory, removed along with its content when dropped.
+pub fn temp_dir() -> TempDir {
+  tempfile::Builder::new().prefix("wgb-").tempdir().unwrap()
+}
+
+/// Function to initialize the logger of the tests, printing only the errors
+/// to stderr. The calls after the first one of a test binary do nothing.
+pub fn init_logger() {
+  let _ = Logger::init_stderr(LoggerOptions {
+    min_level: LogLevel::Error,
+    ..Default::default()
+  });
+}
+
+/// Define a clock which only moves when told to.
+#[derive(Debug)]
+pub struct ManualClock(Mutex<DateTime<Local>>);
+
+/// Implements the logic to move the manual clock
+impl ManualClock {
+  /// Function to create a clock standing at the given time.
+  ///
+  /// # Arguments
+  /// * `now`: The time the clock starts from.
+  ///
+  /// # Returns
+  /// * `Arc<ManualClock>`: The clock, shared with the code under test.
+  pub fn at(now: DateTime<Local>) -> Arc<ManualClock> {
+    Arc::new(ManualClock(Mutex::new(now)))
+  }
+
+  /// Function to move the clock forward.
+  ///
+  /// # Arguments
+  /// * `by`: The time to be added to the clock.
+  pub fn advance(&self, by: chrono::Duration) {
+    *self.0.lock().unwrap() += by;
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> DateTime<Local> {
+    *self.0.lock().unwrap()
+  }
+}
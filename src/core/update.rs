@@ -0,0 +1,162 @@
+// update.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use crate::core::logger::wgb_debug;
+use std::io;
+use std::process::Command;
+
+/// The endpoint describing the latest release of WG-Bridge.
+pub const RELEASES_ENDPOINT: &str = "https://api.github.com/repos/LunaticFringers/wg-bridge/releases/latest";
+
+/// The longest time, in seconds, the release endpoint is waited for.
+const REQUEST_TIMEOUT_SECS: &str = "5";
+
+/// Define the client fetching the release endpoint, so that tests can
+/// replace the network with a canned answer.
+pub trait HttpClient {
+  /// Function to fetch a URL.
+  ///
+  /// # Arguments
+  /// * `url`: The URL to be fetched.
+  ///
+  /// # Returns
+  /// * `io::Result<String>`: The body of the response.
+  fn get(&self, url: &str) -> io::Result<String>;
+}
+
+/// Define the client fetching URLs through `curl`, so that no HTTP and TLS
+/// stack is linked in for a single optional request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CurlClient;
+
+impl HttpClient for CurlClient {
+  fn get(&self, url: &str) -> io::Result<String> {
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", "--max-time", REQUEST_TIMEOUT_SECS])
+        .args(["--header", "Accept: application/vnd.github+json", "--", url])
+        .output()?;
+    if !output.status.success() {
+      return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+  }
+}
+
+/// Function to check whether a newer version than the running one has been
+/// released. Nothing is ever installed.
+///
+/// A failure to reach the endpoint or to read its answer is only logged, so
+/// that a machine without network access is never bothered.
+///
+/// # Arguments
+/// * `client`: The client fetching the endpoint.
+/// * `endpoint`: The URL describing the latest release, as a JSON object
+///   with a `tag_name` such as `v1.2.0`.
+/// * `current`: The running version.
+///
+/// # Returns
+/// * `Option<String>`: The newer version, if any.
+pub fn newer_version(client: &dyn HttpClient, endpoint: &str, current: &str) -> Option<String> {
+  let body = match client.get(endpoint) {
+    Ok(body) => body,
+    Err(e) => {
+      wgb_debug!("Skipping the update check, {endpoint} is unreachable: {e}");
+      return None;
+    }
+  };
+  let latest = serde_json::from_str::<serde_json::Value>(&body)
+      .ok()
+      .and_then(|release| release["tag_name"].as_str().map(|tag| tag.trim_start_matches('v').to_string()));
+  let Some(latest) = latest else {
+    wgb_debug!("Skipping the update check, {endpoint} returned no release tag");
+    return None;
+  };
+  match (parse_version(&latest), parse_version(current)) {
+    (Some(remote), Some(local)) if remote > local => Some(latest),
+    (Some(_), Some(_)) => None,
+    _ => {
+      wgb_debug!("Skipping the update check, cannot compare {latest} with {current}");
+      None
+    }
+  }
+}
+
+/// Function to parse a `major.minor.patch` version, the missing parts being
+/// zero. Pre-releases, such as `1.2.0-rc.1`, are not parsed, so that they are
+/// never announced.
+///
+/// # Arguments
+/// * `version`: The version.
+///
+/// # Returns
+/// * `Option<[u64; 3]>`: The parts of the version, if it is valid.
+fn parse_version(version: &str) -> Option<[u64; 3]> {
+  let mut parts = [0; 3];
+  for (index, part) in version.split('.').enumerate() {
+    *parts.get_mut(index)? = part.parse().ok()?;
+  }
+  Some(parts)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::init_logger;
+  use std::cell::RefCell;
+
+  /// Define a client answering every request with the same response.
+  struct StubClient {
+    response: Result<String, io::ErrorKind>,
+    urls: RefCell<Vec<String>>,
+  }
+
+  impl StubClient {
+    fn tag(tag: &str) -> Self {
+      StubClient {
+        response: Ok(format!(r#"{{"tag_name": "{tag}", "name": "WG-Bridge {tag}"}}"#)),
+        urls: RefCell::new(Vec::new()),
+      }
+    }
+  }
+
+  impl HttpClient for StubClient {
+    fn get(&self, url: &str) -> io::Result<String> {
+      self.urls.borrow_mut().push(url.to_string());
+      self.response.clone().map_err(io::Error::from)
+    }
+  }
+
+  #[test]
+  fn newer_release_is_announced() {
+    init_logger();
+    let client = StubClient::tag("v0.2.0");
+    assert_eq!(newer_version(&client, "https://example.com/latest", "0.1.0").as_deref(), Some("0.2.0"));
+    assert_eq!(client.urls.borrow().as_slice(), ["https://example.com/latest"]);
+    assert_eq!(newer_version(&StubClient::tag("0.1.10"), RELEASES_ENDPOINT, "0.1.9").as_deref(), Some("0.1.10"));
+  }
+
+  #[test]
+  fn equal_or_older_release_is_not_announced() {
+    init_logger();
+    for tag in ["v0.1.0", "0.1", "v0.0.9", "v0.2.0-rc.1"] {
+      assert_eq!(newer_version(&StubClient::tag(tag), RELEASES_ENDPOINT, "0.1.0"), None, "{tag}");
+    }
+  }
+
+  #[test]
+  fn network_failures_are_skipped() {
+    init_logger();
+    let client = StubClient {
+      response: Err(io::ErrorKind::TimedOut),
+      urls: RefCell::new(Vec::new()),
+    };
+    assert_eq!(newer_version(&client, RELEASES_ENDPOINT, "0.1.0"), None);
+    let client = StubClient {
+      response: Ok("<html>rate limited</html>".to_string()),
+      urls: RefCell::new(Vec::new()),
+    };
+    assert_eq!(newer_version(&client, RELEASES_ENDPOINT, "0.1.0"), None);
+  }
+}
@@ -0,0 +1,4600 @@
+// wg.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use super::audit::{AuditError, AuditLog};
+use super::bench::{BenchError, Benchmark, Throughput};
+use super::clock::{Deadline, SystemClock};
+use super::dns::{self, DnsBackend, DnsError};
+use super::lock::{LockError, TunnelLock};
+use super::logger::{LogLevel, wgb_debug, wgb_info, wgb_log, wgb_warn};
+use super::pool;
+use super::session;
+use super::wipe::{WipeGuard, wipe_file};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Debug};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStdin, Command, ExitStatus, Output, Stdio};
+use std::sync::OnceLock;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use x25519_dalek::StaticSecret;
+use zeroize::Zeroizing;
+
+/// Length, in bytes, of a WireGuard key once base64-decoded.
+const KEY_LEN: usize = 32;
+
+/// Program used to bring tunnels up and down.
+const WG_QUICK: &str = "wg-quick";
+
+/// Program used to inspect the interfaces.
+const WG: &str = "wg";
+
+/// Name of the program running the WireGuard programs on a remote host.
+const SSH: &str = "ssh";
+
+/// Program used to delete an interface whose configuration file is gone.
+const IP: &str = "ip";
+
+/// Capability required to create network interfaces.
+#[cfg(target_os = "linux")]
+const CAP_NET_ADMIN: u32 = 12;
+
+/// Maximum size, in bytes, of a WireGuard configuration file. Real files are
+/// a few KiB even with hundreds of peers; the limit keeps a file from an
+/// untrusted source from exhausting the memory.
+const MAX_CONFIG_LEN: usize = 1024 * 1024;
+
+/// Maximum length, in bytes, of a line of a WireGuard configuration file,
+/// leaving room for long `PostUp`/`PostDown` commands.
+const MAX_LINE_LEN: usize = 4096;
+
+/// Prefix of an `AllowedIPs` item naming a set file of networks to be
+/// expanded at connect time, e.g. `AllowedIPs = @routes.set`.
+const SET_FILE_PREFIX: char = '@';
+
+/// Longest time a command waits for another one managing the same tunnel.
+const LOCK_WAIT: Duration = Duration::from_secs(60);
+
+/// Time a cleanup program (e.g. `wg-quick down` after a timed out `up`) may
+/// take, since the deadline of the command has already passed.
+const CLEANUP_GRACE: Duration = Duration::from_secs(10);
+
+/// Interval at which a running program is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Longest time an answer to an endpoint probe is waited for.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest name of a network interface, `IFNAMSIZ` less the trailing NUL.
+pub const MAX_INTERFACE_NAME_LEN: usize = 15;
+
+/// Time between two reads of the handshakes of a tunnel being brought up with
+/// a `connect_timeout`.
+const HANDSHAKE_POLL: Duration = Duration::from_millis(500);
+
+/// Define a variable holding the deadline of the whole command, if any,
+/// bounding every program run by the `SystemRunner`.
+static DEADLINE: OnceLock<Deadline> = OnceLock::new();
+
+/// Define a variable holding the address family endpoint hostnames are
+/// resolved to on connect, if any; `wg` picks the first address otherwise.
+static ADDRESS_FAMILY: OnceLock<AddressFamily> = OnceLock::new();
+
+/// Define a variable holding the SSH destination the WireGuard programs are
+/// run on, if any, instead of the local host.
+static REMOTE: OnceLock<String> = OnceLock::new();
+
+/// Define a variable holding the commands decrypting the encrypted WireGuard
+/// configuration files, if any; they are refused otherwise.
+static DECRYPTORS: OnceLock<Decryptors> = OnceLock::new();
+
+/// Define a variable holding the directory tracking the interfaces brought
+/// up, if any; they are not tracked otherwise (see `session`).
+static SESSION_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Define a variable holding the backend given the DNS of the tunnels, if
+/// any; `wg-quick` runs `resolvconf` otherwise.
+static DNS_BACKEND: OnceLock<DnsBackend> = OnceLock::new();
+
+/// Define a variable holding the interface names given by the
+/// `interface_template`, by path to the WireGuard configuration file, if
+/// any; the interfaces are named after the files otherwise.
+static INTERFACE_NAMES: OnceLock<HashMap<PathBuf, String>> = OnceLock::new();
+
+/// Define the errors raised while parsing a WireGuard configuration file.
+#[derive(Debug, Error)]
+pub enum WgParseError {
+  #[error("Failed to read WireGuard configuration: {0}")]
+  Io(#[from] io::Error),
+  #[error("WireGuard configuration is larger than {0} bytes")]
+  TooLarge(usize),
+  #[error("WireGuard configuration is not valid UTF-8")]
+  NotUtf8,
+  #[error("Line {line}: {reason}")]
+  Syntax { line: usize, reason: String },
+  #[error("Line {line}: {field} is not a valid WireGuard key")]
+  InvalidKey { line: usize, field: &'static str },
+  #[error("Line {line}: invalid {field} value {value}")]
+  InvalidValue { line: usize, field: &'static str, value: String },
+  #[error("Missing [Interface] section")]
+  MissingInterface,
+  #[error("Missing {field} in [{section}] section")]
+  MissingField { section: &'static str, field: &'static str },
+  #[error("Failed to read AllowedIPs set file {}: {source}", .path.display())]
+  SetFile { path: PathBuf, source: io::Error },
+  #[error("AllowedIPs set file {}, line {line}: {entry} is not a valid network", .path.display())]
+  InvalidSetEntry { path: PathBuf, line: usize, entry: String },
+}
+
+/// Define the errors raised while managing a WireGuard tunnel.
+#[derive(Debug, Error)]
+pub enum WgError {
+  #[error("WireGuard configuration file {} does not exist", .0.display())]
+  MissingConfig(PathBuf),
+  #[error("Invalid WireGuard configuration: {0}")]
+  Parse(#[from] WgParseError),
+  #[error("Creating WireGuard interfaces requires root or CAP_NET_ADMIN, try again with sudo")]
+  NotPrivileged,
+  #[error("{0} not found, is wireguard-tools installed?")]
+  NotInstalled(&'static str),
+  #[error("{}", unavailable_message(.operation, .missing, .possible))]
+  Unavailable {
+    operation: Operation,
+    missing: Vec<&'static str>,
+    possible: Vec<Operation>,
+  },
+  #[error("Failed to run {0}: {1}")]
+  Spawn(&'static str, #[source] io::Error),
+  #[error("`{command}` failed ({status}): {reason}")]
+  Failed {
+    command: String,
+    status: ExitStatus,
+    reason: String,
+  },
+  #[error("Failed to record the operation: {0}")]
+  Audit(#[from] AuditError),
+  #[error(transparent)]
+  Lock(#[from] LockError),
+  #[error("Unexpected output from wg: {0}")]
+  UnexpectedOutput(String),
+  #[error("`{0}` was aborted, the command ran out of time")]
+  Timeout(String),
+  #[error("Invalid SSH destination {0}, expected [user@]host")]
+  InvalidRemote(String),
+  #[error("{} uses AllowedIPs set files, which cannot be expanded on a remote host", .0.display())]
+  RemoteSetFiles(PathBuf),
+  #[error("Invalid address pool {0}, expected a network such as 10.9.0.0/24")]
+  InvalidPool(String),
+  #[error("No free address left in the pool {0}")]
+  PoolExhausted(AddressPool),
+  #[error("{} already sets an Address", .0.display())]
+  AddressAlreadySet(PathBuf),
+  #[error("Failed to resolve endpoint {endpoint}: {source}")]
+  Resolve { endpoint: String, source: io::Error },
+  #[error("Endpoint {endpoint} has no address allowed by endpoint_family {family}")]
+  NoAddress { endpoint: String, family: AddressFamily },
+  #[error("Not a valid WireGuard private key")]
+  InvalidPrivateKey,
+  #[error("{} is encrypted with {encryption}, set decrypt.{encryption} in the configuration to use it", .path.display())]
+  Encrypted { path: PathBuf, encryption: Encryption },
+  #[error("{} is encrypted, which cannot be decrypted for a remote host", .0.display())]
+  RemoteEncrypted(PathBuf),
+  #[error("`wg set` argument {argument} is refused: {reason}")]
+  RefusedSetArgument { argument: String, reason: &'static str },
+  #[error("Benchmark failed: {0}")]
+  Benchmark(#[from] BenchError),
+  #[error("Failed to decrypt {} with `{command}`: {reason}", .path.display())]
+  Decrypt { path: PathBuf, command: String, reason: String },
+  #[error("Failed to set the DNS of the tunnel: {0}")]
+  Dns(#[from] DnsError),
+  #[error("{} sets DNS, which dns_backend cannot apply on a remote host", .0.display())]
+  RemoteDns(PathBuf),
+  #[error("{} is given the interface name {name} by interface_template, which cannot be applied on a remote host", .path.display())]
+  RemoteInterfaceName { path: PathBuf, name: String },
+  #[error("No handshake with a peer of {} within {}s, the tunnel was brought down", .path.display(), .timeout.as_secs())]
+  HandshakeTimeout { path: PathBuf, timeout: Duration },
+  #[error("{} is encrypted, edit it with its tool instead", .0.display())]
+  EncryptedEdit(PathBuf),
+  #[error("{} is left unchanged, the edited file is invalid: {source}", .path.display())]
+  InvalidEdit { path: PathBuf, source: WgParseError },
+  #[error("{} is left unchanged, the editor `{editor}` failed: {reason}", .path.display())]
+  Editor { path: PathBuf, editor: String, reason: String },
+}
+
+/// Define a WireGuard private key, base64-encoded as by `wg genkey`. It is
+/// wiped from memory when dropped and never printed by `Debug`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PrivateKey(Zeroizing<String>);
+
+/// Define a WireGuard public key, base64-encoded as by `wg pubkey`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicKey(String);
+
+/// Define the outcome of bringing down a tunnel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Teardown {
+  /// The tunnel was up and has been brought down.
+  Down,
+  /// The tunnel was not active, nothing has been done.
+  NotActive,
+}
+
+/// Define the outcome of bringing down the tunnel of a configuration file.
+pub type TeardownOutcome = (PathBuf, Result<Teardown, WgError>);
+
+/// Define the live state of a WireGuard tunnel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TunnelStatus {
+  /// The name of the interface.
+  pub interface: String,
+  /// Whether the interface is up.
+  pub up: bool,
+  /// The peers of the interface, empty when it is down.
+  pub peers: Vec<PeerStatus>,
+}
+
+/// Define the live state of a peer of a WireGuard tunnel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerStatus {
+  /// The base64 public key of the peer.
+  pub public_key: String,
+  /// The `host:port` the peer was last reached at, if any.
+  pub endpoint: Option<String>,
+  /// The networks routed to the peer on the live interface.
+  pub allowed_ips: Vec<String>,
+  /// The time of the latest handshake, `None` when none took place yet.
+  pub latest_handshake: Option<SystemTime>,
+  /// The bytes received from the peer.
+  pub rx_bytes: u64,
+  /// The bytes sent to the peer.
+  pub tx_bytes: u64,
+}
+
+/// Implements the logic to describe a tunnel
+#[allow(dead_code)]
+impl TunnelStatus {
+  /// Function to create the status of an interface which is down.
+  ///
+  /// # Arguments
+  /// * `interface`: The name of the interface.
+  ///
+  /// # Returns
+  /// * `TunnelStatus`: The status, without peers.
+  pub fn down(interface: &str) -> Self {
+    TunnelStatus {
+      interface: interface.to_string(),
+      up: false,
+      peers: Vec::new(),
+    }
+  }
+}
+
+/// Define a runner of external programs.
+///
+/// Tunnel management goes through a `CommandRunner` instead of spawning
+/// processes directly, so that the programs can be stubbed or replaced by a
+/// native implementation.
+pub trait CommandRunner: Debug + Send + Sync {
+  /// Function to run a program to completion, capturing its output.
+  ///
+  /// # Arguments
+  /// * `program`: The program to be run, looked up in `PATH`.
+  /// * `args`: The arguments of the program.
+  ///
+  /// # Returns
+  /// * `io::Result<Output>`: The exit status and the captured stdout and
+  ///   stderr, or the error raised spawning the program.
+  fn run(&self, program: &str, args: &[&OsStr]) -> io::Result<Output>;
+
+  /// Function to run a program undoing a failed operation, which must run
+  /// even when the deadline of the command has passed.
+  ///
+  /// # Arguments
+  /// * `program`: The program to be run, looked up in `PATH`.
+  /// * `args`: The arguments of the program.
+  ///
+  /// # Returns
+  /// * `io::Result<Output>`: The exit status and the captured stdout and
+  ///   stderr, or the error raised spawning the program.
+  fn run_cleanup(&self, program: &str, args: &[&OsStr]) -> io::Result<Output> {
+    self.run(program, args)
+  }
+
+  /// Function to run a program to completion, writing the given input to
+  /// its stdin and capturing its output.
+  ///
+  /// # Arguments
+  /// * `program`: The program to be run, looked up in `PATH`.
+  /// * `args`: The arguments of the program.
+  /// * `input`: The input written to the program.
+  ///
+  /// # Returns
+  /// * `io::Result<Output>`: The exit status and the captured stdout and
+  ///   stderr, or the error raised spawning the program; unsupported unless
+  ///   implemented by the runner.
+  fn run_with_input(&self, program: &str, _args: &[&OsStr], _input: &[u8]) -> io::Result<Output> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, format!("{program} cannot be given an input")))
+  }
+}
+
+/// Define the runner spawning the programs as child processes.
+///
+/// When a deadline is set, a program still running when it passes is killed
+/// and reported as `io::ErrorKind::TimedOut`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+  fn run(&self, program: &str, args: &[&OsStr]) -> io::Result<Output> {
+    match DEADLINE.get() {
+      Some(deadline) => run_until(program, args, None, deadline),
+      None => Command::new(program).args(args).output(),
+    }
+  }
+
+  fn run_cleanup(&self, program: &str, args: &[&OsStr]) -> io::Result<Output> {
+    match DEADLINE.get() {
+      Some(_) => run_until(program, args, None, &Deadline::after(CLEANUP_GRACE, Arc::new(SystemClock))),
+      None => Command::new(program).args(args).output(),
+    }
+  }
+
+  fn run_with_input(&self, program: &str, args: &[&OsStr], input: &[u8]) -> io::Result<Output> {
+    match DEADLINE.get() {
+      Some(deadline) => run_until(program, args, Some(input), deadline),
+      None => {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        write_input(child.stdin.take(), input)?;
+        child.wait_with_output()
+      }
+    }
+  }
+}
+
+/// Define the address family preferred when an endpoint hostname resolves
+/// to both IPv4 and IPv6 addresses, for networks where one of them is
+/// broken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddressFamily {
+  /// An IPv4 address, or an IPv6 one when there is none.
+  V4First,
+  /// An IPv6 address, or an IPv4 one when there is none.
+  V6First,
+  /// An IPv4 address only.
+  V4Only,
+  /// An IPv6 address only.
+  V6Only,
+}
+
+/// Implements the logic to pick an address of the preferred family
+impl AddressFamily {
+  /// Function to pick the address of an endpoint.
+  ///
+  /// # Arguments
+  /// * `addresses`: The addresses of the endpoint, in resolver order.
+  ///
+  /// # Returns
+  /// * `Option<IpAddr>`: The first address of the preferred family, or of
+  ///   the other one when allowed, or `None` when none is allowed.
+  pub fn choose(&self, addresses: &[IpAddr]) -> Option<IpAddr> {
+    let v4 = addresses.iter().copied().find(IpAddr::is_ipv4);
+    let v6 = addresses.iter().copied().find(IpAddr::is_ipv6);
+    match self {
+      AddressFamily::V4First => v4.or(v6),
+      AddressFamily::V6First => v6.or(v4),
+      AddressFamily::V4Only => v4,
+      AddressFamily::V6Only => v6,
+    }
+  }
+}
+
+impl fmt::Display for AddressFamily {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AddressFamily::V4First => write!(f, "v4-first"),
+      AddressFamily::V6First => write!(f, "v6-first"),
+      AddressFamily::V4Only => write!(f, "v4-only"),
+      AddressFamily::V6Only => write!(f, "v6-only"),
+    }
+  }
+}
+
+/// Define the resolver of endpoint hostnames, so that tests can stub DNS.
+pub trait Resolver {
+  /// Function to resolve a hostname.
+  ///
+  /// # Arguments
+  /// * `host`: The hostname.
+  /// * `port`: The port of the endpoint.
+  ///
+  /// # Returns
+  /// * `io::Result<Vec<IpAddr>>`: The addresses, in resolver order.
+  fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<IpAddr>>;
+}
+
+/// Define the resolver asking the system, like `wg` does.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+  fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<IpAddr>> {
+    Ok((host, port).to_socket_addrs()?.map(|address| address.ip()).collect())
+  }
+}
+
+/// Function to resolve the endpoint hostnames on connect, picking an
+/// address of the given family, for the rest of the process.
+///
+/// # Arguments
+/// * `family`: The preferred address family.
+pub fn set_address_family(family: AddressFamily) {
+  let _ = ADDRESS_FAMILY.set(family);
+}
+
+/// Define the commands decrypting the WireGuard configuration files stored
+/// encrypted, e.g. in git, with age or sops. The path to the file is
+/// appended to the command, which prints the plaintext on stdout. A missing
+/// command leaves the files encrypted with that tool unusable.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decryptors {
+  /// The command decrypting age files, e.g. `age --decrypt -i key.txt`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub age: Option<String>,
+  /// The command decrypting sops files, e.g. `sops --decrypt`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sops: Option<String>,
+}
+
+/// Define the tool a WireGuard configuration file is encrypted with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encryption {
+  /// An age file, armored or binary.
+  Age,
+  /// A sops file, in JSON, YAML or INI format.
+  Sops,
+}
+
+/// Implements the logic to detect and decrypt the encrypted configurations
+impl Encryption {
+  /// Function to tell whether the content of a file is encrypted.
+  ///
+  /// # Arguments
+  /// * `content`: The content of the file.
+  ///
+  /// # Returns
+  /// * `Option<Encryption>`: The tool the content is encrypted with, `None`
+  ///   for a plaintext configuration.
+  pub fn detect(content: &[u8]) -> Option<Self> {
+    let armored = content.trim_ascii_start().starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----");
+    if armored || content.starts_with(b"age-encryption.org/v1\n") {
+      return Some(Encryption::Age);
+    }
+    let text = std::str::from_utf8(content).ok()?;
+    // sops keeps its metadata in a top-level `sops` key
+    let json = text.trim_start().starts_with('{')
+        && serde_json::from_str::<serde_json::Value>(text).is_ok_and(|value| value.get("sops").is_some());
+    let other = text.lines().any(|line| line.trim_end() == "[sops]" || line.starts_with("sops:"));
+    (json || other).then_some(Encryption::Sops)
+  }
+
+  /// Function to retrieve the command decrypting the files of the tool.
+  ///
+  /// # Arguments
+  /// * `decryptors`: The configured commands.
+  ///
+  /// # Returns
+  /// * `Option<&str>`: The command, `None` when it is not configured.
+  fn command(self, decryptors: &Decryptors) -> Option<&str> {
+    match self {
+      Encryption::Age => decryptors.age.as_deref(),
+      Encryption::Sops => decryptors.sops.as_deref(),
+    }
+  }
+}
+
+impl fmt::Display for Encryption {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Encryption::Age => "age",
+      Encryption::Sops => "sops",
+    })
+  }
+}
+
+/// Function to decrypt the encrypted WireGuard configuration files with the
+/// given commands, for the rest of the process.
+///
+/// # Arguments
+/// * `decryptors`: The commands decrypting the files.
+pub fn set_decryptors(decryptors: Decryptors) {
+  let _ = DECRYPTORS.set(decryptors);
+}
+
+/// Function to give the DNS of the tunnels to the given backend instead of
+/// the `resolvconf` run by `wg-quick`, for the rest of the process.
+///
+/// # Arguments
+/// * `backend`: The backend, detected on connect when `Auto`.
+pub fn set_dns_backend(backend: DnsBackend) {
+  let _ = DNS_BACKEND.set(backend);
+}
+
+/// Function to name the interfaces of the given WireGuard configuration
+/// files instead of after the files, for the rest of the process.
+///
+/// # Arguments
+/// * `names`: The interface names, by resolved path to the file.
+pub fn set_interface_names(names: HashMap<PathBuf, String>) {
+  let _ = INTERFACE_NAMES.set(names);
+}
+
+/// Function to track the interfaces brought up and down in the given
+/// directory, for the rest of the process.
+///
+/// # Arguments
+/// * `dir`: The directory of the sessions.
+pub fn set_session_dir(dir: PathBuf) {
+  let _ = SESSION_DIR.set(dir);
+}
+
+/// Function to record or forget the session of an interface, when tracked.
+///
+/// A failure is logged rather than raised, since the tunnel itself is up or
+/// down by then.
+///
+/// # Arguments
+/// * `interface`: The name of the interface.
+/// * `config_path`: The WireGuard configuration file it was brought up
+///   from, `None` once brought down.
+fn track_session(interface: &str, config_path: Option<&Path>) {
+  let Some(dir) = SESSION_DIR.get() else {
+    return;
+  };
+  let tracked = match config_path {
+    Some(config_path) => session::record(dir, interface, config_path),
+    None => session::forget(dir, interface),
+  };
+  if let Err(e) = tracked {
+    wgb_warn!("Failed to track {interface}: {e}");
+  }
+}
+
+/// Define a pool of addresses the tunnels are given an `Address` from, e.g.
+/// `10.9.0.0/24`, for test tunnels whose addresses nobody wants to track.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddressPool {
+  /// The network of the pool, with its host bits cleared.
+  network: IpAddr,
+  /// The prefix length of the pool.
+  prefix: u32,
+}
+
+/// Implements the logic to pick the addresses of a pool
+impl AddressPool {
+  /// Function to tell whether an address belongs to the network of the
+  /// pool.
+  ///
+  /// # Arguments
+  /// * `address`: The address.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the address is in the network, of the same family.
+  pub fn contains(&self, address: IpAddr) -> bool {
+    match (self.network, address) {
+      (IpAddr::V4(network), IpAddr::V4(address)) => {
+        let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+        u32::from(address) & mask == u32::from(network)
+      }
+      (IpAddr::V6(network), IpAddr::V6(address)) => {
+        let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+        u128::from(address) & mask == u128::from(network)
+      }
+      _ => false,
+    }
+  }
+
+  /// Function to pick the lowest address of the pool which is not used.
+  ///
+  /// The network address is never picked, nor is the broadcast address of an
+  /// IPv4 pool larger than a /31.
+  ///
+  /// # Arguments
+  /// * `used`: The addresses used by the other tunnels; the ones outside the
+  ///   pool are ignored.
+  ///
+  /// # Returns
+  /// * `Option<IpAddr>`: The address, or `None` when the pool is exhausted.
+  pub fn next_free(&self, used: &[IpAddr]) -> Option<IpAddr> {
+    let (network, bits) = match self.network {
+      IpAddr::V4(network) => (u128::from(u32::from(network)), 32),
+      IpAddr::V6(network) => (u128::from(network), 128),
+    };
+    let size = 1u128.checked_shl(bits - self.prefix).unwrap_or(0).wrapping_sub(1);
+    let (first, last) = match (self.network.is_ipv4(), bits - self.prefix) {
+      (_, 0) => (0, 0),
+      (true, 1) => (0, 1),
+      (true, _) => (1, size - 1),
+      (false, _) => (1, size),
+    };
+    let mut offsets: Vec<u128> = used
+        .iter()
+        .filter(|address| address.is_ipv4() == self.network.is_ipv4())
+        .map(|address| match address {
+          IpAddr::V4(address) => u128::from(u32::from(*address)),
+          IpAddr::V6(address) => u128::from(*address),
+        })
+        .filter_map(|address| address.checked_sub(network))
+        .filter(|offset| (first..=last).contains(offset))
+        .collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+    let mut candidate = first;
+    for offset in offsets {
+      if offset != candidate {
+        break;
+      }
+      candidate = candidate.checked_add(1)?;
+    }
+    if candidate > last {
+      return None;
+    }
+    let address = network + candidate;
+    Some(match self.network {
+      IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(address as u32)),
+      IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(address)),
+    })
+  }
+}
+
+impl FromStr for AddressPool {
+  type Err = WgError;
+
+  fn from_str(pool: &str) -> Result<Self, Self::Err> {
+    let invalid = || WgError::InvalidPool(pool.to_string());
+    if !pool.contains('/') {
+      return Err(invalid());
+    }
+    let network = normalize_network(pool);
+    let (network, prefix) = split_network(&network).ok_or_else(invalid)?;
+    Ok(AddressPool { network, prefix })
+  }
+}
+
+impl fmt::Display for AddressPool {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.network, self.prefix)
+  }
+}
+
+/// Define the view of the host used to find the UDP ports held by other
+/// programs, so that tests can stub it.
+pub trait PortChecker {
+  /// Function to tell whether a UDP port is bound on the host.
+  ///
+  /// # Arguments
+  /// * `port`: The UDP port.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the port is bound, on IPv4 or IPv6.
+  fn in_use(&self, port: u16) -> bool;
+
+  /// Function to tell whether a network interface exists, in which case a
+  /// WireGuard interface holds its own listen port.
+  ///
+  /// # Arguments
+  /// * `interface`: The name of the interface.
+  ///
+  /// # Returns
+  /// * `bool`: Whether the interface exists.
+  fn interface_exists(&self, interface: &str) -> bool;
+}
+
+/// Define the port checker probing the ports of the local host by binding
+/// them, which needs no privilege since WireGuard ports are above 1023.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemPortChecker;
+
+impl PortChecker for SystemPortChecker {
+  fn in_use(&self, port: u16) -> bool {
+    let in_use = |address: IpAddr| {
+      matches!(UdpSocket::bind((address, port)), Err(e) if e.kind() == io::ErrorKind::AddrInUse)
+    };
+    in_use(IpAddr::V4(Ipv4Addr::UNSPECIFIED)) || in_use(IpAddr::V6(Ipv6Addr::UNSPECIFIED))
+  }
+
+  fn interface_exists(&self, interface: &str) -> bool {
+    Path::new("/sys/class/net").join(interface).exists()
+  }
+}
+
+/// Define the outcome of probing the UDP port of an endpoint. UDP has no
+/// handshake and WireGuard ignores unauthenticated packets, so an open port
+/// usually cannot be told from a silently filtered one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reachability {
+  /// The endpoint answered the probe.
+  Reachable,
+  /// Nothing came back: the port may be open or silently dropped.
+  Unknown,
+  /// The endpoint cannot be reached, for the given reason (e.g. an ICMP
+  /// port unreachable).
+  Unreachable(String),
+}
+
+impl fmt::Display for Reachability {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Reachability::Reachable => write!(f, "reachable"),
+      Reachability::Unknown => write!(f, "unknown (no answer, as usual for UDP)"),
+      Reachability::Unreachable(reason) => write!(f, "unreachable ({reason})"),
+    }
+  }
+}
+
+/// Define the prober of the UDP ports of the endpoints, so that tests can
+/// replace the network.
+pub trait Prober {
+  /// Function to probe the UDP port of an endpoint.
+  ///
+  /// # Arguments
+  /// * `endpoint`: The endpoint, as written in the configuration (i.e.
+  ///   `host:port`).
+  ///
+  /// # Returns
+  /// * `Reachability`: The outcome of the probe.
+  fn probe(&self, endpoint: &str) -> Reachability;
+}
+
+/// Define the prober sending a single byte to the endpoint from this host
+/// and waiting for an answer or an ICMP error.
+#[derive(Clone, Copy, Debug)]
+pub struct UdpProber {
+  /// The longest time an answer is waited for.
+  pub timeout: Duration,
+}
+
+impl Default for UdpProber {
+  fn default() -> Self {
+    UdpProber { timeout: PROBE_TIMEOUT }
+  }
+}
+
+impl Prober for UdpProber {
+  fn probe(&self, endpoint: &str) -> Reachability {
+    let Some((host, port)) = split_endpoint(endpoint) else {
+      return Reachability::Unreachable("invalid endpoint".to_string());
+    };
+    let addresses = match host.parse::<IpAddr>() {
+      Ok(address) => vec![address],
+      Err(_) => match SystemResolver.resolve(host, port) {
+        Ok(addresses) => addresses,
+        Err(e) => return Reachability::Unreachable(format!("cannot resolve {host}: {e}")),
+      },
+    };
+    let family = ADDRESS_FAMILY.get().copied().unwrap_or(AddressFamily::V4First);
+    let Some(address) = family.choose(&addresses).or(addresses.first().copied()) else {
+      return Reachability::Unreachable(format!("{host} has no address"));
+    };
+    let wait = DEADLINE
+        .get()
+        .map_or(self.timeout, |deadline| deadline.remaining().unwrap_or_default().min(self.timeout));
+    match send_probe(SocketAddr::new(address, port), wait) {
+      Ok(()) => Reachability::Reachable,
+      Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Reachability::Unknown,
+      Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+        Reachability::Unreachable("port closed, ICMP port unreachable".to_string())
+      }
+      Err(e) => Reachability::Unreachable(e.to_string()),
+    }
+  }
+}
+
+/// Function to send a probe to a UDP port and wait for an answer. The
+/// socket is connected, so that the ICMP errors are reported by `recv`.
+///
+/// # Arguments
+/// * `address`: The address of the endpoint.
+/// * `wait`: The longest time an answer is waited for.
+///
+/// # Returns
+/// * `io::Result<()>`: Nothing when an answer came back, or the error
+///   raised sending or receiving, `WouldBlock` or `TimedOut` on timeout.
+fn send_probe(address: SocketAddr, wait: Duration) -> io::Result<()> {
+  let local = match address {
+    SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+  };
+  let socket = UdpSocket::bind((local, 0))?;
+  socket.connect(address)?;
+  // A zero timeout is rejected, and would mean waiting forever anyway
+  socket.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+  // A single zero byte is no WireGuard message, so peers drop it
+  socket.send(&[0])?;
+  socket.recv(&mut [0; 64])?;
+  Ok(())
+}
+
+/// Function to probe the endpoints of the peers of a WireGuard
+/// configuration.
+///
+/// # Arguments
+/// * `config`: The WireGuard configuration.
+/// * `prober`: The prober of the endpoints.
+///
+/// # Returns
+/// * `Vec<(String, Reachability)>`: The outcome of each endpoint, in the
+///   order of the peers; peers without an endpoint are left out.
+pub fn probe_endpoints(config: &WgConfig, prober: &dyn Prober) -> Vec<(String, Reachability)> {
+  config
+      .peers
+      .iter()
+      .filter_map(|peer| peer.endpoint.as_deref())
+      .map(|endpoint| (endpoint.to_string(), prober.probe(endpoint)))
+      .collect()
+}
+
+/// Define the runner executing the programs on a remote host over SSH,
+/// through another runner spawning `ssh`.
+///
+/// The remote shell joins the arguments, so every one of them is quoted. SSH
+/// runs in batch mode: it fails instead of prompting for a password, since
+/// the output of the programs is captured.
+#[derive(Clone, Debug)]
+pub struct SshRunner<R: CommandRunner> {
+  /// The SSH destination, i.e. `[user@]host`.
+  destination: String,
+  /// The runner of `ssh`.
+  inner: R,
+}
+
+/// Implements the logic to wrap the programs in an SSH invocation
+#[allow(dead_code)]
+impl<R: CommandRunner> SshRunner<R> {
+  /// Function to create a runner for the given SSH destination.
+  ///
+  /// # Arguments
+  /// * `destination`: The SSH destination, i.e. `[user@]host`.
+  /// * `inner`: The runner of `ssh`.
+  ///
+  /// # Returns
+  /// * `Result<SshRunner<R>, WgError>`: The runner, or
+  ///   `WgError::InvalidRemote` for a destination which is empty, holds
+  ///   whitespace or could be taken for an option of `ssh`.
+  pub fn new(destination: &str, inner: R) -> Result<Self, WgError> {
+    let invalid = destination.is_empty()
+        || destination.starts_with('-')
+        || destination.chars().any(|c| c.is_whitespace() || c.is_control());
+    if invalid {
+      return Err(WgError::InvalidRemote(destination.to_string()));
+    }
+    Ok(SshRunner {
+      destination: destination.to_string(),
+      inner,
+    })
+  }
+
+  /// Function to build the arguments of `ssh` running a program remotely.
+  ///
+  /// # Arguments
+  /// * `program`: The program to be run on the remote host.
+  /// * `args`: The arguments of the program.
+  ///
+  /// # Returns
+  /// * `Vec<OsString>`: The arguments of `ssh`.
+  fn ssh_args(&self, program: &str, args: &[&OsStr]) -> Vec<OsString> {
+    let command = std::iter::once(shell_quote(OsStr::new(program)))
+        .chain(args.iter().map(|arg| shell_quote(arg)))
+        .collect::<Vec<String>>()
+        .join(" ");
+    ["-o", "BatchMode=yes", "--", &self.destination, &command]
+        .into_iter()
+        .map(OsString::from)
+        .collect()
+  }
+}
+
+impl<R: CommandRunner> CommandRunner for SshRunner<R> {
+  fn run(&self, program: &str, args: &[&OsStr]) -> io::Result<Output> {
+    let ssh_args = self.ssh_args(program, args);
+    self.inner.run(SSH, &ssh_args.iter().map(OsString::as_os_str).collect::<Vec<_>>())
+  }
+
+  fn run_cleanup(&self, program: &str, args: &[&OsStr]) -> io::Result<Output> {
+    let ssh_args = self.ssh_args(program, args);
+    self.inner.run_cleanup(SSH, &ssh_args.iter().map(OsString::as_os_str).collect::<Vec<_>>())
+  }
+
+  fn run_with_input(&self, program: &str, args: &[&OsStr], input: &[u8]) -> io::Result<Output> {
+    let ssh_args = self.ssh_args(program, args);
+    self
+        .inner
+        .run_with_input(SSH, &ssh_args.iter().map(OsString::as_os_str).collect::<Vec<_>>(), input)
+  }
+}
+
+/// Function to run the WireGuard programs on a remote host over SSH, for
+/// the rest of the process.
+///
+/// # Arguments
+/// * `destination`: The SSH destination, i.e. `[user@]host`.
+///
+/// # Returns
+/// * `Result<(), WgError>`: `WgError::InvalidRemote` for an invalid
+///   destination.
+pub fn set_remote(destination: &str) -> Result<(), WgError> {
+  SshRunner::new(destination, SystemRunner)?;
+  let _ = REMOTE.set(destination.to_string());
+  Ok(())
+}
+
+/// Function to retrieve the runner of the WireGuard programs: over SSH when
+/// a remote host is set, locally otherwise.
+///
+/// # Returns
+/// * `Box<dyn CommandRunner>`: The runner.
+pub fn system_runner() -> Box<dyn CommandRunner> {
+  match REMOTE.get().and_then(|destination| SshRunner::new(destination, SystemRunner).ok()) {
+    Some(runner) => Box::new(runner),
+    None => Box::new(SystemRunner),
+  }
+}
+
+/// Function to lock the tunnel of a WireGuard configuration file, waiting
+/// at most `LOCK_WAIT` or until the deadline of the command.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<Option<TunnelLock>, WgError>`: The lock, `None` for a file
+///   name which is no interface name and which `wg-quick` rejects anyway, or
+///   the error raised when the lock could not be taken in time.
+fn lock_tunnel(path: &Path) -> Result<Option<TunnelLock>, WgError> {
+  Ok(interface_name(path).map(|interface| TunnelLock::interface(interface, lock_wait())).transpose()?)
+}
+
+/// Function to retrieve the longest time to wait for a tunnel lock.
+///
+/// # Returns
+/// * `Duration`: `LOCK_WAIT`, or the time left before the deadline of the
+///   command when shorter.
+fn lock_wait() -> Duration {
+  DEADLINE
+      .get()
+      .map_or(LOCK_WAIT, |deadline| deadline.remaining().unwrap_or_default().min(LOCK_WAIT))
+}
+
+/// Define the operations needing the WireGuard programs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+  /// Bringing a tunnel up, with `wg-quick up`.
+  Connect,
+  /// Bringing a tunnel down, with `wg show` and `wg-quick down`.
+  Disconnect,
+  /// Reading the live state of the tunnels, with `wg show`.
+  Status,
+}
+
+/// Implements the logic of the operations needing the WireGuard programs
+impl Operation {
+  /// Every operation, in the order they are suggested in.
+  const ALL: [Operation; 3] = [Operation::Connect, Operation::Disconnect, Operation::Status];
+
+  /// Function to retrieve the programs an operation runs.
+  ///
+  /// # Returns
+  /// * `&'static [&'static str]`: The programs.
+  fn programs(&self) -> &'static [&'static str] {
+    match self {
+      Operation::Connect => &[WG_QUICK],
+      Operation::Disconnect => &[WG, WG_QUICK],
+      Operation::Status => &[WG],
+    }
+  }
+}
+
+impl fmt::Display for Operation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Operation::Connect => write!(f, "connect"),
+      Operation::Disconnect => write!(f, "disconnect"),
+      Operation::Status => write!(f, "status"),
+    }
+  }
+}
+
+/// Define the WireGuard programs found on the host, so that an operation
+/// needing a missing one is explained rather than failing to spawn it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+  /// Whether `wg` is installed.
+  pub wg: bool,
+  /// Whether `wg-quick` is installed.
+  pub wg_quick: bool,
+}
+
+/// Implements the logic of the capability detection
+impl Capabilities {
+  /// Function to find the WireGuard programs in the directories of `PATH`.
+  ///
+  /// The programs of a remote host cannot be seen, so they are all assumed
+  /// there and left to fail over SSH.
+  ///
+  /// # Returns
+  /// * `Capabilities`: The programs found.
+  pub fn detect() -> Self {
+    if REMOTE.get().is_some() {
+      return Capabilities { wg: true, wg_quick: true };
+    }
+    Self::detect_in(&std::env::var_os("PATH").unwrap_or_default())
+  }
+
+  /// Function to find the WireGuard programs in a list of directories.
+  ///
+  /// # Arguments
+  /// * `dirs`: The directories, separated like in `PATH`.
+  ///
+  /// # Returns
+  /// * `Capabilities`: The programs found.
+  pub fn detect_in(dirs: &OsStr) -> Self {
+    let found = |program: &str| std::env::split_paths(dirs).any(|dir| is_executable(&dir.join(program)));
+    Capabilities {
+      wg: found(WG),
+      wg_quick: found(WG_QUICK),
+    }
+  }
+
+  /// Function to tell whether a program is installed.
+  ///
+  /// # Arguments
+  /// * `program`: The program.
+  ///
+  /// # Returns
+  /// * `bool`: Whether it was found.
+  fn has(&self, program: &str) -> bool {
+    match program {
+      WG => self.wg,
+      WG_QUICK => self.wg_quick,
+      _ => false,
+    }
+  }
+
+  /// Function to check that an operation can run with the programs found.
+  ///
+  /// # Arguments
+  /// * `operation`: The operation.
+  ///
+  /// # Returns
+  /// * `Result<(), WgError>`: `WgError::Unavailable`, naming the missing
+  ///   programs and the operations still possible, when one is missing.
+  pub fn check(&self, operation: Operation) -> Result<(), WgError> {
+    let missing: Vec<&'static str> = operation.programs().iter().copied().filter(|program| !self.has(program)).collect();
+    if missing.is_empty() {
+      return Ok(());
+    }
+    Err(WgError::Unavailable {
+      operation,
+      missing,
+      possible: Operation::ALL
+          .into_iter()
+          .filter(|other| other.programs().iter().all(|program| self.has(program)))
+          .collect(),
+    })
+  }
+}
+
+/// Function to tell whether a file is an executable program.
+///
+/// # Arguments
+/// * `path`: The path to the file.
+///
+/// # Returns
+/// * `bool`: Whether the file exists and, on Unix, may be executed.
+fn is_executable(path: &Path) -> bool {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+  }
+  #[cfg(not(unix))]
+  {
+    path.is_file()
+  }
+}
+
+/// Function to describe an operation which cannot run, and what still can.
+///
+/// # Arguments
+/// * `operation`: The operation.
+/// * `missing`: The programs it needs which are missing.
+/// * `possible`: The operations the installed programs allow.
+///
+/// # Returns
+/// * `String`: The description.
+fn unavailable_message(operation: &Operation, missing: &[&str], possible: &[Operation]) -> String {
+  let (missing, verb) = match missing {
+    [program] => (program.to_string(), "is"),
+    programs => (programs.join(" and "), "are"),
+  };
+  let still = match possible {
+    [] => "no other operation is possible either".to_string(),
+    [other] => format!("only {other} is possible with the installed programs"),
+    others => {
+      let others: Vec<String> = others.iter().map(Operation::to_string).collect();
+      format!("{} are still possible", others.join(" and "))
+    }
+  };
+  format!("{operation} needs {missing}, which {verb} not installed (see wireguard-tools); {still}")
+}
+
+/// Function to check that the WireGuard programs may manage interfaces. The
+/// privileges of a remote host are left to be checked by the programs.
+///
+/// # Returns
+/// * `Result<(), WgError>`: `WgError::NotPrivileged` when the programs run
+///   locally without root or CAP_NET_ADMIN.
+fn check_privileges() -> Result<(), WgError> {
+  if REMOTE.get().is_none() && !is_privileged() {
+    return Err(WgError::NotPrivileged);
+  }
+  Ok(())
+}
+
+/// Function to quote an argument for a POSIX shell.
+///
+/// # Arguments
+/// * `arg`: The argument.
+///
+/// # Returns
+/// * `String`: The argument as it is when made of safe characters only,
+///   enclosed in single quotes otherwise.
+fn shell_quote(arg: &OsStr) -> String {
+  let arg = arg.to_string_lossy();
+  let safe = |c: char| c.is_ascii_alphanumeric() || "_-./:@=,+%".contains(c);
+  if !arg.is_empty() && arg.chars().all(safe) {
+    return arg.into_owned();
+  }
+  format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Function to bound the total time spent running WireGuard programs.
+///
+/// # Arguments
+/// * `deadline`: The time every program must be over by.
+///
+/// # Returns
+/// * `bool`: Whether the deadline was set, `false` when one already was.
+pub fn set_deadline(deadline: Deadline) -> bool {
+  DEADLINE.set(deadline).is_ok()
+}
+
+/// Function to run a program to completion, killing it when a deadline
+/// passes first.
+///
+/// The outputs are read by threads of their own, so that a program filling
+/// a pipe does not block while it is waited for.
+///
+/// # Arguments
+/// * `program`: The program to be run, looked up in `PATH`.
+/// * `args`: The arguments of the program.
+/// * `deadline`: The time the program must be over by.
+///
+/// # Returns
+/// * `io::Result<Output>`: The exit status and the captured stdout and
+///   stderr, or the error raised spawning the program or
+///   `io::ErrorKind::TimedOut` when it was killed.
+fn run_until(program: &str, args: &[&OsStr], input: Option<&[u8]>, deadline: &Deadline) -> io::Result<Output> {
+  if deadline.remaining().is_none() {
+    return Err(io::Error::new(io::ErrorKind::TimedOut, format!("{program} not started, no time left")));
+  }
+  let mut child = Command::new(program)
+      .args(args)
+      .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()?;
+  let stdout = read_pipe(child.stdout.take());
+  let stderr = read_pipe(child.stderr.take());
+  if let Some(input) = input {
+    write_input(child.stdin.take(), input)?;
+  }
+
+  let status = loop {
+    if let Some(status) = child.try_wait()? {
+      break status;
+    }
+    if deadline.remaining().is_none() {
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(io::Error::new(io::ErrorKind::TimedOut, format!("{program} killed at the deadline")));
+    }
+    thread::sleep(POLL_INTERVAL);
+  };
+  Ok(Output {
+    status,
+    stdout: stdout.join().unwrap_or_default(),
+    stderr: stderr.join().unwrap_or_default(),
+  })
+}
+
+/// Function to write the input of a child process and close its stdin, so
+/// that it reads the end of the input.
+///
+/// # Arguments
+/// * `stdin`: The stdin of the child process, if piped.
+/// * `input`: The input.
+///
+/// # Returns
+/// * `io::Result<()>`: The error, if any, raised while writing; none when
+///   the program exits without reading all of it.
+fn write_input(stdin: Option<ChildStdin>, input: &[u8]) -> io::Result<()> {
+  let Some(mut stdin) = stdin else {
+    return Ok(());
+  };
+  match stdin.write_all(input) {
+    Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+    _ => Ok(()),
+  }
+}
+
+/// Function to read a pipe of a child process to its end, on a thread of
+/// its own.
+///
+/// # Arguments
+/// * `pipe`: The pipe, if captured.
+///
+/// # Returns
+/// * `JoinHandle<Vec<u8>>`: The thread, returning what was read.
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+  thread::spawn(move || {
+    let mut buffer = Vec::new();
+    if let Some(mut pipe) = pipe {
+      let _ = pipe.read_to_end(&mut buffer);
+    }
+    buffer
+  })
+}
+
+/// Define a WireGuard configuration, as read by `wg-quick`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WgConfig {
+  /// The local end of the tunnel.
+  pub interface: Interface,
+  /// The remote ends of the tunnel, in the order they appear in the file.
+  pub peers: Vec<Peer>,
+}
+
+/// Define the `[Interface]` section of a WireGuard configuration.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Interface {
+  /// The base64 private key of the interface.
+  pub private_key: String,
+  /// The addresses, with their prefix length, assigned to the interface.
+  pub addresses: Vec<String>,
+  /// The DNS servers and search domains set while the tunnel is up, in the
+  /// order of the file; `wg-quick` tells them apart the same way as
+  /// `dns_servers` and `search_domains`.
+  pub dns: Vec<String>,
+  /// The UDP port listened on, random when unset.
+  pub listen_port: Option<u16>,
+  /// The MTU of the interface, chosen by `wg-quick` when unset.
+  pub mtu: Option<u16>,
+  /// Whether `wg-quick` saves the live state of the interface back to the
+  /// file when bringing it down, overwriting it.
+  pub save_config: bool,
+  /// The AmneziaWG obfuscation parameters, all unset for standard WireGuard.
+  pub obfuscation: Obfuscation,
+}
+
+/// Implements the logic of the `[Interface]` section
+impl Interface {
+  /// Function to retrieve the DNS servers, i.e. the `DNS` entries which are
+  /// addresses.
+  ///
+  /// # Returns
+  /// * `Vec<IpAddr>`: The DNS servers, in the order of the file.
+  pub fn dns_servers(&self) -> Vec<IpAddr> {
+    self.dns.iter().filter_map(|entry| entry.parse().ok()).collect()
+  }
+
+  /// Function to retrieve the search domains, i.e. the `DNS` entries which
+  /// are not addresses.
+  ///
+  /// # Returns
+  /// * `Vec<&str>`: The search domains, in the order of the file.
+  pub fn search_domains(&self) -> Vec<&str> {
+    self
+        .dns
+        .iter()
+        .filter(|entry| entry.parse::<IpAddr>().is_err())
+        .map(String::as_str)
+        .collect()
+  }
+}
+
+/// Implements the logic of the AmneziaWG obfuscation parameters
+#[allow(dead_code)]
+impl Obfuscation {
+  /// Function to tell whether any obfuscation parameter is set, i.e. whether
+  /// the configuration requires AmneziaWG.
+  ///
+  /// # Returns
+  /// * `bool`: Whether a parameter is set.
+  pub fn is_enabled(&self) -> bool {
+    *self != Obfuscation::default()
+  }
+}
+
+/// Define the obfuscation parameters of the `[Interface]` section added by
+/// AmneziaWG, used to get past deep packet inspection. Standard WireGuard
+/// does not know about them: `wg-quick` rejects a file setting any.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Obfuscation {
+  /// `Jc`: the number of junk packets sent before the handshake.
+  pub junk_count: Option<u16>,
+  /// `Jmin`: the minimum size, in bytes, of the junk packets.
+  pub junk_min: Option<u16>,
+  /// `Jmax`: the maximum size, in bytes, of the junk packets.
+  pub junk_max: Option<u16>,
+  /// `S1`: the size, in bytes, of the junk prepended to handshake initiations.
+  pub init_padding: Option<u16>,
+  /// `S2`: the size, in bytes, of the junk prepended to handshake responses.
+  pub response_padding: Option<u16>,
+  /// `H1` to `H4`: the magic headers replacing the message types of the
+  /// initiation, response, cookie reply and transport messages.
+  pub headers: [Option<u32>; 4],
+}
+
+/// Define a `[Peer]` section of a WireGuard configuration.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Peer {
+  /// The base64 public key of the peer.
+  pub public_key: String,
+  /// The base64 symmetric key mixed into the handshake, if any.
+  pub preshared_key: Option<String>,
+  /// The `host:port` the peer is reached at, if known.
+  pub endpoint: Option<String>,
+  /// The networks routed to the peer.
+  pub allowed_ips: Vec<String>,
+  /// The interval, in seconds, of the keepalive packets sent to the peer.
+  pub persistent_keepalive: Option<u16>,
+}
+
+/// Implements the logic of a peer of a WireGuard configuration
+impl Peer {
+  /// Function to tell whether the networks routed to the peer on the live
+  /// interface are the configured ones.
+  ///
+  /// The networks are compared as sets, once normalized the way `wg` prints
+  /// them: host bits cleared and a missing prefix length made explicit.
+  ///
+  /// # Arguments
+  /// * `live`: The allowed IPs of the peer on the live interface.
+  ///
+  /// # Returns
+  /// * `bool`: Whether both hold the same networks.
+  pub fn allowed_ips_match(&self, live: &[String]) -> bool {
+    let normalize = |networks: &[String]| -> BTreeSet<String> {
+      networks.iter().map(|network| normalize_network(network)).collect()
+    };
+    normalize(&self.allowed_ips) == normalize(live)
+  }
+}
+
+/// Define the section being parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Section {
+  None,
+  Interface,
+  Peer,
+  /// A section WG-Bridge does not know about; its entries are ignored.
+  Other,
+}
+
+impl fmt::Debug for Interface {
+  /// The private key is masked, so that it never ends up in a log.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Interface")
+        .field("private_key", &"***")
+        .field("addresses", &self.addresses)
+        .field("dns", &self.dns)
+        .field("listen_port", &self.listen_port)
+        .field("mtu", &self.mtu)
+        .field("save_config", &self.save_config)
+        .field("obfuscation", &self.obfuscation)
+        .finish()
+  }
+}
+
+impl fmt::Debug for Peer {
+  /// The preshared key is masked, so that it never ends up in a log.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Peer")
+        .field("public_key", &self.public_key)
+        .field("preshared_key", &self.preshared_key.as_ref().map(|_| "***"))
+        .field("endpoint", &self.endpoint)
+        .field("allowed_ips", &self.allowed_ips)
+        .field("persistent_keepalive", &self.persistent_keepalive)
+        .finish()
+  }
+}
+
+impl fmt::Debug for PrivateKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("PrivateKey(***)")
+  }
+}
+
+/// Implements the logic to access a private key
+#[allow(dead_code)]
+impl PrivateKey {
+  /// Function to retrieve the base64 encoding of the key.
+  ///
+  /// # Returns
+  /// * `&str`: The key, as found in the `PrivateKey` entry of a configuration.
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Display for PublicKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+/// Implements the logic to access a public key
+#[allow(dead_code)]
+impl PublicKey {
+  /// Function to retrieve the base64 encoding of the key.
+  ///
+  /// # Returns
+  /// * `&str`: The key, as found in the `PublicKey` entry of a configuration.
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+/// Implements the logic to parse a WireGuard configuration
+#[allow(dead_code)]
+impl WgConfig {
+  /// Function to read and parse a WireGuard configuration file.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the WireGuard configuration file.
+  ///
+  /// # Returns
+  /// * `Result<WgConfig, WgParseError>`: The configuration, or the error
+  ///   raised reading or parsing the file.
+  pub fn parse(path: &Path) -> Result<WgConfig, WgParseError> {
+    let mut content = Vec::new();
+    File::open(path)?
+        .take(MAX_CONFIG_LEN as u64 + 1)
+        .read_to_end(&mut content)?;
+    Self::parse_bytes(&content)
+  }
+
+  /// Function to parse the raw content of a WireGuard configuration file,
+  /// e.g. as imported from an untrusted source.
+  ///
+  /// Any input yields either a configuration or an error: the content must
+  /// be valid UTF-8 and is then parsed by `parse_str`.
+  ///
+  /// # Arguments
+  /// * `content`: The content of the WireGuard configuration file.
+  ///
+  /// # Returns
+  /// * `Result<WgConfig, WgParseError>`: The configuration, or the error
+  ///   raised because the content is too large, not UTF-8 or invalid.
+  pub fn parse_bytes(content: &[u8]) -> Result<WgConfig, WgParseError> {
+    if content.len() > MAX_CONFIG_LEN {
+      return Err(WgParseError::TooLarge(MAX_CONFIG_LEN));
+    }
+    Self::parse_str(std::str::from_utf8(content).map_err(|_| WgParseError::NotUtf8)?)
+  }
+
+  /// Function to parse the content of a WireGuard configuration file.
+  ///
+  /// The format follows `wg-quick`: section names and keys are matched
+  /// case-insensitively, `#` starts a comment, list values are separated by
+  /// commas and may be repeated over several lines. Keys used by `wg-quick`
+  /// only (e.g. `PostUp`, `Table`) and unknown sections are ignored, while the
+  /// AmneziaWG obfuscation parameters are read into `Interface::obfuscation`.
+  ///
+  /// The content is limited to 1 MiB and its lines to 4 KiB, and must not
+  /// hold NUL bytes, which `wg-quick` cannot read either.
+  ///
+  /// # Arguments
+  /// * `content`: The content of the WireGuard configuration file.
+  ///
+  /// # Returns
+  /// * `Result<WgConfig, WgParseError>`: The configuration, or the error
+  ///   pointing at the offending line.
+  pub fn parse_str(content: &str) -> Result<WgConfig, WgParseError> {
+    if content.len() > MAX_CONFIG_LEN {
+      return Err(WgParseError::TooLarge(MAX_CONFIG_LEN));
+    }
+    let mut interface: Option<Interface> = None;
+    let mut peers: Vec<Peer> = Vec::new();
+    let mut section = Section::None;
+
+    for (index, raw) in content.lines().enumerate() {
+      let line = index + 1;
+      if raw.len() > MAX_LINE_LEN {
+        return Err(syntax(line, &format!("line longer than {MAX_LINE_LEN} bytes")));
+      }
+      if raw.contains('\0') {
+        return Err(syntax(line, "unexpected NUL byte"));
+      }
+      let text = raw.split('#').next().unwrap_or_default().trim();
+      if text.is_empty() {
+        continue;
+      }
+
+      if let Some(name) = text.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        section = match name.trim().to_ascii_lowercase().as_str() {
+          "interface" if interface.is_some() => {
+            return Err(syntax(line, "duplicate [Interface] section"));
+          }
+          "interface" => {
+            interface = Some(Interface::default());
+            Section::Interface
+          }
+          "peer" => {
+            peers.push(Peer::default());
+            Section::Peer
+          }
+          _ => Section::Other,
+        };
+        continue;
+      }
+
+      let Some((key, value)) = text.split_once('=') else {
+        return Err(syntax(line, "expected `Key = Value`"));
+      };
+      let key = key.trim().to_ascii_lowercase();
+      let value = value.trim();
+
+      match section {
+        Section::None => return Err(syntax(line, "entry outside of a section")),
+        Section::Other => {}
+        Section::Interface => {
+          let interface = interface.as_mut().expect("[Interface] section started");
+          match key.as_str() {
+            "privatekey" => interface.private_key = parse_key(line, "PrivateKey", value)?,
+            "address" => interface.addresses.extend(split_list(value)),
+            "dns" => interface.dns.extend(parse_dns(line, value)?),
+            "listenport" => interface.listen_port = Some(parse_number(line, "ListenPort", value)?),
+            "mtu" => interface.mtu = Some(parse_number(line, "MTU", value)?),
+            "saveconfig" => interface.save_config = parse_bool(line, "SaveConfig", value)?,
+            "jc" => interface.obfuscation.junk_count = Some(parse_number(line, "Jc", value)?),
+            "jmin" => interface.obfuscation.junk_min = Some(parse_number(line, "Jmin", value)?),
+            "jmax" => interface.obfuscation.junk_max = Some(parse_number(line, "Jmax", value)?),
+            "s1" => interface.obfuscation.init_padding = Some(parse_number(line, "S1", value)?),
+            "s2" => interface.obfuscation.response_padding = Some(parse_number(line, "S2", value)?),
+            "h1" => interface.obfuscation.headers[0] = Some(parse_number(line, "H1", value)?),
+            "h2" => interface.obfuscation.headers[1] = Some(parse_number(line, "H2", value)?),
+            "h3" => interface.obfuscation.headers[2] = Some(parse_number(line, "H3", value)?),
+            "h4" => interface.obfuscation.headers[3] = Some(parse_number(line, "H4", value)?),
+            _ => {}
+          }
+        }
+        Section::Peer => {
+          let peer = peers.last_mut().expect("[Peer] section started");
+          match key.as_str() {
+            "publickey" => peer.public_key = parse_key(line, "PublicKey", value)?,
+            "presharedkey" => peer.preshared_key = Some(parse_key(line, "PresharedKey", value)?),
+            "endpoint" => peer.endpoint = Some(value.to_string()),
+            "allowedips" => peer.allowed_ips.extend(split_list(value)),
+            "persistentkeepalive" if value.eq_ignore_ascii_case("off") => {
+              peer.persistent_keepalive = None
+            }
+            "persistentkeepalive" => {
+              peer.persistent_keepalive = Some(parse_number(line, "PersistentKeepalive", value)?)
+            }
+            _ => {}
+          }
+        }
+      }
+    }
+
+    let interface = interface.ok_or(WgParseError::MissingInterface)?;
+    if interface.private_key.is_empty() {
+      return Err(WgParseError::MissingField {
+        section: "Interface",
+        field: "PrivateKey",
+      });
+    }
+    if peers.iter().any(|peer| peer.public_key.is_empty()) {
+      return Err(WgParseError::MissingField {
+        section: "Peer",
+        field: "PublicKey",
+      });
+    }
+    Ok(WgConfig { interface, peers })
+  }
+
+  /// Function to replace the `@<file>` items of the `AllowedIPs` of the
+  /// peers with the networks listed in the set files.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the WireGuard configuration file, whose directory
+  ///   relative set files are resolved against.
+  ///
+  /// # Returns
+  /// * `Result<bool, WgParseError>`: Whether a set file was expanded, or the
+  ///   error raised reading a set file or pointing at its invalid line.
+  pub fn expand_allowed_ip_sets(&mut self, path: &Path) -> Result<bool, WgParseError> {
+    let mut expanded = false;
+    for peer in &mut self.peers {
+      if peer.allowed_ips.iter().any(|item| item.starts_with(SET_FILE_PREFIX)) {
+        peer.allowed_ips = expand_allowed_ips(&peer.allowed_ips, path)?;
+        expanded = true;
+      }
+    }
+    Ok(expanded)
+  }
+
+  /// Function to write the configuration in the format of `wg-quick`.
+  ///
+  /// Parsing the output gives back the same configuration. The entries
+  /// ignored by `parse_str` (e.g. `PostUp`, comments) are not part of it. The
+  /// output holds the private and preshared keys: it must never be logged.
+  ///
+  /// # Returns
+  /// * `Zeroizing<String>`: The content of the configuration file, wiped
+  ///   from memory when dropped.
+  pub fn serialize(&self) -> Zeroizing<String> {
+    let mut out = Zeroizing::new(String::new());
+    let interface = &self.interface;
+    let obfuscation = &interface.obfuscation;
+    let mut entry = |key: &str, value: &str| {
+      out.push_str(key);
+      out.push_str(" = ");
+      out.push_str(value);
+      out.push('\n');
+    };
+
+    entry("[Interface]\nPrivateKey", &interface.private_key);
+    let list_entries = [("Address", &interface.addresses), ("DNS", &interface.dns)];
+    for (key, values) in list_entries.into_iter().filter(|(_, values)| !values.is_empty()) {
+      entry(key, &values.join(", "));
+    }
+    if let Some(port) = interface.listen_port {
+      entry("ListenPort", &port.to_string());
+    }
+    if let Some(mtu) = interface.mtu {
+      entry("MTU", &mtu.to_string());
+    }
+    if interface.save_config {
+      entry("SaveConfig", "true");
+    }
+    let junk = [
+      ("Jc", obfuscation.junk_count),
+      ("Jmin", obfuscation.junk_min),
+      ("Jmax", obfuscation.junk_max),
+      ("S1", obfuscation.init_padding),
+      ("S2", obfuscation.response_padding),
+    ];
+    for (key, value) in junk {
+      if let Some(value) = value {
+        entry(key, &value.to_string());
+      }
+    }
+    for (key, value) in ["H1", "H2", "H3", "H4"].into_iter().zip(obfuscation.headers) {
+      if let Some(value) = value {
+        entry(key, &value.to_string());
+      }
+    }
+
+    for peer in &self.peers {
+      entry("\n[Peer]\nPublicKey", &peer.public_key);
+      if let Some(preshared_key) = &peer.preshared_key {
+        entry("PresharedKey", preshared_key);
+      }
+      if let Some(endpoint) = &peer.endpoint {
+        entry("Endpoint", endpoint);
+      }
+      if !peer.allowed_ips.is_empty() {
+        entry("AllowedIPs", &peer.allowed_ips.join(", "));
+      }
+      if let Some(keepalive) = peer.persistent_keepalive {
+        entry("PersistentKeepalive", &keepalive.to_string());
+      }
+    }
+    out
+  }
+}
+
+/// Function to remove the `SaveConfig` entries of a WireGuard configuration
+/// file, so that `wg-quick down` no longer overwrites it.
+///
+/// The rest of the file is kept byte for byte.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<bool, WgParseError>`: Whether entries were removed, or the
+///   error raised reading or writing the file.
+pub fn strip_save_config(path: &Path) -> Result<bool, WgParseError> {
+  let content = read_config_file(path)?;
+  let Some(stripped) = strip_interface_entries_str(&content, "SaveConfig") else {
+    return Ok(false);
+  };
+  replace_config_file(path, &stripped)?;
+  Ok(true)
+}
+
+/// Function to set the `Address` of a WireGuard configuration file to the
+/// next free address of a pool.
+///
+/// The entry is added right after the `[Interface]` header, the rest of the
+/// file being kept byte for byte. The address has the prefix length of the
+/// pool, so that the tunnel routes the whole pool.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+/// * `pool`: The pool the address is picked from.
+/// * `used`: The addresses used by the other tunnels.
+///
+/// # Returns
+/// * `Result<String, WgError>`: The assigned address, with its prefix length,
+///   or the error raised because the file is missing, invalid or sets an
+///   address already, the pool is exhausted or while writing the file.
+pub fn assign_address(path: &Path, pool: &AddressPool, used: &[IpAddr]) -> Result<String, WgError> {
+  check_config(path)?;
+  let content = Zeroizing::new(read_config_file(path)?);
+  let config = WgConfig::parse_str(&content)?;
+  if !config.interface.addresses.is_empty() {
+    return Err(WgError::AddressAlreadySet(path.to_path_buf()));
+  }
+  let address = pool.next_free(used).ok_or(WgError::PoolExhausted(*pool))?;
+  let address = format!("{address}/{}", pool.prefix);
+  let assigned = insert_address_str(&content, &address).ok_or(WgParseError::MissingField {
+    section: "Interface",
+    field: "PrivateKey",
+  })?;
+  replace_config_file(path, &assigned).map_err(WgParseError::from)?;
+  Ok(address)
+}
+
+/// Function to retrieve the addresses of the `[Interface]` section of a
+/// WireGuard configuration file, e.g. to feed `AddressPool::next_free`.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<Vec<IpAddr>, WgError>`: The addresses, without their prefix
+///   length, or the error raised reading or parsing the file.
+pub fn interface_addresses(path: &Path) -> Result<Vec<IpAddr>, WgError> {
+  let config = WgConfig::parse(path)?;
+  Ok(config.interface.addresses.iter().filter_map(|address| split_network(address)).map(|(address, _)| address).collect())
+}
+
+/// Function to replace the private key of a WireGuard configuration file
+/// with a newly generated one.
+///
+/// The file is first copied to `<file>.bak`, replacing an older backup, and
+/// the rest of it is kept byte for byte. The tunnel, if up, keeps using the
+/// old key until it is brought up again.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<(PublicKey, PathBuf), WgError>`: The new public key, to be
+///   installed on the peers, and the path of the backup, or the error raised
+///   because the file is missing or invalid or while writing it.
+pub fn rotate_private_key(path: &Path) -> Result<(PublicKey, PathBuf), WgError> {
+  check_config(path)?;
+  let content = read_config_file(path)?;
+  WgConfig::parse_str(&content)?;
+  let (private, public) = generate_keypair();
+  let rotated = replace_private_key_str(&content, private.as_str()).ok_or(WgParseError::MissingField {
+    section: "Interface",
+    field: "PrivateKey",
+  })?;
+
+  let mut backup_name = path.file_name().unwrap_or_default().to_os_string();
+  backup_name.push(".bak");
+  let backup = path.with_file_name(backup_name);
+  fs::copy(path, &backup).map_err(WgParseError::from)?;
+  replace_config_file(path, &rotated).map_err(WgParseError::from)?;
+  Ok((public, backup))
+}
+
+/// Function to read a WireGuard configuration file to be shown, decrypting
+/// it with the configured command when encrypted (see `Decryptors`).
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+/// * `show_secrets`: Whether the private and preshared keys are kept.
+///
+/// # Returns
+/// * `Result<Zeroizing<String>, WgError>`: The content, its keys replaced
+///   by `<redacted>` unless `show_secrets` is set, or the error raised
+///   because the file is missing or cannot be read or decrypted.
+pub fn read_config_text(path: &Path, show_secrets: bool) -> Result<Zeroizing<String>, WgError> {
+  check_config(path)?;
+  let (content, _) = read_plaintext(&*system_runner(), path, DECRYPTORS.get())?;
+  Ok(if show_secrets { content } else { Zeroizing::new(redact_secrets_str(&content)) })
+}
+
+/// Function to edit a WireGuard configuration file with an editor, keeping
+/// the changes only when the edited file is still a valid configuration.
+///
+/// The editor is given a private copy, so that the original is replaced
+/// once checked, with its permissions, and never left half written or
+/// invalid. The copy is wiped afterwards. Encrypted files are refused, since
+/// the copy would not be encrypted again.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+/// * `editor`: The editor command, run by `sh` with the copy appended, as
+///   in `$EDITOR`.
+///
+/// # Returns
+/// * `Result<bool, WgError>`: Whether the file changed, or the error raised
+///   because it is missing or encrypted, the editor failed or the edited
+///   file is invalid, in which case the file is left as it was.
+pub fn edit_config(path: &Path, editor: &str) -> Result<bool, WgError> {
+  check_config(path)?;
+  let content = Zeroizing::new(read_config_bytes(path)?);
+  if Encryption::detect(&content).is_some() {
+    return Err(WgError::EncryptedEdit(path.to_path_buf()));
+  }
+  let original = Zeroizing::new(String::from_utf8(content.to_vec()).map_err(|_| WgParseError::NotUtf8)?);
+  let copy = ExpandedConfig::private_copy(path.file_name().unwrap_or_default(), &original)?;
+  let editor_error = |reason: String| WgError::Editor {
+    path: path.to_path_buf(),
+    editor: editor.to_string(),
+    reason,
+  };
+  // The editor takes over the terminal, so it is not run by a CommandRunner
+  let status = Command::new("sh")
+      .arg("-c")
+      .arg(format!("{editor} \"$1\""))
+      .arg("sh")
+      .arg(&copy.path)
+      .status()
+      .map_err(|e| editor_error(e.to_string()))?;
+  if !status.success() {
+    return Err(editor_error(status.to_string()));
+  }
+  let edited = Zeroizing::new(read_config_file(&copy.path)?);
+  if *edited == *original {
+    return Ok(false);
+  }
+  WgConfig::parse_str(&edited).map_err(|source| WgError::InvalidEdit {
+    path: path.to_path_buf(),
+    source,
+  })?;
+  replace_config_file(path, &edited).map_err(WgParseError::from)?;
+  Ok(true)
+}
+
+/// Function to replace the values of the private and preshared keys of a
+/// WireGuard configuration by `<redacted>`, e.g. to show the file.
+///
+/// # Arguments
+/// * `content`: The content of the WireGuard configuration file.
+///
+/// # Returns
+/// * `String`: The content, its other lines untouched.
+fn redact_secrets_str(content: &str) -> String {
+  let mut redacted = String::with_capacity(content.len());
+  for raw in content.split_inclusive('\n') {
+    let line = raw.trim_end_matches(['\r', '\n']);
+    match line.split_once('=') {
+      Some((key, _)) if ["PrivateKey", "PresharedKey"].iter().any(|secret| key.trim().eq_ignore_ascii_case(secret)) => {
+        redacted.push_str(key.trim_end());
+        redacted.push_str(" = <redacted>");
+        redacted.push_str(&raw[line.len()..]);
+      }
+      _ => redacted.push_str(raw),
+    }
+  }
+  redacted
+}
+
+/// Function to read a WireGuard configuration file, bounded by
+/// `MAX_CONFIG_LEN`.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<String, WgParseError>`: The content of the file, or the error
+///   raised reading it or because it is too large or not UTF-8.
+fn read_config_file(path: &Path) -> Result<String, WgParseError> {
+  String::from_utf8(read_config_bytes(path)?).map_err(|_| WgParseError::NotUtf8)
+}
+
+/// Function to read the raw content of a WireGuard configuration file,
+/// bounded by `MAX_CONFIG_LEN`.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<Vec<u8>, WgParseError>`: The content of the file, or the error
+///   raised reading it or because it is too large.
+fn read_config_bytes(path: &Path) -> Result<Vec<u8>, WgParseError> {
+  let mut content = Vec::new();
+  File::open(path)?
+      .take(MAX_CONFIG_LEN as u64 + 1)
+      .read_to_end(&mut content)?;
+  if content.len() > MAX_CONFIG_LEN {
+    return Err(WgParseError::TooLarge(MAX_CONFIG_LEN));
+  }
+  Ok(content)
+}
+
+/// Function to read a WireGuard configuration file, decrypted through the
+/// given runner when it is encrypted with age or sops.
+///
+/// The plaintext is only held in memory, wiped when dropped. Decrypting is
+/// refused when no command is configured for the tool, and for a remote
+/// host, since the plaintext would have to be copied there.
+///
+/// # Arguments
+/// * `runner`: The runner of the decrypting command.
+/// * `path`: The path to the WireGuard configuration file.
+/// * `decryptors`: The commands decrypting the files, if any.
+///
+/// # Returns
+/// * `Result<(Zeroizing<String>, Option<Encryption>), WgError>`: The
+///   plaintext and the tool it was encrypted with, if any, or the error
+///   raised reading or decrypting the file.
+fn read_plaintext(
+  runner: &dyn CommandRunner,
+  path: &Path,
+  decryptors: Option<&Decryptors>,
+) -> Result<(Zeroizing<String>, Option<Encryption>), WgError> {
+  let content = Zeroizing::new(read_config_bytes(path)?);
+  let Some(encryption) = Encryption::detect(&content) else {
+    let content = String::from_utf8(content.to_vec()).map_err(|_| WgParseError::NotUtf8)?;
+    return Ok((Zeroizing::new(content), None));
+  };
+  let Some(command) = decryptors.and_then(|decryptors| encryption.command(decryptors)) else {
+    return Err(WgError::Encrypted {
+      path: path.to_path_buf(),
+      encryption,
+    });
+  };
+  if REMOTE.get().is_some() {
+    return Err(WgError::RemoteEncrypted(path.to_path_buf()));
+  }
+  let decrypt_error = |reason: String| WgError::Decrypt {
+    path: path.to_path_buf(),
+    command: command.to_string(),
+    reason,
+  };
+  let mut words = command.split_whitespace();
+  let program = words.next().ok_or_else(|| decrypt_error("the command is empty".to_string()))?;
+  let args: Vec<&OsStr> = words.map(OsStr::new).chain([path.as_os_str()]).collect();
+  wgb_debug!("Decrypting {} with {command}", path.display());
+  let output = runner.run(program, &args).map_err(|e| match e.kind() {
+    io::ErrorKind::NotFound => decrypt_error(format!("{program} not found")),
+    io::ErrorKind::TimedOut => WgError::Timeout(command.to_string()),
+    _ => decrypt_error(e.to_string()),
+  })?;
+  let mut stdout = Zeroizing::new(output.stdout);
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or_default();
+    return Err(decrypt_error(format!("{} ({reason})", output.status)));
+  }
+  if stdout.len() > MAX_CONFIG_LEN {
+    return Err(WgParseError::TooLarge(MAX_CONFIG_LEN).into());
+  }
+  let plaintext = String::from_utf8(std::mem::take(&mut *stdout)).map_err(|_| WgParseError::NotUtf8)?;
+  Ok((Zeroizing::new(plaintext), Some(encryption)))
+}
+
+/// Function to replace the content of a WireGuard configuration file.
+///
+/// The content is written to a temporary file renamed over the original.
+/// Since it holds the private key, the temporary file is created readable
+/// by its owner only, given the permissions of the original once written,
+/// and wiped when anything fails before the rename.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+/// * `content`: The new content of the file.
+///
+/// # Returns
+/// * `io::Result<()>`: The error, if any, raised writing the file.
+fn replace_config_file(path: &Path, content: &str) -> io::Result<()> {
+  let permissions = fs::metadata(path)?.permissions();
+  let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+  temp_name.push(format!(".{}.tmp", std::process::id()));
+  let temp_path = path.with_file_name(temp_name);
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  // Opened first, so that the guard never wipes a file it did not create
+  let mut file = options.open(&temp_path)?;
+  let temp = WipeGuard::new(temp_path);
+  file.write_all(content.as_bytes())?;
+  file.sync_all()?;
+  file.set_permissions(permissions)?;
+  fs::rename(temp.path(), path)?;
+  temp.disarm();
+  Ok(())
+}
+
+/// Define a copy of a WireGuard configuration file whose `AllowedIPs` set
+/// files are expanded, since `wg-quick` cannot read them, and whose endpoint
+/// hostnames are resolved when an address family is preferred, or which is
+/// the plaintext of an encrypted file. The copy, holding the private key, is
+/// wiped along with its private directory when dropped.
+#[derive(Debug)]
+struct ExpandedConfig {
+  /// The private directory holding the copy.
+  dir: PathBuf,
+  /// The copy, named as the original so that the interface name is kept.
+  path: PathBuf,
+}
+
+/// Implements the logic of the expanded copy of a configuration file
+impl ExpandedConfig {
+  /// Function to write the expanded copy of a configuration file, if it
+  /// uses set files, has endpoint hostnames to be resolved or is forced.
+  ///
+  /// Endpoints are resolved locally only: a remote host resolves them.
+  ///
+  /// # Arguments
+  /// * `path`: The path to the WireGuard configuration file.
+  /// * `content`: The content of the WireGuard configuration file, i.e. the
+  ///   plaintext of an encrypted one.
+  /// * `forced`: Whether the copy is written even when nothing is to be
+  ///   expanded, e.g. since the content was decrypted.
+  ///
+  /// # Returns
+  /// * `Result<Option<ExpandedConfig>, WgError>`: The copy, `None` when
+  ///   nothing is to be expanded, or the error raised reading a set file,
+  ///   resolving an endpoint or writing the copy.
+  fn write(path: &Path, content: &str, forced: bool) -> Result<Option<Self>, WgError> {
+    // `wg-quick` names the interface after the file, so a renamed one needs
+    // a copy named after its interface
+    let renamed = renamed_interface(path);
+    if let (Some(name), Some(_)) = (renamed, REMOTE.get()) {
+      return Err(WgError::RemoteInterfaceName {
+        path: path.to_path_buf(),
+        name: name.to_string(),
+      });
+    }
+    let forced = forced || renamed.is_some();
+    let sets = expand_allowed_ip_sets_str(content, path)?;
+    if sets.is_some() && REMOTE.get().is_some() {
+      return Err(WgError::RemoteSetFiles(path.to_path_buf()));
+    }
+    let endpoints = match (ADDRESS_FAMILY.get(), REMOTE.get()) {
+      (Some(family), None) => {
+        resolve_endpoints_str(sets.as_deref().map_or(content, String::as_str), *family, &SystemResolver)?
+      }
+      _ => None,
+    };
+    let Some(expanded) = endpoints.or(sets).or_else(|| forced.then(|| Zeroizing::new(content.to_string()))) else {
+      return Ok(None);
+    };
+    let name = renamed.map_or_else(|| path.file_name().unwrap_or_default().to_os_string(), |name| format!("{name}.conf").into());
+    Self::private_copy(&name, &expanded).map(Some)
+  }
+
+  /// Function to write a copy of a configuration file into a private
+  /// directory of its own, readable by the owner only.
+  ///
+  /// # Arguments
+  /// * `name`: The file name of the copy.
+  /// * `content`: The content of the copy.
+  ///
+  /// # Returns
+  /// * `Result<ExpandedConfig, WgError>`: The copy, or the error raised
+  ///   writing it.
+  fn private_copy(name: &OsStr, content: &str) -> Result<Self, WgError> {
+    let mut random = [0u8; 8];
+    OsRng.fill_bytes(&mut random);
+    let suffix: String = random.iter().map(|byte| format!("{byte:02x}")).collect();
+    let dir = std::env::temp_dir().join(format!("wgb-{suffix}"));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir).map_err(WgParseError::from)?;
+
+    // The guard removes the directory even when writing the copy fails
+    let copy = ExpandedConfig {
+      path: dir.join(name),
+      dir,
+    };
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&copy.path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(WgParseError::from)?;
+    Ok(copy)
+  }
+}
+
+impl Drop for ExpandedConfig {
+  fn drop(&mut self) {
+    if self.path.exists() {
+      let _ = wipe_file(&self.path);
+    }
+    let _ = fs::remove_dir(&self.dir);
+  }
+}
+
+/// Function to resolve the endpoint hostnames of a WireGuard configuration
+/// to an address of the preferred family.
+///
+/// # Arguments
+/// * `content`: The content of the WireGuard configuration file.
+/// * `family`: The preferred address family.
+/// * `resolver`: The resolver of the hostnames.
+///
+/// # Returns
+/// * `Result<Option<Zeroizing<String>>, WgError>`: The content with the
+///   hostnames replaced by addresses, its other lines untouched, `None` when
+///   every endpoint is an address already, or the error raised because a
+///   hostname cannot be resolved or has no address of the allowed families.
+fn resolve_endpoints_str(
+  content: &str,
+  family: AddressFamily,
+  resolver: &dyn Resolver,
+) -> Result<Option<Zeroizing<String>>, WgError> {
+  let mut resolved = Zeroizing::new(String::with_capacity(content.len()));
+  let mut section = Section::None;
+  let mut found = false;
+  for raw in content.split_inclusive('\n') {
+    let text = raw.split('#').next().unwrap_or_default().trim();
+    if let Some(name) = text.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+      section = match name.trim().to_ascii_lowercase().as_str() {
+        "interface" => Section::Interface,
+        "peer" => Section::Peer,
+        _ => Section::Other,
+      };
+    } else if section == Section::Peer
+      && let Some((key, value)) = text.split_once('=')
+      && key.trim().eq_ignore_ascii_case("endpoint")
+      && let Some((host, port)) = split_endpoint(value.trim())
+      && host.parse::<IpAddr>().is_err()
+    {
+      let endpoint = value.trim().to_string();
+      let addresses = resolver.resolve(host, port).map_err(|source| WgError::Resolve {
+        endpoint: endpoint.clone(),
+        source,
+      })?;
+      let address = family.choose(&addresses).ok_or(WgError::NoAddress { endpoint, family })?;
+      resolved.push_str(&format!("Endpoint = {}\n", SocketAddr::new(address, port)));
+      found = true;
+      continue;
+    }
+    resolved.push_str(raw);
+  }
+  Ok(found.then_some(resolved))
+}
+
+/// Function to split an endpoint into its host and port.
+///
+/// # Arguments
+/// * `endpoint`: The endpoint, as `host:port` or `[IPv6]:port`.
+///
+/// # Returns
+/// * `Option<(&str, u16)>`: The host, without brackets, and the port, or
+///   `None` when it is not a valid endpoint.
+pub fn split_endpoint(endpoint: &str) -> Option<(&str, u16)> {
+  let (host, port) = endpoint.rsplit_once(':')?;
+  let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+  Some((host, port.parse().ok()?)).filter(|(host, _)| !host.is_empty())
+}
+
+/// Function to expand the `@<file>` items of a list of allowed IPs.
+///
+/// # Arguments
+/// * `allowed_ips`: The allowed IPs of a peer.
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<Vec<String>, WgParseError>`: The allowed IPs, each set file
+///   replaced by its networks in place, or the error raised reading a set
+///   file or pointing at its invalid line.
+fn expand_allowed_ips(allowed_ips: &[String], path: &Path) -> Result<Vec<String>, WgParseError> {
+  let mut expanded = Vec::with_capacity(allowed_ips.len());
+  for item in allowed_ips {
+    match item.strip_prefix(SET_FILE_PREFIX) {
+      Some(set_file) => {
+        let base = path.parent().unwrap_or(Path::new(""));
+        expanded.extend(read_set_file(&base.join(set_file.trim()))?);
+      }
+      None => expanded.push(item.clone()),
+    }
+  }
+  Ok(expanded)
+}
+
+/// Function to read a set file of networks, one per line. `#` starts a
+/// comment and blank lines are skipped.
+///
+/// # Arguments
+/// * `path`: The path to the set file.
+///
+/// # Returns
+/// * `Result<Vec<String>, WgParseError>`: The networks, or the error raised
+///   reading the file or pointing at the line which is not a valid network.
+fn read_set_file(path: &Path) -> Result<Vec<String>, WgParseError> {
+  let set_error = |source| WgParseError::SetFile {
+    path: path.to_path_buf(),
+    source,
+  };
+  let mut content = String::new();
+  File::open(path)
+      .and_then(|file| file.take(MAX_CONFIG_LEN as u64 + 1).read_to_string(&mut content))
+      .map_err(set_error)?;
+  if content.len() > MAX_CONFIG_LEN {
+    return Err(set_error(io::Error::other(format!("larger than {MAX_CONFIG_LEN} bytes"))));
+  }
+
+  let mut networks = Vec::new();
+  for (index, raw) in content.lines().enumerate() {
+    let entry = raw.split('#').next().unwrap_or_default().trim();
+    if entry.is_empty() {
+      continue;
+    }
+    if split_network(entry).is_none() {
+      return Err(WgParseError::InvalidSetEntry {
+        path: path.to_path_buf(),
+        line: index + 1,
+        entry: entry.to_string(),
+      });
+    }
+    networks.push(entry.to_string());
+  }
+  Ok(networks)
+}
+
+/// Function to expand the `AllowedIPs` set files of a WireGuard
+/// configuration, each network being written on an `AllowedIPs` line of its
+/// own.
+///
+/// # Arguments
+/// * `content`: The content of the WireGuard configuration file.
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<Option<Zeroizing<String>>, WgParseError>`: The content with the
+///   set files expanded, its other lines untouched, `None` when no set file
+///   is used, or the error raised reading a set file.
+fn expand_allowed_ip_sets_str(content: &str, path: &Path) -> Result<Option<Zeroizing<String>>, WgParseError> {
+  let mut expanded = Zeroizing::new(String::with_capacity(content.len()));
+  let mut section = Section::None;
+  let mut found = false;
+  for raw in content.split_inclusive('\n') {
+    let text = raw.split('#').next().unwrap_or_default().trim();
+    if let Some(name) = text.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+      section = match name.trim().to_ascii_lowercase().as_str() {
+        "interface" => Section::Interface,
+        "peer" => Section::Peer,
+        _ => Section::Other,
+      };
+    } else if section == Section::Peer
+      && let Some((key, value)) = text.split_once('=')
+      && key.trim().eq_ignore_ascii_case("allowedips")
+      && value.contains(SET_FILE_PREFIX)
+    {
+      for network in expand_allowed_ips(&split_list(value).collect::<Vec<_>>(), path)? {
+        expanded.push_str(&format!("AllowedIPs = {network}\n"));
+      }
+      found = true;
+      continue;
+    }
+    expanded.push_str(raw);
+  }
+  Ok(found.then_some(expanded))
+}
+
+/// Function to replace the value of the `PrivateKey` entries of the
+/// `[Interface]` section of a WireGuard configuration.
+///
+/// # Arguments
+/// * `content`: The content of the WireGuard configuration file.
+/// * `private_key`: The base64 private key to be set.
+///
+/// # Returns
+/// * `Option<Zeroizing<String>>`: The content with the new key, its other
+///   bytes untouched, or `None` when there was no entry.
+fn replace_private_key_str(content: &str, private_key: &str) -> Option<Zeroizing<String>> {
+  let mut replaced = Zeroizing::new(String::with_capacity(content.len()));
+  let mut section = Section::None;
+  let mut found = false;
+  for raw in content.split_inclusive('\n') {
+    let text = raw.split('#').next().unwrap_or_default().trim();
+    if let Some(name) = text.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+      section = match name.trim().to_ascii_lowercase().as_str() {
+        "interface" => Section::Interface,
+        "peer" => Section::Peer,
+        _ => Section::Other,
+      };
+    } else if section == Section::Interface
+      && let Some((key, value)) = text.split_once('=')
+      && key.trim().eq_ignore_ascii_case("privatekey")
+      && let Some(equals) = raw.find('=')
+      && let Some(offset) = raw[equals..].find(value.trim())
+    {
+      let start = equals + offset;
+      replaced.push_str(&raw[..start]);
+      replaced.push_str(private_key);
+      replaced.push_str(&raw[start + value.trim().len()..]);
+      found = true;
+      continue;
+    }
+    replaced.push_str(raw);
+  }
+  found.then_some(replaced)
+}
+
+/// Function to add an `Address` entry right after the `[Interface]` header
+/// of a WireGuard configuration.
+///
+/// # Arguments
+/// * `content`: The content of the WireGuard configuration file.
+/// * `address`: The address to be set, with its prefix length.
+///
+/// # Returns
+/// * `Option<Zeroizing<String>>`: The content with the entry, its other
+///   bytes untouched, or `None` when there is no `[Interface]` section.
+fn insert_address_str(content: &str, address: &str) -> Option<Zeroizing<String>> {
+  let mut inserted = Zeroizing::new(String::with_capacity(content.len() + address.len() + 11));
+  let mut found = false;
+  for raw in content.split_inclusive('\n') {
+    inserted.push_str(raw);
+    let text = raw.split('#').next().unwrap_or_default().trim();
+    if !found
+      && let Some(name) = text.strip_prefix('[').and_then(|rest| rest.strip_suffix(']'))
+      && name.trim().eq_ignore_ascii_case("interface")
+    {
+      if !raw.ends_with('\n') {
+        inserted.push('\n');
+      }
+      inserted.push_str(&format!("Address = {address}\n"));
+      found = true;
+    }
+  }
+  found.then_some(inserted)
+}
+
+/// Function to remove the entries with the given key of the `[Interface]`
+/// section of a WireGuard configuration, e.g. `SaveConfig`.
+///
+/// # Arguments
+/// * `content`: The content of the WireGuard configuration file.
+/// * `key`: The key of the entries, matched regardless of case.
+///
+/// # Returns
+/// * `Option<String>`: The content without the entries, its other lines and
+///   line breaks untouched, or `None` when there were none.
+fn strip_interface_entries_str(content: &str, key: &str) -> Option<String> {
+  let mut stripped = String::with_capacity(content.len());
+  let mut section = Section::None;
+  let mut removed = false;
+  for raw in content.split_inclusive('\n') {
+    let text = raw.split('#').next().unwrap_or_default().trim();
+    if let Some(name) = text.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+      section = match name.trim().to_ascii_lowercase().as_str() {
+        "interface" => Section::Interface,
+        "peer" => Section::Peer,
+        _ => Section::Other,
+      };
+    } else if section == Section::Interface
+      && let Some((entry, _)) = text.split_once('=')
+      && entry.trim().eq_ignore_ascii_case(key)
+    {
+      removed = true;
+      continue;
+    }
+    stripped.push_str(raw);
+  }
+  removed.then_some(stripped)
+}
+
+/// Function to bring up the tunnel of a WireGuard configuration file, set
+/// the MTU of its interface when one is given and, when a timeout is given,
+/// wait for a handshake with one of its peers, bringing it down again when
+/// none takes place in time or the MTU cannot be set.
+///
+/// Commands managing the same tunnel wait for each other (see `TunnelLock`);
+/// the tunnel is locked while the handshake is waited for too.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+/// * `timeout`: The longest time a handshake is waited for, if any.
+/// * `mtu`: The MTU set on the interface, e.g. probed, in place of the one
+///   of `wg-quick`.
+///
+/// # Returns
+/// * `Result<(), WgError>`: The error, if any, raised because the file is
+///   missing or invalid, the privileges are insufficient, `wg-quick` or `ip`
+///   failed or for a `HandshakeTimeout`.
+pub fn bring_up(path: &Path, timeout: Option<Duration>, mtu: Option<u16>) -> Result<(), WgError> {
+  check_config(path)?;
+  Capabilities::detect().check(Operation::Connect)?;
+  if timeout.is_some() {
+    Capabilities::detect().check(Operation::Disconnect)?;
+  }
+  check_privileges()?;
+  let _lock = lock_tunnel(path)?;
+  let runner = system_runner();
+  bring_up_with(&*runner, path)?;
+  if let Some(mtu) = mtu
+    && let Err(e) = set_mtu_with(&*runner, path, mtu)
+  {
+    wgb_warn!("Bringing down {}, its MTU cannot be set", path.display());
+    let _ = bring_down_with(&*runner, path);
+    return Err(e);
+  }
+  match timeout {
+    Some(timeout) => await_handshake_with(&*runner, path, timeout, &Deadline::after(timeout, Arc::new(SystemClock)), HANDSHAKE_POLL),
+    None => Ok(()),
+  }
+}
+
+/// Function to set the MTU of the interface of a tunnel which is up, through
+/// the given runner.
+///
+/// # Arguments
+/// * `runner`: The runner of `ip`.
+/// * `path`: The path to the WireGuard configuration file.
+/// * `mtu`: The MTU.
+///
+/// # Returns
+/// * `Result<(), WgError>`: The error, if any, raised because `ip` failed.
+fn set_mtu_with(runner: &dyn CommandRunner, path: &Path, mtu: u16) -> Result<(), WgError> {
+  let interface = interface_name(path).unwrap_or_default();
+  let mtu = mtu.to_string();
+  let args = ["link", "set", "dev", interface, "mtu", &mtu].map(OsStr::new);
+  run(runner, IP, &args, Some(LogLevel::Info))?;
+  Ok(())
+}
+
+/// Function to wait for a handshake with a peer of a tunnel which was just
+/// brought up, through the given runner, bringing the tunnel down again once
+/// the deadline passes without one, so that no dead interface is left
+/// behind with its routes.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg` and `wg-quick`.
+/// * `path`: The path to the WireGuard configuration file.
+/// * `timeout`: The timeout the deadline was set from, for the error.
+/// * `deadline`: The time a handshake must take place by.
+/// * `poll`: The time between two reads of the handshakes.
+///
+/// # Returns
+/// * `Result<(), WgError>`: The error, if any, raised reading the state of
+///   the tunnel or bringing it down, or `HandshakeTimeout`.
+fn await_handshake_with(
+  runner: &dyn CommandRunner,
+  path: &Path,
+  timeout: Duration,
+  deadline: &Deadline,
+  poll: Duration,
+) -> Result<(), WgError> {
+  let interface = interface_name(path).unwrap_or_default();
+  loop {
+    let tunnels = status_with(runner)?;
+    let mut peers = tunnels.iter().filter(|tunnel| tunnel.interface == interface).flat_map(|tunnel| &tunnel.peers);
+    if peers.any(|peer| peer.latest_handshake.is_some()) {
+      return Ok(());
+    }
+    match deadline.remaining() {
+      Some(remaining) => thread::sleep(poll.min(remaining)),
+      None => break,
+    }
+  }
+  wgb_warn!("Bringing down {}, no handshake within {}s", path.display(), timeout.as_secs());
+  bring_down_with(runner, path)?;
+  Err(WgError::HandshakeTimeout {
+    path: path.to_path_buf(),
+    timeout,
+  })
+}
+
+/// Function to bring up a tunnel through the given runner.
+///
+/// The configuration is checked and parsed first, so that mistakes are
+/// reported with their line rather than by `wg-quick`. A warning is logged
+/// when it sets `SaveConfig`, which WG-Bridge does not expect, or AmneziaWG
+/// parameters, which `wg-quick` rejects. `AllowedIPs` set files are expanded,
+/// and endpoint hostnames resolved when an address family is preferred, into
+/// a private copy of the file given to `wg-quick up`, wiped afterwards;
+/// `wg-quick down` reads the routes from the interface, so it is given the
+/// original file. A file encrypted with age or sops is decrypted with the
+/// configured command into such a copy (see `Decryptors`). When a DNS backend
+/// is set, the `DNS` entries are left out of such a copy and given to the
+/// backend once the tunnel is up (see `DnsBackend`). The operation is
+/// recorded in the audit log, when enabled, before it takes place. When
+/// `wg-quick up` runs out of time, or the DNS cannot be set, the tunnel is
+/// brought down again, so that no half-applied routes are left behind.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg-quick`, of the decrypting command and of
+///   the DNS backend.
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<(), WgError>`: The error, if any, raised because the file is
+///   missing, invalid or cannot be decrypted, or `wg-quick` or the DNS
+///   backend failed.
+pub fn bring_up_with(runner: &dyn CommandRunner, path: &Path) -> Result<(), WgError> {
+  bring_up_with_dns(runner, path, DNS_BACKEND.get().copied())
+}
+
+/// Function to bring up a tunnel through the given runner, giving its DNS
+/// to the given backend (see `bring_up_with`).
+///
+/// # Arguments
+/// * `runner`: The runner of `wg-quick`, of the decrypting command and of
+///   the DNS backend.
+/// * `path`: The path to the WireGuard configuration file.
+/// * `dns_backend`: The backend given the DNS, `None` leaving it to
+///   `wg-quick`.
+///
+/// # Returns
+/// * `Result<(), WgError>`: The error, if any, raised because the file is
+///   missing, invalid or cannot be decrypted, or `wg-quick` or the DNS
+///   backend failed.
+fn bring_up_with_dns(runner: &dyn CommandRunner, path: &Path, dns_backend: Option<DnsBackend>) -> Result<(), WgError> {
+  check_config(path)?;
+  let (content, encryption) = read_plaintext(runner, path, DECRYPTORS.get())?;
+  let config = WgConfig::parse_str(&content)?;
+  if config.interface.save_config {
+    wgb_warn!("SaveConfig is set in {}, `wg-quick down` will overwrite it", path.display());
+  }
+  if config.interface.obfuscation.is_enabled() {
+    wgb_warn!("{} sets AmneziaWG parameters, which `wg-quick` does not support", path.display());
+  }
+  let dns_backend = match dns_backend {
+    Some(_) if config.interface.dns.is_empty() => None,
+    Some(_) if REMOTE.get().is_some() => return Err(WgError::RemoteDns(path.to_path_buf())),
+    Some(backend) => Some(backend.detect(runner)?),
+    None => None,
+  };
+  let search_domains = config.interface.search_domains();
+  if !search_domains.is_empty() {
+    let servers: Vec<String> = config.interface.dns_servers().iter().map(IpAddr::to_string).collect();
+    let setter = dns_backend.map_or("wg-quick sets".to_string(), |backend| format!("{backend} gets"));
+    wgb_info!(
+      "{setter} the DNS servers [{}] and the search domains [{}] of {}",
+      servers.join(", "),
+      search_domains.join(", "),
+      path.display()
+    );
+  }
+  // The backend sets the DNS in place of `wg-quick`, which is given a copy
+  // without the entries
+  let stripped = dns_backend.and_then(|_| strip_interface_entries_str(&content, "DNS").map(Zeroizing::new));
+  let up_content = stripped.as_deref().map_or(content.as_str(), String::as_str);
+  let expanded = ExpandedConfig::write(path, up_content, encryption.is_some() || stripped.is_some())?;
+  if let Some(audit) = AuditLog::get() {
+    audit.record("connect", &path.display().to_string())?;
+  }
+  let up_path = expanded.as_ref().map_or(path, |copy| copy.path.as_path());
+  let args = [OsStr::new("up"), up_path.as_os_str()];
+  if let Err(e) = run(runner, WG_QUICK, &args, Some(LogLevel::Info)) {
+    if matches!(e, WgError::Timeout(_)) {
+      // `wg-quick up` may have been killed halfway, with routes or DNS set
+      wgb_warn!("Bringing down {} after the timeout", path.display());
+      let copied = encryption.is_some() || stripped.is_some() || renamed_interface(path).is_some();
+      let down_path = if copied { up_path } else { path };
+      let _ = runner.run_cleanup(WG_QUICK, &[OsStr::new("down"), down_path.as_os_str()]);
+    }
+    return Err(e);
+  }
+  if let (Some(backend), Some(interface)) = (dns_backend, interface_name(path)) {
+    let commands = backend.apply_commands(interface, &config.interface.dns_servers(), &search_domains);
+    if let Err(e) = dns::run_all(runner, &commands) {
+      wgb_warn!("Bringing down {}, its DNS cannot be set", path.display());
+      let _ = runner.run_cleanup(WG_QUICK, &[OsStr::new("down"), up_path.as_os_str()]);
+      return Err(e.into());
+    }
+  }
+  if let Some(interface) = interface_name(path) {
+    track_session(interface, Some(path));
+  }
+  Ok(())
+}
+
+/// Function to bring down the tunnel of a WireGuard configuration file.
+///
+/// Commands managing the same tunnel wait for each other (see `TunnelLock`).
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<Teardown, WgError>`: Whether the tunnel was brought down or was
+///   not active, or the error raised because the file is missing, the
+///   privileges are insufficient or `wg-quick` failed.
+pub fn bring_down(path: &Path) -> Result<Teardown, WgError> {
+  check_config(path)?;
+  Capabilities::detect().check(Operation::Disconnect)?;
+  check_privileges()?;
+  let _lock = lock_tunnel(path)?;
+  bring_down_with(&*system_runner(), path)
+}
+
+/// Function to bring down a tunnel through the given runner.
+///
+/// Bringing down a tunnel which is not active is not an error, so that the
+/// operation can be repeated safely. The operation is recorded in the audit
+/// log, when enabled, before it takes place.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg` and `wg-quick`.
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<Teardown, WgError>`: Whether the tunnel was brought down or was
+///   not active, or the error raised because the file is missing or `wg` or
+///   `wg-quick` failed.
+pub fn bring_down_with(runner: &dyn CommandRunner, path: &Path) -> Result<Teardown, WgError> {
+  check_config(path)?;
+  let active = active_interfaces_with(runner)?;
+  bring_down_if_active(runner, path, &active)
+}
+
+/// Function to bring an active tunnel down and up again, e.g. once the
+/// network it runs over changed.
+///
+/// The tunnel is locked for both steps (see `TunnelLock`).
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<bool, WgError>`: Whether the tunnel was reconnected, `false`
+///   when it was not active, or the error raised because the file is
+///   missing, the privileges are insufficient or `wg-quick` failed.
+pub fn reconnect(path: &Path) -> Result<bool, WgError> {
+  check_config(path)?;
+  Capabilities::detect().check(Operation::Disconnect)?;
+  Capabilities::detect().check(Operation::Connect)?;
+  check_privileges()?;
+  let _lock = lock_tunnel(path)?;
+  reconnect_with(&*system_runner(), path)
+}
+
+/// Function to reconnect an active tunnel through the given runner.
+///
+/// A tunnel which is not active is left down, so that a tunnel the user
+/// disconnected is not brought back.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg` and `wg-quick`.
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<bool, WgError>`: Whether the tunnel was reconnected, or the
+///   error raised bringing it down or up.
+pub fn reconnect_with(runner: &dyn CommandRunner, path: &Path) -> Result<bool, WgError> {
+  match bring_down_with(runner, path)? {
+    Teardown::NotActive => Ok(false),
+    Teardown::Down => bring_up_with(runner, path).map(|()| true),
+  }
+}
+
+/// Function to measure the throughput through the tunnel of a WireGuard
+/// configuration file.
+///
+/// The tunnel is locked for the whole benchmark (see `TunnelLock`).
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+/// * `benchmark`: How the throughput is measured.
+///
+/// # Returns
+/// * `Result<(Throughput, bool), WgError>`: The throughput and whether the
+///   tunnel was brought up for the benchmark, or the error raised because
+///   the file is missing, the privileges are insufficient, or the tunnel or
+///   the benchmark failed.
+pub fn benchmark(path: &Path, benchmark: &Benchmark) -> Result<(Throughput, bool), WgError> {
+  check_config(path)?;
+  Capabilities::detect().check(Operation::Disconnect)?;
+  check_privileges()?;
+  let _lock = lock_tunnel(path)?;
+  benchmark_with(&*system_runner(), path, benchmark)
+}
+
+/// Function to measure the throughput through a tunnel with the given
+/// runner.
+///
+/// A tunnel which is not up is brought up for the benchmark and down again
+/// afterwards, even when the benchmark fails, so that it is left as it was.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg`, `wg-quick` and the benchmark program.
+/// * `path`: The path to the WireGuard configuration file.
+/// * `benchmark`: How the throughput is measured.
+///
+/// # Returns
+/// * `Result<(Throughput, bool), WgError>`: The throughput and whether the
+///   tunnel was brought up for the benchmark, or the error raised by the
+///   benchmark or bringing the tunnel up or down.
+pub fn benchmark_with(
+  runner: &dyn CommandRunner,
+  path: &Path,
+  benchmark: &Benchmark,
+) -> Result<(Throughput, bool), WgError> {
+  let active = active_interfaces_with(runner)?;
+  let up = interface_name(path).is_some_and(|name| active.iter().any(|active| active == name));
+  if up {
+    return Ok((benchmark.run_with(runner)?, false));
+  }
+  bring_up_with(runner, path)?;
+  let result = benchmark.run_with(runner);
+  let brought_up: Vec<String> = interface_name(path).map(str::to_string).into_iter().collect();
+  let down = bring_down_if_active(runner, path, &brought_up);
+  let throughput = result?;
+  down?;
+  Ok((throughput, true))
+}
+
+/// Function to change a live interface with `wg set`, for the settings
+/// WG-Bridge does not manage itself.
+///
+/// Commands managing the same tunnel wait for each other (see `TunnelLock`).
+///
+/// # Arguments
+/// * `interface`: The name of the interface.
+/// * `args`: The arguments given to `wg set` after the interface.
+///
+/// # Returns
+/// * `Result<(), WgError>`: The error, if any, raised because the privileges
+///   are insufficient, an argument is refused or `wg` failed.
+pub fn set(interface: &str, args: &[String]) -> Result<(), WgError> {
+  Capabilities::detect().check(Operation::Status)?;
+  check_privileges()?;
+  let _lock = TunnelLock::interface(interface, lock_wait())?;
+  set_with(&*system_runner(), interface, args)
+}
+
+/// Function to change a live interface with `wg set` through the given
+/// runner.
+///
+/// The arguments are passed as they are, except for `private-key`, refused
+/// since `rotate-keys` replaces the key in the file as well, so that the
+/// next `connect` does not bring the old one back. The operation is recorded
+/// in the audit log, when enabled, before it takes place.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg`.
+/// * `interface`: The name of the interface.
+/// * `args`: The arguments given to `wg set` after the interface.
+///
+/// # Returns
+/// * `Result<(), WgError>`: The error, if any, raised because an argument is
+///   refused or `wg` failed.
+pub fn set_with(runner: &dyn CommandRunner, interface: &str, args: &[String]) -> Result<(), WgError> {
+  if let Some(argument) = args.iter().find(|argument| argument.as_str() == "private-key") {
+    return Err(WgError::RefusedSetArgument {
+      argument: argument.clone(),
+      reason: "use `wgb rotate-keys` to replace the private key",
+    });
+  }
+  if let Some(audit) = AuditLog::get() {
+    audit.record("set", &format!("{interface} {}", args.join(" ")))?;
+  }
+  let args: Vec<&OsStr> = [OsStr::new("set"), OsStr::new(interface)]
+      .into_iter()
+      .chain(args.iter().map(OsStr::new))
+      .collect();
+  run(runner, WG, &args, Some(LogLevel::Info))?;
+  Ok(())
+}
+
+/// Function to bring down the tunnels of several WireGuard configuration
+/// files.
+///
+/// # Arguments
+/// * `paths`: The paths to the WireGuard configuration files.
+/// * `workers`: The most tunnels brought down at once.
+///
+/// # Returns
+/// * `Result<Vec<TeardownOutcome>, WgError>`: The
+///   outcome for each file, in order, or the error raised before any tunnel
+///   is touched because the privileges are insufficient or `wg` failed.
+pub fn bring_down_all(paths: &[PathBuf], workers: usize) -> Result<Vec<TeardownOutcome>, WgError> {
+  Capabilities::detect().check(Operation::Disconnect)?;
+  check_privileges()?;
+  let _lock = TunnelLock::all(lock_wait())?;
+  bring_down_all_with(&*system_runner(), paths, workers)
+}
+
+/// Function to bring down several tunnels through the given runner.
+///
+/// The active interfaces are listed once, and a failure does not stop the
+/// remaining tunnels from being brought down. A tunnel which is not active
+/// needs no configuration file, so a deleted one only fails when its
+/// interface is still up. With several workers, the tunnels are brought
+/// down concurrently (see `pool::map_bounded`).
+///
+/// # Arguments
+/// * `runner`: The runner of `wg` and `wg-quick`.
+/// * `paths`: The paths to the WireGuard configuration files.
+/// * `workers`: The most tunnels brought down at once.
+///
+/// # Returns
+/// * `Result<Vec<TeardownOutcome>, WgError>`: The
+///   outcome for each file, in order, or the error raised listing the
+///   active interfaces.
+pub fn bring_down_all_with(
+  runner: &dyn CommandRunner,
+  paths: &[PathBuf],
+  workers: usize,
+) -> Result<Vec<TeardownOutcome>, WgError> {
+  let active = active_interfaces_with(runner)?;
+  Ok(pool::map_bounded(paths, workers, |path| {
+    (path.clone(), bring_down_if_active(runner, path, &active))
+  }))
+}
+
+/// Function to bring down an interface brought up by WG-Bridge whose
+/// configuration is gone (see `session::orphans`).
+///
+/// Commands managing the same tunnel wait for each other (see `TunnelLock`).
+///
+/// # Arguments
+/// * `interface`: The name of the interface.
+/// * `config_path`: The WireGuard configuration file it was brought up
+///   from.
+///
+/// # Returns
+/// * `Result<(), WgError>`: The error, if any, raised because the privileges
+///   are insufficient or `wg-quick` or `ip` failed.
+pub fn tear_down_orphan(interface: &str, config_path: &Path) -> Result<(), WgError> {
+  Capabilities::detect().check(Operation::Disconnect)?;
+  check_privileges()?;
+  let _lock = TunnelLock::interface(interface, lock_wait())?;
+  tear_down_orphan_with(&*system_runner(), interface, config_path)
+}
+
+/// Function to bring down an orphaned interface through the given runner.
+///
+/// While the WireGuard configuration file is still there, `wg-quick down`
+/// undoes its routes, DNS and hooks as usual. Once gone, the interface is
+/// deleted with `ip link`, which drops its routes along with it. The session
+/// is forgotten either way.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg-quick` and `ip`.
+/// * `interface`: The name of the interface.
+/// * `config_path`: The WireGuard configuration file it was brought up
+///   from.
+///
+/// # Returns
+/// * `Result<(), WgError>`: The error, if any, raised because `wg-quick` or
+///   `ip` failed.
+pub fn tear_down_orphan_with(runner: &dyn CommandRunner, interface: &str, config_path: &Path) -> Result<(), WgError> {
+  if config_path.is_file() && interface_name(config_path) == Some(interface) {
+    return bring_down_if_active(runner, config_path, &[interface.to_string()]).map(|_| ());
+  }
+  if let Some(audit) = AuditLog::get() {
+    audit.record("cleanup", interface)?;
+  }
+  let args = ["link", "delete", "dev", interface].map(OsStr::new);
+  run(runner, IP, &args, Some(LogLevel::Info))?;
+  track_session(interface, None);
+  Ok(())
+}
+
+/// Function to bring down a tunnel when its interface is active.
+///
+/// An encrypted file is decrypted into a private copy given to `wg-quick
+/// down`, as on connect. When a DNS backend is set, the DNS is taken back
+/// from it and left out of such a copy.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg-quick`, of the decrypting command and of
+///   the DNS backend.
+/// * `path`: The path to the WireGuard configuration file.
+/// * `active`: The names of the active interfaces.
+///
+/// # Returns
+/// * `Result<Teardown, WgError>`: Whether the tunnel was brought down or was
+///   not active, or the error raised because the file is missing, cannot be
+///   decrypted or `wg-quick` failed.
+fn bring_down_if_active(runner: &dyn CommandRunner, path: &Path, active: &[String]) -> Result<Teardown, WgError> {
+  bring_down_with_dns(runner, path, active, DNS_BACKEND.get().copied())
+}
+
+/// Function to bring down a tunnel when its interface is active, taking its
+/// DNS back from the given backend (see `bring_down_if_active`).
+///
+/// # Arguments
+/// * `runner`: The runner of `wg-quick`, of the decrypting command and of
+///   the DNS backend.
+/// * `path`: The path to the WireGuard configuration file.
+/// * `active`: The names of the active interfaces.
+/// * `dns_backend`: The backend the DNS was given to, `None` leaving it to
+///   `wg-quick`.
+///
+/// # Returns
+/// * `Result<Teardown, WgError>`: Whether the tunnel was brought down or was
+///   not active, or the error raised because the file is missing, cannot be
+///   decrypted or `wg-quick` failed.
+fn bring_down_with_dns(
+  runner: &dyn CommandRunner,
+  path: &Path,
+  active: &[String],
+  dns_backend: Option<DnsBackend>,
+) -> Result<Teardown, WgError> {
+  if !interface_name(path).is_some_and(|name| active.iter().any(|active| active == name)) {
+    wgb_info!("{} is not active", path.display());
+    return Ok(Teardown::NotActive);
+  }
+  check_config(path)?;
+  // `wg-quick down` runs the PreDown and PostDown of the file, which are
+  // only readable in the plaintext of an encrypted one, and unsets the DNS
+  // it would have set, unless the backend set it
+  let dns_backend = dns_backend.filter(|_| REMOTE.get().is_none());
+  let encrypted = Encryption::detect(&read_config_bytes(path)?).is_some();
+  let renamed = renamed_interface(path).is_some();
+  let (copy, dns) = if encrypted || renamed || dns_backend.is_some() {
+    let (content, _) = read_plaintext(runner, path, DECRYPTORS.get())?;
+    let stripped = dns_backend.and_then(|_| strip_interface_entries_str(&content, "DNS").map(Zeroizing::new));
+    let dns = dns_backend.filter(|_| stripped.is_some());
+    match stripped {
+      Some(stripped) => (ExpandedConfig::write(path, &stripped, true)?, dns),
+      None if encrypted || renamed => (ExpandedConfig::write(path, &content, true)?, None),
+      None => (None, None),
+    }
+  } else {
+    (None, None)
+  };
+  if let Some(audit) = AuditLog::get() {
+    audit.record("disconnect", &path.display().to_string())?;
+  }
+  if let (Some(backend), Some(interface)) = (dns, interface_name(path)) {
+    // The tunnel is brought down regardless, its DNS going with the link
+    if let Err(e) = backend
+        .detect(runner)
+        .and_then(|backend| dns::run_all(runner, &backend.remove_commands(interface)))
+    {
+      wgb_warn!("Failed to unset the DNS of {}: {e}", path.display());
+    }
+  }
+  let down_path = copy.as_ref().map_or(path, |copy| copy.path.as_path());
+  run(runner, WG_QUICK, &[OsStr::new("down"), down_path.as_os_str()], Some(LogLevel::Info))?;
+  if let Some(interface) = interface_name(path) {
+    track_session(interface, None);
+  }
+  Ok(Teardown::Down)
+}
+
+/// Function to list the WireGuard interfaces up through the given runner.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg`.
+///
+/// # Returns
+/// * `Result<Vec<String>, WgError>`: The names of the interfaces, or the
+///   error raised because `wg` failed.
+pub fn active_interfaces_with(runner: &dyn CommandRunner) -> Result<Vec<String>, WgError> {
+  let output = run(runner, WG, &[OsStr::new("show"), OsStr::new("interfaces")], Some(LogLevel::Debug))?;
+  Ok(String::from_utf8_lossy(&output.stdout)
+      .split_whitespace()
+      .map(str::to_string)
+      .collect())
+}
+
+/// Function to retrieve the live state of the WireGuard interfaces up.
+///
+/// # Returns
+/// * `Result<Vec<TunnelStatus>, WgError>`: The state of every interface up,
+///   or the error raised because the privileges are insufficient, `wg` is
+///   not installed or it failed.
+pub fn status() -> Result<Vec<TunnelStatus>, WgError> {
+  Capabilities::detect().check(Operation::Status)?;
+  check_privileges()?;
+  status_with(&*system_runner())
+}
+
+/// Function to retrieve the live state of the interfaces through the given
+/// runner, from the output of `wg show all dump`.
+///
+/// # Arguments
+/// * `runner`: The runner of `wg`.
+///
+/// # Returns
+/// * `Result<Vec<TunnelStatus>, WgError>`: The state of every interface up,
+///   or the error raised because `wg` failed or its output is unexpected.
+pub fn status_with(runner: &dyn CommandRunner) -> Result<Vec<TunnelStatus>, WgError> {
+  let args = [OsStr::new("show"), OsStr::new("all"), OsStr::new("dump")];
+  // The dump holds the private keys, it is never logged
+  let output = run(runner, WG, &args, None)?;
+  parse_dump(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Function to parse the output of `wg show all dump`.
+///
+/// Every interface is described by a line of 5 tab-separated fields (name,
+/// private key, public key, listen port, fwmark), followed by a line of 9
+/// fields per peer (name, public key, preshared key, endpoint, allowed IPs,
+/// latest handshake, rx bytes, tx bytes, persistent keepalive).
+///
+/// # Arguments
+/// * `dump`: The output of `wg show all dump`.
+///
+/// # Returns
+/// * `Result<Vec<TunnelStatus>, WgError>`: The state of every interface, or
+///   `WgError::UnexpectedOutput` for a line not matching the format.
+fn parse_dump(dump: &str) -> Result<Vec<TunnelStatus>, WgError> {
+  let mut tunnels: Vec<TunnelStatus> = Vec::new();
+  for line in dump.lines().filter(|line| !line.trim().is_empty()) {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let unexpected = || WgError::UnexpectedOutput(line.to_string());
+    match fields.as_slice() {
+      [interface, _, _, _, _] => tunnels.push(TunnelStatus {
+        interface: interface.to_string(),
+        up: true,
+        peers: Vec::new(),
+      }),
+      [interface, public_key, _, endpoint, allowed_ips, handshake, rx, tx, _] => {
+        let tunnel = tunnels
+            .last_mut()
+            .filter(|tunnel| tunnel.interface == *interface)
+            .ok_or_else(unexpected)?;
+        let handshake: u64 = handshake.parse().map_err(|_| unexpected())?;
+        tunnel.peers.push(PeerStatus {
+          public_key: public_key.to_string(),
+          endpoint: (*endpoint != "(none)").then(|| endpoint.to_string()),
+          allowed_ips: match *allowed_ips {
+            "(none)" => Vec::new(),
+            allowed_ips => split_list(allowed_ips).collect(),
+          },
+          latest_handshake: (handshake != 0).then(|| UNIX_EPOCH + Duration::from_secs(handshake)),
+          rx_bytes: rx.parse().map_err(|_| unexpected())?,
+          tx_bytes: tx.parse().map_err(|_| unexpected())?,
+        });
+      }
+      _ => return Err(unexpected()),
+    }
+  }
+  Ok(tunnels)
+}
+
+/// Function to retrieve the name of the interface of a configuration file.
+///
+/// As for `wg-quick`, the interface is named after the file, without its
+/// extension (e.g. `wg0` for `/etc/wireguard/wg0.conf`), unless the
+/// `interface_template` names it.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Option<&str>`: The name of the interface, or `None` when the path has
+///   no valid UTF-8 file name.
+pub fn interface_name(path: &Path) -> Option<&str> {
+  renamed_interface(path).or_else(|| path.file_stem().and_then(OsStr::to_str))
+}
+
+/// Function to tell whether a name is valid for a network interface, as
+/// `wg-quick` checks it: 1 to `MAX_INTERFACE_NAME_LEN` ASCII letters, digits
+/// or `_=+.-`.
+///
+/// # Arguments
+/// * `name`: The interface name.
+///
+/// # Returns
+/// * `bool`: Whether the name is valid.
+pub fn is_valid_interface_name(name: &str) -> bool {
+  (1..=MAX_INTERFACE_NAME_LEN).contains(&name.len())
+      && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"_=+.-".contains(&byte))
+}
+
+/// Function to retrieve the interface name the `interface_template` gives a
+/// configuration file, when it differs from the file name.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Option<&'static str>`: The name of the interface, or `None` when the
+///   interface is named after the file.
+fn renamed_interface(path: &Path) -> Option<&'static str> {
+  let name = INTERFACE_NAMES.get()?.get(path)?;
+  (path.file_stem() != Some(OsStr::new(name))).then_some(name.as_str())
+}
+
+/// Function to run a WireGuard program.
+///
+/// Every line the program prints is logged at the given level, if any, and
+/// a non-zero exit is turned into `WgError::Failed`, carrying the last line
+/// printed to stderr.
+///
+/// # Arguments
+/// * `runner`: The runner of the program.
+/// * `program`: The program (i.e. `wg` or `wg-quick`).
+/// * `args`: The arguments of the program.
+/// * `level`: The level the output of the program is logged at, `None` for
+///   output which must not be logged.
+///
+/// # Returns
+/// * `Result<Output, WgError>`: The output of a successful run, or the error
+///   raised spawning the program or because it failed.
+fn run(
+  runner: &dyn CommandRunner,
+  program: &'static str,
+  args: &[&OsStr],
+  level: Option<LogLevel>,
+) -> Result<Output, WgError> {
+  let command = args
+      .iter()
+      .fold(program.to_string(), |command, arg| format!("{command} {}", arg.to_string_lossy()));
+  wgb_debug!("Running {command}");
+
+  let output = runner.run(program, args).map_err(|e| match e.kind() {
+    io::ErrorKind::NotFound => WgError::NotInstalled(program),
+    io::ErrorKind::TimedOut => WgError::Timeout(command.clone()),
+    _ => WgError::Spawn(program, e),
+  })?;
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  if let Some(level) = level {
+    for line in stdout.lines().chain(stderr.lines()).filter(|line| !line.trim().is_empty()) {
+      wgb_log!(level, "{line}");
+    }
+  }
+
+  if output.status.success() {
+    return Ok(output);
+  }
+  let reason = stderr
+      .lines()
+      .rev()
+      .find(|line| !line.trim().is_empty())
+      .unwrap_or("no error output")
+      .trim()
+      .to_string();
+  Err(WgError::Failed {
+    command,
+    status: output.status,
+    reason,
+  })
+}
+
+/// Function to check that a WireGuard configuration file exists.
+///
+/// # Arguments
+/// * `path`: The path to the WireGuard configuration file.
+///
+/// # Returns
+/// * `Result<(), WgError>`: `WgError::MissingConfig` when it is not a file.
+fn check_config(path: &Path) -> Result<(), WgError> {
+  if path.is_file() {
+    Ok(())
+  } else {
+    Err(WgError::MissingConfig(path.to_path_buf()))
+  }
+}
+
+/// Function to tell whether the process may create network interfaces.
+///
+/// # Returns
+/// * `bool`: Whether CAP_NET_ADMIN is in the effective capabilities, which
+///   is the case for root. When they cannot be read, `true` is returned and
+///   `wg-quick` is left to report the problem.
+#[cfg(target_os = "linux")]
+fn is_privileged() -> bool {
+  let Ok(status) = fs::read_to_string("/proc/self/status") else {
+    return true;
+  };
+  status
+      .lines()
+      .find_map(|line| line.strip_prefix("CapEff:"))
+      .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+      .is_none_or(|caps| caps & (1 << CAP_NET_ADMIN) != 0)
+}
+
+/// Function to tell whether the process may create network interfaces.
+///
+/// # Returns
+/// * `bool`: Always `true`, `wg-quick` is left to report the problem.
+#[cfg(not(target_os = "linux"))]
+fn is_privileged() -> bool {
+  true
+}
+
+/// Function to generate a new WireGuard keypair, without `wg genkey`.
+///
+/// The private key is clamped as by `wg genkey`, so that the keys are
+/// interchangeable with the ones generated by WireGuard.
+///
+/// # Returns
+/// * `(PrivateKey, PublicKey)`: The base64-encoded private and public keys.
+pub fn generate_keypair() -> (PrivateKey, PublicKey) {
+  let mut bytes = Zeroizing::new(StaticSecret::random_from_rng(OsRng).to_bytes());
+  bytes[0] &= 248;
+  bytes[31] = (bytes[31] & 127) | 64;
+
+  let public = public_key(&StaticSecret::from(*bytes));
+  (PrivateKey(Zeroizing::new(STANDARD.encode(*bytes))), public)
+}
+
+/// Function to derive the public key of a WireGuard private key, as by
+/// `wg pubkey`.
+///
+/// # Arguments
+/// * `priv_b64`: The base64-encoded private key.
+///
+/// # Returns
+/// * `Result<PublicKey, WgError>`: The base64-encoded public key, or
+///   `WgError::InvalidPrivateKey` when the key does not decode to 32 bytes.
+pub fn public_from_private(priv_b64: &str) -> Result<PublicKey, WgError> {
+  let decoded = Zeroizing::new(STANDARD.decode(priv_b64.trim()).map_err(|_| WgError::InvalidPrivateKey)?);
+  let bytes: Zeroizing<[u8; KEY_LEN]> =
+      Zeroizing::new(decoded.as_slice().try_into().map_err(|_| WgError::InvalidPrivateKey)?);
+  Ok(public_key(&StaticSecret::from(*bytes)))
+}
+
+/// Function to compute the public key of a secret.
+///
+/// # Arguments
+/// * `secret`: The X25519 secret.
+///
+/// # Returns
+/// * `PublicKey`: The base64-encoded public key.
+fn public_key(secret: &StaticSecret) -> PublicKey {
+  PublicKey(STANDARD.encode(x25519_dalek::PublicKey::from(secret).as_bytes()))
+}
+
+/// Function to tell whether a string is a valid WireGuard key.
+///
+/// # Arguments
+/// * `key`: The key, base64-encoded.
+///
+/// # Returns
+/// * `bool`: Whether the key decodes to 32 bytes.
+pub fn is_valid_key(key: &str) -> bool {
+  STANDARD.decode(key).is_ok_and(|bytes| bytes.len() == KEY_LEN)
+}
+
+/// Function to build a syntax error.
+///
+/// # Arguments
+/// * `line`: The number of the offending line.
+/// * `reason`: What is wrong with the line.
+///
+/// # Returns
+/// * `WgParseError`: The error.
+fn syntax(line: usize, reason: &str) -> WgParseError {
+  WgParseError::Syntax {
+    line,
+    reason: reason.to_string(),
+  }
+}
+
+/// Function to parse a key entry.
+///
+/// # Arguments
+/// * `line`: The number of the line holding the entry.
+/// * `field`: The name of the entry.
+/// * `value`: The value of the entry.
+///
+/// # Returns
+/// * `Result<String, WgParseError>`: The key, or the error raised when it is
+///   not a valid WireGuard key.
+fn parse_key(line: usize, field: &'static str, value: &str) -> Result<String, WgParseError> {
+  if is_valid_key(value) {
+    Ok(value.to_string())
+  } else {
+    Err(WgParseError::InvalidKey { line, field })
+  }
+}
+
+/// Function to parse a numeric entry.
+///
+/// # Arguments
+/// * `line`: The number of the line holding the entry.
+/// * `field`: The name of the entry.
+/// * `value`: The value of the entry.
+///
+/// # Returns
+/// * `Result<T, WgParseError>`: The number, or the error raised when it is
+///   not a valid integer of the expected type.
+fn parse_number<T: FromStr>(line: usize, field: &'static str, value: &str) -> Result<T, WgParseError> {
+  value.parse().map_err(|_| WgParseError::InvalidValue {
+    line,
+    field,
+    value: value.to_string(),
+  })
+}
+
+/// Function to parse a `DNS` entry, a list of DNS server addresses and
+/// search domains.
+///
+/// # Arguments
+/// * `line`: The number of the line holding the entry.
+/// * `value`: The value of the entry.
+///
+/// # Returns
+/// * `Result<Vec<String>, WgParseError>`: The servers and domains, in order,
+///   or the error raised for an item which is neither an address nor a
+///   domain name, such as a mistyped address.
+fn parse_dns(line: usize, value: &str) -> Result<Vec<String>, WgParseError> {
+  let entries: Vec<String> = split_list(value).collect();
+  match entries.iter().find(|entry| entry.parse::<IpAddr>().is_err() && !is_domain_name(entry)) {
+    Some(invalid) => Err(WgParseError::InvalidValue {
+      line,
+      field: "DNS",
+      value: invalid.clone(),
+    }),
+    None => Ok(entries),
+  }
+}
+
+/// Function to tell whether a text is a domain name usable as a search
+/// domain.
+///
+/// # Arguments
+/// * `name`: The text, with an optional trailing dot.
+///
+/// # Returns
+/// * `bool`: Whether its labels are letters, digits and inner hyphens, and
+///   the last one is not numeric, so that `10.0.0.300` is not taken for one.
+fn is_domain_name(name: &str) -> bool {
+  let name = name.strip_suffix('.').unwrap_or(name);
+  let labels: Vec<&str> = name.split('.').collect();
+  let valid_label = |label: &&str| {
+    (1..=63).contains(&label.len())
+        && label.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+  };
+  name.len() <= 253
+      && labels.iter().all(valid_label)
+      && labels.last().is_some_and(|label| !label.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+/// Function to normalize a network the way `wg` prints it.
+///
+/// # Arguments
+/// * `network`: The network, as an address with an optional prefix length.
+///
+/// # Returns
+/// * `String`: The network with its host bits cleared and its prefix length
+///   (e.g. "10.0.0.0/24" for "10.0.0.7/24", "::/0" for "::0/0"), or the
+///   trimmed input when it is not a valid network.
+fn normalize_network(network: &str) -> String {
+  let network = network.trim();
+  let Some((address, prefix)) = split_network(network) else {
+    return network.to_string();
+  };
+  let address = match address {
+    IpAddr::V4(address) => {
+      let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+      IpAddr::V4(Ipv4Addr::from(u32::from(address) & mask))
+    }
+    IpAddr::V6(address) => {
+      let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+      IpAddr::V6(Ipv6Addr::from(u128::from(address) & mask))
+    }
+  };
+  format!("{address}/{prefix}")
+}
+
+/// Function to split a network into its address and prefix length.
+///
+/// # Arguments
+/// * `network`: The network, as an address with an optional prefix length.
+///
+/// # Returns
+/// * `Option<(IpAddr, u32)>`: The address and the prefix length, the full
+///   length of the address when missing, or `None` when it is not a valid
+///   network.
+fn split_network(network: &str) -> Option<(IpAddr, u32)> {
+  let (address, prefix) = match network.split_once('/') {
+    Some((address, prefix)) => (address, Some(prefix)),
+    None => (network, None),
+  };
+  let address = address.parse::<IpAddr>().ok()?;
+  let max = if address.is_ipv4() { 32 } else { 128 };
+  match prefix.map(str::parse::<u32>) {
+    None => Some((address, max)),
+    Some(Ok(prefix)) if prefix <= max => Some((address, prefix)),
+    Some(_) => None,
+  }
+}
+
+/// Function to parse a boolean entry.
+///
+/// # Arguments
+/// * `line`: The number of the line holding the entry.
+/// * `field`: The name of the entry.
+/// * `value`: The value of the entry.
+///
+/// # Returns
+/// * `Result<bool, WgParseError>`: The value, `true` or `false` in any case,
+///   or the error pointing at the line.
+fn parse_bool(line: usize, field: &'static str, value: &str) -> Result<bool, WgParseError> {
+  match value.to_ascii_lowercase().as_str() {
+    "true" => Ok(true),
+    "false" => Ok(false),
+    _ => Err(WgParseError::InvalidValue {
+      line,
+      field,
+      value: value.to_string(),
+    }),
+  }
+}
+
+/// Function to split a comma-separated list value.
+///
+/// # Arguments
+/// * `value`: The value of the entry.
+///
+/// # Returns
+/// * `impl Iterator<Item = String>`: The trimmed, non-empty items.
+fn split_list(value: &str) -> impl Iterator<Item = String> + '_ {
+  value
+      .split(',')
+      .map(str::trim)
+      .filter(|item| !item.is_empty())
+      .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::clock::Clock;
+  use crate::core::testing::{KEY, init_logger, temp_dir};
+  use chrono::{DateTime, Local};
+  use std::collections::HashMap;
+  use std::os::unix::process::ExitStatusExt;
+  use std::sync::Mutex;
+
+  /// Define a runner answering from canned outputs and recording its calls.
+  #[derive(Debug, Default)]
+  struct StubRunner {
+    responses: HashMap<String, (i32, String, String)>,
+    timeouts: Vec<String>,
+    calls: Mutex<Vec<String>>,
+  }
+
+  impl StubRunner {
+    fn respond(mut self, command: &str, code: i32, stdout: &str, stderr: &str) -> Self {
+      self
+          .responses
+          .insert(command.to_string(), (code, stdout.to_string(), stderr.to_string()));
+      self
+    }
+
+    fn time_out(mut self, command: &str) -> Self {
+      self.timeouts.push(command.to_string());
+      self
+    }
+
+    fn calls(&self) -> Vec<String> {
+      self.calls.lock().unwrap().clone()
+    }
+  }
+
+  impl CommandRunner for StubRunner {
+    fn run(&self, program: &str, args: &[&OsStr]) -> io::Result<Output> {
+      let command = args
+          .iter()
+          .fold(program.to_string(), |command, arg| format!("{command} {}", arg.to_string_lossy()));
+      self.calls.lock().unwrap().push(command.clone());
+      if self.timeouts.contains(&command) {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "killed"));
+      }
+      let (code, stdout, stderr) = self.responses.get(&command).cloned().unwrap_or_default();
+      Ok(Output {
+        status: ExitStatus::from_raw(code << 8),
+        stdout: stdout.into_bytes(),
+        stderr: stderr.into_bytes(),
+      })
+    }
+
+    fn run_with_input(&self, program: &str, args: &[&OsStr], input: &[u8]) -> io::Result<Output> {
+      let output = self.run(program, args)?;
+      let mut calls = self.calls.lock().unwrap();
+      if let Some(call) = calls.last_mut() {
+        call.push_str(&format!(" < {:?}", String::from_utf8_lossy(input)));
+      }
+      Ok(output)
+    }
+  }
+
+  fn write_config(dir: &Path, interface: &str) -> PathBuf {
+    let path = dir.join(format!("{interface}.conf"));
+    fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\n")).unwrap();
+    path
+  }
+
+  #[test]
+  fn bring_down_all_continues_after_a_failure() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let wg0 = write_config(dir, "wg0");
+    let wg1 = dir.join("wg1.conf");
+    let wg2 = write_config(dir, "wg2");
+    let wg3 = dir.join("wg3.conf");
+    // The outcomes are in order whether the tunnels go down one at a time or
+    // concurrently
+    for workers in [1, 4] {
+      let runner = StubRunner::default()
+          .respond("wg show interfaces", 0, "wg0 wg1 wg2\n", "")
+          .respond(&format!("wg-quick down {}", wg0.display()), 1, "", "wg-quick: busy\n");
+
+      let outcomes = bring_down_all_with(&runner, &[wg0.clone(), wg1.clone(), wg2.clone(), wg3.clone()], workers).unwrap();
+
+      assert!(matches!(&outcomes[0], (path, Err(WgError::Failed { reason, .. })) if *path == wg0 && reason == "wg-quick: busy"));
+      assert!(matches!(&outcomes[1], (path, Err(WgError::MissingConfig(_))) if *path == wg1));
+      assert!(matches!(&outcomes[2], (path, Ok(Teardown::Down)) if *path == wg2));
+      assert!(matches!(&outcomes[3], (path, Ok(Teardown::NotActive)) if *path == wg3));
+      let listings = runner.calls().iter().filter(|call| *call == "wg show interfaces").count();
+      assert_eq!(listings, 1);
+    }
+  }
+
+  #[test]
+  fn bring_up_is_undone_when_it_times_out() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let wg0 = write_config(dir, "wg0");
+    let runner = StubRunner::default().time_out(&format!("wg-quick up {}", wg0.display()));
+
+    let result = bring_up_with(&runner, &wg0);
+    assert!(matches!(result, Err(WgError::Timeout(command)) if command == format!("wg-quick up {}", wg0.display())));
+    assert_eq!(runner.calls()[1], format!("wg-quick down {}", wg0.display()));
+  }
+
+  #[test]
+  fn run_until_kills_a_program_at_the_deadline() {
+    let deadline = |budget| Deadline::after(budget, Arc::new(SystemClock));
+    let started = std::time::Instant::now();
+    let result = run_until("sleep", &[OsStr::new("5")], None, &deadline(Duration::from_millis(200)));
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    assert!(started.elapsed() < Duration::from_secs(4));
+
+    let output = run_until("echo", &[OsStr::new("up")], None, &deadline(Duration::from_secs(10))).unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"up\n");
+    let result = run_until("echo", &[], None, &deadline(Duration::ZERO));
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+  }
+
+  #[test]
+  fn ssh_runner_wraps_the_programs() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let wg0 = write_config(dir, "wg0");
+    let runner = SshRunner::new("root@vpn.example.com", StubRunner::default()).unwrap();
+
+    bring_up_with(&runner, &wg0).unwrap();
+    let calls = runner.inner.calls();
+    assert_eq!(calls[0], format!("ssh -o BatchMode=yes -- root@vpn.example.com wg-quick up {}", wg0.display()));
+
+    runner.run(WG, &[OsStr::new("show"), OsStr::new("it's mine"), OsStr::new("")]).unwrap();
+    assert_eq!(
+      runner.inner.calls().last().unwrap(),
+      "ssh -o BatchMode=yes -- root@vpn.example.com wg show 'it'\\''s mine' ''"
+    );
+  }
+
+  #[test]
+  fn ssh_runner_rejects_invalid_destinations() {
+    for destination in ["", "-oProxyCommand=sh", "root@host extra"] {
+      let result = SshRunner::new(destination, StubRunner::default());
+      assert!(matches!(result, Err(WgError::InvalidRemote(_))), "{destination:?}");
+    }
+    assert!(SshRunner::new("vpn", StubRunner::default()).is_ok());
+  }
+
+  #[test]
+  fn bring_down_of_an_inactive_tunnel_is_not_an_error() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let wg0 = write_config(dir, "wg0");
+    let runner = StubRunner::default().respond("wg show interfaces", 0, "wg1\n", "");
+
+    assert!(matches!(bring_down_with(&runner, &wg0), Ok(Teardown::NotActive)));
+    assert_eq!(runner.calls(), vec!["wg show interfaces"]);
+  }
+
+  #[test]
+  fn public_from_private_matches_the_rfc_7748_vectors() {
+    // RFC 7748, section 6.1, base64-encoded as by `wg genkey` / `wg pubkey`
+    let vectors = [
+      ("dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo=", "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo="),
+      ("XasIfmJKikt54X+Lg4AO5m87sSkmGLb9HC+LJ/+I4Os=", "3p7bfXt9wbTTW2HC7OQ1Nz+DQ8hbeGdNrfx+FG+IK08="),
+    ];
+    for (private, public) in vectors {
+      assert_eq!(public_from_private(private).unwrap().as_str(), public);
+    }
+  }
+
+  #[test]
+  fn public_from_private_ignores_surrounding_whitespace() {
+    let public = public_from_private("dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo=\n").unwrap();
+    assert_eq!(public.as_str(), "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=");
+  }
+
+  #[test]
+  fn public_from_private_rejects_malformed_keys() {
+    for key in ["", "not base64!", "AAAA", "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=AAAA"] {
+      assert!(matches!(public_from_private(key), Err(WgError::InvalidPrivateKey)), "{key}");
+    }
+  }
+
+  #[test]
+  fn generated_keypairs_are_clamped_and_consistent() {
+    let (private, public) = generate_keypair();
+    let bytes = STANDARD.decode(private.as_str()).unwrap();
+
+    assert_eq!(bytes.len(), KEY_LEN);
+    assert_eq!(bytes[0] & 7, 0);
+    assert_eq!(bytes[31] & 128, 0);
+    assert_eq!(bytes[31] & 64, 64);
+    assert!(is_valid_key(public.as_str()));
+    assert_eq!(public_from_private(private.as_str()).unwrap(), public);
+    assert_ne!(generate_keypair().0, private);
+  }
+
+  #[test]
+  fn private_key_debug_is_redacted() {
+    let (private, _) = generate_keypair();
+    assert_eq!(format!("{private:?}"), "PrivateKey(***)");
+  }
+
+  /// The example of wg-quick(8).
+  const WG_QUICK_EXAMPLE: &str = "\
+[Interface]
+Address = 10.200.100.8/24
+DNS = 10.200.100.1
+PrivateKey = oK56DE9Ue9zK76rAc8pBl6opph+1v36lm7cXXsQKrQM=
+
+[Peer]
+PublicKey = GtL7fZc/bLnqZldpVofMCD6hDjrK28SsdLxevJ+qtKU=
+PresharedKey = /UwcSPg38hW/D9Y3tcS1FOV0K1wuURMbS0sesJEP5ak=
+AllowedIPs = 0.0.0.0/0
+Endpoint = demo.wireguard.com:51820
+";
+
+  /// A server with several peers, wg-quick only keys and inline comments,
+  /// as written by hand.
+  const SERVER: &str = "\
+# wg0 - office gateway
+[Interface]
+Address = 10.8.0.1/24, fd42:42:42::1/64
+ListenPort = 51820
+PrivateKey = dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo=
+MTU = 1420
+SaveConfig = false
+PostUp = iptables -A FORWARD -i %i -j ACCEPT; iptables -t nat -A POSTROUTING -o eth0 -j MASQUERADE
+PostDown = iptables -D FORWARD -i %i -j ACCEPT; iptables -t nat -D POSTROUTING -o eth0 -j MASQUERADE
+
+[Peer] # laptop
+PublicKey = 3p7bfXt9wbTTW2HC7OQ1Nz+DQ8hbeGdNrfx+FG+IK08=
+AllowedIPs = 10.8.0.2/32, fd42:42:42::2/128
+
+[Peer] # branch office
+PublicKey = hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=
+Endpoint = [2001:db8::1]:51820
+AllowedIPs = 10.8.0.3/32
+AllowedIPs = 192.168.10.0/24 # the branch LAN
+PersistentKeepalive = 25
+";
+
+  /// A commercial VPN client configuration, with lowercase keys, a search
+  /// domain and Windows line endings.
+  const CLIENT: &str = "[interface]\r\n\
+privatekey = XasIfmJKikt54X+Lg4AO5m87sSkmGLb9HC+LJ/+I4Os=\r\n\
+address = 10.64.12.7/32,fc00:bbbb:bbbb:bb01::1:c06/128\r\n\
+dns = 10.64.0.1, vpn.example\r\n\
+\r\n\
+[ Peer ]\r\n\
+publickey = hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=\r\n\
+allowedips = 0.0.0.0/0,::0/0\r\n\
+endpoint = 185.213.154.68:51820\r\n\
+persistentkeepalive = off\r\n";
+
+  #[test]
+  fn parse_reads_the_wg_quick_example() {
+    let config = WgConfig::parse_str(WG_QUICK_EXAMPLE).unwrap();
+
+    assert_eq!(config.interface.private_key, "oK56DE9Ue9zK76rAc8pBl6opph+1v36lm7cXXsQKrQM=");
+    assert_eq!(config.interface.addresses, ["10.200.100.8/24"]);
+    assert_eq!(config.interface.dns, ["10.200.100.1"]);
+    assert_eq!(config.interface.listen_port, None);
+    assert_eq!(
+      config.peers,
+      [Peer {
+        public_key: "GtL7fZc/bLnqZldpVofMCD6hDjrK28SsdLxevJ+qtKU=".to_string(),
+        preshared_key: Some("/UwcSPg38hW/D9Y3tcS1FOV0K1wuURMbS0sesJEP5ak=".to_string()),
+        endpoint: Some("demo.wireguard.com:51820".to_string()),
+        allowed_ips: vec!["0.0.0.0/0".to_string()],
+        persistent_keepalive: None,
+      }]
+    );
+  }
+
+  #[test]
+  fn parse_reads_a_server_with_several_peers() {
+    let config = WgConfig::parse_str(SERVER).unwrap();
+
+    assert_eq!(config.interface.addresses, ["10.8.0.1/24", "fd42:42:42::1/64"]);
+    assert_eq!(config.interface.listen_port, Some(51820));
+    assert_eq!(config.interface.mtu, Some(1420));
+    assert_eq!(config.peers.len(), 2);
+    assert_eq!(config.peers[0].allowed_ips, ["10.8.0.2/32", "fd42:42:42::2/128"]);
+    assert_eq!(config.peers[0].endpoint, None);
+    assert_eq!(config.peers[1].endpoint.as_deref(), Some("[2001:db8::1]:51820"));
+    assert_eq!(config.peers[1].allowed_ips, ["10.8.0.3/32", "192.168.10.0/24"]);
+    assert_eq!(config.peers[1].persistent_keepalive, Some(25));
+  }
+
+  #[test]
+  fn parse_reads_a_client_with_lowercase_keys_and_crlf() {
+    let config = WgConfig::parse_str(CLIENT).unwrap();
+
+    assert_eq!(config.interface.private_key, "XasIfmJKikt54X+Lg4AO5m87sSkmGLb9HC+LJ/+I4Os=");
+    assert_eq!(config.interface.addresses, ["10.64.12.7/32", "fc00:bbbb:bbbb:bb01::1:c06/128"]);
+    assert_eq!(config.interface.dns, ["10.64.0.1", "vpn.example"]);
+    assert_eq!(config.interface.dns_servers(), ["10.64.0.1".parse::<IpAddr>().unwrap()]);
+    assert_eq!(config.interface.search_domains(), ["vpn.example"]);
+    assert_eq!(config.peers[0].allowed_ips, ["0.0.0.0/0", "::0/0"]);
+    assert_eq!(config.peers[0].endpoint.as_deref(), Some("185.213.154.68:51820"));
+    assert_eq!(config.peers[0].persistent_keepalive, None);
+  }
+
+  #[test]
+  fn parse_ignores_unknown_sections_and_keys() {
+    let content = format!("[Interface]\nPrivateKey = {KEY}\nTable = off\nFwMark = 0x1234\n\n[Extra]\nAnything = goes\n");
+    let config = WgConfig::parse_str(&content).unwrap();
+    assert!(config.peers.is_empty());
+  }
+
+  #[test]
+  fn parse_points_at_the_offending_line() {
+    let cases = [
+      (format!("[Interface]\nPrivateKey = {KEY}\n[Interface]\n"), "Line 3"),
+      ("Address = 10.0.0.1/24\n".to_string(), "Line 1"),
+      (format!("[Interface]\nPrivateKey = {KEY}\nListenPort\n"), "Line 3"),
+    ];
+    for (content, line) in cases {
+      let error = WgConfig::parse_str(&content).unwrap_err();
+      assert!(matches!(error, WgParseError::Syntax { .. }), "{content}");
+      assert!(error.to_string().contains(line), "{error}");
+    }
+
+    let content = format!("[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = not-a-key\n");
+    assert!(matches!(
+      WgConfig::parse_str(&content),
+      Err(WgParseError::InvalidKey { line: 5, field: "PublicKey" })
+    ));
+    let content = format!("[Interface]\nPrivateKey = {KEY}\nListenPort = 70000\n");
+    assert!(matches!(
+      WgConfig::parse_str(&content),
+      Err(WgParseError::InvalidValue { line: 3, field: "ListenPort", .. })
+    ));
+  }
+
+  #[test]
+  fn dns_entries_are_split_into_servers_and_search_domains() {
+    let content = format!("[Interface]\nPrivateKey = {KEY}\nDNS = 10.0.0.1, example.internal, fd00::53\nDNS = corp.example.\n");
+    let interface = WgConfig::parse_str(&content).unwrap().interface;
+    assert_eq!(interface.dns, ["10.0.0.1", "example.internal", "fd00::53", "corp.example."]);
+    let servers: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "fd00::53".parse().unwrap()];
+    assert_eq!(interface.dns_servers(), servers);
+    assert_eq!(interface.search_domains(), ["example.internal", "corp.example."]);
+
+    for invalid in ["10.0.0.300", "-bad.example", "under_score.example", "a..b"] {
+      let content = format!("[Interface]\nPrivateKey = {KEY}\nDNS = 10.0.0.1, {invalid}\n");
+      let error = WgConfig::parse_str(&content).unwrap_err();
+      assert!(matches!(&error, WgParseError::InvalidValue { line: 3, field: "DNS", value } if value == invalid), "{error}");
+    }
+  }
+
+  #[test]
+  fn parse_requires_the_mandatory_fields() {
+    assert!(matches!(WgConfig::parse_str(""), Err(WgParseError::MissingInterface)));
+    assert!(matches!(
+      WgConfig::parse_str("[Interface]\nAddress = 10.0.0.1/24\n"),
+      Err(WgParseError::MissingField { section: "Interface", field: "PrivateKey" })
+    ));
+    assert!(matches!(
+      WgConfig::parse_str(&format!("[Interface]\nPrivateKey = {KEY}\n[Peer]\nAllowedIPs = 0.0.0.0/0\n")),
+      Err(WgParseError::MissingField { section: "Peer", field: "PublicKey" })
+    ));
+  }
+
+  #[test]
+  fn interface_debug_masks_the_private_key() {
+    let config = WgConfig::parse_str(SERVER).unwrap();
+    assert!(!format!("{config:?}").contains(&config.interface.private_key));
+    let config = WgConfig::parse_str(WG_QUICK_EXAMPLE).unwrap();
+    assert!(!format!("{config:?}").contains("/UwcSPg38hW/D9Y3tcS1FOV0K1wuURMbS0sesJEP5ak="));
+  }
+
+  #[test]
+  fn parse_rejects_oversized_input() {
+    let huge = format!("[Interface]\nPrivateKey = {KEY}\n{}", "# padding\n".repeat(MAX_CONFIG_LEN / 10 + 1));
+    assert!(matches!(WgConfig::parse_str(&huge), Err(WgParseError::TooLarge(MAX_CONFIG_LEN))));
+    assert!(matches!(WgConfig::parse_bytes(huge.as_bytes()), Err(WgParseError::TooLarge(_))));
+    // An endless file is not read past the limit
+    assert!(matches!(WgConfig::parse(Path::new("/dev/zero")), Err(WgParseError::TooLarge(_))));
+
+    let long_line = format!("[Interface]\nPrivateKey = {KEY}\nPostUp = {}\n", "x".repeat(MAX_LINE_LEN));
+    assert!(matches!(WgConfig::parse_str(&long_line), Err(WgParseError::Syntax { line: 3, .. })));
+  }
+
+  #[test]
+  fn parse_rejects_nul_bytes_and_invalid_utf8() {
+    let content = format!("[Interface]\nPrivateKey = {KEY}\0\n");
+    assert!(matches!(WgConfig::parse_str(&content), Err(WgParseError::Syntax { line: 2, .. })));
+    assert!(matches!(WgConfig::parse_bytes(b"[Interface]\n\xff\xfe\n"), Err(WgParseError::NotUtf8)));
+  }
+
+  #[test]
+  fn parse_handles_repeated_sections() {
+    let content = format!("[Interface]\nPrivateKey = {KEY}\n{}", "[Peer]\n".repeat(100_000));
+    assert!(matches!(
+      WgConfig::parse_str(&content),
+      Err(WgParseError::MissingField { section: "Peer", .. })
+    ));
+    let content = format!("[Peer]\nPublicKey = {KEY}\n").repeat(10_000);
+    let content = format!("[Interface]\nPrivateKey = {KEY}\n{content}");
+    assert_eq!(WgConfig::parse_str(&content).unwrap().peers.len(), 10_000);
+    let content = "[Interface]\n".repeat(10_000);
+    assert!(matches!(WgConfig::parse_str(&content), Err(WgParseError::Syntax { line: 2, .. })));
+  }
+
+  #[test]
+  fn parse_never_panics_on_mutated_input() {
+    // Deterministic xorshift, so that a failure is reproducible
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      state
+    };
+    let interesting = b"[]=#,\n\r\0 \xff";
+
+    for sample in [WG_QUICK_EXAMPLE, SERVER, CLIENT] {
+      for end in 0..=sample.len() {
+        let _ = WgConfig::parse_bytes(&sample.as_bytes()[..end]);
+      }
+      for _ in 0..2_000 {
+        let mut bytes = sample.as_bytes().to_vec();
+        for _ in 0..1 + next() % 8 {
+          let position = (next() as usize) % bytes.len();
+          match next() % 3 {
+            0 => bytes[position] = interesting[(next() as usize) % interesting.len()],
+            1 => bytes.insert(position, next() as u8),
+            _ => {
+              bytes.remove(position);
+            }
+          }
+        }
+        let _ = WgConfig::parse_bytes(&bytes);
+      }
+    }
+  }
+
+  #[test]
+  fn status_reads_the_allowed_ips_from_the_dump() {
+    init_logger();
+    let dump = "wg0\tPRIVATE\tPUBLIC\t51820\toff\n\
+wg0\tPEER1\t(none)\t203.0.113.1:51820\t10.0.0.0/24,fd00::/64\t1700000000\t10\t20\t25\n\
+wg0\tPEER2\t(none)\t(none)\t(none)\t0\t0\t0\toff\n";
+    let runner = StubRunner::default().respond("wg show all dump", 0, dump, "");
+
+    let tunnels = status_with(&runner).unwrap();
+    assert_eq!(tunnels[0].peers[0].allowed_ips, ["10.0.0.0/24", "fd00::/64"]);
+    assert!(tunnels[0].peers[1].allowed_ips.is_empty());
+    assert_eq!(tunnels[0].peers[1].endpoint, None);
+    // A zero handshake means none took place, the others are seconds since the epoch
+    let handshake = tunnels[0].peers[0].latest_handshake.unwrap();
+    assert_eq!(handshake.duration_since(UNIX_EPOCH).unwrap(), Duration::from_secs(1_700_000_000));
+    assert_eq!(tunnels[0].peers[1].latest_handshake, None);
+  }
+
+  #[test]
+  fn networks_are_normalized_like_wg() {
+    for (network, normalized) in [
+      ("10.0.0.7/24", "10.0.0.0/24"),
+      ("10.0.0.7", "10.0.0.7/32"),
+      ("0.0.0.0/0", "0.0.0.0/0"),
+      ("::0/0", "::/0"),
+      ("fd42:42:42::2/64", "fd42:42:42::/64"),
+      (" fd00::1 ", "fd00::1/128"),
+      ("10.0.0.0/33", "10.0.0.0/33"),
+      ("not a network", "not a network"),
+    ] {
+      assert_eq!(normalize_network(network), normalized);
+    }
+  }
+
+  #[test]
+  fn capabilities_explain_the_missing_programs() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = temp_dir();
+    let dir = temp.path();
+    let bin = dir.join("bin");
+    fs::create_dir(&bin).unwrap();
+    fs::write(bin.join(WG_QUICK), "#!/bin/sh\n").unwrap();
+    fs::set_permissions(bin.join(WG_QUICK), fs::Permissions::from_mode(0o755)).unwrap();
+    // A file which cannot be executed is no program
+    fs::write(bin.join(WG), "").unwrap();
+    let dirs = std::env::join_paths([dir.join("missing"), bin.clone()]).unwrap();
+    let only_wg_quick = Capabilities::detect_in(&dirs);
+    assert_eq!(only_wg_quick, Capabilities { wg: false, wg_quick: true });
+
+    assert!(only_wg_quick.check(Operation::Connect).is_ok());
+    let error = only_wg_quick.check(Operation::Status).unwrap_err();
+    assert!(matches!(&error, WgError::Unavailable { missing, .. } if missing == &[WG]));
+    assert_eq!(
+      error.to_string(),
+      "status needs wg, which is not installed (see wireguard-tools); only connect is possible with the installed programs"
+    );
+    assert!(only_wg_quick.check(Operation::Disconnect).is_err());
+
+    let only_wg = Capabilities { wg: true, wg_quick: false };
+    assert!(only_wg.check(Operation::Status).is_ok());
+    assert_eq!(
+      only_wg.check(Operation::Disconnect).unwrap_err().to_string(),
+      "disconnect needs wg-quick, which is not installed (see wireguard-tools); only status is possible with the installed programs"
+    );
+    let none = Capabilities { wg: false, wg_quick: false };
+    assert!(none.check(Operation::Disconnect).unwrap_err().to_string().starts_with("disconnect needs wg and wg-quick, which are"));
+    assert!(Capabilities { wg: true, wg_quick: true }.check(Operation::Disconnect).is_ok());
+  }
+
+  #[test]
+  fn address_pool_picks_the_next_free_address() {
+    let ips = |addresses: &[&str]| -> Vec<IpAddr> { addresses.iter().map(|address| address.parse().unwrap()).collect() };
+    let pool: AddressPool = "10.9.0.7/24".parse().unwrap();
+    assert_eq!(pool.to_string(), "10.9.0.0/24");
+    assert_eq!(pool.next_free(&[]), Some("10.9.0.1".parse().unwrap()));
+    assert_eq!(pool.next_free(&ips(&["10.9.0.2", "10.9.0.1", "10.9.0.4"])), Some("10.9.0.3".parse().unwrap()));
+    // Addresses of other networks and families are not in the way
+    assert_eq!(pool.next_free(&ips(&["10.8.0.1", "fd00::1", "10.9.0.1"])), Some("10.9.0.2".parse().unwrap()));
+
+    let small: AddressPool = "10.9.0.0/30".parse().unwrap();
+    assert_eq!(small.next_free(&ips(&["10.9.0.1"])), Some("10.9.0.2".parse().unwrap()));
+    assert_eq!(small.next_free(&ips(&["10.9.0.1", "10.9.0.2"])), None);
+    let point_to_point: AddressPool = "10.9.0.0/31".parse().unwrap();
+    assert_eq!(point_to_point.next_free(&ips(&["10.9.0.0"])), Some("10.9.0.1".parse().unwrap()));
+    let v6: AddressPool = "fd42::/64".parse().unwrap();
+    assert_eq!(v6.next_free(&ips(&["fd42::1", "fd42::1"])), Some("fd42::2".parse().unwrap()));
+
+    for invalid in ["10.9.0.1", "10.9.0.0/33", "pool"] {
+      assert!(matches!(invalid.parse::<AddressPool>(), Err(WgError::InvalidPool(_))), "{invalid}");
+    }
+  }
+
+  #[test]
+  fn assigned_address_follows_the_interface_header() {
+    let content = format!("# test\n[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\n");
+    assert_eq!(
+      insert_address_str(&content, "10.9.0.2/24").unwrap().as_str(),
+      format!("# test\n[Interface]\nAddress = 10.9.0.2/24\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\n")
+    );
+    assert!(insert_address_str(&format!("[Peer]\nPublicKey = {KEY}\n"), "10.9.0.2/24").is_none());
+
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wg0.conf");
+    fs::write(&path, &content).unwrap();
+    let pool: AddressPool = "10.9.0.0/24".parse().unwrap();
+    assert_eq!(assign_address(&path, &pool, &["10.9.0.1".parse().unwrap()]).unwrap(), "10.9.0.2/24");
+    assert_eq!(interface_addresses(&path).unwrap(), ["10.9.0.2".parse::<IpAddr>().unwrap()]);
+    assert!(matches!(assign_address(&path, &pool, &[]), Err(WgError::AddressAlreadySet(_))));
+  }
+
+  #[test]
+  fn allowed_ips_match_ignores_order_and_notation() {
+    let peer = Peer {
+      allowed_ips: vec!["10.8.0.2/32".to_string(), "::0/0".to_string()],
+      ..Default::default()
+    };
+    assert!(peer.allowed_ips_match(&["::/0".to_string(), "10.8.0.2/32".to_string()]));
+    assert!(!peer.allowed_ips_match(&["10.8.0.2/32".to_string()]));
+    assert!(!peer.allowed_ips_match(&["10.8.0.2/32".to_string(), "::/0".to_string(), "10.9.0.0/16".to_string()]));
+  }
+
+  #[test]
+  fn parse_reads_save_config() {
+    let parse = |value: &str| WgConfig::parse_str(&format!("[Interface]\nPrivateKey = {KEY}\nSaveConfig = {value}\n"));
+    assert!(parse("true").unwrap().interface.save_config);
+    assert!(parse("TRUE").unwrap().interface.save_config);
+    assert!(!parse("false").unwrap().interface.save_config);
+    assert!(!WgConfig::parse_str(&format!("[Interface]\nPrivateKey = {KEY}\n")).unwrap().interface.save_config);
+    assert!(matches!(parse("yes"), Err(WgParseError::InvalidValue { line: 3, field: "SaveConfig", .. })));
+  }
+
+  #[test]
+  fn strip_save_config_removes_only_the_interface_entries() {
+    let content = format!(
+      "# managed by hand\r\n[Interface]\r\nPrivateKey = {KEY}\r\nSaveConfig = true # set by wg-quick\r\n\
+       saveconfig=false\r\n\r\n[Peer]\r\nPublicKey = {KEY}\r\nSaveConfig = true\r\n"
+    );
+    let stripped = strip_interface_entries_str(&content, "SaveConfig").unwrap();
+
+    assert_eq!(
+      stripped,
+      format!("# managed by hand\r\n[Interface]\r\nPrivateKey = {KEY}\r\n\r\n[Peer]\r\nPublicKey = {KEY}\r\nSaveConfig = true\r\n")
+    );
+    assert!(!WgConfig::parse_str(&stripped).is_ok_and(|config| config.interface.save_config));
+    assert_eq!(strip_interface_entries_str(&stripped, "SaveConfig"), None);
+  }
+
+  #[test]
+  fn strip_save_config_rewrites_the_file_keeping_its_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wg0.conf");
+    fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\nSaveConfig = true\n")).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+    assert!(strip_save_config(&path).unwrap());
+    assert_eq!(fs::read_to_string(&path).unwrap(), format!("[Interface]\nPrivateKey = {KEY}\n"));
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    assert!(!strip_save_config(&path).unwrap());
+    assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+  }
+
+  #[test]
+  fn failed_replacements_leave_no_temporary_file() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    // A directory cannot be renamed over, so the replacement fails
+    let path = dir.join("wg0.conf");
+    fs::create_dir(&path).unwrap();
+    fs::write(path.join("keep"), KEY).unwrap();
+
+    assert!(replace_config_file(&path, &format!("[Interface]\nPrivateKey = {KEY}\n")).is_err());
+    assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+    assert_eq!(fs::read_to_string(path.join("keep")).unwrap(), KEY);
+  }
+
+  #[test]
+  fn replace_private_key_keeps_the_rest_of_the_file() {
+    let content = format!(
+      "[Interface]\r\n  privatekey={KEY} # old key\r\nAddress = 10.0.0.2/32\r\n\r\n[Peer]\r\nPublicKey = {KEY}\r\n"
+    );
+    let (private, _) = generate_keypair();
+    let replaced = replace_private_key_str(&content, private.as_str()).unwrap();
+
+    assert_eq!(*replaced, content.replacen(KEY, private.as_str(), 1));
+    assert_eq!(replace_private_key_str("[Peer]\nPublicKey = x\n", private.as_str()), None);
+  }
+
+  #[test]
+  fn rotate_private_key_replaces_the_keypair_and_backs_up_the_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wg0.conf");
+    let content = format!("[Interface]\nPrivateKey = {KEY}\nListenPort = 51820\n\n[Peer]\nPublicKey = {KEY}\n");
+    fs::write(&path, &content).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+    let (public, backup) = rotate_private_key(&path).unwrap();
+    let config = WgConfig::parse(&path).unwrap();
+    assert_ne!(config.interface.private_key, KEY);
+    assert_eq!(public_from_private(&config.interface.private_key).unwrap(), public);
+    assert_ne!(public, public_from_private(KEY).unwrap());
+    assert_eq!(config.interface.listen_port, Some(51820));
+    assert_eq!(config.peers[0].public_key, KEY);
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(backup, dir.join("wg0.conf.bak"));
+    assert_eq!(fs::read_to_string(&backup).unwrap(), content);
+
+    fs::write(&path, "[Interface]\n").unwrap();
+    assert!(matches!(rotate_private_key(&path), Err(WgError::Parse(_))));
+    assert!(matches!(rotate_private_key(&dir.join("wg1.conf")), Err(WgError::MissingConfig(_))));
+  }
+
+  #[test]
+  fn system_port_checker_sees_bound_ports() {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+    let port = socket.local_addr().unwrap().port();
+    assert!(SystemPortChecker.in_use(port));
+    drop(socket);
+    assert!(SystemPortChecker.interface_exists("lo"));
+  }
+
+  #[test]
+  fn udp_prober_tells_the_outcomes() {
+    let prober = UdpProber {
+      timeout: Duration::from_millis(200),
+    };
+    let echo = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let echo_port = echo.local_addr().unwrap().port();
+    let answering = thread::spawn(move || {
+      let mut buffer = [0; 64];
+      let (len, from) = echo.recv_from(&mut buffer).unwrap();
+      echo.send_to(&buffer[..len], from).unwrap();
+    });
+    assert_eq!(prober.probe(&format!("127.0.0.1:{echo_port}")), Reachability::Reachable);
+    answering.join().unwrap();
+
+    let silent = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let silent_port = silent.local_addr().unwrap().port();
+    assert_eq!(prober.probe(&format!("127.0.0.1:{silent_port}")), Reachability::Unknown);
+    drop(silent);
+    // Nothing listens anymore, so the loopback answers with ICMP
+    assert!(matches!(prober.probe(&format!("127.0.0.1:{silent_port}")), Reachability::Unreachable(_)));
+    assert!(matches!(prober.probe("vpn.invalid"), Reachability::Unreachable(_)));
+  }
+
+  #[test]
+  fn allowed_ip_sets_are_expanded() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = write_config(dir, "wg0");
+    fs::create_dir(dir.join("sets")).unwrap();
+    fs::write(dir.join("sets/office.set"), "# Office\n10.1.0.0/16\n\n192.168.5.0/24  # printers\nfd00::/64\n").unwrap();
+    let content = format!(
+      "[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\nAllowedIPs = 10.0.0.1/32, @sets/office.set\n"
+    );
+
+    let expanded = expand_allowed_ip_sets_str(&content, &path).unwrap().unwrap();
+    assert_eq!(
+      expanded.as_str(),
+      format!(
+        "[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\nAllowedIPs = 10.0.0.1/32\n\
+         AllowedIPs = 10.1.0.0/16\nAllowedIPs = 192.168.5.0/24\nAllowedIPs = fd00::/64\n"
+      )
+    );
+    let mut config = WgConfig::parse_str(&content).unwrap();
+    assert!(config.expand_allowed_ip_sets(&path).unwrap());
+    assert_eq!(config.peers[0].allowed_ips, ["10.0.0.1/32", "10.1.0.0/16", "192.168.5.0/24", "fd00::/64"]);
+    assert!(expand_allowed_ip_sets_str(&content.replace(", @sets/office.set", ""), &path).unwrap().is_none());
+  }
+
+  #[test]
+  fn allowed_ip_sets_report_the_invalid_line() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = write_config(dir, "wg0");
+    fs::write(dir.join("bad.set"), "10.1.0.0/16\n10.2.0.0/33\n").unwrap();
+    let content = format!("[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\nAllowedIPs = @bad.set\n");
+
+    let error = expand_allowed_ip_sets_str(&content, &path).unwrap_err();
+    assert!(matches!(&error, WgParseError::InvalidSetEntry { line: 2, entry, .. } if entry == "10.2.0.0/33"));
+    assert!(error.to_string().contains("bad.set, line 2"));
+    let missing = expand_allowed_ip_sets_str(&content.replace("bad", "missing"), &path);
+    assert!(matches!(missing, Err(WgParseError::SetFile { .. })));
+  }
+
+  #[test]
+  fn bring_up_gives_wg_quick_an_expanded_copy() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wg0.conf");
+    fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\nAllowedIPs = @routes.set\n")).unwrap();
+    fs::write(dir.join("routes.set"), "10.1.0.0/16\n").unwrap();
+    let runner = StubRunner::default();
+
+    bring_up_with(&runner, &path).unwrap();
+    let calls = runner.calls();
+    let copy = PathBuf::from(calls[0].strip_prefix("wg-quick up ").unwrap());
+    assert_ne!(copy, path);
+    assert_eq!(copy.file_name().unwrap(), "wg0.conf");
+    assert!(!copy.exists() && !copy.parent().unwrap().exists());
+  }
+
+  #[test]
+  fn encrypted_configs_are_decrypted_into_a_private_copy() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wg0.conf");
+    fs::write(&path, "-----BEGIN AGE ENCRYPTED FILE-----\nYWdlLWVuY3J5cHRpb24ub3JnL3YxCg==\n-----END AGE ENCRYPTED FILE-----\n").unwrap();
+    let decrypt = format!("age --decrypt -i /keys/age.txt {}", path.display());
+    let plaintext = format!("[Interface]\nPrivateKey = {KEY}\n");
+
+    // Decrypting is refused unless a command is configured for the tool
+    let refused = read_plaintext(&StubRunner::default(), &path, None).unwrap_err();
+    assert!(matches!(refused, WgError::Encrypted { encryption: Encryption::Age, .. }));
+    let failing = StubRunner::default().respond(&decrypt, 1, "", "age: error: no identity matched any of the recipients\n");
+    let decryptors = Decryptors {
+      age: Some("age --decrypt -i /keys/age.txt".to_string()),
+      sops: None,
+    };
+    let error = read_plaintext(&failing, &path, Some(&decryptors)).unwrap_err().to_string();
+    assert!(error.ends_with("(age: error: no identity matched any of the recipients)"), "{error}");
+
+    set_decryptors(decryptors);
+    let runner = StubRunner::default()
+        .respond(&decrypt, 0, &plaintext, "")
+        .respond("wg show interfaces", 0, "wg0\n", "");
+    bring_up_with(&runner, &path).unwrap();
+    assert_eq!(bring_down_with(&runner, &path).unwrap(), Teardown::Down);
+    let calls = runner.calls();
+    assert_eq!(calls.len(), 5);
+    assert_eq!((calls[0].as_str(), calls[3].as_str()), (decrypt.as_str(), decrypt.as_str()));
+    for (call, command) in [(&calls[1], "wg-quick up "), (&calls[4], "wg-quick down ")] {
+      let copy = PathBuf::from(call.strip_prefix(command).unwrap());
+      assert_ne!(copy, path);
+      assert_eq!(copy.file_name().unwrap(), "wg0.conf");
+      assert!(!copy.exists() && !copy.parent().unwrap().exists());
+    }
+  }
+
+  /// Define a clock moving a second forward each time it is read.
+  #[derive(Debug)]
+  struct TickingClock(Mutex<DateTime<Local>>);
+
+  impl Clock for TickingClock {
+    fn now(&self) -> DateTime<Local> {
+      let mut now = self.0.lock().unwrap();
+      *now += chrono::Duration::seconds(1);
+      *now
+    }
+  }
+
+  #[test]
+  fn secrets_are_redacted() {
+    let content = format!(
+      "[Interface]\r\nPrivateKey = {KEY}\r\n# The PrivateKey is rotated yearly\r\nListenPort = 51820\n\n[Peer]\n  presharedkey={KEY}\nPublicKey = {KEY}"
+    );
+    assert_eq!(
+      redact_secrets_str(&content),
+      format!(
+        "[Interface]\r\nPrivateKey = <redacted>\r\n# The PrivateKey is rotated yearly\r\nListenPort = 51820\n\n[Peer]\n  presharedkey = <redacted>\nPublicKey = {KEY}"
+      )
+    );
+  }
+
+  #[test]
+  fn tunnels_without_a_handshake_are_rolled_back() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wg0.conf");
+    fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\n")).unwrap();
+    let dump = |handshake| format!("wg0\tPRIVATE\tPUBLIC\t51820\toff\nwg0\tPEER\t(none)\t(none)\t(none)\t{handshake}\t0\t0\toff\n");
+    let deadline = || Deadline::after(Duration::from_secs(3), Arc::new(TickingClock(Mutex::new(Local::now()))));
+    let timeout = Duration::from_secs(3);
+
+    // The peer never answers: the tunnel is polled until the deadline, then brought down
+    let runner = StubRunner::default()
+        .respond("wg show all dump", 0, &dump(0), "")
+        .respond("wg show interfaces", 0, "wg0\n", "");
+    let error = await_handshake_with(&runner, &path, timeout, &deadline(), Duration::ZERO).unwrap_err();
+    assert!(matches!(error, WgError::HandshakeTimeout { timeout: t, .. } if t == timeout));
+    let calls = runner.calls();
+    assert!(calls.iter().filter(|call| *call == "wg show all dump").count() > 1);
+    assert_eq!(calls[calls.len() - 2..], ["wg show interfaces".to_string(), format!("wg-quick down {}", path.display())]);
+
+    // The peer answers: the tunnel is kept
+    let runner = StubRunner::default().respond("wg show all dump", 0, &dump(1_700_000_000), "");
+    await_handshake_with(&runner, &path, timeout, &deadline(), Duration::ZERO).unwrap();
+    assert_eq!(runner.calls(), ["wg show all dump"]);
+  }
+
+  #[test]
+  fn dns_is_given_to_the_selected_backend() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = dir.join("wg0.conf");
+    fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\nDNS = 10.0.0.1, corp.internal\n")).unwrap();
+    let record = r#""nameserver 10.0.0.1\nsearch corp.internal\n""#;
+    let sequence = |backend, runner: StubRunner| {
+      bring_up_with_dns(&runner, &path, Some(backend)).unwrap();
+      let down = bring_down_with_dns(&runner, &path, &["wg0".to_string()], Some(backend)).unwrap();
+      assert_eq!(down, Teardown::Down);
+      // wg-quick is given a copy without the DNS, which it would set itself
+      runner
+          .calls()
+          .into_iter()
+          .map(|call| match call.split_once(" /") {
+            Some((command, copy)) if command.starts_with("wg-quick") => {
+              assert_ne!(Path::new(&format!("/{copy}")), path);
+              format!("{command} <copy>")
+            }
+            _ => call,
+          })
+          .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+      sequence(DnsBackend::Resolvconf, StubRunner::default()),
+      [
+        "wg-quick up <copy>".to_string(),
+        format!("resolvconf -a tun.wg0 < {record}"),
+        "resolvconf -d tun.wg0 -f".to_string(),
+        "wg-quick down <copy>".to_string(),
+      ]
+    );
+    assert_eq!(
+      sequence(DnsBackend::Openresolv, StubRunner::default()),
+      [
+        "wg-quick up <copy>".to_string(),
+        format!("resolvconf -a wg0 -m 0 -x < {record}"),
+        "resolvconf -d wg0 -f".to_string(),
+        "wg-quick down <copy>".to_string(),
+      ]
+    );
+    assert_eq!(
+      sequence(DnsBackend::SystemdResolved, StubRunner::default()),
+      [
+        "wg-quick up <copy>",
+        "resolvectl dns wg0 10.0.0.1",
+        "resolvectl domain wg0 corp.internal ~.",
+        "resolvectl revert wg0",
+        "wg-quick down <copy>",
+      ]
+    );
+    let detected = StubRunner::default().respond("resolvconf --version", 0, "openresolv 3.13.2\n", "");
+    assert_eq!(
+      sequence(DnsBackend::Auto, detected),
+      [
+        "resolvconf --version".to_string(),
+        "wg-quick up <copy>".to_string(),
+        format!("resolvconf -a wg0 -m 0 -x < {record}"),
+        "resolvconf --version".to_string(),
+        "resolvconf -d wg0 -f".to_string(),
+        "wg-quick down <copy>".to_string(),
+      ]
+    );
+
+    // The tunnel is brought down again when its DNS cannot be set
+    let failing = StubRunner::default().respond("resolvectl dns wg0 10.0.0.1", 1, "", "Failed to set DNS configuration\n");
+    let error = bring_up_with_dns(&failing, &path, Some(DnsBackend::SystemdResolved)).unwrap_err();
+    assert!(matches!(error, WgError::Dns(DnsError::Failed { .. })), "{error}");
+    assert!(failing.calls().last().unwrap().starts_with("wg-quick down /"));
+    // Nor is the file copied when it sets no DNS
+    fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\n")).unwrap();
+    let runner = StubRunner::default();
+    bring_up_with_dns(&runner, &path, Some(DnsBackend::Auto)).unwrap();
+    assert_eq!(runner.calls(), [format!("wg-quick up {}", path.display())]);
+  }
+
+  #[test]
+  fn encryption_is_detected() {
+    assert_eq!(Encryption::detect(b"age-encryption.org/v1\n-> X25519 abc\n"), Some(Encryption::Age));
+    assert_eq!(Encryption::detect(b"{\"data\": \"ENC[AES256_GCM,data:abc]\", \"sops\": {}}"), Some(Encryption::Sops));
+    assert_eq!(Encryption::detect(b"[Interface]\nPrivateKey = ENC[AES256_GCM]\n\n[sops]\nversion = 3.9.0\n"), Some(Encryption::Sops));
+    assert_eq!(Encryption::detect(b"data: ENC[AES256_GCM]\nsops:\n    version: 3.9.0\n"), Some(Encryption::Sops));
+    assert_eq!(Encryption::detect(format!("[Interface]\nPrivateKey = {KEY}\n").as_bytes()), None);
+    assert_eq!(Encryption::detect(b"\xff\xfe"), None);
+  }
+
+  #[test]
+  fn benchmarks_leave_the_tunnel_as_it_was() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = write_config(dir, "wg0");
+    let benchmark = Benchmark::Download {
+      url: "http://10.0.0.1/1M.bin".to_string(),
+    };
+    let download = "curl --fail --silent --show-error --location --output /dev/null --write-out %{speed_download} -- http://10.0.0.1/1M.bin";
+    let up = format!("wg-quick up {}", path.display());
+
+    // A tunnel which is up stays up
+    let runner = StubRunner::default()
+        .respond("wg show interfaces", 0, "wg0\n", "")
+        .respond(download, 0, "1250000.000", "");
+    let (throughput, brought_up) = benchmark_with(&runner, &path, &benchmark).unwrap();
+    assert_eq!((throughput.to_string(), brought_up), ("10.0 Mbit/s".to_string(), false));
+    assert_eq!(runner.calls(), ["wg show interfaces", download]);
+
+    // A tunnel which is down is brought down again, even when curl fails
+    let runner = StubRunner::default().respond(download, 22, "", "curl: (22) The requested URL returned error: 404\n");
+    let error = benchmark_with(&runner, &path, &benchmark).unwrap_err();
+    assert!(matches!(error, WgError::Benchmark(BenchError::Failed { .. })), "{error}");
+    let down = format!("wg-quick down {}", path.display());
+    assert_eq!(runner.calls(), ["wg show interfaces", up.as_str(), download, down.as_str()]);
+  }
+
+  #[test]
+  fn orphans_are_brought_down_with_or_without_their_file() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = write_config(dir, "wg0");
+    let runner = StubRunner::default();
+    tear_down_orphan_with(&runner, "wg0", &path).unwrap();
+    fs::remove_file(&path).unwrap();
+    tear_down_orphan_with(&runner, "wg0", &path).unwrap();
+    let down = format!("wg-quick down {}", path.display());
+    assert_eq!(runner.calls(), [down.as_str(), "ip link delete dev wg0"]);
+  }
+
+  #[test]
+  fn only_active_tunnels_are_reconnected() {
+    init_logger();
+    let temp = temp_dir();
+    let dir = temp.path();
+    let path = write_config(dir, "wg0");
+    let runner = StubRunner::default().respond("wg show interfaces", 0, "wg0\n", "");
+    assert!(reconnect_with(&runner, &path).unwrap());
+    let down = format!("wg-quick down {}", path.display());
+    let up = format!("wg-quick up {}", path.display());
+    assert_eq!(runner.calls(), ["wg show interfaces", down.as_str(), up.as_str()]);
+
+    // A tunnel the user disconnected stays down
+    let runner = StubRunner::default();
+    assert!(!reconnect_with(&runner, &path).unwrap());
+    assert_eq!(runner.calls(), ["wg show interfaces"]);
+  }
+
+  #[test]
+  fn set_arguments_are_forwarded_verbatim() {
+    init_logger();
+    let runner = StubRunner::default();
+    let args: Vec<String> = ["peer", KEY, "endpoint", "[2001:db8::1]:51820", "allowed-ips", "10.0.0.0/24,fd00::/64"]
+        .map(String::from)
+        .to_vec();
+    set_with(&runner, "wg0", &args).unwrap();
+    let expected = format!("wg set wg0 peer {KEY} endpoint [2001:db8::1]:51820 allowed-ips 10.0.0.0/24,fd00::/64");
+    assert_eq!(runner.calls(), [expected]);
+
+    let refused = ["private-key", "/root/leaked.key"].map(String::from);
+    let error = set_with(&runner, "wg0", &refused).unwrap_err();
+    assert!(matches!(&error, WgError::RefusedSetArgument { argument, .. } if argument == "private-key"), "{error}");
+    assert_eq!(runner.calls().len(), 1);
+
+    let failing = StubRunner::default().respond("wg set wg0 listen-port x", 1, "", "Unable to parse port\n");
+    let error = set_with(&failing, "wg0", &["listen-port", "x"].map(String::from)).unwrap_err();
+    assert!(error.to_string().ends_with("Unable to parse port"), "{error}");
+  }
+
+  /// Define a resolver answering every hostname with an IPv4 and an IPv6
+  /// address, in that order.
+  struct DualResolver;
+
+  impl Resolver for DualResolver {
+    fn resolve(&self, host: &str, _port: u16) -> io::Result<Vec<IpAddr>> {
+      match host {
+        "vpn.example.com" => Ok(vec!["192.0.2.10".parse().unwrap(), "2001:db8::10".parse().unwrap()]),
+        _ => Err(io::Error::new(io::ErrorKind::NotFound, "unknown host")),
+      }
+    }
+  }
+
+  #[test]
+  fn endpoint_family_picks_the_address() {
+    let dual = DualResolver.resolve("vpn.example.com", 51820).unwrap();
+    let v4: IpAddr = "192.0.2.10".parse().unwrap();
+    let v6: IpAddr = "2001:db8::10".parse().unwrap();
+    assert_eq!(AddressFamily::V4First.choose(&dual), Some(v4));
+    assert_eq!(AddressFamily::V6First.choose(&dual), Some(v6));
+    assert_eq!(AddressFamily::V4Only.choose(&dual), Some(v4));
+    assert_eq!(AddressFamily::V6Only.choose(&dual), Some(v6));
+    assert_eq!(AddressFamily::V6First.choose(&[v4]), Some(v4));
+    assert_eq!(AddressFamily::V6Only.choose(&[v4]), None);
+    assert_eq!(serde_json::from_str::<AddressFamily>("\"v6-only\"").unwrap(), AddressFamily::V6Only);
+  }
+
+  #[test]
+  fn endpoints_are_resolved_to_the_preferred_family() {
+    let content = format!(
+      "[Interface]\nPrivateKey = {KEY}\n\n[Peer]\nPublicKey = {KEY}\nEndpoint = vpn.example.com:51820\n\n\
+       [Peer]\nPublicKey = {KEY}\nEndpoint = 198.51.100.1:51820\n"
+    );
+    let resolved = |family| resolve_endpoints_str(&content, family, &DualResolver).unwrap().unwrap();
+    assert!(resolved(AddressFamily::V4First).contains("Endpoint = 192.0.2.10:51820\n"));
+    assert!(resolved(AddressFamily::V6First).contains("Endpoint = [2001:db8::10]:51820\n"));
+    assert!(resolved(AddressFamily::V6Only).contains("Endpoint = 198.51.100.1:51820\n"));
+
+    let literals = content.replace("vpn.example.com", "[2001:db8::1]");
+    assert!(resolve_endpoints_str(&literals, AddressFamily::V4Only, &DualResolver).unwrap().is_none());
+    let unknown = content.replace("vpn.example.com", "gone.example.com");
+    let error = resolve_endpoints_str(&unknown, AddressFamily::V4First, &DualResolver).unwrap_err();
+    assert!(matches!(&error, WgError::Resolve { endpoint, .. } if endpoint == "gone.example.com:51820"));
+  }
+
+  #[test]
+  fn parse_reads_the_amneziawg_parameters() {
+    let content = format!(
+      "[Interface]\nPrivateKey = {KEY}\nJc = 4\nJmin = 40\nJmax = 70\nS1 = 15\nS2 = 68\n\
+       H1 = 1733852312\nH2 = 2045911262\nH3 = 1412174367\nH4 = 1122673969\n"
+    );
+    let config = WgConfig::parse_str(&content).unwrap();
+    let obfuscation = &config.interface.obfuscation;
+
+    assert!(obfuscation.is_enabled());
+    assert_eq!(
+      (obfuscation.junk_count, obfuscation.junk_min, obfuscation.junk_max),
+      (Some(4), Some(40), Some(70))
+    );
+    assert_eq!((obfuscation.init_padding, obfuscation.response_padding), (Some(15), Some(68)));
+    assert_eq!(
+      obfuscation.headers,
+      [Some(1733852312), Some(2045911262), Some(1412174367), Some(1122673969)]
+    );
+    assert!(!WgConfig::parse_str(SERVER).unwrap().interface.obfuscation.is_enabled());
+    assert!(matches!(
+      WgConfig::parse_str(&format!("[Interface]\nPrivateKey = {KEY}\nH1 = 4294967296\n")),
+      Err(WgParseError::InvalidValue { line: 3, field: "H1", .. })
+    ));
+  }
+
+  #[test]
+  fn serialize_round_trips() {
+    let amneziawg = format!("{SERVER}\n[Interface]\n").replacen(
+      "MTU = 1420",
+      "Jc = 4\nJmin = 40\nJmax = 70\nS1 = 0\nS2 = 68\nH1 = 1\nH2 = 2\nH3 = 3\nH4 = 4294967295",
+      1,
+    );
+    let amneziawg = amneziawg.trim_end_matches("\n[Interface]\n");
+
+    for content in [WG_QUICK_EXAMPLE, SERVER, CLIENT, amneziawg] {
+      let config = WgConfig::parse_str(content).unwrap();
+      let serialized = config.serialize();
+      assert_eq!(WgConfig::parse_str(&serialized).unwrap(), config, "{}", serialized.as_str());
+    }
+
+    let config = WgConfig::parse_str(amneziawg).unwrap();
+    assert!(config.serialize().contains("\nJc = 4\n"));
+    assert!(config.serialize().contains("\nH4 = 4294967295\n"));
+    assert!(!WgConfig::parse_str(SERVER).unwrap().serialize().contains("Jc"));
+  }
+
+  #[test]
+  fn templated_interfaces_get_a_copy_named_after_them() {
+    init_logger();
+    let temp = temp_dir();
+    let path = temp.path().join("alice.conf");
+    fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\n")).unwrap();
+    set_interface_names(HashMap::from([(path.clone(), "wg-prod-1".to_string())]));
+    assert_eq!(interface_name(&path), Some("wg-prod-1"));
+    assert_eq!(interface_name(&temp.path().join("bob.conf")), Some("bob"));
+
+    let runner = StubRunner::default().respond("wg show interfaces", 0, "wg-prod-1\n", "");
+    bring_up_with(&runner, &path).unwrap();
+    assert_eq!(bring_down_with(&runner, &path).unwrap(), Teardown::Down);
+    let calls = runner.calls();
+    for (call, command) in [(&calls[0], "wg-quick up "), (calls.last().unwrap(), "wg-quick down ")] {
+      let copy = PathBuf::from(call.strip_prefix(command).unwrap());
+      assert_eq!(copy.file_name().unwrap(), "wg-prod-1.conf");
+      assert!(!copy.exists() && !copy.parent().unwrap().exists());
+    }
+  }
+}
+
@@ -0,0 +1,140 @@
+// wipe.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Size of the buffer used to overwrite the file contents.
+const WIPE_CHUNK: usize = 4096;
+
+/// Function to securely remove a file holding sensitive data.
+///
+/// The contents of the file are overwritten with zeros and synced to disk
+/// before the file is unlinked. Overwriting is best-effort, since journaling
+/// and copy-on-write filesystems may keep older copies of the data: a failure
+/// while overwriting does not prevent the removal of the file.
+///
+/// # Arguments
+/// * `path`: The path of the file to be wiped.
+///
+/// # Returns
+/// * `io::Result<()>`: The error, if any, raised while removing the file.
+pub fn wipe_file(path: &Path) -> io::Result<()> {
+  let _ = overwrite(path);
+  fs::remove_file(path)
+}
+
+/// Function to overwrite the whole content of a file with zeros.
+///
+/// # Arguments
+/// * `path`: The path of the file to be overwritten.
+///
+/// # Returns
+/// * `io::Result<()>`: The error, if any, raised while overwriting the file.
+fn overwrite(path: &Path) -> io::Result<()> {
+  let mut remaining = fs::metadata(path)?.len();
+  let mut file = OpenOptions::new().write(true).open(path)?;
+  let zeros = [0u8; WIPE_CHUNK];
+
+  while remaining > 0 {
+    let chunk = remaining.min(WIPE_CHUNK as u64) as usize;
+    file.write_all(&zeros[..chunk])?;
+    remaining -= chunk as u64;
+  }
+  file.sync_all()
+}
+
+/// Define a guard that wipes a temporary secret file when dropped.
+///
+/// The guard makes sure the file is wiped on every exit path, including
+/// early returns on error and unwinding panics.
+#[derive(Debug)]
+pub struct WipeGuard {
+  path: PathBuf,
+  armed: bool,
+}
+
+/// Implements the logic of the wipe guard
+impl WipeGuard {
+  /// Function to create a guard for the given file.
+  ///
+  /// # Arguments
+  /// * `path`: The path of the file to be wiped when the guard is dropped.
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    WipeGuard {
+      path: path.into(),
+      armed: true,
+    }
+  }
+
+  /// Function to retrieve the path of the guarded file.
+  ///
+  /// # Returns
+  /// * `&Path`: The path of the guarded file.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Function to release the file without wiping it.
+  ///
+  /// This is used when the temporary file has been moved into its final
+  /// location and must be kept.
+  ///
+  /// # Returns
+  /// * `PathBuf`: The path of the released file.
+  pub fn disarm(mut self) -> PathBuf {
+    self.armed = false;
+    std::mem::take(&mut self.path)
+  }
+}
+
+impl Drop for WipeGuard {
+  fn drop(&mut self) {
+    if self.armed && self.path.exists() {
+      let _ = wipe_file(&self.path);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::testing::{KEY, temp_dir};
+
+  #[test]
+  fn wiped_files_are_overwritten_and_removed() {
+    let temp = temp_dir();
+    let path = temp.path().join("secret");
+    fs::write(&path, KEY.repeat(200)).unwrap();
+    // A hard link keeps the data reachable once the file is removed
+    let link = temp.path().join("link");
+    fs::hard_link(&path, &link).unwrap();
+
+    wipe_file(&path).unwrap();
+    assert!(!path.exists());
+    let left = fs::read(&link).unwrap();
+    assert_eq!(left.len(), KEY.len() * 200);
+    assert!(left.iter().all(|byte| *byte == 0));
+    assert!(wipe_file(&path).is_err());
+  }
+
+  #[test]
+  fn guards_wipe_their_file_unless_disarmed() {
+    let temp = temp_dir();
+    let path = temp.path().join("secret");
+    fs::write(&path, KEY).unwrap();
+    drop(WipeGuard::new(&path));
+    assert!(!path.exists());
+    // A guard whose file is gone already does nothing
+    drop(WipeGuard::new(&path));
+
+    fs::write(&path, KEY).unwrap();
+    let guard = WipeGuard::new(&path);
+    assert_eq!(guard.path(), path);
+    assert_eq!(guard.disarm(), path);
+    assert_eq!(fs::read_to_string(&path).unwrap(), KEY);
+  }
+}
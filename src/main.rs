@@ -8,16 +8,377 @@ mod cli;
 mod core;
 mod ui;
 
-use core::logger::Logger;
+use chrono::{DateTime, Local};
+use cli::commands::{Bulk, GlobalFlags, HealthCheck, Preflight};
+use cli::{CliError, Command, ConfigCommand, LogsCommand, PathCommand, TagCommand, WgConfigCommand};
+use core::audit::AuditLog;
+use core::bench::Benchmark;
+use core::clock::{Clock, Deadline, FixedClock, SystemClock};
+use core::color;
+use core::config::{Config, ConfigFormat, expand_path};
+use core::logger::{LogLevel, Logger, LoggerOptions};
+use core::mtu::{MtuProbe, PingProbe};
+use core::session;
+use core::update::{self, CurlClient};
+use core::netwatch::SystemNetwork;
+use core::wg::{self, Prober, SystemRunner, UdpProber};
+use std::error::Error;
+use std::io;
+use std::sync::Arc;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
-use chrono::Local;
+/// Path of the log file, a new one every day.
+const LOG_FILE: &str = "./%Y-%m-%d.log";
 
+fn main() {
+  let cli = cli::parse();
+  color::init(cli.no_color);
+  if let Some(format) = cli.config_format {
+    let _ = ConfigFormat::force(format);
+  }
 
+  // Reading the log file settings ahead of the configuration, whose loading
+  // already logs; a file failing to load is reported by `Config::init` below
+  let log_settings = cli
+      .config
+      .clone()
+      .map_or_else(Config::resolve_path, Ok)
+      .and_then(|path| Config::load_config(&path))
+      .map(|config| config.log)
+      .unwrap_or_default();
+  let permissions = log_settings.permissions();
 
-fn main() {
-  // Initializing logger
-  let date = Local::now().format("%Y-%m-%d").to_string();
-  let log_path = &format!("./{date}.log");
-  Logger::init(log_path);
-  let _log = Logger::get();
+  // Initializing logger, switching to a new file every day
+  let mut options = LoggerOptions {
+    color: color::allowed(),
+    ..Default::default()
+  };
+  if let Ok(permissions) = &permissions {
+    options.permissions = permissions.clone();
+  }
+  if cli.verbose {
+    options.min_level = LogLevel::Debug;
+    options.mirror_stderr = true;
+  }
+  if let Err(e) = Logger::init_with_options(LOG_FILE, options.clone()) {
+    eprintln!("{e}, logging to stderr only");
+    let _ = Logger::init_stderr(options);
+  }
+  let log = Logger::get();
+  if let Err(e) = permissions {
+    fail(log, &e);
+  }
+
+  // Bounding the time spent in WireGuard programs, when --timeout is given
+  if let Some(timeout) = cli.timeout {
+    wg::set_deadline(Deadline::after(timeout, Arc::new(SystemClock)));
+  }
+
+  // Running the WireGuard programs over SSH, when --remote is given
+  if let Some(remote) = &cli.remote
+      && let Err(e) = wg::set_remote(remote)
+  {
+    fail(log, &e);
+  }
+
+  // Loading the configuration, from --config when given
+  if let Err(e) = Config::init_with_path(cli.config.clone()) {
+    fail(log, &e);
+  }
+
+  // Resolving the endpoints to the preferred address family, when set
+  if let Some(family) = Config::get().endpoint_family {
+    wg::set_address_family(family);
+  }
+
+  // Naming the interfaces after the interface_template, when set
+  let interface_template = Config::get().interface_template.clone();
+  if let Some(template) = interface_template {
+    match cli::commands::interface_names(&Config::get().user, &template) {
+      Ok(names) => wg::set_interface_names(names),
+      Err(e) => fail(log, &e),
+    }
+  }
+
+  // Giving the DNS of the tunnels to the configured backend, when set
+  if let Some(backend) = Config::get().dns_backend {
+    wg::set_dns_backend(backend);
+  }
+
+  // Decrypting the encrypted WireGuard configurations, when enabled
+  if let Some(decryptors) = Config::get().decrypt.clone() {
+    wg::set_decryptors(decryptors);
+  }
+
+  // Tracking the interfaces brought up, so that `cleanup` finds the orphans
+  if let Some(dir) = session::default_dir() {
+    wg::set_session_dir(dir);
+  }
+
+  // Opening the audit log, when enabled in the configuration
+  let audit_log = Config::get().audit_log.clone();
+  if let Some(audit_log) = audit_log {
+    match expand_path(&audit_log) {
+      Ok(path) => {
+        if let Err(e) = AuditLog::init(&path) {
+          fail(log, &e);
+        }
+      }
+      Err(e) => fail(log, &e),
+    }
+  }
+
+  let result: Result<(), CliError> = match cli.command {
+    Command::Connect {
+      config_path,
+      all,
+      tag,
+      probe,
+      auto_mtu,
+      parallel,
+      detach,
+      interval,
+    } => cli::commands::connect(
+      &mut io::stdin().lock(),
+      &mut io::stdout().lock(),
+      &mut io::stderr().lock(),
+      config_path.as_deref(),
+      all.then(|| Bulk {
+        tag: tag.as_deref(),
+        workers: cli::workers(parallel),
+      }),
+      Preflight {
+        prober: probe.then_some(&UdpProber::default() as &dyn Prober),
+        mtu: auto_mtu.then_some(&PingProbe(wg::system_runner()) as &dyn MtuProbe),
+      },
+      &SystemNetwork(SystemRunner),
+    )
+    .and_then(|()| match detach {
+      true => cli::commands::detach(
+        &mut io::stdout().lock(),
+        config_path.as_deref(),
+        interval,
+        &monitor_globals(&cli.config, cli.config_format, &cli.remote),
+        Local::now().format(LOG_FILE).to_string().as_ref(),
+      ),
+      false => Ok(()),
+    }),
+    Command::Disconnect {
+      config_path,
+      all,
+      tag,
+      parallel,
+    } => cli::commands::disconnect(
+      &mut io::stdout().lock(),
+      &mut io::stderr().lock(),
+      config_path.as_deref(),
+      all.then(|| Bulk {
+        tag: tag.as_deref(),
+        workers: cli::workers(parallel),
+      }),
+    ),
+    Command::Status {
+      json,
+      fail_if_down,
+      threshold,
+      stale_after,
+      tag,
+      template,
+      since,
+    } => {
+      let check = fail_if_down.then(|| HealthCheck {
+        threshold,
+        stale_after: Duration::from_secs(stale_after),
+      });
+      cli::commands::status(&mut io::stdout().lock(), json, check.as_ref(), tag.as_deref(), template.as_deref(), since)
+    }
+    Command::Up { json } => cli::commands::up(&mut io::stdout().lock(), json),
+    Command::List { json, tag, template } => {
+      cli::commands::list(&mut io::stdout().lock(), json, tag.as_deref(), template.as_deref())
+    }
+    Command::RotateKeys { config_path, reload } => {
+      cli::commands::rotate_keys(&mut io::stdout().lock(), &config_path, reload)
+    }
+    Command::Benchmark {
+      config_path,
+      server,
+      url,
+      duration,
+    } => {
+      let benchmark = match (server, url) {
+        (Some(server), _) => Benchmark::Iperf {
+          server,
+          seconds: duration,
+        },
+        (None, url) => Benchmark::Download {
+          url: url.unwrap_or_default(),
+        },
+      };
+      cli::commands::benchmark(&mut io::stdout().lock(), &config_path, &benchmark)
+    }
+    Command::WgSet { interface, args } => cli::commands::wg_set(&mut io::stdout().lock(), &interface, &args),
+    Command::Cleanup { yes } => cli::commands::cleanup(&mut io::stdin().lock(), &mut io::stdout().lock(), yes),
+    Command::WatchNetwork { interval } => {
+      cli::commands::watch_network(&mut io::stdout().lock(), &mut io::stderr().lock(), interval)
+    }
+    Command::Monitor { config_path, interval } => {
+      cli::commands::monitor(&mut io::stdout().lock(), &mut io::stderr().lock(), &config_path, interval)
+    }
+    Command::GenerateTotp { config_path, at } => cli::commands::generate_totp(
+      &mut io::stdin().lock(),
+      &mut io::stdout().lock(),
+      &mut io::stderr().lock(),
+      config_path.as_deref(),
+      clock_at(at).as_ref(),
+    ),
+    Command::VerifyOtp { code, config_path, at } => cli::commands::verify_otp(
+      &mut io::stdin().lock(),
+      &mut io::stdout().lock(),
+      &mut io::stderr().lock(),
+      config_path.as_deref(),
+      &code,
+      clock_at(at).as_ref(),
+    ),
+    Command::Stop { config_path } => cli::commands::stop(&mut io::stdout().lock(), config_path.as_deref()),
+    Command::Doctor { probe } => {
+      cli::commands::doctor(&mut io::stdout().lock(), probe.then_some(&UdpProber::default() as &dyn Prober))
+    }
+    Command::DumpEffectiveConfig { json } => {
+      let flags = GlobalFlags {
+        config: cli.config.as_deref(),
+        config_format: cli.config_format,
+        timeout: cli.timeout,
+        remote: cli.remote.as_deref(),
+        no_color: cli.no_color,
+        verbose: cli.verbose,
+      };
+      cli::commands::dump_effective_config(&mut io::stdout().lock(), &flags, json)
+    }
+    Command::Lint { config_path, json } => cli::commands::lint(&mut io::stdout().lock(), config_path.as_deref(), json),
+    Command::AddUser {
+      config_path,
+      otp,
+      otp_uri,
+      encrypt,
+      assign_address,
+    } => cli::commands::add_user(
+      &mut io::stdin().lock(),
+      &mut io::stdout().lock(),
+      config_path,
+      otp,
+      otp_uri,
+      encrypt,
+      assign_address,
+    ),
+    Command::Discover { dirs, yes } => {
+      cli::commands::discover(&mut io::stdin().lock(), &mut io::stdout().lock(), &dirs, yes)
+    }
+    Command::RemoveUser { config_path, yes } => {
+      cli::commands::remove_user(&mut io::stdin().lock(), &mut io::stdout().lock(), &config_path, yes)
+    }
+    Command::Freeze { config_path } => cli::commands::freeze(&mut io::stdout().lock(), &config_path, true),
+    Command::Unfreeze { config_path } => cli::commands::freeze(&mut io::stdout().lock(), &config_path, false),
+    Command::Tag { command } => match command {
+      TagCommand::Add { config_path, tag } => cli::commands::tag_add(&mut io::stdout().lock(), &config_path, &tag),
+      TagCommand::Remove { config_path, tag } => cli::commands::tag_remove(&mut io::stdout().lock(), &config_path, &tag),
+    },
+    Command::Logs { command } => match command {
+      LogsCommand::Grep {
+        pattern,
+        ignore_case,
+        level,
+        context,
+        dir,
+      } => cli::commands::logs_grep(&mut io::stdout().lock(), &dir, &pattern, ignore_case, level, context),
+    },
+    Command::Path { command } => match command {
+      PathCommand::Add { path } => cli::commands::path_add(&mut io::stdout().lock(), path),
+      PathCommand::Delete { path } => cli::commands::path_delete(&mut io::stdout().lock(), &path),
+      PathCommand::List { json } => cli::commands::path_list(&mut io::stdout().lock(), json),
+    },
+    Command::Config { command } => match command {
+      ConfigCommand::Normalize => cli::commands::config_normalize(&mut io::stdout().lock()),
+    },
+    Command::WgConfig { command } => match command {
+      WgConfigCommand::Show {
+        config_path,
+        show_secrets,
+      } => cli::commands::wg_config_show(&mut io::stdout().lock(), config_path.as_deref(), show_secrets),
+      WgConfigCommand::Edit { config_path } => cli::commands::wg_config_edit(&mut io::stdout().lock(), config_path.as_deref()),
+    },
+  };
+
+  // Announcing a newer release, when --check-update is given
+  if cli.check_update
+      && let Some(version) = update::newer_version(&CurlClient, update::RELEASES_ENDPOINT, env!("CARGO_PKG_VERSION"))
+  {
+    log.info(&format!("WG-Bridge {version} is available"));
+    eprintln!("WG-Bridge {version} is available, running {}", env!("CARGO_PKG_VERSION"));
+  }
+  if let Err(e) = result {
+    fail(log, &e);
+  }
+
+  log.shutdown();
+}
+
+/// Function to build the global options a background monitor is started
+/// with, so that it uses the same configuration file and host.
+///
+/// # Arguments
+/// * `config`: The configuration file given with --config, if any.
+/// * `config_format`: The format given with --config-format, if any.
+/// * `remote`: The host given with --remote, if any.
+///
+/// # Returns
+/// * `Vec<OsString>`: The options, given before the monitor command.
+fn monitor_globals(config: &Option<PathBuf>, config_format: Option<ConfigFormat>, remote: &Option<String>) -> Vec<OsString> {
+  let mut globals = Vec::new();
+  if let Some(config) = config {
+    globals.extend(["--config".into(), config.clone().into_os_string()]);
+  }
+  if let Some(format) = config_format {
+    let format = match format {
+      ConfigFormat::Json => "json",
+      ConfigFormat::Toml => "toml",
+    };
+    globals.extend(["--config-format".into(), format.into()]);
+  }
+  if let Some(remote) = remote {
+    globals.extend(["--remote".into(), remote.into()]);
+  }
+  globals
+}
+
+/// Function to build the clock the one-time passwords are computed at.
+///
+/// # Arguments
+/// * `at`: The time given with `--at`, if any.
+///
+/// # Returns
+/// * `Box<dyn Clock>`: A clock stopped at `at`, or the system clock.
+fn clock_at(at: Option<SystemTime>) -> Box<dyn Clock> {
+  match at {
+    Some(at) => Box::new(FixedClock(DateTime::from(at))),
+    None => Box::new(SystemClock),
+  }
+}
+
+/// Function to report a fatal error and exit with a non-zero code.
+///
+/// The error is logged and, unless the log is already echoed to stderr,
+/// printed to stderr. The Logger is shut down before exiting.
+///
+/// # Arguments
+/// * `log`: The Logger.
+/// * `error`: The error to be reported.
+fn fail(log: &Logger, error: &dyn Error) -> ! {
+  log.error(&error.to_string());
+  if !log.mirrors_stderr() {
+    eprintln!("wgb: {error}");
+  }
+  log.shutdown();
+  std::process::exit(1);
 }
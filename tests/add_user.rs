@@ -0,0 +1,160 @@
+// add_user.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+mod common;
+
+use common::{KEY, temp_dir, wgb};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// A valid OTP URI.
+const OTP_URI: &str = "otpauth://totp/wgb:alice?secret=JBSWY3DPEHPK3PXP";
+
+/// Function to write a WireGuard configuration file.
+fn write_wg_config(dir: &Path) -> String {
+  let path = dir.join("wg0.conf");
+  fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\n")).unwrap();
+  path.display().to_string()
+}
+
+/// Function to read the users of the configuration file.
+fn users(dir: &Path) -> Vec<Value> {
+  let config: Value = serde_json::from_str(&fs::read_to_string(dir.join("wgb.json")).unwrap()).unwrap();
+  config["user"].as_array().unwrap().clone()
+}
+
+/// Function to read the stderr of a finished command.
+fn stderr(output: &std::process::Output) -> String {
+  String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn add_user_persists_the_user() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let wg_config = write_wg_config(dir);
+
+  let output = wgb(dir)
+      .args(["add-user", "--config-path", &wg_config, "--otp", "--otp-uri", OTP_URI])
+      .assert()
+      .success()
+      .get_output()
+      .clone();
+
+  assert_eq!(String::from_utf8_lossy(&output.stdout), format!("Added {wg_config}\n"));
+  let users = users(dir);
+  assert_eq!(users.len(), 1);
+  assert_eq!(users[0]["config_path"], wg_config.as_str());
+  assert_eq!(users[0]["otp"], true);
+  assert_eq!(users[0]["otp_uri"], OTP_URI);
+}
+
+#[test]
+fn add_user_rejects_a_duplicate_path() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let wg_config = write_wg_config(dir);
+  wgb(dir).args(["add-user", "--config-path", &wg_config]).assert().success();
+
+  let output = wgb(dir)
+      .args(["add-user", "--config-path", &wg_config])
+      .assert()
+      .failure()
+      .get_output()
+      .clone();
+
+  assert!(stderr(&output).contains(&format!("A user with config path {wg_config} already exists")));
+  assert_eq!(users(dir).len(), 1);
+}
+
+#[test]
+fn add_user_rejects_a_missing_wireguard_config() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let wg_config = dir.join("wg9.conf").display().to_string();
+
+  let output = wgb(dir)
+      .args(["add-user", "--config-path", &wg_config])
+      .assert()
+      .failure()
+      .get_output()
+      .clone();
+
+  assert!(stderr(&output).contains("does not exist"));
+  assert!(!dir.join("wgb.json").exists());
+}
+
+#[test]
+fn add_user_rejects_an_invalid_otp_uri() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let wg_config = write_wg_config(dir);
+
+  wgb(dir).args(["add-user", "--config-path", &wg_config, "--otp"]).assert().failure();
+  let output = wgb(dir)
+      .args(["add-user", "--config-path", &wg_config, "--otp", "--otp-uri", "https://example.com"])
+      .assert()
+      .failure()
+      .get_output()
+      .clone();
+
+  assert!(stderr(&output).contains("Invalid OTP URI"));
+  assert!(!dir.join("wgb.json").exists());
+}
+
+#[test]
+fn add_user_turns_a_bare_secret_into_an_otp_uri() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let wg_config = write_wg_config(dir);
+
+  wgb(dir)
+      .args(["add-user", "--config-path", &wg_config, "--otp", "--otp-uri", "jbsw y3dp ehpk 3pxp"])
+      .assert()
+      .success();
+  let otp_uri = "otpauth://totp/WG-Bridge:wg0?secret=JBSWY3DPEHPK3PXP&issuer=WG-Bridge&algorithm=SHA1&digits=6&period=30";
+  assert_eq!(users(dir)[0]["otp_uri"], otp_uri);
+  // The stored URI is loaded back
+  wgb(dir).arg("list").assert().success();
+}
+
+#[test]
+fn add_user_encrypts_the_otp_uri() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let wg_config = write_wg_config(dir);
+
+  wgb(dir)
+      .args(["add-user", "--config-path", &wg_config, "--otp", "--otp-uri", OTP_URI, "--encrypt"])
+      .write_stdin("passphrase\npassphrase\n")
+      .assert()
+      .success();
+
+  let users = users(dir);
+  assert_eq!(users[0]["otp_encrypted"], true);
+  assert_ne!(users[0]["otp_uri"], OTP_URI);
+}
+
+#[test]
+fn remove_user_rejects_an_unknown_path() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let wg_config = write_wg_config(dir);
+  wgb(dir).args(["add-user", "--config-path", &wg_config]).assert().success();
+  let missing = dir.join("wg9.conf").display().to_string();
+
+  let output = wgb(dir)
+      .args(["remove-user", &missing, "--yes"])
+      .assert()
+      .failure()
+      .get_output()
+      .clone();
+
+  assert!(stderr(&output).contains(&format!("No user with config path {missing}, configured paths: {wg_config}")));
+  assert_eq!(users(dir).len(), 1);
+  wgb(dir).args(["remove-user", &wg_config, "--yes"]).assert().success();
+  assert!(users(dir).is_empty());
+}
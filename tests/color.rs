@@ -0,0 +1,41 @@
+// color.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+mod common;
+
+use common::{KEY, temp_dir, wgb};
+use std::fs;
+use std::path::Path;
+
+/// Function to lint a WireGuard configuration with warnings, with colors
+/// forced, and to return the output.
+fn lint(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> String {
+  let path = dir.join("wg0.conf");
+  fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\nMTU = 1000\n")).unwrap();
+  let output = wgb(dir)
+      .env("CLICOLOR_FORCE", "1")
+      .envs(env.iter().copied())
+      .args(args)
+      .arg("lint")
+      .arg(&path)
+      .assert()
+      .failure()
+      .get_output()
+      .clone();
+  String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn colors_are_forced_or_disabled() {
+  let temp = temp_dir();
+  let dir = temp.path();
+
+  assert!(lint(dir, &[], &[]).contains("\x1b[33mwarning\x1b[0m"));
+  for output in [lint(dir, &["--no-color"], &[]), lint(dir, &[], &[("NO_COLOR", "1")])] {
+    assert!(output.contains("warning: Interface: MTU 1000"), "{output}");
+    assert!(!output.contains('\x1b'), "{output}");
+  }
+  assert!(lint(dir, &[], &[("NO_COLOR", "")]).contains('\x1b'));
+}
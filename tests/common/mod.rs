@@ -0,0 +1,57 @@
+// mod.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+// Each test binary uses its own subset of the helpers
+#![allow(dead_code)]
+
+use assert_cmd::Command;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// A valid WireGuard key, used wherever the key itself does not matter.
+pub const KEY: &str = "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=";
+
+/// Function to create an empty directory for a test, holding the
+/// configuration file, the WireGuard configurations and the log.
+///
+/// # Returns
+/// * `TempDir`: The directory, removed along with its content when dropped.
+pub fn temp_dir() -> TempDir {
+  tempfile::Builder::new().prefix("wgb-").tempdir().unwrap()
+}
+
+/// Function to build a `wgb` command using the configuration file of `dir`.
+///
+/// # Arguments
+/// * `dir`: The directory of the test, also used as the home directory.
+///
+/// # Returns
+/// * `Command`: The command, see `wgb_with_config`.
+pub fn wgb(dir: &Path) -> Command {
+  wgb_with_config(dir, &dir.join("wgb.json"))
+}
+
+/// Function to build a `wgb` command using the given configuration file,
+/// with the environment variables changing the behavior of `wgb` removed.
+///
+/// # Arguments
+/// * `dir`: The directory of the test, also used as the home directory.
+/// * `config`: The path to the configuration file.
+///
+/// # Returns
+/// * `Command`: The command, run from `dir`.
+pub fn wgb_with_config(dir: &Path, config: &Path) -> Command {
+  let mut command = Command::cargo_bin("wgb").unwrap();
+  command
+      .current_dir(dir)
+      .env("HOME", dir)
+      .env_remove("WGB_CONFIG")
+      .env_remove("NO_COLOR")
+      .env_remove("VISUAL")
+      .env_remove("EDITOR")
+      .arg("--config")
+      .arg(config);
+  command
+}
@@ -0,0 +1,37 @@
+// config_format.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+mod common;
+
+use common::{temp_dir, wgb_with_config};
+use std::fs;
+
+#[test]
+fn config_format_overrides_the_extension() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let config = dir.join("wgbconf");
+  fs::write(&config, "schema_version = 1\npaths = [\"/etc/wireguard\"]\n").unwrap();
+  let wgb = |args: &[&str]| {
+    let mut command = wgb_with_config(dir, &config);
+    command.args(args);
+    command
+  };
+
+  wgb(&["path", "list"]).assert().failure();
+  wgb(&["--config-format", "toml", "path", "list"])
+      .assert()
+      .success()
+      .stdout("/etc/wireguard\n");
+  wgb(&["--config-format", "toml", "path", "add", &dir.display().to_string()])
+      .assert()
+      .success();
+
+  let written: toml::Value = toml::from_str(&fs::read_to_string(&config).unwrap()).unwrap();
+  let paths = written["paths"].as_array().unwrap();
+  assert_eq!(paths.len(), 2);
+  assert_eq!(paths[1].as_str(), Some(dir.display().to_string().as_str()));
+  wgb(&["--config-format", "yaml", "path", "list"]).assert().failure();
+}
@@ -0,0 +1,81 @@
+// discover.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+mod common;
+
+use common::{KEY, temp_dir, wgb};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Function to create a directory for a test, holding the configuration
+/// file and a directory of sample WireGuard configuration files: two valid
+/// ones, an invalid one and a file which is not a configuration.
+fn sample_dir() -> TempDir {
+  let dir = temp_dir();
+  let wireguard = dir.path().join("wireguard");
+  fs::create_dir_all(&wireguard).unwrap();
+  for name in ["wg0.conf", "wg1.conf"] {
+    fs::write(wireguard.join(name), format!("[Interface]\nPrivateKey = {KEY}\n")).unwrap();
+  }
+  fs::write(wireguard.join("bad.conf"), "[Interface]\nPrivateKey = nope\n").unwrap();
+  fs::write(wireguard.join("notes.txt"), "not a configuration\n").unwrap();
+  dir
+}
+
+/// Function to read the config paths of the users of the configuration file.
+fn config_paths(dir: &Path) -> Vec<String> {
+  let Ok(content) = fs::read_to_string(dir.join("wgb.json")) else {
+    return Vec::new();
+  };
+  let config: Value = serde_json::from_str(&content).unwrap();
+  config["user"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|user| user["config_path"].as_str().unwrap().to_string())
+      .collect()
+}
+
+#[test]
+fn discover_adds_the_valid_files_not_configured_yet() {
+  let temp = sample_dir();
+  let dir = temp.path();
+  let wireguard = dir.join("wireguard");
+  let wg0 = wireguard.join("wg0.conf").display().to_string();
+  let wg1 = wireguard.join("wg1.conf").display().to_string();
+  wgb(dir).args(["add-user", "--config-path", &wg1]).write_stdin("").assert().success();
+
+  let output = wgb(dir)
+      .args(["discover", "-y"])
+      .arg(&wireguard)
+      .assert()
+      .success()
+      .get_output()
+      .clone();
+
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  let lines: Vec<&str> = stdout.lines().collect();
+  assert!(lines[0].starts_with(&format!("Skipped {}: ", wireguard.join("bad.conf").display())), "{stdout}");
+  assert_eq!(lines[1..], [format!("Found {wg0}"), format!("{wg1} is configured already"), format!("Added {wg0}")]);
+  assert_eq!(config_paths(dir), [wg1, wg0]);
+
+  // Nothing is left to add the second time
+  let output = wgb(dir).arg("discover").arg(&wireguard).assert().success().get_output().clone();
+  assert!(String::from_utf8_lossy(&output.stdout).ends_with("No new WireGuard configuration files\n"));
+}
+
+#[test]
+fn discover_adds_nothing_unless_confirmed() {
+  let temp = sample_dir();
+  let dir = temp.path();
+  let wireguard = dir.join("wireguard");
+
+  wgb(dir).arg("discover").arg(&wireguard).write_stdin("n\n").assert().success();
+  assert!(config_paths(dir).is_empty());
+  wgb(dir).arg("discover").arg(&wireguard).write_stdin("y\n").assert().success();
+  assert_eq!(config_paths(dir).len(), 2);
+}
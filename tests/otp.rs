@@ -0,0 +1,100 @@
+// otp.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+mod common;
+
+use common::{KEY, temp_dir, wgb};
+use std::fs;
+use std::path::Path;
+
+/// The OTP URI of the SHA1 test vectors of RFC 6238.
+const OTP_URI: &str = "otpauth://totp/wgb:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8";
+
+/// Function to add a configuration requiring a one-time password.
+fn add_user(dir: &Path, encrypt: bool) -> String {
+  let path = dir.join("wg0.conf");
+  fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\n")).unwrap();
+  let path = path.display().to_string();
+  let mut command = wgb(dir);
+  command.args(["add-user", "--config-path", &path, "--otp", "--otp-uri", OTP_URI]);
+  if encrypt {
+    command.arg("--encrypt").write_stdin("secret\nsecret\n");
+  }
+  command.assert().success();
+  path
+}
+
+#[test]
+fn generate_totp_computes_the_code_at_the_given_time() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let path = add_user(dir, false);
+
+  for (at, code) in [("59", "94287082"), ("1111111109", "07081804"), ("2005-03-18T01:58:31Z", "14050471")] {
+    wgb(dir)
+        .args(["generate-totp", &path, "--at", at])
+        .assert()
+        .success()
+        .stdout(format!("{code}\n"));
+  }
+  // The path is optional with a single configuration
+  wgb(dir).args(["generate-totp", "--at", "1234567890"]).assert().success().stdout("89005924\n");
+}
+
+#[test]
+fn verify_otp_accepts_the_current_code_only() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let path = add_user(dir, false);
+
+  wgb(dir)
+      .args(["verify-otp", "69279037", &path, "--at", "2000000000"])
+      .assert()
+      .success()
+      .stdout(format!("The one-time password of {path} is valid\n"));
+  let output = wgb(dir)
+      .args(["verify-otp", "69279037", &path, "--at", "20000000000"])
+      .assert()
+      .failure()
+      .get_output()
+      .clone();
+  assert!(String::from_utf8_lossy(&output.stderr).contains(&format!("The one-time password of {path} is invalid")));
+}
+
+#[test]
+fn encrypted_otp_uris_are_decrypted_with_the_passphrase() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let path = add_user(dir, true);
+
+  wgb(dir)
+      .args(["generate-totp", &path, "--at", "59"])
+      .write_stdin("secret\n")
+      .assert()
+      .success()
+      .stdout("94287082\n")
+      .stderr("Passphrase: ");
+  let output = wgb(dir)
+      .args(["generate-totp", &path, "--at", "59"])
+      .write_stdin("wrong\n")
+      .assert()
+      .failure()
+      .get_output()
+      .clone();
+  assert!(String::from_utf8_lossy(&output.stderr).contains("Wrong passphrase"));
+}
+
+#[test]
+fn generate_totp_needs_an_otp_uri() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let path = dir.join("wg0.conf");
+  fs::write(&path, format!("[Interface]\nPrivateKey = {KEY}\n")).unwrap();
+  let path = path.display().to_string();
+  wgb(dir).args(["add-user", "--config-path", &path]).assert().success();
+
+  let output = wgb(dir).args(["generate-totp", &path]).assert().failure().get_output().clone();
+  assert!(String::from_utf8_lossy(&output.stderr).contains(&format!("{path} has no otp_uri")));
+}
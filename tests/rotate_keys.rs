@@ -0,0 +1,62 @@
+// rotate_keys.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+mod common;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use common::{KEY, temp_dir, wgb};
+use std::fs;
+use std::path::Path;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Function to derive the public key of a base64 private key.
+fn public_key(private_key: &str) -> String {
+  let bytes: [u8; 32] = STANDARD.decode(private_key).unwrap().try_into().unwrap();
+  STANDARD.encode(PublicKey::from(&StaticSecret::from(bytes)).as_bytes())
+}
+
+/// Function to read the private key of a WireGuard configuration file.
+fn private_key(path: &Path) -> String {
+  let content = fs::read_to_string(path).unwrap();
+  let line = content.lines().find(|line| line.starts_with("PrivateKey")).unwrap();
+  line.split_once('=').unwrap().1.trim().to_string()
+}
+
+#[test]
+fn rotate_keys_replaces_the_keypair_and_reports_the_public_key() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let path = dir.join("wg0.conf");
+  let content = format!("[Interface]\nPrivateKey = {KEY}\nAddress = 10.0.0.2/32\n\n[Peer]\nPublicKey = {KEY}\n");
+  fs::write(&path, &content).unwrap();
+
+  let output = wgb(dir)
+      .args(["rotate-keys", &path.display().to_string()])
+      .assert()
+      .success()
+      .get_output()
+      .clone();
+
+  let private = private_key(&path);
+  assert_ne!(private, KEY);
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  assert!(stdout.contains(&format!("New public key: {}\n", public_key(&private))));
+  assert!(!stdout.contains(&private));
+  assert_eq!(fs::read_to_string(&path).unwrap(), content.replacen(KEY, &private, 1));
+  assert_eq!(fs::read_to_string(dir.join("wg0.conf.bak")).unwrap(), content);
+}
+
+#[test]
+fn rotate_keys_rejects_a_missing_file() {
+  let temp = temp_dir();
+  let dir = temp.path();
+
+  wgb(dir)
+      .args(["rotate-keys", &dir.join("wg0.conf").display().to_string()])
+      .assert()
+      .failure();
+  assert!(!dir.join("wg0.conf.bak").exists());
+}
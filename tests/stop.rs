@@ -0,0 +1,37 @@
+// stop.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+mod common;
+
+use common::{temp_dir, wgb};
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+use std::thread;
+
+#[test]
+fn stop_terminates_the_monitor_and_removes_its_pid_file() {
+  let temp = temp_dir();
+  let dir = temp.path();
+  let path = dir.join("wg0.conf").display().to_string();
+  // Standing in for the monitor started by `connect --detach`
+  let mut monitor = Command::new("sleep").arg("30").spawn().unwrap();
+  let pid = monitor.id();
+  let waiter = thread::spawn(move || monitor.wait().unwrap());
+  let pid_file = dir.join(".wgbmonitors/wg0.pid");
+  fs::create_dir(dir.join(".wgbmonitors")).unwrap();
+  fs::write(&pid_file, format!("{pid}\n")).unwrap();
+
+  wgb(dir)
+      .args(["stop", &path])
+      .assert()
+      .success()
+      .stdout(format!("Stopped the monitor of {path} (PID {pid})\n"));
+  assert_eq!(waiter.join().unwrap().signal(), Some(libc::SIGTERM));
+  assert!(!pid_file.exists());
+
+  let output = wgb(dir).args(["stop", &path]).assert().failure().get_output().clone();
+  assert!(String::from_utf8_lossy(&output.stderr).contains("No monitor of wg0 is running"));
+}
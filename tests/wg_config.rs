@@ -0,0 +1,80 @@
+// wg_config.rs
+// Copyright (c) 2025 Lunatic Fringers
+// This file is part of "WG-Bridge" under the AGPL-3.0-or-later license.
+// See the LICENSE file in the project root or <https://www.gnu.org/licenses/> for details.
+
+mod common;
+
+use common::{KEY, temp_dir, wgb};
+use std::fs;
+use tempfile::TempDir;
+
+/// Function to create a directory for a test, holding a WireGuard
+/// configuration file with a private and a preshared key.
+fn sample_dir() -> TempDir {
+  let dir = temp_dir();
+  fs::write(dir.path().join("wg0.conf"), config("ListenPort = 51820")).unwrap();
+  dir
+}
+
+/// Function to build the content of the WireGuard configuration file.
+fn config(listen_port: &str) -> String {
+  format!("[Interface]\nPrivateKey = {KEY}\n{listen_port}\n\n[Peer]\nPublicKey = {KEY}\nPresharedKey = {KEY}\n")
+}
+
+#[test]
+fn show_redacts_the_keys_unless_asked() {
+  let temp = sample_dir();
+  let dir = temp.path();
+  let wg0 = dir.join("wg0.conf");
+
+  let output = wgb(dir).args(["wg-config", "show"]).arg(&wg0).assert().success().get_output().clone();
+  assert_eq!(
+    String::from_utf8_lossy(&output.stdout),
+    format!("[Interface]\nPrivateKey = <redacted>\nListenPort = 51820\n\n[Peer]\nPublicKey = {KEY}\nPresharedKey = <redacted>\n")
+  );
+  let output = wgb(dir)
+      .args(["wg-config", "show", "--show-secrets"])
+      .arg(&wg0)
+      .assert()
+      .success()
+      .get_output()
+      .clone();
+  assert_eq!(String::from_utf8_lossy(&output.stdout), config("ListenPort = 51820"));
+}
+
+#[test]
+fn edit_keeps_only_valid_changes() {
+  let temp = sample_dir();
+  let dir = temp.path();
+  let wg0 = dir.join("wg0.conf");
+
+  let output = wgb(dir)
+      .env("EDITOR", "sed -i s/51820/51821/")
+      .args(["wg-config", "edit"])
+      .arg(&wg0)
+      .assert()
+      .success()
+      .get_output()
+      .clone();
+  assert_eq!(String::from_utf8_lossy(&output.stdout), format!("Saved {}\n", wg0.display()));
+  assert_eq!(fs::read_to_string(&wg0).unwrap(), config("ListenPort = 51821"));
+
+  // An invalid edit, or a failed editor, leaves the file as it was
+  let output = wgb(dir)
+      .env("VISUAL", "sed -i s/51821/nope/")
+      .env("EDITOR", "true")
+      .args(["wg-config", "edit"])
+      .arg(&wg0)
+      .assert()
+      .failure()
+      .get_output()
+      .clone();
+  assert!(String::from_utf8_lossy(&output.stderr).contains("the edited file is invalid"));
+  assert_eq!(fs::read_to_string(&wg0).unwrap(), config("ListenPort = 51821"));
+  wgb(dir).env("EDITOR", "false").args(["wg-config", "edit"]).arg(&wg0).assert().failure();
+  assert_eq!(fs::read_to_string(&wg0).unwrap(), config("ListenPort = 51821"));
+
+  let output = wgb(dir).env("EDITOR", "true").args(["wg-config", "edit"]).arg(&wg0).assert().success().get_output().clone();
+  assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{} is unchanged\n", wg0.display()));
+}